@@ -9,10 +9,12 @@ use clap::Parser;
 use clap::Subcommand;
 use daemonize::Daemonize;
 use libc::kill;
+use libc::SIGTERM;
 use rings_node::logger::LogLevel;
 use rings_node::logger::Logger;
 use rings_node::prelude::rings_core::async_trait;
 use rings_node::prelude::rings_core::dht::PeerRing;
+use rings_node::prelude::rings_core::dht::PeerRingConfig;
 use rings_node::prelude::rings_core::dht::Stabilization;
 use rings_node::prelude::rings_core::dht::TStabilize;
 use rings_node::prelude::rings_core::ecc::SecretKey;
@@ -25,6 +27,11 @@ use rings_node::prelude::rings_core::message::MessagePayload;
 use rings_node::prelude::rings_core::prelude::url;
 use rings_node::prelude::rings_core::session::SessionManager;
 use rings_node::prelude::rings_core::swarm::Swarm;
+#[cfg(feature = "websocket")]
+use rings_node::prelude::rings_core::transports::websocket::WebSocketTransport;
+use rings_node::prelude::rings_core::transports::Network;
+#[cfg(feature = "websocket")]
+use rings_node::prelude::rings_core::transports::TransportKind;
 use rings_node::prelude::rings_core::types::message::MessageListener;
 use rings_node::service::run_service;
 use rings_node::service::run_udp_turn;
@@ -108,6 +115,64 @@ struct RunArgs {
 
     #[clap(long, env, help = "external ip address")]
     pub external_ip: Option<String>,
+
+    /// Max length of successor list.
+    #[clap(long, default_value = "3", env)]
+    pub successor_max: u8,
+
+    /// Size of finger table.
+    #[clap(long, default_value = "160", env)]
+    pub finger_size: usize,
+
+    /// Max number of virtual nodes kept in cache.
+    #[clap(long, default_value = "1024", env)]
+    pub cache_capacity: usize,
+
+    /// Milliseconds a fetched virtual node is kept in cache.
+    #[clap(long, default_value = "300000", env)]
+    pub cache_ttl_ms: u64,
+
+    /// Number of copies kept for each stored virtual node.
+    #[clap(long, default_value = "1", env)]
+    pub replica_factor: u8,
+
+    /// Id of network, peers from other networks are rejected.
+    #[clap(long, default_value = "rings", env)]
+    pub network_id: String,
+
+    /// Pre-shared key of network.
+    #[clap(long, env)]
+    pub network_psk: Option<String>,
+
+    /// Number of virtual identities hosted besides the node's own, for load balancing.
+    #[clap(long, default_value = "0", env)]
+    pub virtual_ids: u8,
+
+    /// Address to accept websocket connections, e.g. 0.0.0.0:50001.
+    #[cfg(feature = "websocket")]
+    #[clap(long, env)]
+    pub ws_bind: Option<String>,
+
+    /// Public url of websocket listener told to peers, e.g. ws://1.2.3.4:50001.
+    #[cfg(feature = "websocket")]
+    #[clap(long, env)]
+    pub ws_url: Option<String>,
+}
+
+impl RunArgs {
+    fn peer_ring_config(&self) -> PeerRingConfig {
+        PeerRingConfig {
+            successor_max: self.successor_max,
+            finger_size: self.finger_size,
+            cache_capacity: self.cache_capacity,
+            cache_ttl_ms: self.cache_ttl_ms,
+            replica_factor: self.replica_factor,
+        }
+    }
+
+    fn network(&self) -> Network {
+        Network::new(&self.network_id, self.network_psk.as_deref())
+    }
 }
 
 #[derive(Args, Debug)]
@@ -118,7 +183,8 @@ struct ShutdownArgs {
 
 async fn run_jobs(args: &RunArgs) -> anyhow::Result<()> {
     let key: &SecretKey = &args.ecdsa_key;
-    let dht = Arc::new(PeerRing::new(key.address().into()).await?);
+    let dht =
+        Arc::new(PeerRing::new_with_config(key.address().into(), args.peer_ring_config()).await?);
 
     let (auth, s_key) = SessionManager::gen_unsign_info(
         key.address(),
@@ -152,12 +218,24 @@ async fn run_jobs(args: &RunArgs) -> anyhow::Result<()> {
     };
 
     let ice_servers = ice_servers.join(";");
-    let swarm = Arc::new(Swarm::new_with_external_address(
+    #[allow(unused_mut)]
+    let mut swarm = Swarm::new_with_network(
         &ice_servers,
         key.address(),
         session,
         args.external_ip.clone(),
-    ));
+        args.network(),
+    );
+    // offer websocket links only when peers can dial in, answers follow the offer of peer
+    #[cfg(feature = "websocket")]
+    let ws_listener = match (&args.ws_bind, &args.ws_url) {
+        (Some(bind), Some(url)) => {
+            swarm.set_transport_kind(TransportKind::WebSocket);
+            Some(WebSocketTransport::listen(bind, url).await?)
+        }
+        _ => None,
+    };
+    let swarm = Arc::new(swarm);
 
     // let listen_event = MessageHandler::new(dht.clone(), swarm.clone());
     let message_callback = MessageCallback {};
@@ -171,6 +249,18 @@ async fn run_jobs(args: &RunArgs) -> anyhow::Result<()> {
         swarm.clone(),
         args.stabilize_timeout,
     ));
+    let mut virtual_stabilizations = vec![];
+    for i in 1..=args.virtual_ids {
+        let id = listen_event.add_virtual_identity(i).await?;
+        if let Some(ring) = listen_event.virtual_ring(id) {
+            let s = Arc::new(Stabilization::new(
+                ring,
+                swarm.clone(),
+                args.stabilize_timeout,
+            ));
+            virtual_stabilizations.push(tokio::spawn(s.wait()));
+        }
+    }
     let http_addr = args.http_addr.clone();
     let listen_event_1 = listen_event.clone();
     let listen_event_2 = listen_event.clone();
@@ -191,9 +281,23 @@ async fn run_jobs(args: &RunArgs) -> anyhow::Result<()> {
             AnyhowResult::Ok(())
         },
     ));
-    signal::ctrl_c().await.expect("failed to listen for event");
-    println!("\nClosing connection now...");
+    // `rings-daemon shutdown` sends SIGTERM, so both leave the DHT gracefully
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        r = signal::ctrl_c() => r.expect("failed to listen for event"),
+        _ = terminate.recv() => {}
+    }
+    println!("\nLeaving DHT now...");
+    if let Err(e) = listen_event.leave().await {
+        println!("leave DHT failed, {}", e);
+    }
+    println!("Closing connection now...");
     j.abort();
+    for s in virtual_stabilizations {
+        s.abort();
+    }
+    #[cfg(feature = "websocket")]
+    let _ = ws_listener.map(|l| l.abort());
     if let Some(s) = turn_server {
         if let Err(e) = s.close().await {
             println!("close turn_server failed, {}", e);
@@ -258,10 +362,11 @@ fn run_daemon(args: &RunArgs) -> AnyhowResult<()> {
 
 fn shutdown_daemon(args: &ShutdownArgs) -> anyhow::Result<()> {
     let pid: i32 = fs::read_to_string(args.pid_file.as_str())?.parse()?;
-    unsafe {
-        kill(pid, 9);
+    // SIGTERM can be caught, so the daemon leaves the DHT before exiting
+    if unsafe { kill(pid, SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
     }
-    println!("Killed: {}", pid);
+    println!("Sent SIGTERM to: {}", pid);
     Ok(())
 }
 
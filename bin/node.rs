@@ -4,12 +4,14 @@ use clap::Args;
 use clap::Parser;
 use clap::Subcommand;
 use rings_core::dht::PeerRing;
+use rings_core::dht::PeerRingConfig;
 use rings_core::dht::Stabilization;
 use rings_core::dht::TStabilize;
 use rings_core::ecc::SecretKey;
 use rings_core::message::MessageHandler;
 use rings_core::session::SessionManager;
 use rings_core::swarm::Swarm;
+use rings_core::transports::Network;
 use rings_core::types::message::MessageListener;
 use rings_node::cli::Client;
 use rings_node::logger::LogLevel;
@@ -43,6 +45,10 @@ enum Command {
     Peer(PeerCommand),
     #[clap(subcommand)]
     Pending(PendingCommand),
+    #[clap(subcommand)]
+    Node(NodeCommand),
+    #[clap(subcommand)]
+    File(FileCommand),
     Send(Send),
     NewSecretKey,
 }
@@ -69,6 +75,47 @@ struct Daemon {
 
     #[clap(long, env, help = "external ip address")]
     pub external_ip: Option<String>,
+
+    #[clap(long, default_value = "3", env, help = "max length of successor list")]
+    pub successor_max: u8,
+
+    #[clap(long, default_value = "160", env, help = "size of finger table")]
+    pub finger_size: usize,
+
+    #[clap(
+        long,
+        default_value = "1024",
+        env,
+        help = "max number of virtual nodes kept in cache"
+    )]
+    pub cache_capacity: usize,
+
+    #[clap(
+        long,
+        default_value = "300000",
+        env,
+        help = "milliseconds a fetched virtual node is kept in cache"
+    )]
+    pub cache_ttl_ms: u64,
+
+    #[clap(
+        long,
+        default_value = "1",
+        env,
+        help = "number of copies kept for each stored virtual node"
+    )]
+    pub replica_factor: u8,
+
+    #[clap(
+        long,
+        default_value = "rings",
+        env,
+        help = "id of network, peers from other networks are rejected"
+    )]
+    pub network_id: String,
+
+    #[clap(long, env, help = "pre-shared key of network")]
+    pub network_psk: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -210,6 +257,81 @@ struct PendingCloseTransport {
     transport_id: String,
 }
 
+#[derive(Subcommand, Debug)]
+#[clap(rename_all = "kebab-case")]
+enum NodeCommand {
+    #[clap(about = "dump did, predecessor, successors and finger table of the node")]
+    Topology(NodeTopologyArgs),
+    #[clap(about = "show status of the node, with estimated network size")]
+    Status(NodeStatusArgs),
+    #[clap(about = "crawl the ring, and export the network map as json or graphviz dot")]
+    Crawl(NodeCrawlArgs),
+}
+
+#[derive(Args, Debug)]
+struct NodeTopologyArgs {
+    #[clap(flatten)]
+    client_args: ClientArgs,
+}
+
+#[derive(Args, Debug)]
+struct NodeStatusArgs {
+    #[clap(flatten)]
+    client_args: ClientArgs,
+}
+
+#[derive(Args, Debug)]
+struct NodeCrawlArgs {
+    #[clap(flatten)]
+    client_args: ClientArgs,
+
+    #[clap(long, default_value = "json", possible_values = ["json", "dot"])]
+    format: String,
+
+    #[clap(long, default_value = "1024", help = "max number of nodes to reach")]
+    max_nodes: u64,
+
+    #[clap(
+        long,
+        short = 'o',
+        help = "write network map to file instead of stdout"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+#[clap(rename_all = "kebab-case")]
+enum FileCommand {
+    #[clap(about = "store a file on DHT, signed by the session of the node")]
+    Store(FileStoreArgs),
+    #[clap(about = "fetch a file from DHT by its id")]
+    Fetch(FileFetchArgs),
+}
+
+#[derive(Args, Debug)]
+struct FileStoreArgs {
+    #[clap(flatten)]
+    client_args: ClientArgs,
+
+    #[clap(help = "path of file to store")]
+    path: String,
+
+    #[clap(long, help = "name of stored file, default to the file name of path")]
+    name: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct FileFetchArgs {
+    #[clap(flatten)]
+    client_args: ClientArgs,
+
+    #[clap(help = "id of file")]
+    id: String,
+
+    #[clap(long, short = 'o', help = "write content to file instead of stdout")]
+    output: Option<String>,
+}
+
 #[derive(Args, Debug)]
 struct Send {
     #[clap(flatten)]
@@ -226,8 +348,10 @@ async fn daemon_run(
     stuns: &str,
     stabilize_timeout: usize,
     external_ip: Option<String>,
+    config: PeerRingConfig,
+    network: Network,
 ) -> anyhow::Result<()> {
-    let dht = Arc::new(PeerRing::new(key.address().into()).await?);
+    let dht = Arc::new(PeerRing::new_with_config(key.address().into(), config).await?);
     let (auth, temp_key) = SessionManager::gen_unsign_info(
         key.address(),
         Some(rings_core::session::Ttl::Never),
@@ -235,11 +359,12 @@ async fn daemon_run(
     )?;
     let sig = key.sign(&auth.to_string()?).to_vec();
     let session = SessionManager::new(&sig, &auth, &temp_key);
-    let swarm = Arc::new(Swarm::new_with_external_address(
+    let swarm = Arc::new(Swarm::new_with_network(
         stuns,
         key.address(),
         session.clone(),
         external_ip,
+        network,
     ));
     let listen_event = Arc::new(MessageHandler::new(dht.clone(), swarm.clone()));
     let stabilize = Arc::new(Stabilization::new(
@@ -283,6 +408,14 @@ async fn main() -> anyhow::Result<()> {
                 args.ice_servers.as_str(),
                 args.stabilize_timeout,
                 args.external_ip,
+                PeerRingConfig {
+                    successor_max: args.successor_max,
+                    finger_size: args.finger_size,
+                    cache_capacity: args.cache_capacity,
+                    cache_ttl_ms: args.cache_ttl_ms,
+                    replica_factor: args.replica_factor,
+                },
+                Network::new(&args.network_id, args.network_psk.as_deref()),
             )
             .await
         }
@@ -367,6 +500,70 @@ async fn main() -> anyhow::Result<()> {
                 .display();
             Ok(())
         }
+        Command::Node(NodeCommand::Topology(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .node_topology()
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Node(NodeCommand::Status(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .node_status()
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Node(NodeCommand::Crawl(args)) => {
+            let output = args
+                .client_args
+                .new_client()
+                .await?
+                .crawl_ring(args.format.as_str(), args.max_nodes)
+                .await?;
+            match args.output {
+                Some(path) => std::fs::write(path, output.result)?,
+                None => output.display(),
+            }
+            Ok(())
+        }
+        Command::File(FileCommand::Store(args)) => {
+            let data = std::fs::read(&args.path)?;
+            let name = match args.name {
+                Some(name) => name,
+                None => std::path::Path::new(&args.path)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| args.path.clone()),
+            };
+            args.client_args
+                .new_client()
+                .await?
+                .store_file(name.as_str(), &data)
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::File(FileCommand::Fetch(args)) => {
+            let output = args
+                .client_args
+                .new_client()
+                .await?
+                .fetch_file(args.id.as_str())
+                .await?;
+            match args.output {
+                Some(path) => {
+                    std::fs::write(path, &output.result)?;
+                    output.display();
+                }
+                None => std::io::Write::write_all(&mut std::io::stdout(), &output.result)?,
+            }
+            Ok(())
+        }
         Command::Send(args) => {
             args.client_args
                 .new_client()
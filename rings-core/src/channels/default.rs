@@ -46,4 +46,12 @@ impl<T: Send> Channel<T> for AcChannel<T> {
             Err(_) => Err(Error::ChannelRecvMessageFailed),
         }
     }
+
+    async fn try_recv(receiver: &Self::Receiver) -> Result<Option<T>> {
+        match receiver.try_recv() {
+            Ok(v) => Ok(Some(v)),
+            Err(ac::TryRecvError::Empty) => Ok(None),
+            Err(ac::TryRecvError::Closed) => Err(Error::ChannelRecvMessageFailed),
+        }
+    }
 }
@@ -56,4 +56,14 @@ impl<T: Send> Channel<T> for CbChannel<T> {
             Ok(None) => Ok(None),
         }
     }
+
+    async fn try_recv(receiver: &Self::Receiver) -> Result<Option<T>> {
+        let mut receiver = receiver.lock().await;
+        match receiver.try_next() {
+            Ok(Some(x)) => Ok(Some(x)),
+            // no message queued for now
+            Err(_) => Ok(None),
+            Ok(None) => Err(Error::ChannelRecvMessageFailed),
+        }
+    }
 }
@@ -6,23 +6,32 @@ use std::sync::MutexGuard;
 use async_trait::async_trait;
 use itertools::Itertools;
 use num_bigint::BigUint;
+use num_traits::ToPrimitive;
 use serde::Deserialize;
 use serde::Serialize;
 
 use super::did::BiasId;
+use super::subring::SubRingAdminAction;
 use super::successor::Successor;
 use super::types::Chord;
 use super::types::ChordStabilize;
 use super::types::ChordStorage;
+use super::vnode::VNodeRemoval;
+use super::vnode::VNodeType;
 use super::vnode::VirtualNode;
 use super::FingerTable;
 use crate::dht::Did;
+use crate::dht::MAX_VIRTUAL_IDS;
 use crate::err::Error;
 use crate::err::Result;
-use crate::storage::MemStorage;
+use crate::message::Decoder;
+use crate::message::Message;
+use crate::message::MessagePayload;
+use crate::storage::CacheStats;
+use crate::storage::LruStorage;
 use crate::storage::PersistenceStorage;
+use crate::storage::PersistenceStorageOperation;
 use crate::storage::PersistenceStorageReadAndWrite;
-// use crate::storage::PersistenceStorageOperation;
 use crate::storage::PersistenceStorageRemove;
 
 /// Remote actions
@@ -37,6 +46,10 @@ pub enum RemoteAction {
     FindAndStore(VirtualNode),
     /// Ask did_a to find virtual peer for subring joining
     FindAndJoinSubRing(Did),
+    /// Ask did_a to find virtual peer for subring leaving
+    FindAndLeaveSubRing(Did),
+    /// Ask did_a to find virtual peer for applying a subring admin action
+    FindAndAdminSubRing(SubRingAdminAction),
     /// Ask Did_a to notify(did_b)
     Notify(Did),
     /// Async data with it's successor
@@ -45,6 +58,18 @@ pub enum RemoteAction {
     FindSuccessorForFix(Did),
     /// Check predecessor
     CheckPredecessor,
+    /// Push replicas of locally stored VNodes to a successor
+    StoreReplica(Vec<VirtualNode>),
+    /// Tell did_a that self is leaving, with self's predecessor and successor
+    NotifyLeave(Option<Did>, Option<Did>),
+    /// Hand off locally stored VNodes to did_a before leaving
+    HandoffVNode(Vec<VirtualNode>),
+    /// Ask did_a to find virtual peer for removing
+    FindAndRemove(VNodeRemoval),
+    /// Remove a replica from a successor
+    RemoveReplica(VNodeRemoval),
+    /// Remove delivered messages of did_b from a replica of its relay messages
+    AckRelayMessageReplica(Did, Vec<uuid::Uuid>),
 }
 
 /// Result of PeerRing algorithm
@@ -111,41 +136,170 @@ pub struct PeerRing {
     /// LocalStorage for DHT Query
     pub storage: Arc<PersistenceStorage>,
     /// LocalCache
-    pub cache: Arc<MemStorage<Did, VirtualNode>>,
+    pub cache: Arc<LruStorage<Did, VirtualNode>>,
+    /// Number of copies kept for each stored VNode, including the copy of the owner.
+    /// The owner pushes `replica_factor - 1` replicas to its successor list.
+    pub replica_factor: u8,
+}
+
+/// By default only the owner keeps a VNode.
+pub const DEFAULT_REPLICA_FACTOR: u8 = 1;
+
+/// By default a cached VNode is fetched again after 5 minutes.
+pub const DEFAULT_CACHE_TTL_MS: u64 = 300_000;
+
+/// Max size of finger table, finger `k` for `k >= 160` would wrap around the ring.
+pub const MAX_FINGER_SIZE: usize = 160;
+
+/// Parameters of a PeerRing.
+/// Small test networks and large production rings may need different settings.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerRingConfig {
+    /// Max length of successor list
+    pub successor_max: u8,
+    /// Size of finger table, finger `k` is the successor of `(id + 2^k) mod 2^160`
+    pub finger_size: usize,
+    /// Max number of VNodes kept in cache
+    pub cache_capacity: usize,
+    /// Milliseconds a fetched VNode is kept in cache, which bounds how long
+    /// a node may serve a VNode updated since it fetched it
+    pub cache_ttl_ms: u64,
+    /// Number of copies kept for each stored VNode, including the copy of the owner
+    pub replica_factor: u8,
+}
+
+impl Default for PeerRingConfig {
+    fn default() -> Self {
+        Self {
+            successor_max: 3,
+            // for Eth address, it's 160
+            finger_size: MAX_FINGER_SIZE,
+            cache_capacity: 1024,
+            cache_ttl_ms: DEFAULT_CACHE_TTL_MS,
+            replica_factor: DEFAULT_REPLICA_FACTOR,
+        }
+    }
+}
+
+impl PeerRingConfig {
+    /// Check if all values are in their valid range.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::InvalidPeerRingConfig(reason.to_string()));
+        if self.successor_max == 0 {
+            return invalid("successor_max should be at least 1");
+        }
+        if self.finger_size == 0 || self.finger_size > MAX_FINGER_SIZE {
+            return invalid("finger_size should be in 1..=160");
+        }
+        if self.cache_capacity == 0 {
+            return invalid("cache_capacity should be at least 1");
+        }
+        if self.cache_ttl_ms == 0 {
+            return invalid("cache_ttl_ms should be at least 1");
+        }
+        // replicas are pushed to the successor list
+        if self.replica_factor == 0 || self.replica_factor > self.successor_max.saturating_add(1) {
+            return invalid("replica_factor should be in 1..=successor_max + 1");
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of the routing state of a PeerRing, for inspection and debugging.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRingTopology {
+    /// Did of the node
+    pub did: Did,
+    /// Predecessor of the node
+    pub predecessor: Option<Did>,
+    /// Successor list, ordered from the closest one
+    pub successors: Vec<Did>,
+    /// Non-empty entries of finger table, with their index
+    pub fingers: Vec<(usize, Did)>,
+    /// Number of VNodes in cache
+    pub cache_size: usize,
+    /// Hit and miss counters of cache
+    pub cache_stats: CacheStats,
+    /// Number of entries in persistence storage
+    pub storage_count: u64,
+}
+
+impl PeerRingTopology {
+    /// Known neighbours of the node: predecessor, successors and fingers, without duplicates.
+    pub fn neighbours(&self) -> Vec<Did> {
+        self.predecessor
+            .iter()
+            .chain(self.successors.iter())
+            .chain(self.fingers.iter().map(|(_, did)| did))
+            .filter(|x| **x != self.did)
+            .unique()
+            .copied()
+            .collect()
+    }
 }
 
 impl PeerRing {
     /// Create a new Chord ring.
     pub async fn new(id: Did) -> Result<Self> {
-        Self::new_with_config(id, 3).await
+        Self::new_with_config(id, PeerRingConfig::default()).await
     }
 
-    /// Create a new Chord Ring with given successor_max, and finger_size
-    pub async fn new_with_config(id: Did, succ_max: u8) -> Result<Self> {
-        Ok(Self {
-            successor: Arc::new(Mutex::new(Successor::new(id, succ_max))),
-            predecessor: Arc::new(Mutex::new(None)),
-            // for Eth address, it's 160
-            finger: Arc::new(Mutex::new(FingerTable::new(id, 160))),
-            id,
-            storage: Arc::new(PersistenceStorage::new().await?),
-            cache: Arc::new(MemStorage::<Did, VirtualNode>::new()),
-        })
+    /// Create a new Chord Ring with given config
+    pub async fn new_with_config(id: Did, config: PeerRingConfig) -> Result<Self> {
+        Self::new_with_storage_and_config(id, Arc::new(PersistenceStorage::new().await?), config)
     }
 
     /// Init with given Storage
     pub fn new_with_storage(id: Did, storage: Arc<PersistenceStorage>) -> Self {
+        Self::build(id, storage, PeerRingConfig::default())
+    }
+
+    /// Init with given Storage and config, the config is validated first.
+    pub fn new_with_storage_and_config(
+        id: Did,
+        storage: Arc<PersistenceStorage>,
+        config: PeerRingConfig,
+    ) -> Result<Self> {
+        config.validate()?;
+        Ok(Self::build(id, storage, config))
+    }
+
+    fn build(id: Did, storage: Arc<PersistenceStorage>, config: PeerRingConfig) -> Self {
         Self {
-            successor: Arc::new(Mutex::new(Successor::new(id, 3))),
+            successor: Arc::new(Mutex::new(Successor::new(id, config.successor_max))),
             predecessor: Arc::new(Mutex::new(None)),
-            // for Eth address, it's 160
-            finger: Arc::new(Mutex::new(FingerTable::new(id, 160))),
-            storage: Arc::clone(&storage),
-            cache: Arc::new(MemStorage::<Did, VirtualNode>::new()),
+            finger: Arc::new(Mutex::new(FingerTable::new(id, config.finger_size))),
+            storage,
+            cache: Arc::new(LruStorage::<Did, VirtualNode>::new(
+                config.cache_capacity,
+                config.cache_ttl_ms,
+            )),
+            replica_factor: config.replica_factor,
             id,
         }
     }
 
+    /// Create ring of the `index`th virtual identity hosted by this node.
+    /// It has its own position on the ring, but shares storage and cache with this one.
+    pub fn new_virtual(&self, index: u8) -> Result<Self> {
+        if index == 0 || index > MAX_VIRTUAL_IDS {
+            return Err(Error::InvalidVirtualIdentity);
+        }
+        let id = self.id.virtual_id(index);
+        let successor_max = self.lock_successor()?.capacity();
+        let finger_size = self.lock_finger()?.size();
+        Ok(Self {
+            successor: Arc::new(Mutex::new(Successor::new(id, successor_max))),
+            predecessor: Arc::new(Mutex::new(None)),
+            finger: Arc::new(Mutex::new(FingerTable::new(id, finger_size))),
+            storage: self.storage.clone(),
+            cache: self.cache.clone(),
+            replica_factor: self.replica_factor,
+            id,
+        })
+    }
+
     /// Lock and return MutexGuard of Successor
     pub fn lock_successor(&self) -> Result<MutexGuard<Successor>> {
         self.successor.lock().map_err(|_| Error::DHTSyncLockError)
@@ -194,11 +348,345 @@ impl PeerRing {
         BiasId::new(&self.id, &id)
     }
 
+    /// Take a snapshot of routing state and storage usage.
+    pub async fn topology(&self) -> Result<PeerRingTopology> {
+        let fingers = {
+            let finger = self.lock_finger()?;
+            finger
+                .list()
+                .iter()
+                .enumerate()
+                .filter_map(|(i, x)| x.map(|did| (i, did)))
+                .collect()
+        };
+        let successors = { self.lock_successor()?.list() };
+        let predecessor = { *self.lock_predecessor()? };
+        Ok(PeerRingTopology {
+            did: self.id,
+            predecessor,
+            successors,
+            fingers,
+            cache_size: self.cache.len(),
+            cache_stats: self.cache.stats(),
+            storage_count: self.storage.count().await?,
+        })
+    }
+
+    /// Estimate number of nodes in the ring from the density of known nodes.
+    /// Dids are uniformly distributed in the 2^160 space, so the distance from a point to the
+    /// next node has a mean of `2^160 / N`. Samples of such gaps are taken from:
+    /// * predecessor and successor list, which are consecutive nodes on the ring;
+    /// * each finger `k`, as the distance from `id + 2^k` to it, if `id + 2^k` is beyond the
+    ///   successor list. Fingers sharing a node are all taken, skipping them would drop
+    ///   exactly the samples of large gaps.
+    ///
+    /// A lonely node estimates 1.
+    pub fn estimate_network_size(&self) -> Result<u64> {
+        let fingers = { self.lock_finger()?.list().clone() };
+        let successors = { self.lock_successor()?.list() };
+        let predecessor = { *self.lock_predecessor()? };
+        let distance = |did: Did| BigUint::from(self.bias(did).pos());
+
+        let mut samples = 0u32;
+        let mut span = BigUint::default();
+        let mut known = successors.clone();
+        if let Some(last) = successors.last() {
+            samples += successors.len() as u32;
+            span += distance(*last);
+        }
+        let successors_span = span.clone();
+        if let Some(p) = predecessor.filter(|p| *p != self.id) {
+            samples += 1;
+            span += BigUint::from(BiasId::new(&p, &self.id).pos());
+            known.push(p);
+        }
+        for (k, did) in fingers.iter().enumerate() {
+            let did = match did {
+                Some(did) => *did,
+                None => continue,
+            };
+            known.push(did);
+            let start = BigUint::from(2u16).pow(k as u32);
+            let d = distance(did);
+            if start > successors_span && d >= start {
+                samples += 1;
+                span += d - start;
+            }
+        }
+
+        let known = known.into_iter().unique().count() as u64 + 1;
+        if samples == 0 || span == BigUint::default() {
+            return Ok(known);
+        }
+        let estimate = (BigUint::from(2u16).pow(160) * samples / span)
+            .to_u64()
+            .unwrap_or(u64::MAX);
+        Ok(estimate.max(known))
+    }
+
     /// finger length
     pub fn number_of_fingers(&self) -> Result<usize> {
         let finger = self.lock_finger()?;
         Ok(finger.len())
     }
+
+    /// Known nodes in range (self, id), ordered from the closest to id.
+    /// Used by iterative lookup, which asks the closest one first, and the others as alternatives.
+    pub fn closest_preceding_nodes(&self, id: Did, n: usize) -> Result<Vec<Did>> {
+        let bid = self.bias(id);
+        let finger = self.lock_finger()?;
+        let successor = self.lock_successor()?;
+        Ok(finger
+            .list()
+            .iter()
+            .flatten()
+            .copied()
+            .chain(successor.list())
+            .filter(|did| *did != self.id && self.bias(*did) < bid)
+            .unique()
+            .sorted_by_key(|did| std::cmp::Reverse(self.bias(*did)))
+            .take(n)
+            .collect())
+    }
+
+    /// Successors which should keep a replica of the VNodes stored on this node.
+    pub fn replica_holders(&self) -> Result<Vec<Did>> {
+        let successor = self.lock_successor()?;
+        Ok(successor
+            .list()
+            .into_iter()
+            .take(self.replica_factor.saturating_sub(1).into())
+            .collect())
+    }
+
+    /// Check if `owner` may push a replica of `vid` to self.
+    /// As far as self knows, `owner` should be responsible for `vid`, which is in
+    /// (owner, successor of owner], and self should be one of the `replica_factor - 1`
+    /// successors of `owner`, that's to say, fewer than `replica_factor - 1` known nodes,
+    /// including the predecessor, are between them.
+    pub fn is_replica_source(&self, owner: Did, vid: Did) -> Result<bool> {
+        let predecessor = match *self.lock_predecessor()? {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        let pos = |did: Did| BiasId::new(&owner, &did).pos();
+        if owner == self.id || vid == owner || pos(vid) > pos(self.id) {
+            return Ok(false);
+        }
+        let known = {
+            let successor = self.lock_successor()?;
+            let finger = self.lock_finger()?;
+            std::iter::once(predecessor)
+                .chain(successor.list())
+                .chain(finger.list().iter().flatten().copied())
+                .filter(|did| *did != self.id && *did != owner)
+                .unique()
+                .collect::<Vec<_>>()
+        };
+        // a known node in (owner, vid) is responsible for vid
+        if known.iter().any(|did| pos(*did) < pos(vid)) {
+            return Ok(false);
+        }
+        let between = known.iter().filter(|did| pos(**did) < pos(self.id)).count();
+        Ok(between < self.replica_factor.saturating_sub(1).into())
+    }
+
+    /// Store a replica pushed by `owner` of a VNode, thus the data survives
+    /// when the owner goes offline. The replica is refused unless self is
+    /// in the replica chain of `owner`, see [PeerRing::is_replica_source].
+    pub async fn store_replica(&self, owner: Did, vnode: VirtualNode) -> Result<()> {
+        if !self.is_replica_source(owner, vnode.did())? {
+            return Err(Error::InvalidReplica);
+        }
+        self.store_locally(&vnode).await
+    }
+
+    /// A replica of `vid` kept in local storage. It's only a fallback
+    /// when the node responsible for `vid` can't answer, see [ChordStorage::lookup].
+    pub async fn local_replica(&self, vid: &Did) -> Option<VirtualNode> {
+        self.storage
+            .get(vid)
+            .await
+            .ok()
+            .filter(|v: &VirtualNode| v.kind != VNodeType::Tombstone)
+    }
+
+    /// Remove a replica, when its owner removed the VNode.
+    pub async fn remove_replica(&self, removal: VNodeRemoval) -> Result<()> {
+        self.remove_locally(&removal).await
+    }
+
+    /// Remove VNodes moved to successor, once they are sent.
+    pub async fn remove_synced(&self, data: &[VirtualNode]) -> Result<()> {
+        for vnode in data {
+            self.storage.remove(&vnode.did()).await?;
+            self.cache.remove(&vnode.did());
+        }
+        Ok(())
+    }
+
+    /// Messages relayed for an offline `did`, kept in the RelayMessage vnode at `did`.
+    pub async fn relay_messages(&self, did: Did) -> Option<VirtualNode> {
+        match self.storage.get(&did).await {
+            Ok(v) if v.kind == VNodeType::RelayMessage => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Remove messages relayed for `did` which are delivered, by tx_id,
+    /// the vnode is removed once all of its messages are delivered.
+    /// If self is responsible for the vnode, replica holders are told to do the same.
+    pub async fn ack_relay_messages(
+        &self,
+        did: Did,
+        tx_ids: &[uuid::Uuid],
+    ) -> Result<PeerRingAction> {
+        let vnode = match self.relay_messages(did).await {
+            Some(v) => v,
+            None => return Ok(PeerRingAction::None),
+        };
+        let data = vnode
+            .data
+            .iter()
+            .filter(|e| match MessagePayload::<Message>::from_encoded(e) {
+                Ok(payload) => !tx_ids.contains(&payload.tx_id),
+                Err(_) => false,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if data.is_empty() {
+            self.storage.remove(&did).await?;
+            self.cache.remove(&did);
+        } else {
+            let vnode = VirtualNode { data, ..vnode };
+            self.storage
+                .put_with_expiry(&did, &vnode, vnode.expired_at)
+                .await?;
+        }
+
+        if !matches!(self.find_successor(did)?, PeerRingAction::Some(_)) {
+            return Ok(PeerRingAction::None);
+        }
+        let acts = self
+            .replica_holders()?
+            .into_iter()
+            .map(|holder| {
+                PeerRingAction::RemoteAction(
+                    holder,
+                    RemoteAction::AckRelayMessageReplica(did, tx_ids.to_vec()),
+                )
+            })
+            .collect::<Vec<_>>();
+        match acts.len() {
+            0 => Ok(PeerRingAction::None),
+            _ => Ok(PeerRingAction::MultiActions(acts)),
+        }
+    }
+
+    /// Replace a VNode in local storage with its tombstone, and remove it from cache,
+    /// if the removal is signed by its owner. A missing or removed VNode is treated as removed.
+    async fn remove_locally(&self, removal: &VNodeRemoval) -> Result<()> {
+        let vnode: VirtualNode = match self.storage.get(&removal.did).await {
+            Ok(v) if v.kind != VNodeType::Tombstone => v,
+            _ => return Ok(()),
+        };
+        let tombstone = vnode.tombstone(removal)?;
+        self.storage
+            .put_with_expiry(&removal.did, &tombstone, tombstone.expired_at)
+            .await?;
+        self.cache.remove(&removal.did);
+        Ok(())
+    }
+
+    async fn store_locally(&self, vnode: &VirtualNode) -> Result<()> {
+        vnode.verify()?;
+        let vid = vnode.did();
+        // the record is updated, a cached copy is stale
+        self.cache.remove(&vid);
+        let vnode = match self.storage.get(&vid).await {
+            Ok(v) => VirtualNode::concat(&v, vnode)?,
+            Err(_) => vnode.clone(),
+        };
+        self.storage
+            .put_with_expiry(&vid, &vnode, vnode.expired_at)
+            .await
+    }
+
+    /// Build the actions to push replicas of a VNode to the replica holders.
+    fn replicate(&self, vnode: VirtualNode) -> Result<PeerRingAction> {
+        let acts = self
+            .replica_holders()?
+            .into_iter()
+            .map(|did| {
+                PeerRingAction::RemoteAction(did, RemoteAction::StoreReplica(vec![vnode.clone()]))
+            })
+            .collect::<Vec<_>>();
+        match acts.len() {
+            0 => Ok(PeerRingAction::None),
+            _ => Ok(PeerRingAction::MultiActions(acts)),
+        }
+    }
+
+    /// Build the actions to remove the replicas of a VNode from the replica holders.
+    fn replicate_removal(&self, removal: VNodeRemoval) -> Result<PeerRingAction> {
+        let acts = self
+            .replica_holders()?
+            .into_iter()
+            .map(|did| {
+                PeerRingAction::RemoteAction(did, RemoteAction::RemoveReplica(removal.clone()))
+            })
+            .collect::<Vec<_>>();
+        match acts.len() {
+            0 => Ok(PeerRingAction::None),
+            _ => Ok(PeerRingAction::MultiActions(acts)),
+        }
+    }
+
+    /// Build the actions to leave the ring gracefully.
+    /// Both predecessor and successor are told to splice the ring,
+    /// then VNodes owned by self, which are in (self, successor], are handed off
+    /// to the predecessor, who takes over the range. Replicas are not handed off,
+    /// the owners of them keep pushing replicas to their successors.
+    pub async fn leave(&self) -> Result<PeerRingAction> {
+        let predecessor = *self.lock_predecessor()?;
+        let successor = {
+            let successor = self.lock_successor()?;
+            if successor.is_none() {
+                None
+            } else {
+                Some(successor.min())
+            }
+        };
+        let mut acts = [predecessor, successor]
+            .into_iter()
+            .flatten()
+            .filter(|did| *did != self.id)
+            .unique()
+            .map(|did| {
+                PeerRingAction::RemoteAction(did, RemoteAction::NotifyLeave(predecessor, successor))
+            })
+            .collect::<Vec<_>>();
+        if let Some(next) = predecessor.filter(|did| *did != self.id) {
+            let data = self
+                .storage
+                .get_all()
+                .await?
+                .into_iter()
+                .filter(|(vid, _)| matches!(self.find_successor(*vid), Ok(PeerRingAction::Some(_))))
+                .map(|(_, v)| v)
+                .collect::<Vec<VirtualNode>>();
+            if !data.is_empty() {
+                acts.push(PeerRingAction::RemoteAction(
+                    next,
+                    RemoteAction::HandoffVNode(data),
+                ));
+            }
+        }
+        match acts.len() {
+            0 => Ok(PeerRingAction::None),
+            _ => Ok(PeerRingAction::MultiActions(acts)),
+        }
+    }
 }
 
 impl Chord<PeerRingAction> for PeerRing {
@@ -283,7 +771,7 @@ impl ChordStabilize<PeerRingAction> for PeerRing {
         // for index start with 0
         // finger[next] = find_successor(n + 2^(next) );
         fix_finger_index += 1;
-        if fix_finger_index >= 159 {
+        if fix_finger_index as usize >= self.lock_finger()?.size().saturating_sub(1) {
             fix_finger_index = 0;
         }
 
@@ -345,13 +833,15 @@ impl ChordStabilize<PeerRingAction> for PeerRing {
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl ChordStorage<PeerRingAction> for PeerRing {
-    /// lookup always check data via finger table
+    /// lookup check data via finger table, if self is responsible for vid, check local storage.
+    /// A replica kept locally is not served here, since it may be stale,
+    /// use [PeerRing::local_replica] when the responsible node can't answer.
     async fn lookup(&self, vid: &Did) -> Result<PeerRingAction> {
         match self.find_successor(*vid) {
             // if vid is in [self, successor]
             Ok(PeerRingAction::Some(_)) => match self.storage.get(vid).await {
-                Ok(v) => Ok(PeerRingAction::SomeVNode(v)),
-                Err(_) => Ok(PeerRingAction::None),
+                Ok(v) if v.kind != VNodeType::Tombstone => Ok(PeerRingAction::SomeVNode(v)),
+                _ => Ok(PeerRingAction::None),
             },
             Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(id))) => {
                 Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindVNode(id)))
@@ -361,9 +851,15 @@ impl ChordStorage<PeerRingAction> for PeerRing {
         }
     }
 
-    /// When a vnode data is fetched from remote, it should be cache at local
+    /// When a vnode data is fetched from remote, it should be cache at local.
+    /// It's kept until the VNode or cache TTL expires, and if cache is full,
+    /// an expired VNode, or the least recently used one is dropped.
+    /// Caches of other nodes are not told when the owner updates the VNode,
+    /// so they may serve the previous copy until it expires.
     fn cache(&self, vnode: VirtualNode) {
-        self.cache.set(&vnode.did(), vnode);
+        let vid = vnode.did();
+        let expired_at = vnode.expired_at;
+        self.cache.set_with_expiry(&vid, vnode, expired_at);
     }
 
     /// When a VNode data is fetched from remote, it should be cache at local
@@ -372,26 +868,19 @@ impl ChordStorage<PeerRingAction> for PeerRing {
     }
 
     /// If address of VNode is in range(self, successor), it should store locally,
-    /// otherwise, it should on remote successor
+    /// and push replicas to the replica holders, otherwise, it should on remote successor
     async fn store(&self, peer: VirtualNode) -> Result<PeerRingAction> {
         let vid = peer.did();
+        // owner is updating the record, drop the cached copy
+        self.cache.remove(&vid);
         // find VNode's closest successor
         match self.find_successor(vid) {
             // if vid is in range(self, successor)
             // self should store it
-            Ok(PeerRingAction::Some(_)) => match self.storage.get(&vid).await {
-                Ok(v) => {
-                    let _ = self
-                        .storage
-                        .put(&vid, &VirtualNode::concat(&v, &peer)?)
-                        .await?;
-                    Ok(PeerRingAction::None)
-                }
-                Err(_) => {
-                    let _ = self.storage.put(&vid, &peer).await?;
-                    Ok(PeerRingAction::None)
-                }
-            },
+            Ok(PeerRingAction::Some(_)) => {
+                self.store_locally(&peer).await?;
+                self.replicate(peer)
+            }
             Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(_))) => Ok(
                 PeerRingAction::RemoteAction(n, RemoteAction::FindAndStore(peer)),
             ),
@@ -400,6 +889,22 @@ impl ChordStorage<PeerRingAction> for PeerRing {
         }
     }
 
+    /// If address of VNode is in range(self, successor), it should remove locally,
+    /// and remove replicas from the replica holders, otherwise, it should on remote successor
+    async fn remove_vnode(&self, removal: VNodeRemoval) -> Result<PeerRingAction> {
+        match self.find_successor(removal.did) {
+            Ok(PeerRingAction::Some(_)) => {
+                self.remove_locally(&removal).await?;
+                self.replicate_removal(removal)
+            }
+            Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(_))) => Ok(
+                PeerRingAction::RemoteAction(n, RemoteAction::FindAndRemove(removal)),
+            ),
+            Ok(a) => Err(Error::PeerRingUnexpectedAction(a)),
+            Err(e) => Err(e),
+        }
+    }
+
     /// store a vec of data
     async fn store_vec(&self, vps: Vec<VirtualNode>) -> Result<PeerRingAction> {
         let acts: Vec<PeerRingAction> =
@@ -418,13 +923,20 @@ impl ChordStorage<PeerRingAction> for PeerRing {
         }
     }
 
-    /// This function should call when successor is updated
+    /// This function should call when successor is updated.
+    /// Self is responsible for (self, successor], so VNodes after the new successor
+    /// are moved to it. Replicas, which are in (predecessor, self], are kept.
+    /// The VNodes are kept locally until they are sent, see [PeerRing::remove_synced].
     async fn sync_with_successor(&self, new_successor: Did) -> Result<PeerRingAction> {
+        let predecessor = *self.lock_predecessor()?;
         let mut data = Vec::<VirtualNode>::new();
         let all_items: Vec<(Did, VirtualNode)> = self.storage.get_all().await?;
         for (k, v) in all_items.iter() {
-            // k < self.successor
-            if self.bias(*k) < self.bias(new_successor) && self.storage.remove(k).await.is_ok() {
+            if predecessor.map_or(false, |p| self.bias(*k) > self.bias(p)) {
+                continue;
+            }
+            // k is not in (self, new_successor], self is no longer responsible for it
+            if self.bias(*k) > self.bias(new_successor) {
                 data.push(v.clone());
             }
         }
@@ -445,33 +957,28 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::dht::tests::new_test_ring;
+    use crate::dht::tests::new_test_storage;
+    use crate::dht::vnode::SignedRecord;
+    use crate::ecc::HashStr;
     use crate::ecc::SecretKey;
+    use crate::message::Encoder;
+    use crate::session::SessionManager;
+    use crate::storage::PersistenceStorageOperation;
 
     #[tokio::test]
     async fn test_chord_finger() -> Result<()> {
-        let db_path_a = PersistenceStorage::random_path("./tmp");
-        let db_path_b = PersistenceStorage::random_path("./tmp");
-        let db_path_c = PersistenceStorage::random_path("./tmp");
         let a = Did::from_str("0x00E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
         let b = Did::from_str("0x119999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
         let c = Did::from_str("0xccffee254729296a45a3885639AC7E10F9d54979").unwrap();
         let d = Did::from_str("0xffffee254729296a45a3885639AC7E10F9d54979").unwrap();
 
-        let db_1 = PersistenceStorage::new_with_path(db_path_a.as_str())
-            .await
-            .unwrap();
-        let db_2 = PersistenceStorage::new_with_path(db_path_b.as_str())
-            .await
-            .unwrap();
-        let db_3 = PersistenceStorage::new_with_path(db_path_c.as_str())
-            .await
-            .unwrap();
-
         assert!(a < b && b < c);
         // distence between (a, d) is less than (b, d)
         assert!((a - d) < (b - d));
 
-        let node_a = PeerRing::new_with_storage(a, Arc::new(db_1));
+        let (node_a, path_a) = new_test_ring(a).await;
         assert_eq!(
             node_a.lock_successor()?.list(),
             vec![],
@@ -542,7 +1049,7 @@ mod tests {
         );
 
         // for decrease seq join
-        let node_d = PeerRing::new_with_storage(d, Arc::new(db_2));
+        let (node_d, path_d) = new_test_ring(d).await;
         assert_eq!(
             node_d.join(c)?,
             PeerRingAction::RemoteAction(c, RemoteAction::FindSuccessor(d))
@@ -557,7 +1064,7 @@ mod tests {
         );
 
         // for over half ring join
-        let node_d = PeerRing::new_with_storage(d, Arc::new(db_3));
+        let (node_d, path_d2) = new_test_ring(d).await;
         assert_eq!(
             node_d.join(a)?,
             PeerRingAction::RemoteAction(a, RemoteAction::FindSuccessor(d))
@@ -576,7 +1083,9 @@ mod tests {
         );
         assert!(d + Did::from(BigUint::from(2u16).pow(159)) > b);
         assert!(node_d.lock_successor()?.list().contains(&a));
-        tokio::fs::remove_dir_all("./tmp").await.ok();
+        for path in [path_a, path_d, path_d2] {
+            tokio::fs::remove_dir_all(path).await.ok();
+        }
 
         Ok(())
     }
@@ -590,16 +1099,8 @@ mod tests {
         }
         let did1: Did = key1.address().into();
         let did2: Did = key2.address().into();
-        let db_path1 = PersistenceStorage::random_path("./tmp");
-        let db_path2 = PersistenceStorage::random_path("./tmp");
-        let db_1 = PersistenceStorage::new_with_path(db_path1.as_str())
-            .await
-            .unwrap();
-        let db_2 = PersistenceStorage::new_with_path(db_path2.as_str())
-            .await
-            .unwrap();
-        let node1 = PeerRing::new_with_storage(did1, Arc::new(db_1));
-        let node2 = PeerRing::new_with_storage(did2, Arc::new(db_2));
+        let (node1, path1) = new_test_ring(did1).await;
+        let (node2, path2) = new_test_ring(did2).await;
 
         node1.join(did2)?;
         node2.join(did1)?;
@@ -618,7 +1119,9 @@ mod tests {
             did1,
             did2
         );
-        tokio::fs::remove_dir_all("./tmp").await.ok();
+        for path in [path1, path2] {
+            tokio::fs::remove_dir_all(path).await.ok();
+        }
 
         Ok(())
     }
@@ -630,16 +1133,8 @@ mod tests {
         let max = Did::from(BigUint::from(2u16).pow(160) - 1u16);
         let zero = Did::from(BigUint::from(2u16).pow(160));
 
-        let db_path1 = PersistenceStorage::random_path("./tmp");
-        let db_path2 = PersistenceStorage::random_path("./tmp");
-        let db_1 = PersistenceStorage::new_with_path(db_path1.as_str())
-            .await
-            .unwrap();
-        let db_2 = PersistenceStorage::new_with_path(db_path2.as_str())
-            .await
-            .unwrap();
-        let node1 = PeerRing::new_with_storage(did1, Arc::new(db_1));
-        let node2 = PeerRing::new_with_storage(did2, Arc::new(db_2));
+        let (node1, path1) = new_test_ring(did1).await;
+        let (node2, path2) = new_test_ring(did2).await;
 
         node1.join(did2)?;
         node2.join(did1)?;
@@ -664,8 +1159,460 @@ mod tests {
             did2,
             did1
         );
-        tokio::fs::remove_dir_all("./tmp").await.ok();
+        for path in [path1, path2] {
+            tokio::fs::remove_dir_all(path).await.ok();
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_with_replicas() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (mut node_a, path_a) = new_test_ring(dids[0]).await;
+        node_a.replica_factor = 3;
+        let (mut node_b, path_b) = new_test_ring(dids[1]).await;
+        node_b.replica_factor = 3;
+        for did in dids.iter().skip(1).rev() {
+            node_a.join(*did)?;
+        }
+        assert_eq!(node_a.lock_successor()?.list(), dids[1..4]);
+        assert_eq!(node_a.replica_holders()?, dids[1..3]);
+
+        // vnode is in range (node_a, node_b], so node_a is responsible for it
+        let vnode = VirtualNode {
+            address: dids[0] + Did::from(BigUint::from(1u16)),
+            data: vec!["replicated data".to_string().encode()?],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        let vid = vnode.did();
+        assert_eq!(
+            node_a.store(vnode.clone()).await?,
+            PeerRingAction::MultiActions(vec![
+                PeerRingAction::RemoteAction(
+                    dids[1],
+                    RemoteAction::StoreReplica(vec![vnode.clone()])
+                ),
+                PeerRingAction::RemoteAction(
+                    dids[2],
+                    RemoteAction::StoreReplica(vec![vnode.clone()])
+                ),
+            ])
+        );
+        assert_eq!(
+            node_a.lookup(&vid).await?,
+            PeerRingAction::SomeVNode(vnode.clone())
+        );
+
+        // a replica is only accepted from a node in the replica chain
+        for did in [dids[2], dids[3], dids[0]] {
+            node_b.join(did)?;
+        }
+        assert!(node_b.store_replica(dids[0], vnode.clone()).await.is_err());
+        node_b.notify(dids[0])?;
+        assert!(node_b.store_replica(dids[3], vnode.clone()).await.is_err());
+        assert!(node_b.store_replica(dids[2], vnode.clone()).await.is_err());
+        node_b.store_replica(dids[0], vnode.clone()).await?;
+
+        // a replica holder still routes to the owner, the replica is only a fallback
+        assert_eq!(
+            node_b.lookup(&vid).await?,
+            PeerRingAction::RemoteAction(dids[0], RemoteAction::FindVNode(vid))
+        );
+        assert_eq!(node_b.local_replica(&vid).await, Some(vnode));
+
+        // without replica_factor, only the owner keeps the data
+        node_a.replica_factor = DEFAULT_REPLICA_FACTOR;
+        assert!(node_a.replica_holders()?.is_empty());
+        for path in [path_a, path_b] {
+            tokio::fs::remove_dir_all(path).await.ok();
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ack_relay_messages() -> Result<()> {
+        let dids = gen_ordered_dids(2);
+        // a lonely node is responsible for all vnodes
+        let (node, path) = new_test_ring(dids[0]).await;
+        let target = dids[1];
+        let sm = SessionManager::new_with_seckey(&SecretKey::random())?;
+
+        let payloads = (0..2)
+            .map(|i| MessagePayload::new_direct(Message::custom(&[i], &None)?, &sm, target))
+            .collect::<Result<Vec<_>>>()?;
+        for payload in payloads.iter() {
+            let vnode: VirtualNode = payload.clone().try_into()?;
+            assert_eq!(vnode.did(), target);
+            node.store(vnode).await?;
+        }
+        assert!(node.relay_messages(dids[0]).await.is_none());
+        assert_eq!(node.relay_messages(target).await.unwrap().data.len(), 2);
+
+        assert_eq!(
+            node.ack_relay_messages(target, &[payloads[0].tx_id])
+                .await?,
+            PeerRingAction::None
+        );
+        let left = node.relay_messages(target).await.unwrap();
+        assert_eq!(left.data.len(), 1);
+        assert_eq!(
+            MessagePayload::<Message>::from_encoded(&left.data[0])?.tx_id,
+            payloads[1].tx_id
+        );
+
+        node.ack_relay_messages(target, &[payloads[1].tx_id])
+            .await?;
+        assert!(node.relay_messages(target).await.is_none());
+
+        // messages of a node are kept by the node preceding it, never by itself
+        node.join(target)?;
+        assert!(matches!(
+            node.find_successor(target)?,
+            PeerRingAction::Some(_)
+        ));
+        assert!(matches!(
+            node.find_successor(dids[0])?,
+            PeerRingAction::RemoteAction(next, _) if next == target
+        ));
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leave_with_handoff() -> Result<()> {
+        let dids = gen_ordered_dids(3);
+        let (mut node, path) = new_test_ring(dids[0]).await;
+
+        // a lonely node has nothing to do when leaving
+        assert!(node.leave().await?.is_none());
+
+        node.join(dids[1])?;
+        node.notify(dids[2])?;
+        let vnode = VirtualNode {
+            address: dids[0] + Did::from(BigUint::from(1u16)),
+            data: vec!["handoff data".to_string().encode()?],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        assert!(node.store(vnode.clone()).await?.is_none());
+
+        // a replica pushed by predecessor is not handed off
+        node.replica_factor = 2;
+        let replica = VirtualNode {
+            address: dids[2] + Did::from(BigUint::from(1u16)),
+            data: vec!["replica data".to_string().encode()?],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        node.store_replica(dids[2], replica).await?;
+
+        // owned range (dids[0], dids[1]] is taken over by predecessor
+        assert_eq!(
+            node.leave().await?,
+            PeerRingAction::MultiActions(vec![
+                PeerRingAction::RemoteAction(
+                    dids[2],
+                    RemoteAction::NotifyLeave(Some(dids[2]), Some(dids[1]))
+                ),
+                PeerRingAction::RemoteAction(
+                    dids[1],
+                    RemoteAction::NotifyLeave(Some(dids[2]), Some(dids[1]))
+                ),
+                PeerRingAction::RemoteAction(dids[2], RemoteAction::HandoffVNode(vec![vnode])),
+            ])
+        );
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn test_sync_with_successor() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (node, path) = new_test_ring(dids[0]).await;
+        node.join(dids[2])?;
+        node.notify(dids[3])?;
+
+        let vnode_at = |did: Did| VirtualNode {
+            address: did + Did::from(BigUint::from(1u16)),
+            data: vec![],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        // both are in (dids[0], dids[2]], self is responsible for them
+        let kept = vnode_at(dids[0]);
+        let moved = vnode_at(dids[1]);
+        // a replica in (predecessor, self]
+        let replica = vnode_at(dids[3]);
+        for v in [&kept, &moved, &replica] {
+            node.storage.put(&v.did(), v).await?;
+        }
+
+        // dids[1] joins between self and dids[2], and takes over (dids[1], dids[2]]
+        assert_eq!(
+            node.sync_with_successor(dids[1]).await?,
+            PeerRingAction::RemoteAction(
+                dids[1],
+                RemoteAction::SyncVNodeWithSuccessor(vec![moved.clone()])
+            )
+        );
+        // the moved one is kept until it's sent
+        let v: VirtualNode = node.storage.get(&moved.did()).await?;
+        assert_eq!(v, moved);
+        node.remove_synced(&[moved.clone()]).await?;
+        let v: Result<VirtualNode> = node.storage.get(&moved.did()).await;
+        assert!(v.is_err());
+        let v: VirtualNode = node.storage.get(&kept.did()).await?;
+        assert_eq!(v, kept);
+        let v: VirtualNode = node.storage.get(&replica.did()).await?;
+        assert_eq!(v, replica);
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_expiring_vnode() -> Result<()> {
+        let dids = gen_ordered_dids(2);
+        let (node, path) = new_test_ring(dids[0]).await;
+        node.join(dids[1])?;
+
+        let vnode = VirtualNode {
+            address: dids[0] + Did::from(BigUint::from(1u16)),
+            data: vec!["expiring data".to_string().encode()?],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        let vid = vnode.did();
+
+        node.store(vnode.clone().with_ttl(60000)).await?;
+        assert!(matches!(
+            node.lookup(&vid).await?,
+            PeerRingAction::SomeVNode(v) if v.did() == vid
+        ));
+
+        node.storage.clear().await?;
+        node.store(vnode.with_ttl(0)).await?;
+        assert!(node.lookup(&vid).await?.is_none());
+        assert_eq!(node.storage.remove_expired().await?, 0);
+        assert_eq!(node.storage.count().await?, 0);
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_vnode_by_owner() -> Result<()> {
+        let key = SecretKey::random();
+        let attacker = SecretKey::random();
+        // a lonely node is responsible for all vnodes
+        let (node, path) = new_test_ring(key.address().into()).await;
+
+        let vnode: VirtualNode =
+            SignedRecord::new(&key, "profile", 1, "v1".to_string().encode()?)?.try_into()?;
+        let vid = vnode.did();
+        node.store(vnode.clone()).await?;
+
+        // only the writer of record can remove it
+        assert!(node
+            .remove_vnode(VNodeRemoval::new(&attacker, vid, 1))
+            .await
+            .is_err());
+        assert_eq!(node.lookup(&vid).await?, PeerRingAction::SomeVNode(vnode));
+
+        assert!(node
+            .remove_vnode(VNodeRemoval::new(&key, vid, 1))
+            .await?
+            .is_none());
+        assert!(node.lookup(&vid).await?.is_none());
+
+        // a tombstone is kept, the removed record can't be stored again
+        let tombstone: VirtualNode = node.storage.get(&vid).await?;
+        assert_eq!(tombstone.kind, VNodeType::Tombstone);
+        assert!(tombstone.expired_at.is_some());
+        assert!(matches!(
+            node.store(vnode).await,
+            Err(Error::SignedRecordSeqTooLow(1, 1))
+        ));
+        let v2: VirtualNode =
+            SignedRecord::new(&key, "profile", 2, "v2".to_string().encode()?)?.try_into()?;
+        node.store(v2.clone()).await?;
+        assert_eq!(node.lookup(&vid).await?, PeerRingAction::SomeVNode(v2));
+
+        // only SignedData can be removed
+        let data: VirtualNode = "plain".to_string().try_into()?;
+        node.store(data.clone()).await?;
+        assert!(matches!(
+            node.remove_vnode(VNodeRemoval::new(&key, data.did(), 0))
+                .await,
+            Err(Error::InvalidVNodeType)
+        ));
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_closest_preceding_nodes() -> Result<()> {
+        let dids = gen_ordered_dids(5);
+        let (node, path) = new_test_ring(dids[0]).await;
+        for did in dids.iter().skip(1).rev() {
+            node.join(*did)?;
+        }
+
+        // known nodes in (dids[0], dids[4]), the closest to dids[4] first
+        assert_eq!(node.closest_preceding_nodes(dids[4], 2)?, vec![
+            dids[3], dids[2]
+        ]);
+        assert_eq!(node.closest_preceding_nodes(dids[4], 5)?, vec![
+            dids[3], dids[2], dids[1]
+        ]);
+        assert!(node.closest_preceding_nodes(dids[1], 5)?.is_empty());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_ring_config() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (db, path) = new_test_storage().await;
+        let node = PeerRing::new_with_storage_and_config(dids[0], db, PeerRingConfig {
+            successor_max: 2,
+            finger_size: 8,
+            cache_capacity: 1,
+            ..Default::default()
+        })?;
+        for did in dids.iter().skip(1).rev() {
+            node.join(*did)?;
+        }
+        assert_eq!(node.lock_successor()?.list(), dids[1..3]);
+        assert_eq!(node.lock_finger()?.list().len(), 8);
+
+        // cache keeps at most one vnode
+        let vnodes = ["cached 1", "cached 2"]
+            .iter()
+            .map(|s| s.to_string().try_into())
+            .collect::<Result<Vec<VirtualNode>>>()?;
+        node.cache(vnodes[0].clone());
+        node.cache(vnodes[1].clone());
+        assert_eq!(node.cache.len(), 1);
+        assert!(node.fetch_cache(&vnodes[0].did()).is_none());
+        assert_eq!(node.fetch_cache(&vnodes[1].did()), Some(vnodes[1].clone()));
+
+        // out of range values are rejected
+        for config in [
+            PeerRingConfig {
+                finger_size: 0,
+                ..Default::default()
+            },
+            PeerRingConfig {
+                finger_size: MAX_FINGER_SIZE + 1,
+                ..Default::default()
+            },
+            PeerRingConfig {
+                successor_max: 0,
+                ..Default::default()
+            },
+            PeerRingConfig {
+                cache_capacity: 0,
+                ..Default::default()
+            },
+            PeerRingConfig {
+                cache_ttl_ms: 0,
+                ..Default::default()
+            },
+            PeerRingConfig {
+                successor_max: 2,
+                replica_factor: 4,
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                config.validate(),
+                Err(Error::InvalidPeerRingConfig(_))
+            ));
+        }
+        assert!(PeerRingConfig::default().validate().is_ok());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_ring_cache() -> Result<()> {
+        let dids = gen_ordered_dids(1);
+        let (node, path) = new_test_ring(dids[0]).await;
+        let vnode: VirtualNode = "cached".to_string().try_into()?;
+        let vid = vnode.did();
+
+        node.cache(vnode.clone());
+        assert_eq!(node.fetch_cache(&vid), Some(vnode.clone()));
+        // an expired vnode is never served from cache
+        node.cache(vnode.clone().with_ttl(0));
+        assert!(node.fetch_cache(&vid).is_none());
+        assert!(node.cache.is_empty());
+
+        // storing an update of the record invalidates the cached copy
+        node.cache(vnode.clone());
+        node.store(vnode).await?;
+        assert!(node.fetch_cache(&vid).is_none());
+
+        let topology = node.topology().await?;
+        assert_eq!(topology.cache_stats.hits, 1);
+        assert_eq!(topology.cache_stats.misses, 2);
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_ring_topology() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (node, path) = new_test_ring(dids[0]).await;
+        let topology = node.topology().await?;
+        assert_eq!(topology.did, dids[0]);
+        assert!(topology.predecessor.is_none());
+        assert!(topology.fingers.is_empty());
+
+        node.join(dids[2])?;
+        node.join(dids[1])?;
+        node.notify(dids[3])?;
+        let vnode: VirtualNode = "stored".to_string().try_into()?;
+        node.storage.put(&vnode.did(), &vnode).await?;
+        node.cache(vnode);
+
+        let topology = node.topology().await?;
+        assert_eq!(topology.predecessor, Some(dids[3]));
+        assert_eq!(topology.successors, vec![dids[1], dids[2]]);
+        assert!(!topology.fingers.is_empty());
+        for (i, did) in topology.fingers.iter() {
+            assert_eq!(node.lock_finger()?.get(*i), &Some(*did));
+        }
+        assert_eq!(topology.cache_size, 1);
+        assert_eq!(topology.storage_count, 1);
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_estimate_network_size() -> Result<()> {
+        // 128 nodes at fixed pseudo random positions
+        let dids = (0..128)
+            .map(|i| Did::try_from(HashStr::from(format!("node{}", i))))
+            .collect::<Result<Vec<_>>>()?;
+        let (db, path) = new_test_storage().await;
+        let node = PeerRing::new_with_storage_and_config(dids[0], db, PeerRingConfig {
+            successor_max: 16,
+            ..Default::default()
+        })?;
+        assert_eq!(node.estimate_network_size()?, 1);
+
+        // join from the farthest, so the successor list is filled with the closest ones
+        let mut others = dids[1..].to_vec();
+        others.sort_by_key(|did| node.bias(*did));
+        for did in others.iter().rev() {
+            node.join(*did)?;
+        }
+        node.notify(*others.last().unwrap())?;
+        let estimate = node.estimate_network_size()?;
+        assert!((96..=160).contains(&estimate), "estimate: {}", estimate);
+        tokio::fs::remove_dir_all(path).await.ok();
         Ok(())
     }
 }
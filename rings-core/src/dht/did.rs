@@ -10,6 +10,7 @@ use num_bigint::BigUint;
 use serde::Deserialize;
 use serde::Serialize;
 use web3::contract::tokens::Tokenizable;
+use web3::signing::keccak256;
 use web3::types::H160;
 
 /// Did is a finate Ring R(P) where P = 2^160
@@ -17,6 +18,9 @@ use crate::ecc::HashStr;
 use crate::err::Error;
 use crate::err::Result;
 
+/// Max number of virtual identities a node can host, besides its own Did.
+pub const MAX_VIRTUAL_IDS: u8 = 16;
+
 #[derive(Copy, Clone, Eq, Ord, PartialEq, PartialOrd, Debug, Serialize, Deserialize, Hash)]
 pub struct Did(H160);
 
@@ -111,6 +115,22 @@ impl Did {
     pub fn bias(&self, id: &Self) -> BiasId {
         BiasId::new(id, self)
     }
+
+    /// The `index`th virtual identity hosted by node of this Did, index 0 is the Did itself.
+    /// It's derived by hashing, so anyone can check which node hosts a virtual identity.
+    pub fn virtual_id(&self, index: u8) -> Self {
+        if index == 0 {
+            return *self;
+        }
+        let mut data = self.0.as_bytes().to_vec();
+        data.push(index);
+        H160::from_slice(&keccak256(&data)[12..]).into()
+    }
+
+    /// Check if self is a virtual identity hosted by node `physical`.
+    pub fn is_virtual_of(&self, physical: &Self) -> bool {
+        (1..=MAX_VIRTUAL_IDS).any(|i| physical.virtual_id(i) == *self)
+    }
 }
 
 pub trait SortRing {
@@ -214,6 +234,20 @@ mod tests {
         assert_eq!(-(-a), a);
     }
 
+    #[test]
+    fn test_virtual_id() {
+        let a = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let b = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        assert_eq!(a.virtual_id(0), a);
+        assert_eq!(a.virtual_id(1), a.virtual_id(1));
+        assert_ne!(a.virtual_id(1), a.virtual_id(2));
+        assert!(a.virtual_id(1).is_virtual_of(&a));
+        assert!(a.virtual_id(MAX_VIRTUAL_IDS).is_virtual_of(&a));
+        assert!(!a.virtual_id(MAX_VIRTUAL_IDS + 1).is_virtual_of(&a));
+        assert!(!a.virtual_id(1).is_virtual_of(&b));
+        assert!(!a.is_virtual_of(&a));
+    }
+
     #[test]
     fn test_sort() {
         let a = Did::from_str("0xaaE807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
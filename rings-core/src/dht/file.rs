@@ -0,0 +1,244 @@
+#![warn(missing_docs)]
+//! Content-addressed storage of large objects.
+//! A blob is split into fixed-size chunks, each stored as a Data vnode addressed by its hash,
+//! and a [FileManifest] listing the chunk addresses is signed by the session of uploader
+//! and stored as a FileManifest vnode. Readers fetch the manifest, then verify each chunk against it.
+//! A manifest is valid while the session signing it is live, the uploader signs it again to keep
+//! the file readable after that.
+use serde::Deserialize;
+use serde::Serialize;
+use web3::types::Address;
+
+use crate::dht::vnode::VNodeType;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::ecc::signers;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::Decoder;
+use crate::message::Encoder;
+use crate::session::Session;
+use crate::session::SessionManager;
+use crate::session::Signer;
+use crate::utils::get_epoch_ms;
+
+/// Size of a file chunk, a chunk is carried by one message.
+pub const FILE_CHUNK_SIZE: usize = 64 * 1024;
+/// Max difference between the signing time of a manifest and the clock of node storing it,
+/// in milliseconds.
+pub const MAX_MANIFEST_CLOCK_SKEW_MS: u128 = 5 * 60 * 1000;
+
+/// Index of chunks of a file, signed by a session of the uploader.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileManifest {
+    /// address of uploader, the authorizer of `session`
+    pub owner: Address,
+    /// name of file
+    pub name: String,
+    /// size of file in bytes
+    pub size: u64,
+    /// size of each chunk in bytes, the last chunk may be smaller
+    pub chunk_size: u64,
+    /// addresses of chunk vnodes, in order
+    pub chunks: Vec<Did>,
+    /// time of signing, in milliseconds since epoch
+    pub ts_ms: u128,
+    /// session of uploader which signed the manifest, it should be live at `ts_ms` and now
+    pub session: Session,
+    /// signature of session key, over `name:size:chunk_size:chunks:ts_ms`
+    pub sig: Vec<u8>,
+}
+
+impl FileManifest {
+    /// Split `data` into chunk vnodes of at most `chunk_size` bytes.
+    pub fn split(data: &[u8], chunk_size: usize) -> Result<Vec<VirtualNode>> {
+        if chunk_size == 0 {
+            return Err(Error::InvalidFileManifest);
+        }
+        data.chunks(chunk_size)
+            .map(|c| c.encode()?.try_into())
+            .collect()
+    }
+
+    /// Create a manifest of `chunks` signed by the session of `session_manager`.
+    pub fn new(
+        session_manager: &SessionManager,
+        name: &str,
+        size: u64,
+        chunk_size: u64,
+        chunks: Vec<Did>,
+    ) -> Result<Self> {
+        let ts_ms = get_epoch_ms();
+        let sig =
+            session_manager.sign(&Self::sign_message(name, size, chunk_size, &chunks, ts_ms))?;
+        let session = session_manager.session()?;
+        Ok(Self {
+            owner: session.auth.authorizer,
+            name: name.to_owned(),
+            size,
+            chunk_size,
+            chunks,
+            ts_ms,
+            session,
+            sig,
+        })
+    }
+
+    fn sign_message(name: &str, size: u64, chunk_size: u64, chunks: &[Did], ts_ms: u128) -> String {
+        let chunks = chunks.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        format!(
+            "{}:{}:{}:{}:{}",
+            name,
+            size,
+            chunk_size,
+            chunks.join(","),
+            ts_ms
+        )
+    }
+
+    /// Check if the manifest is signed by a session of its owner, and the chunks can hold the file.
+    /// The session should be live now, so a leaked session key can't sign a backdated manifest
+    /// after the session expired.
+    pub fn verify(&self) -> bool {
+        let expected_chunks = match self.chunk_size {
+            0 => return false,
+            n => (self.size + n - 1) / n,
+        };
+        if expected_chunks != self.chunks.len() as u64
+            || self.owner != self.session.auth.authorizer
+            || self.ts_ms < self.session.auth.ts_ms
+            || self.session.is_expired_at(self.ts_ms)
+            || !self.session.verify()
+        {
+            return false;
+        }
+        let msg = Self::sign_message(
+            &self.name,
+            self.size,
+            self.chunk_size,
+            &self.chunks,
+            self.ts_ms,
+        );
+        match self.session.auth.signer {
+            Signer::DEFAULT => signers::default::verify(&msg, &self.session.auth.addr, &self.sig),
+            Signer::EIP712 => signers::eip712::verify(&msg, &self.session.auth.addr, &self.sig),
+        }
+    }
+
+    /// Check if the manifest is signed around the time of local clock, which is checked
+    /// when it's stored by the uploader, so the signing time can't be made up.
+    pub fn is_fresh(&self) -> bool {
+        get_epoch_ms().abs_diff(self.ts_ms) <= MAX_MANIFEST_CLOCK_SKEW_MS
+    }
+
+    /// Get a manifest from its vnode, the signature is verified.
+    pub fn from_vnode(vnode: &VirtualNode) -> Result<Self> {
+        let encoded = match (&vnode.kind, vnode.data.as_slice()) {
+            (VNodeType::FileManifest, [encoded]) => encoded,
+            _ => return Err(Error::InvalidFileManifest),
+        };
+        let decoded: String = encoded.decode()?;
+        let manifest: Self = serde_json::from_str(&decoded).map_err(Error::Deserialize)?;
+        if !manifest.verify() {
+            return Err(Error::InvalidFileManifest);
+        }
+        Ok(manifest)
+    }
+
+    /// Check the `index`th chunk vnode against the manifest, return data of the chunk.
+    pub fn verify_chunk(&self, index: usize, vnode: &VirtualNode) -> Result<Vec<u8>> {
+        let encoded = match (&vnode.kind, vnode.data.as_slice()) {
+            (VNodeType::Data, [encoded]) => encoded,
+            _ => return Err(Error::InvalidFileChunk(index)),
+        };
+        let expected = self
+            .chunks
+            .get(index)
+            .ok_or(Error::InvalidFileChunk(index))?;
+        // address of a Data vnode is the hash of its data
+        let hashed: VirtualNode = encoded.clone().try_into()?;
+        if hashed.did() != *expected {
+            return Err(Error::InvalidFileChunk(index));
+        }
+        encoded.decode()
+    }
+}
+
+impl TryFrom<FileManifest> for VirtualNode {
+    type Error = Error;
+    fn try_from(manifest: FileManifest) -> Result<Self> {
+        let data = serde_json::to_string(&manifest).map_err(|_| Error::SerializeToString)?;
+        let vnode: VirtualNode = data.try_into()?;
+        Ok(Self {
+            kind: VNodeType::FileManifest,
+            ..vnode
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    #[test]
+    fn test_file_manifest() -> Result<()> {
+        let key = SecretKey::random();
+        let session_manager = SessionManager::new_with_seckey(&key)?;
+        let data = (0..2500u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let chunks = FileManifest::split(&data, 1000)?;
+        assert_eq!(chunks.len(), 3);
+
+        let manifest = FileManifest::new(
+            &session_manager,
+            "data.bin",
+            data.len() as u64,
+            1000,
+            chunks.iter().map(|c| c.did()).collect(),
+        )?;
+        assert_eq!(manifest.owner, key.address());
+        assert!(manifest.is_fresh());
+        let vnode: VirtualNode = manifest.clone().try_into()?;
+        assert_eq!(vnode.kind, VNodeType::FileManifest);
+        assert!(vnode.verify().is_ok());
+        assert_eq!(FileManifest::from_vnode(&vnode)?, manifest);
+
+        let restored = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, c)| manifest.verify_chunk(i, c))
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        assert_eq!(restored, data);
+
+        // a chunk at wrong position, or a tampered manifest is rejected
+        assert!(manifest.verify_chunk(0, &chunks[1]).is_err());
+        assert!(manifest.verify_chunk(3, &chunks[0]).is_err());
+        let tampered = FileManifest {
+            name: "other.bin".to_owned(),
+            ..manifest.clone()
+        };
+        assert!(!tampered.verify());
+        // a session can't sign for another owner
+        let forged = FileManifest {
+            owner: SecretKey::random().address(),
+            ..manifest.clone()
+        };
+        assert!(!forged.verify());
+        // nor sign after it expired
+        let late = FileManifest {
+            ts_ms: u128::MAX,
+            ..manifest.clone()
+        };
+        assert!(!late.verify());
+        // and a manifest signed long ago is not stored by the uploader
+        let stale = FileManifest {
+            ts_ms: manifest.ts_ms - MAX_MANIFEST_CLOCK_SKEW_MS - 1,
+            ..manifest
+        };
+        assert!(!stale.is_fresh());
+        let vnode: VirtualNode = tampered.try_into()?;
+        assert!(FileManifest::from_vnode(&vnode).is_err());
+        Ok(())
+    }
+}
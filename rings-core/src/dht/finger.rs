@@ -30,6 +30,11 @@ impl FingerTable {
         }
     }
 
+    /// max number of fingers
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     /// is empty
     pub fn is_empty(&self) -> bool {
         self.len() == 0
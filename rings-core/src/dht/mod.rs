@@ -4,6 +4,7 @@
 
 mod did;
 pub use did::Did;
+pub use did::MAX_VIRTUAL_IDS;
 mod chord;
 /// Finger table for Rings
 pub mod finger;
@@ -11,12 +12,16 @@ mod successor;
 mod types;
 pub use chord::PeerRing;
 pub use chord::PeerRingAction;
+pub use chord::PeerRingConfig;
+pub use chord::PeerRingTopology;
 pub use chord::RemoteAction as PeerRingRemoteAction;
 pub use finger::FingerTable;
 pub use types::Chord;
 pub use types::ChordStabilize;
 pub use types::ChordStorage;
 pub use types::SubRingManager;
+/// Chunked storage of large objects with manifests
+pub mod file;
 mod stabilization;
 pub use stabilization::Stabilization;
 pub use stabilization::TStabilize;
@@ -27,8 +32,13 @@ pub mod vnode;
 
 #[cfg(test)]
 pub mod tests {
+    #[cfg(not(feature = "wasm"))]
+    use std::sync::Arc;
+
     use super::*;
     use crate::ecc::tests::gen_ordered_keys;
+    #[cfg(not(feature = "wasm"))]
+    use crate::storage::PersistenceStorage;
 
     pub fn gen_ordered_dids(n: usize) -> Vec<Did> {
         gen_ordered_keys(n)
@@ -36,4 +46,21 @@ pub mod tests {
             .map(|x| x.address().into())
             .collect()
     }
+
+    /// A storage at a random path under `./tmp`, with the path to remove when done.
+    #[cfg(not(feature = "wasm"))]
+    pub async fn new_test_storage() -> (Arc<PersistenceStorage>, String) {
+        let path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+        (Arc::new(db), path)
+    }
+
+    /// A ring of `did` on its own storage, with the path of storage to remove when done.
+    #[cfg(not(feature = "wasm"))]
+    pub async fn new_test_ring(did: Did) -> (PeerRing, String) {
+        let (db, path) = new_test_storage().await;
+        (PeerRing::new_with_storage(did, db), path)
+    }
 }
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 
 use crate::dht::ChordStabilize;
+use crate::dht::Did;
 use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
@@ -10,9 +11,16 @@ use crate::err::Result;
 use crate::message::FindSuccessorSend;
 use crate::message::FindSuccessorThen;
 use crate::message::Message;
+use crate::message::MessagePayload;
 use crate::message::NotifyPredecessorSend;
 use crate::message::PayloadSender;
+use crate::message::Ping;
+use crate::storage::PersistenceStorageOperation;
 use crate::swarm::Swarm;
+use crate::swarm::TransportManager;
+
+/// A predecessor or successor which misses this number of pings in a row is evicted.
+pub const MAX_MISSED_PINGS: u8 = 3;
 
 #[derive(Clone)]
 pub struct Stabilization {
@@ -49,9 +57,7 @@ impl Stabilization {
         let msg = Message::NotifyPredecessorSend(NotifyPredecessorSend { id: self.chord.id });
         if self.chord.id != successor_min {
             for s in successor_list {
-                self.swarm
-                    .send_message(msg.clone(), s, self.swarm.address().into())
-                    .await?;
+                self.send_message(msg.clone(), s).await?;
             }
             Ok(())
         } else {
@@ -59,6 +65,48 @@ impl Stabilization {
         }
     }
 
+    /// Ping predecessor and successors, and evict those which stop answering.
+    /// Called periodically along with `stabilize`.
+    pub async fn check_liveness(&self) -> Result<()> {
+        let mut peers = self.chord.lock_successor()?.list();
+        if let PeerRingAction::RemoteAction(p, PeerRingRemoteAction::CheckPredecessor) =
+            self.chord.check_predecessor()?
+        {
+            peers.push(p);
+        }
+        peers.sort();
+        peers.dedup();
+
+        // virtual identities are pinged as themselves, but liveness is of their hosting node
+        for did in peers
+            .into_iter()
+            .filter(|x| !self.swarm.is_local(&(*x).into()))
+        {
+            let address = self.swarm.physical_address(&did.into());
+            let missed = self
+                .swarm
+                .liveness(&address)
+                .map(|x| x.missed_pings)
+                .unwrap_or(0);
+            if missed >= MAX_MISSED_PINGS {
+                log::warn!("[stabilize] evict {:?}, missed {} pings", did, missed);
+                self.chord.remove(did)?;
+                if let Some((_address, trans)) = self.swarm.remove_transport(&address) {
+                    if let Err(e) = trans.close().await {
+                        log::warn!("[stabilize] Failed on close {:?}: {:?}", did, e);
+                    }
+                }
+                continue;
+            }
+            let id = self.swarm.record_ping(&address);
+            let msg = Message::Ping(Ping { id });
+            if let Err(e) = self.swarm.send_direct_message(msg, did).await {
+                log::warn!("[stabilize] Failed on ping {:?}: {:?}", did, e);
+            }
+        }
+        Ok(())
+    }
+
     async fn fix_fingers(&self) -> Result<()> {
         match self.chord.fix_fingers() {
             Ok(action) => match action {
@@ -74,9 +122,7 @@ impl Stabilization {
                         id: current,
                         then: FindSuccessorThen::FixFingerTable,
                     });
-                    self.swarm
-                        .send_message(msg.clone(), next, self.swarm.address().into())
-                        .await
+                    self.send_message(msg, next).await
                 }
                 _ => {
                     log::error!("Invalid PeerRing Action");
@@ -90,6 +136,18 @@ impl Stabilization {
         }
     }
 
+    /// Send message from identity of the ring, which may be a virtual one.
+    async fn send_message(&self, msg: Message, next: Did) -> Result<()> {
+        let payload = MessagePayload::new_send_from(
+            msg,
+            self.swarm.session_manager(),
+            self.chord.id,
+            next,
+            self.chord.id,
+        )?;
+        self.swarm.send_payload(payload).await
+    }
+
     pub async fn stabilize(&self) -> Result<()> {
         if let Err(e) = self.notify_predecessor().await {
             log::error!("[stabilize] Failed on notify predecessor {:?}", e);
@@ -97,6 +155,12 @@ impl Stabilization {
         if let Err(e) = self.fix_fingers().await {
             log::error!("[stabilize] Failed on fix_finger {:?}", e);
         }
+        // sweep expired vnodes, and evict if storage is full
+        if let Err(e) = self.chord.storage.prune().await {
+            log::error!("[stabilize] Failed on prune storage {:?}", e);
+        }
+        self.chord.cache.prune();
+        self.swarm.prune_seen();
         Ok(())
     }
 }
@@ -122,10 +186,16 @@ mod stabilizer {
                 let timeout = Delay::new(Duration::from_secs(self.timeout as u64)).fuse();
                 pin_mut!(timeout);
                 select! {
-                    _ = timeout => self
-                        .stabilize()
-                        .await
-                        .unwrap_or_else(|e| log::error!("failed to stabilize {:?}", e)),
+                    _ = timeout => {
+                        self
+                            .check_liveness()
+                            .await
+                            .unwrap_or_else(|e| log::error!("failed to check liveness {:?}", e));
+                        self
+                            .stabilize()
+                            .await
+                            .unwrap_or_else(|e| log::error!("failed to stabilize {:?}", e));
+                    }
                 }
             }
         }
@@ -150,6 +220,10 @@ mod stabilizer {
             let func = move || {
                 let caller = caller.clone();
                 spawn_local(Box::pin(async move {
+                    caller
+                        .check_liveness()
+                        .await
+                        .unwrap_or_else(|e| log::error!("failed to check liveness {:?}", e));
                     caller
                         .stabilize()
                         .await
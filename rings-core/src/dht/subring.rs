@@ -4,6 +4,7 @@ use std::str::FromStr;
 use async_trait::async_trait;
 use serde::Deserialize;
 use serde::Serialize;
+use web3::types::Address;
 
 use super::chord::PeerRing;
 use super::chord::PeerRingAction;
@@ -14,7 +15,9 @@ use super::vnode::VNodeType;
 use super::vnode::VirtualNode;
 use super::FingerTable;
 use crate::dht::Did;
+use crate::ecc::signers;
 use crate::ecc::HashStr;
+use crate::ecc::SecretKey;
 use crate::err::Error;
 use crate::err::Result;
 use crate::storage::PersistenceStorageReadAndWrite;
@@ -34,6 +37,43 @@ pub struct SubRing {
     pub admin: Option<Did>,
     /// creator
     pub creator: Did,
+    /// joined nodes, ordered by did
+    #[serde(default)]
+    pub members: Vec<Did>,
+    /// sequence number of the last applied admin action
+    #[serde(default)]
+    pub seq: u64,
+    /// nodes kicked by admin, which can't join again, ordered by did
+    #[serde(default)]
+    pub banned: Vec<Did>,
+}
+
+/// Operations which can only be done by the admin of a subring.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum SubRingAdminOp {
+    /// Remove a member from subring, and ban it from joining again
+    Kick(Did),
+    /// Allow a kicked node to join again
+    Unban(Did),
+    /// Change the name of subring, the did of subring is not changed
+    Rename(String),
+    /// Hand over the admin of subring
+    SetAdmin(Did),
+}
+
+/// An admin operation on subring, signed by the admin.
+/// Each action must carry a higher `seq` than the last applied one, so it can't be replayed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubRingAdminAction {
+    /// did of subring
+    pub rid: Did,
+    /// operation to apply
+    pub op: SubRingAdminOp,
+    /// sequence number of action
+    pub seq: u64,
+    /// signature of admin, over `subring:rid:seq:op`
+    pub sig: Vec<u8>,
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -42,10 +82,8 @@ impl SubRingManager<PeerRingAction> for PeerRing {
     async fn join_subring(&self, id: &Did, rid: &Did) -> Result<PeerRingAction> {
         match self.find_successor(*rid) {
             Ok(PeerRingAction::Some(_)) => {
-                let id = id.to_owned();
-                if let Ok(subring) = self.get_subring(rid).await {
-                    let mut sr = subring;
-                    sr.finger.join(id);
+                if let Ok(mut sr) = self.get_subring(rid).await {
+                    sr.join(*id)?;
                     self.store_subring(&sr).await?;
                 }
                 Ok(PeerRingAction::None)
@@ -58,6 +96,39 @@ impl SubRingManager<PeerRingAction> for PeerRing {
         }
     }
 
+    async fn leave_subring(&self, id: &Did, rid: &Did) -> Result<PeerRingAction> {
+        match self.find_successor(*rid) {
+            Ok(PeerRingAction::Some(_)) => {
+                if let Ok(mut sr) = self.get_subring(rid).await {
+                    sr.leave(*id);
+                    self.store_subring(&sr).await?;
+                }
+                Ok(PeerRingAction::None)
+            }
+            Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(_))) => Ok(
+                PeerRingAction::RemoteAction(n, RemoteAction::FindAndLeaveSubRing(*rid)),
+            ),
+            Ok(a) => Err(Error::PeerRingUnexpectedAction(a)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn admin_subring(&self, action: &SubRingAdminAction) -> Result<PeerRingAction> {
+        match self.find_successor(action.rid) {
+            Ok(PeerRingAction::Some(_)) => {
+                let mut sr = self.get_subring(&action.rid).await?;
+                sr.apply(action)?;
+                self.store_subring(&sr).await?;
+                Ok(PeerRingAction::None)
+            }
+            Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(_))) => Ok(
+                PeerRingAction::RemoteAction(n, RemoteAction::FindAndAdminSubRing(action.clone())),
+            ),
+            Ok(a) => Err(Error::PeerRingUnexpectedAction(a)),
+            Err(e) => Err(e),
+        }
+    }
+
     async fn cloest_preceding_node_for_subring(&self, id: &Did, rid: &Did) -> Option<Result<Did>> {
         let id = id.to_owned();
         if let Ok(subring) = self.get_subring(rid).await {
@@ -123,6 +194,9 @@ impl SubRing {
             finger: FingerTable::new(did, 1),
             admin: None,
             creator: *creator,
+            members: vec![*creator],
+            seq: 0,
+            banned: vec![],
         })
     }
 
@@ -137,8 +211,89 @@ impl SubRing {
             finger: (*finger).clone(),
             admin: None,
             creator: ring.id,
+            members: vec![ring.id],
+            seq: 0,
+            banned: vec![],
         })
     }
+
+    /// Admin of subring, the creator if it's not set.
+    pub fn admin(&self) -> Did {
+        self.admin.unwrap_or(self.creator)
+    }
+
+    /// Joined nodes of subring.
+    pub fn members(&self) -> &Vec<Did> {
+        &self.members
+    }
+
+    /// Add a node to subring, unless it's banned.
+    pub fn join(&mut self, id: Did) -> Result<()> {
+        if self.banned.binary_search(&id).is_ok() {
+            return Err(Error::SubRingMemberBanned(id));
+        }
+        self.finger.join(id);
+        if let Err(i) = self.members.binary_search(&id) {
+            self.members.insert(i, id);
+        }
+        Ok(())
+    }
+
+    /// Remove a node from subring.
+    pub fn leave(&mut self, id: Did) {
+        self.finger.remove(id);
+        self.members.retain(|m| *m != id);
+    }
+
+    /// Verify an admin action, then apply it.
+    pub fn apply(&mut self, action: &SubRingAdminAction) -> Result<()> {
+        action.verify(self)?;
+        match &action.op {
+            SubRingAdminOp::Kick(id) => {
+                self.leave(*id);
+                if let Err(i) = self.banned.binary_search(id) {
+                    self.banned.insert(i, *id);
+                }
+            }
+            SubRingAdminOp::Unban(id) => self.banned.retain(|b| b != id),
+            SubRingAdminOp::Rename(name) => self.name = name.clone(),
+            SubRingAdminOp::SetAdmin(id) => self.admin = Some(*id),
+        }
+        self.seq = action.seq;
+        Ok(())
+    }
+}
+
+impl SubRingAdminAction {
+    /// Create an admin action signed by `key`.
+    pub fn new(key: &SecretKey, rid: Did, op: SubRingAdminOp, seq: u64) -> Result<Self> {
+        let sig = key.sign(&Self::sign_message(&rid, &op, seq)?).to_vec();
+        Ok(Self { rid, op, seq, sig })
+    }
+
+    fn sign_message(rid: &Did, op: &SubRingAdminOp, seq: u64) -> Result<String> {
+        let op = serde_json::to_string(op).map_err(|_| Error::SerializeToString)?;
+        Ok(format!("subring:{}:{}:{}", rid.to_string(), seq, op))
+    }
+
+    /// Check if the action is signed by the current admin of `subring`, and not replayed.
+    pub fn verify(&self, subring: &SubRing) -> Result<()> {
+        if subring.did != self.rid {
+            return Err(Error::AddressNotEqual);
+        }
+        if self.seq <= subring.seq {
+            return Err(Error::SubRingAdminSeqTooLow(self.seq, subring.seq));
+        }
+        let admin: Address = subring.admin().into();
+        if !signers::default::verify(
+            &Self::sign_message(&self.rid, &self.op, self.seq)?,
+            &admin,
+            &self.sig,
+        ) {
+            return Err(Error::InvalidSubRingAdminAction);
+        }
+        Ok(())
+    }
 }
 
 impl TryFrom<SubRing> for VirtualNode {
@@ -149,6 +304,7 @@ impl TryFrom<SubRing> for VirtualNode {
             address: ring.did,
             data: vec![data.into()],
             kind: VNodeType::SubRing,
+            expired_at: None,
         })
     }
 }
@@ -179,3 +335,90 @@ impl TryFrom<VirtualNode> for SubRing {
 //         pr
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_subring_join_and_leave() -> Result<()> {
+        let creator: Did = SecretKey::random().address().into();
+        let member: Did = SecretKey::random().address().into();
+        let mut subring = SubRing::new("test", &creator)?;
+        subring.join(member)?;
+        subring.join(member)?;
+        let mut expected = vec![creator, member];
+        expected.sort();
+        assert_eq!(subring.members(), &expected);
+        assert_eq!(subring.finger.first(), Some(member));
+
+        subring.leave(member);
+        assert_eq!(subring.members(), &vec![creator]);
+        assert_eq!(subring.finger.first(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subring_admin_action() -> Result<()> {
+        let admin_key = SecretKey::random();
+        let other_key = SecretKey::random();
+        let admin: Did = admin_key.address().into();
+        let other: Did = other_key.address().into();
+        let mut subring = SubRing::new("test", &admin)?;
+        subring.join(other)?;
+        let rid = subring.did;
+
+        // only admin can operate
+        let forged = SubRingAdminAction::new(&other_key, rid, SubRingAdminOp::Kick(admin), 1)?;
+        assert!(matches!(
+            subring.apply(&forged),
+            Err(Error::InvalidSubRingAdminAction)
+        ));
+        let mut tampered =
+            SubRingAdminAction::new(&admin_key, rid, SubRingAdminOp::Kick(other), 1)?;
+        tampered.op = SubRingAdminOp::Kick(admin);
+        assert!(matches!(
+            subring.apply(&tampered),
+            Err(Error::InvalidSubRingAdminAction)
+        ));
+
+        let rename =
+            SubRingAdminAction::new(&admin_key, rid, SubRingAdminOp::Rename("new".into()), 1)?;
+        subring.apply(&rename)?;
+        assert_eq!(subring.name, "new");
+        assert_eq!(subring.did, rid);
+        // action can't be replayed
+        assert!(matches!(
+            subring.apply(&rename),
+            Err(Error::SubRingAdminSeqTooLow(1, 1))
+        ));
+
+        // kicked node is banned until unbanned
+        let kick = SubRingAdminAction::new(&admin_key, rid, SubRingAdminOp::Kick(other), 2)?;
+        subring.apply(&kick)?;
+        assert_eq!(subring.members(), &vec![admin]);
+        assert!(matches!(
+            subring.join(other),
+            Err(Error::SubRingMemberBanned(id)) if id == other
+        ));
+        assert_eq!(subring.members(), &vec![admin]);
+        let unban = SubRingAdminAction::new(&admin_key, rid, SubRingAdminOp::Unban(other), 3)?;
+        subring.apply(&unban)?;
+        subring.join(other)?;
+        assert!(subring.members().contains(&other));
+
+        // after handing over, old admin loses the right
+        let set_admin =
+            SubRingAdminAction::new(&admin_key, rid, SubRingAdminOp::SetAdmin(other), 4)?;
+        subring.apply(&set_admin)?;
+        assert_eq!(subring.admin(), other);
+        let rename =
+            SubRingAdminAction::new(&admin_key, rid, SubRingAdminOp::Rename("x".into()), 5)?;
+        assert!(subring.apply(&rename).is_err());
+        let rename =
+            SubRingAdminAction::new(&other_key, rid, SubRingAdminOp::Rename("x".into()), 5)?;
+        subring.apply(&rename)?;
+        assert_eq!(subring.name, "x");
+        Ok(())
+    }
+}
@@ -22,6 +22,11 @@ impl Successor {
         }
     }
 
+    /// Max length of successor list.
+    pub fn capacity(&self) -> u8 {
+        self.max
+    }
+
     pub fn is_none(&self) -> bool {
         self.successors.is_empty()
     }
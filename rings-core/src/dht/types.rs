@@ -2,6 +2,8 @@ use async_trait::async_trait;
 
 use super::did::Did;
 use super::subring::SubRing;
+use super::subring::SubRingAdminAction;
+use super::vnode::VNodeRemoval;
 use super::vnode::VirtualNode;
 use crate::err::Result;
 
@@ -30,10 +32,12 @@ pub trait ChordStorage<A>: Chord<A> {
     /// store VNode to it's successor
     /// A VNode's successor should store the data
     async fn store(&self, peer: VirtualNode) -> Result<A>;
+    /// remove VNode from it's successor, only the owner of VNode can remove it
+    async fn remove_vnode(&self, removal: VNodeRemoval) -> Result<A>;
     /// Batch store
     async fn store_vec(&self, peer: Vec<VirtualNode>) -> Result<A>;
     /// When A Node's successor is updated, it should check the storage that
-    /// if exist some VNode's address is in (self.id, new_successor), then
+    /// if exist some VNode's address is not in (self.id, new_successor], then
     /// sync the data to the new successor
     async fn sync_with_successor(&self, new_successor: Did) -> Result<A>;
 }
@@ -68,6 +72,13 @@ pub trait SubRingManager<A>: ChordStorage<A> {
     /// And Noti closest preceding node that A is Joined
     async fn join_subring(&self, id: &Did, rid: &Did) -> Result<A>;
 
+    /// remove a node from subring, the node storing the subring's vnode updates it
+    async fn leave_subring(&self, id: &Did, rid: &Did) -> Result<A>;
+
+    /// apply an admin action to subring, the node storing the subring's vnode
+    /// verifies the admin's signature before updating it
+    async fn admin_subring(&self, action: &SubRingAdminAction) -> Result<A>;
+
     /// search a cloest preceding node
     async fn cloest_preceding_node_for_subring(&self, id: &Did, rid: &Did) -> Option<Result<Did>>;
 }
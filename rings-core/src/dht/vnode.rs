@@ -1,19 +1,24 @@
 #![warn(missing_docs)]
 use std::str::FromStr;
 
-use num_bigint::BigUint;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
+use web3::types::Address;
 
+use crate::dht::file::FileManifest;
 use crate::dht::subring::SubRing;
 use crate::dht::Did;
+use crate::ecc::signers;
 use crate::ecc::HashStr;
+use crate::ecc::SecretKey;
 use crate::err::Error;
 use crate::err::Result;
 use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
+use crate::transports::fragment::MAX_MESSAGE_LEN;
+use crate::utils::get_epoch_ms;
 
 /// VNode Types
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -22,15 +27,88 @@ pub enum VNodeType {
     Data,
     /// SubRing: Finger table of a SubRing
     SubRing,
-    /// RelayMessage: A Relayed but unreach message, which is stored on it's successor
+    /// RelayMessage: A Relayed but unreach message, which is stored on the node preceding its target
     RelayMessage,
+    /// SignedData: Mutable data signed by it's writer, see [SignedRecord]
+    SignedData,
+    /// Tombstone: A removed SignedData, which keeps the last record and its removal
+    /// until `TOMBSTONE_TTL_MS` passed, thus the record can't be stored again with a lower seq
+    Tombstone,
+    /// FileManifest: Index of chunks of a file signed by its uploader, see [FileManifest]
+    FileManifest,
+}
+
+/// How long a tombstone of removed SignedData is kept, in milliseconds.
+pub const TOMBSTONE_TTL_MS: u128 = 24 * 60 * 60 * 1000;
+/// Max length of VNodes sent in one message, leaving room for the payload around them
+/// within `MAX_MESSAGE_LEN`.
+pub const MAX_VNODE_BATCH_LEN: usize = MAX_MESSAGE_LEN / 2;
+
+/// A mutable record in the style of BEP44.
+/// The address of a SignedData vnode is `sha1(writer:salt)`, so one writer can hold many records
+/// by using different salts. Each update must be signed by the writer and carry a higher `seq`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedRecord {
+    /// address of writer
+    pub writer: Address,
+    /// salt of record
+    pub salt: String,
+    /// sequence number, a stored record is only replaced by one with higher seq
+    pub seq: u64,
+    /// encoded value of record
+    pub value: Encoded,
+    /// signature of writer, over `did:seq:value`
+    pub sig: Vec<u8>,
+}
+
+impl SignedRecord {
+    /// Create a record signed by `key`.
+    pub fn new(key: &SecretKey, salt: &str, seq: u64, value: Encoded) -> Result<Self> {
+        let writer = key.address();
+        let did = Self::gen_did(&writer, salt)?;
+        let sig = key.sign(&Self::sign_message(&did, seq, &value)).to_vec();
+        Ok(Self {
+            writer,
+            salt: salt.to_owned(),
+            seq,
+            value,
+            sig,
+        })
+    }
+
+    /// Generate the virtual address of record with `sha1(writer:salt)`.
+    pub fn gen_did(writer: &Address, salt: &str) -> Result<Did> {
+        let address: HashStr = format!("{:?}:{}", writer, salt).into();
+        Did::from_str(&address.inner())
+    }
+
+    /// Virtual address of record.
+    pub fn did(&self) -> Result<Did> {
+        Self::gen_did(&self.writer, &self.salt)
+    }
+
+    fn sign_message(did: &Did, seq: u64, value: &Encoded) -> String {
+        format!("{}:{}:{}", did.to_string(), seq, value.value())
+    }
+
+    /// Check if the record is signed by it's writer.
+    pub fn verify(&self) -> bool {
+        match self.did() {
+            Ok(did) => signers::default::verify(
+                &Self::sign_message(&did, self.seq, &self.value),
+                &self.writer,
+                &self.sig,
+            ),
+            Err(_) => false,
+        }
+    }
 }
 
 /// A Virtual Node is a Node that dont have real network address.
 /// The Address of a Virtual Node is virutal,
 /// For Encoded Data, it's sha1 of data, for a SubRing, it's sha1 of SubRing's name,
-/// and for the RelayedMessage, it's the target address of message plus 1 (for ensure that the message is
-/// sent to the successor of target), thus while target Node going online, it will sync from it's successor.
+/// and for the RelayedMessage, it's the target address of message, which is stored on the node
+/// preceding target, thus while target Node going online, it will get messages from that node.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct VirtualNode {
     /// address of vnode
@@ -39,6 +117,9 @@ pub struct VirtualNode {
     pub data: Vec<Encoded>,
     /// vnode type
     pub kind: VNodeType,
+    /// expiration time in milliseconds, a vnode without it never expires
+    #[serde(default)]
+    pub expired_at: Option<u128>,
 }
 
 impl VirtualNode {
@@ -46,6 +127,168 @@ impl VirtualNode {
     pub fn did(&self) -> Did {
         self.address
     }
+
+    /// Split `vnodes` into batches no longer than `MAX_VNODE_BATCH_LEN`, each is sent by one message.
+    /// A vnode longer than that is put in a batch alone.
+    pub fn batch(vnodes: Vec<Self>) -> Vec<Vec<Self>> {
+        let mut batches: Vec<Vec<Self>> = vec![];
+        let mut len = 0;
+        for vnode in vnodes {
+            let vnode_len = serde_json::to_vec(&vnode).map_or(0, |v| v.len());
+            match batches.last_mut() {
+                Some(batch) if len + vnode_len <= MAX_VNODE_BATCH_LEN => {
+                    len += vnode_len;
+                    batch.push(vnode);
+                }
+                _ => {
+                    len = vnode_len;
+                    batches.push(vec![vnode]);
+                }
+            }
+        }
+        batches
+    }
+
+    /// Make the vnode expire after `ttl_ms` milliseconds.
+    pub fn with_ttl(mut self, ttl_ms: u128) -> Self {
+        self.expired_at = Some(get_epoch_ms() + ttl_ms);
+        self
+    }
+
+    /// Check if the vnode is expired.
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expired_at, Some(ts) if ts <= get_epoch_ms())
+    }
+
+    /// Get the signed record of a SignedData vnode, the signature is verified,
+    /// so readers can trust the `writer` of returned record.
+    pub fn signed_record(&self) -> Result<SignedRecord> {
+        if self.kind != VNodeType::SignedData {
+            return Err(Error::InvalidVNodeType);
+        }
+        self.decode_signed_record()
+    }
+
+    fn decode_signed_record(&self) -> Result<SignedRecord> {
+        let decoded: String = self
+            .data
+            .first()
+            .ok_or(Error::InvalidSignedRecord)?
+            .decode()?;
+        let record: SignedRecord = serde_json::from_str(&decoded).map_err(Error::Deserialize)?;
+        if !record.verify() || record.did()? != self.address {
+            return Err(Error::InvalidSignedRecord);
+        }
+        Ok(record)
+    }
+
+    /// Make a tombstone of a SignedData vnode, if the removal is signed by its writer.
+    pub fn tombstone(&self, removal: &VNodeRemoval) -> Result<Self> {
+        removal.verify(self)?;
+        let removal = serde_json::to_string(removal).map_err(|_| Error::SerializeToString)?;
+        Ok(Self {
+            address: self.address,
+            data: vec![self.data[0].clone(), removal.encode()?],
+            kind: VNodeType::Tombstone,
+            expired_at: None,
+        }
+        .with_ttl(TOMBSTONE_TTL_MS))
+    }
+
+    /// Get the removed record and the removal of a tombstone, both are verified.
+    pub fn tombstone_removal(&self) -> Result<(SignedRecord, VNodeRemoval)> {
+        if self.kind != VNodeType::Tombstone {
+            return Err(Error::InvalidVNodeType);
+        }
+        let record = self.decode_signed_record()?;
+        let decoded: String = self
+            .data
+            .get(1)
+            .ok_or(Error::InvalidVNodeRemoval)?
+            .decode()?;
+        let removal: VNodeRemoval = serde_json::from_str(&decoded).map_err(Error::Deserialize)?;
+        removal.verify_record(&self.address, &record)?;
+        Ok((record, removal))
+    }
+
+    /// Verify a vnode before storing it.
+    /// Only SignedData, Tombstone and FileManifest vnodes carry a signature,
+    /// other kinds are always valid.
+    pub fn verify(&self) -> Result<()> {
+        match self.kind {
+            VNodeType::SignedData => self.signed_record().map(|_| ()),
+            VNodeType::Tombstone => self.tombstone_removal().map(|_| ()),
+            VNodeType::FileManifest => FileManifest::from_vnode(self).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A request to remove a SignedData vnode, signed by the writer of the stored record.
+/// `seq` should not be lower than the seq of stored record, so an outdated removal
+/// cannot remove a newer record. The removed record is replaced by a Tombstone vnode,
+/// so it can't be stored again, unless with a seq higher than the removal.
+/// Other kinds of vnode can't be removed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VNodeRemoval {
+    /// address of vnode
+    pub did: Did,
+    /// sequence number of record to remove
+    pub seq: u64,
+    /// signature of writer, over `remove:did:seq`
+    pub sig: Vec<u8>,
+}
+
+impl VNodeRemoval {
+    /// Create a removal signed by `key`.
+    pub fn new(key: &SecretKey, did: Did, seq: u64) -> Self {
+        let sig = key.sign(&Self::sign_message(&did, seq)).to_vec();
+        Self { did, seq, sig }
+    }
+
+    fn sign_message(did: &Did, seq: u64) -> String {
+        format!("remove:{}:{}", did.to_string(), seq)
+    }
+
+    /// Check if the removal is signed by the recorded owner of `vnode`.
+    /// Only SignedData vnodes can be removed, other kinds have no owner,
+    /// and are rejected with `Error::InvalidVNodeType`.
+    pub fn verify(&self, vnode: &VirtualNode) -> Result<()> {
+        if vnode.kind != VNodeType::SignedData {
+            return Err(Error::InvalidVNodeType);
+        }
+        self.verify_record(&vnode.did(), &vnode.signed_record()?)
+    }
+
+    fn verify_record(&self, did: &Did, record: &SignedRecord) -> Result<()> {
+        if *did != self.did {
+            return Err(Error::AddressNotEqual);
+        }
+        if self.seq < record.seq {
+            return Err(Error::SignedRecordSeqTooLow(self.seq, record.seq));
+        }
+        if !signers::default::verify(
+            &Self::sign_message(&self.did, self.seq),
+            &record.writer,
+            &self.sig,
+        ) {
+            return Err(Error::InvalidVNodeRemoval);
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<SignedRecord> for VirtualNode {
+    type Error = Error;
+    fn try_from(record: SignedRecord) -> Result<Self> {
+        let data = serde_json::to_string(&record).map_err(|_| Error::SerializeToString)?;
+        Ok(Self {
+            address: record.did()?,
+            data: vec![data.encode()?],
+            kind: VNodeType::SignedData,
+            expired_at: None,
+        })
+    }
 }
 
 impl<T> TryFrom<MessagePayload<T>> for VirtualNode
@@ -53,12 +296,14 @@ where T: Serialize + DeserializeOwned
 {
     type Error = Error;
     fn try_from(msg: MessagePayload<T>) -> Result<Self> {
-        let address = BigUint::from(Did::from(msg.addr)) + BigUint::from(1u16);
+        // stored at the destination's did, so it's kept by the node preceding the destination
+        let address = msg.relay.destination;
         let data = msg.encode()?;
         Ok(Self {
-            address: address.into(),
+            address,
             data: vec![data],
             kind: VNodeType::RelayMessage,
+            expired_at: None,
         })
     }
 }
@@ -71,6 +316,7 @@ impl TryFrom<Encoded> for VirtualNode {
             address: Did::from_str(&address.inner())?,
             data: vec![e],
             kind: VNodeType::Data,
+            expired_at: None,
         })
     }
 }
@@ -97,21 +343,194 @@ impl VirtualNode {
                         address: a.address,
                         data: [&a.data[..], &b.data[..]].concat(),
                         kind: a.kind.clone(),
+                        // keep the vnode until all of the messages are expired,
+                        // a side without expiry doesn't make the relayed messages kept forever
+                        expired_at: match (a.expired_at, b.expired_at) {
+                            (Some(ts_a), Some(ts_b)) => Some(ts_a.max(ts_b)),
+                            (ts_a, ts_b) => ts_a.or(ts_b),
+                        },
                     })
                 }
             }
-            VNodeType::Data => Ok(a.clone()),
+            VNodeType::Data | VNodeType::FileManifest => Ok(a.clone()),
+            // the record is removed, a tombstone of it replaces it
+            VNodeType::SignedData if b.kind == VNodeType::Tombstone => {
+                let (_, removal) = b.tombstone_removal()?;
+                removal.verify(a)?;
+                Ok(b.clone())
+            }
+            // only a record with higher seq than the removal brings it back
+            VNodeType::Tombstone => {
+                let (_, removal) = a.tombstone_removal()?;
+                match b.kind {
+                    VNodeType::SignedData => {
+                        let record_b = b.signed_record()?;
+                        if record_b.seq > removal.seq {
+                            Ok(b.clone())
+                        } else {
+                            Err(Error::SignedRecordSeqTooLow(record_b.seq, removal.seq))
+                        }
+                    }
+                    VNodeType::Tombstone => match b.tombstone_removal()? {
+                        (_, removal_b) if removal_b.seq > removal.seq => Ok(b.clone()),
+                        _ => Ok(a.clone()),
+                    },
+                    _ => Err(Error::InvalidVNodeType),
+                }
+            }
+            VNodeType::SignedData => {
+                // keep the record with highest seq, an update must come from the same writer
+                let record_a = a.signed_record()?;
+                let record_b = b.signed_record()?;
+                if record_a.writer != record_b.writer {
+                    Err(Error::InvalidSignedRecord)
+                } else if record_b.seq > record_a.seq {
+                    Ok(b.clone())
+                } else if record_b.seq == record_a.seq {
+                    Ok(a.clone())
+                } else {
+                    Err(Error::SignedRecordSeqTooLow(record_b.seq, record_a.seq))
+                }
+            }
             VNodeType::SubRing => {
                 // if subring exists, just join creator to new subring
                 let decoded_a: String = a.data[0].decode()?;
-                let decoded_b: String = a.data[0].decode()?;
+                let decoded_b: String = b.data[0].decode()?;
                 let mut subring_a: SubRing =
                     serde_json::from_str(&decoded_a).map_err(Error::Deserialize)?;
                 let subring_b: SubRing =
                     serde_json::from_str(&decoded_b).map_err(Error::Deserialize)?;
-                subring_a.finger.join(subring_b.creator);
+                subring_a.join(subring_b.creator);
                 subring_a.try_into()
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_record_update() -> Result<()> {
+        let key = SecretKey::random();
+        let record = SignedRecord::new(&key, "profile", 1, "v1".to_string().encode()?)?;
+        assert!(record.verify());
+        assert_eq!(
+            record.did()?,
+            SignedRecord::gen_did(&key.address(), "profile")?
+        );
+
+        let v1: VirtualNode = record.try_into()?;
+        assert!(v1.verify().is_ok());
+        assert_eq!(v1.signed_record()?.writer, key.address());
+
+        // higher seq replaces the stored record, lower seq is rejected
+        let v2: VirtualNode =
+            SignedRecord::new(&key, "profile", 2, "v2".to_string().encode()?)?.try_into()?;
+        assert_eq!(v2.did(), v1.did());
+        assert_eq!(VirtualNode::concat(&v1, &v2)?, v2);
+        assert!(matches!(
+            VirtualNode::concat(&v2, &v1),
+            Err(Error::SignedRecordSeqTooLow(1, 2))
+        ));
+        assert_eq!(VirtualNode::concat(&v2, &v2)?, v2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_vnode_removal() -> Result<()> {
+        let key = SecretKey::random();
+        let attacker = SecretKey::random();
+        let v2: VirtualNode =
+            SignedRecord::new(&key, "profile", 2, "v2".to_string().encode()?)?.try_into()?;
+
+        assert!(VNodeRemoval::new(&key, v2.did(), 2).verify(&v2).is_ok());
+        assert!(VNodeRemoval::new(&key, v2.did(), 3).verify(&v2).is_ok());
+        assert!(matches!(
+            VNodeRemoval::new(&key, v2.did(), 1).verify(&v2),
+            Err(Error::SignedRecordSeqTooLow(1, 2))
+        ));
+        assert!(matches!(
+            VNodeRemoval::new(&attacker, v2.did(), 2).verify(&v2),
+            Err(Error::InvalidVNodeRemoval)
+        ));
+
+        // vnode without a recorded owner cannot be removed
+        let data: VirtualNode = "plain".to_string().try_into()?;
+        assert!(VNodeRemoval::new(&key, data.did(), 0)
+            .verify(&data)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_message_expiry_on_concat() -> Result<()> {
+        let a = VirtualNode {
+            address: SecretKey::random().address().into(),
+            data: vec!["relayed".to_string().encode()?],
+            kind: VNodeType::RelayMessage,
+            expired_at: Some(100),
+        };
+        let b = VirtualNode {
+            expired_at: Some(200),
+            ..a.clone()
+        };
+        let forever = VirtualNode {
+            expired_at: None,
+            ..a.clone()
+        };
+        assert_eq!(VirtualNode::concat(&a, &b)?.expired_at, Some(200));
+        assert_eq!(VirtualNode::concat(&a, &forever)?.expired_at, Some(100));
+        assert_eq!(VirtualNode::concat(&forever, &b)?.expired_at, Some(200));
+        assert_eq!(VirtualNode::concat(&forever, &forever)?.expired_at, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_vnode_batch() -> Result<()> {
+        let small: VirtualNode = "small".to_string().try_into()?;
+        let big: VirtualNode = "x".repeat(MAX_VNODE_BATCH_LEN).try_into()?;
+        assert!(VirtualNode::batch(vec![]).is_empty());
+        // a vnode longer than a batch is sent alone
+        assert_eq!(
+            VirtualNode::batch(vec![
+                small.clone(),
+                small.clone(),
+                big.clone(),
+                small.clone()
+            ]),
+            vec![vec![small.clone(), small.clone()], vec![big], vec![small]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_record_forged() -> Result<()> {
+        let key = SecretKey::random();
+        let attacker = SecretKey::random();
+        let v1: VirtualNode =
+            SignedRecord::new(&key, "profile", 1, "v1".to_string().encode()?)?.try_into()?;
+
+        // attacker signs a record but claims it's written by key
+        let mut forged = SignedRecord::new(&attacker, "profile", 2, "evil".to_string().encode()?)?;
+        forged.writer = key.address();
+        assert!(!forged.verify());
+
+        // attacker tampers the value of a valid record
+        let mut tampered = v1.signed_record()?;
+        tampered.seq = 3;
+        assert!(!tampered.verify());
+        let data = serde_json::to_string(&tampered).map_err(|_| Error::SerializeToString)?;
+        let tampered = VirtualNode {
+            data: vec![data.encode()?],
+            ..v1.clone()
+        };
+        assert!(matches!(tampered.verify(), Err(Error::InvalidSignedRecord)));
+        assert!(matches!(
+            VirtualNode::concat(&v1, &tampered),
+            Err(Error::InvalidSignedRecord)
+        ));
+        Ok(())
+    }
+}
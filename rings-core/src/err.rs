@@ -187,6 +187,41 @@ pub enum Error {
     #[error("DataChannel state not open")]
     RTCDataChannelStateNotOpen,
 
+    #[error("Invalid virtual identity")]
+    InvalidVirtualIdentity,
+
+    #[error("Memory transport {0} not found")]
+    MemoryTransportNotFound(String),
+
+    #[error("Transport of kind {0:?} is not supported")]
+    TransportNotSupported(crate::transports::TransportKind),
+
+    #[error("Handshake is made by a transport of kind {0:?}, but local one is {1:?}")]
+    TransportKindMismatch(
+        crate::transports::TransportKind,
+        crate::transports::TransportKind,
+    ),
+
+    #[cfg(all(not(feature = "wasm"), feature = "websocket"))]
+    #[error("WebSocket error")]
+    WebSocketError(#[source] tokio_tungstenite::tungstenite::Error),
+
+    #[cfg(feature = "wasm")]
+    #[error("WebSocket error: {0}")]
+    WebSocketError(String),
+
+    #[error("Neither side of websocket transport is listening")]
+    WebSocketNoListener,
+
+    #[error("WebSocket transport {0} is not waiting for connection")]
+    WebSocketTransportNotFound(String),
+
+    #[error("First frame of websocket does not match the handshake of transport")]
+    WebSocketHelloMismatch,
+
+    #[error("WebSocket peer did not send its first frame in time")]
+    WebSocketHelloTimeout,
+
     #[cfg(not(feature = "wasm"))]
     #[error("RTC peer_connection add ice candidate error")]
     RTCPeerConnectionAddIceCandidateError(#[source] webrtc::Error),
@@ -273,6 +308,87 @@ pub enum Error {
 
     #[error("Failed to get dht from a sync lock")]
     DHTSyncLockError,
+
+    #[error("Only the leaving node itself can announce its leave")]
+    InvalidLeaveNotification,
+
+    #[error("Signed record is invalid or not signed by its writer")]
+    InvalidSignedRecord,
+
+    #[error("Sequence of signed record is too low, got {0}, current {1}")]
+    SignedRecordSeqTooLow(u64, u64),
+
+    #[error("Removal of virtual node is not signed by its owner")]
+    InvalidVNodeRemoval,
+
+    #[error("Replica is not pushed by the owner of virtual node")]
+    InvalidReplica,
+
+    #[error("Invalid PeerRing config: {0}")]
+    InvalidPeerRingConfig(String),
+
+    #[error("File manifest is invalid or not signed by its owner")]
+    InvalidFileManifest,
+
+    #[error("Chunk {0} of file does not match its manifest")]
+    InvalidFileChunk(usize),
+
+    #[error("Virtual node {0:?} not found")]
+    VNodeNotFound(crate::dht::Did),
+
+    #[error("Fragment of message is malformed")]
+    InvalidFragment,
+
+    #[error("Too many fragmented messages or bytes are being reassembled")]
+    TooManyPendingFragments,
+
+    #[error("Message of {0} bytes is too large to be sent")]
+    MessageTooLarge(usize),
+
+    #[error("Send buffer of transport is full, {0} bytes are queued")]
+    SendBufferFull(usize),
+
+    #[error("Send buffer of data channel {1} to {0:?} is full")]
+    SendBufferFullTo(web3::types::Address, String),
+
+    #[error("Data channel {0} is not opened on transport")]
+    DataChannelNotFound(String),
+
+    #[error("Message of {1} bytes needs fragments, which unreliable data channel {0} can't carry")]
+    MessageTooLargeForChannel(String, usize),
+
+    #[error("RPC call is not answered in time")]
+    RpcTimeout,
+
+    #[error("RPC call failed: {0}")]
+    RpcCallFailed(String),
+
+    #[error("Iterative lookup failed, no node answered in time")]
+    IterativeLookupFailed,
+
+    #[error("SubRing admin action is not signed by the admin of subring")]
+    InvalidSubRingAdminAction,
+
+    #[error("SubRing not found")]
+    SubRingNotFound,
+
+    #[error("Sequence of subring admin action is too low, got {0}, current {1}")]
+    SubRingAdminSeqTooLow(u64, u64),
+
+    #[error("Node {0:?} is banned from subring")]
+    SubRingMemberBanned(crate::dht::Did),
+
+    #[error("Relayed messages can only be acknowledged by their destination")]
+    InvalidRelayMessageAck,
+
+    #[error("Peer is from network {0}, but local network is {1}")]
+    NetworkMismatch(String, String),
+
+    #[error("Pre-shared key of peer does not match local network")]
+    InvalidNetworkPsk,
+
+    #[error("Handshake of peer does not answer the challenge of local offer")]
+    HandshakeChallengeMismatch,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -3,7 +3,8 @@ use std::str::FromStr;
 use async_trait::async_trait;
 
 use crate::dht::Chord;
-use crate::dht::ChordStorage;
+use crate::dht::ChordStabilize;
+use crate::dht::Did;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
 use crate::err::Error;
@@ -15,13 +16,15 @@ use crate::message::types::FindSuccessorReport;
 use crate::message::types::FindSuccessorSend;
 use crate::message::types::JoinDHT;
 use crate::message::types::Message;
-use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::types::NotifyLeave;
+use crate::message::types::VirtualIdentities;
 use crate::message::FindSuccessorThen;
 use crate::message::HandleMsg;
 use crate::message::LeaveDHT;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
 use crate::message::PayloadSender;
+use crate::message::TChordStorage;
 use crate::prelude::RTCSdpType;
 use crate::swarm::TransportManager;
 use crate::types::ice_transport::IceTrickleScheme;
@@ -36,33 +39,94 @@ impl HandleMsg<LeaveDHT> for MessageHandler {
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
-impl HandleMsg<JoinDHT> for MessageHandler {
-    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &JoinDHT) -> Result<()> {
-        // here is two situation.
-        // finger table just have no other node(beside next), it will be a `create` op
-        // otherwise, it will be a `send` op
-        match self.dht.join(msg.id)? {
-            PeerRingAction::None => Ok(()),
-            PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindSuccessor(id)) => {
-                // if there is only two nodes A, B, it may cause recursion
-                // A.successor == B
-                // B.successor == A
-                // A.find_successor(B)
-                if next != ctx.addr.into() {
-                    self.send_direct_message(
-                        Message::FindSuccessorSend(FindSuccessorSend {
-                            id,
-                            then: FindSuccessorThen::Connect,
-                        }),
-                        next,
-                    )
+impl HandleMsg<NotifyLeave> for MessageHandler {
+    // neighbour is leaving gracefully, splice the ring around it
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &NotifyLeave) -> Result<()> {
+        // a virtual identity leaves with its hosting node, which signs the notification
+        let signer: Did = ctx.addr.into();
+        if msg.id != signer && !msg.id.is_virtual_of(&signer) {
+            return Err(Error::InvalidLeaveNotification);
+        }
+        // VNodes of a partial notification are taken over once the leaving node is
+        // out of the ring, and its transport is kept for the following notifications
+        if msg.partial {
+            self.dht.remove(msg.id)?;
+        } else {
+            self.disconnect(msg.id.into()).await?;
+        }
+        // predecessor of the leaving node takes over its successor
+        if let Some(successor) = msg.successor {
+            if successor != self.dht.id {
+                if self
+                    .swarm
+                    .get_and_check_transport(&successor.into())
                     .await
+                    .is_some()
+                {
+                    self.dht.lock_successor()?.update(successor);
                 } else {
-                    Ok(())
+                    self.connect(&successor.into()).await?;
                 }
             }
-            _ => unreachable!(),
         }
+        // successor of the leaving node takes over its predecessor
+        if let Some(predecessor) = msg.predecessor {
+            if predecessor != self.dht.id {
+                self.dht.notify(predecessor)?;
+            }
+        }
+        // the ring is spliced, take over VNodes owned by the leaving node
+        for vnode in msg.data.iter().cloned() {
+            if let Err(e) = self.store(vnode).await {
+                log::warn!("failed to take over vnode from {:?}: {:?}", msg.id, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<JoinDHT> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &JoinDHT) -> Result<()> {
+        // virtual identities hosted here only join the new peer
+        let is_physical = self.dht.id == self.swarm.address().into();
+        // deliver messages stored for the node while it was offline
+        if is_physical {
+            if let Err(e) = self.deliver_relay_messages(msg.id).await {
+                log::warn!(
+                    "failed to deliver relayed messages to {:?}: {:?}",
+                    msg.id,
+                    e
+                );
+            }
+        }
+        self.join_dht(ctx, msg.id).await?;
+        // tell the new peer which virtual identities are hosted here
+        let ids = self.virtual_identities();
+        if is_physical && !ids.is_empty() {
+            self.send_direct_message(
+                Message::VirtualIdentities(VirtualIdentities { ids }),
+                msg.id,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<VirtualIdentities> for MessageHandler {
+    /// Aliases are only learned here, from the announcement signed by the hosting node.
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &VirtualIdentities) -> Result<()> {
+        for id in msg.ids.iter() {
+            self.swarm.register_alias(&(*id).into(), &ctx.addr)?;
+        }
+        for id in msg.ids.iter() {
+            self.join_dht(ctx, *id).await?;
+        }
+        Ok(())
     }
 }
 
@@ -96,10 +160,9 @@ impl HandleMsg<ConnectNodeSend> for MessageHandler {
             relay.relay(self.dht.id, None)?;
             match self.swarm.get_and_check_transport(&relay.sender()).await {
                 None => {
-                    let trans = self.swarm.new_transport().await?;
-                    trans
-                        .register_remote_info(msg.handshake_info.to_owned().into())
-                        .await?;
+                    let offer = msg.handshake_info.to_owned().into();
+                    let trans = self.swarm.new_transport_for(&offer).await?;
+                    trans.register_remote_info(offer).await?;
                     let handshake_info = trans
                         .get_handshake_info(self.swarm.session_manager(), RTCSdpType::Answer)
                         .await?
@@ -210,33 +273,57 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
         if relay.next_hop.is_some() {
             return self.transpond_payload(ctx, relay).await;
         }
+        self.find_successor_then(msg.id, msg.then).await
+    }
+}
+
+impl MessageHandler {
+    /// Join node `id` into local ring, and ask it to find successor of local node.
+    pub(crate) async fn join_dht(&self, ctx: &MessagePayload<Message>, id: Did) -> Result<()> {
+        // here is two situation.
+        // finger table just have no other node(beside next), it will be a `create` op
+        // otherwise, it will be a `send` op
+        match self.dht.join(id)? {
+            PeerRingAction::None => Ok(()),
+            PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindSuccessor(id)) => {
+                // if there is only two nodes A, B, it may cause recursion
+                // A.successor == B
+                // B.successor == A
+                // A.find_successor(B)
+                if next != ctx.addr.into() {
+                    self.send_direct_message(
+                        Message::FindSuccessorSend(FindSuccessorSend {
+                            id,
+                            then: FindSuccessorThen::Connect,
+                        }),
+                        next,
+                    )
+                    .await
+                } else {
+                    Ok(())
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
 
-        match msg.then {
-            FindSuccessorThen::FixFingerTable => self.dht.lock_finger()?.set_fix(msg.id),
+    /// Deal with the found successor `id`, no matter which lookup mode found it.
+    pub(crate) async fn find_successor_then(&self, id: Did, then: FindSuccessorThen) -> Result<()> {
+        match then {
+            FindSuccessorThen::FixFingerTable => self.dht.lock_finger()?.set_fix(id),
             FindSuccessorThen::Connect => {
-                if self.swarm.get_and_check_transport(&msg.id).await.is_none()
-                    && msg.id != self.swarm.address().into()
+                if self.swarm.get_and_check_transport(&id).await.is_none()
+                    && !self.swarm.is_local(&id.into())
                 {
-                    self.connect(&msg.id.into()).await?;
+                    self.connect(&id.into()).await?;
                 }
             }
             FindSuccessorThen::SyncStorage => {
-                self.dht.lock_successor()?.update(msg.id);
-                if let Ok(PeerRingAction::RemoteAction(
-                    next,
-                    PeerRingRemoteAction::SyncVNodeWithSuccessor(data),
-                )) = self.dht.sync_with_successor(msg.id).await
-                {
-                    self.send_direct_message(
-                        Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data }),
-                        next,
-                    )
-                    .await?
-                }
+                self.dht.lock_successor()?.update(id);
+                self.sync_vnodes_with_successor(id).await?
             }
             _ => {}
         }
-
         Ok(())
     }
 }
@@ -261,6 +348,7 @@ pub mod tests {
     use crate::swarm::Swarm;
     use crate::swarm::TransportManager;
     use crate::types::ice_transport::IceTransport;
+    use crate::types::message::MessageListener;
 
     // ndoe1.key < node2.key < node3.key
     //
@@ -990,6 +1078,43 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_virtual_identities_are_announced() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+        let (did1, dht1, swarm1, node1, _path1) = prepare_node(&key1).await;
+        let (did2, dht2, swarm2, node2, _path2) = prepare_node(&key2).await;
+
+        let v1 = node2.add_virtual_identity(1).await?;
+        let v2 = node2.add_virtual_identity(2).await?;
+        assert_eq!(v1, did2.virtual_id(1));
+        assert_eq!(node2.virtual_identities(), vec![v1, v2]);
+        assert!(swarm2.is_local(&v1.into()));
+        assert!(dht2.lock_finger()?.contains(&Some(v1)));
+        assert!(node2.add_virtual_identity(0).await.is_err());
+
+        manually_establish_connection(&swarm1, &swarm2).await?;
+        let node1 = Arc::new(node1);
+        let node2 = Arc::new(node2);
+        let listener1 = tokio::spawn(node1.clone().listen());
+        let listener2 = tokio::spawn(node2.clone().listen());
+        sleep(Duration::from_secs(3)).await;
+
+        // node1 reaches virtual identities through transport of node2
+        for v in [v1, v2] {
+            assert_eq!(swarm1.physical_address(&v.into()), did2.into());
+            assert!(swarm1.get_transport(&v.into()).is_some());
+            assert!(dht1.lock_finger()?.contains(&Some(v)));
+        }
+        // virtual rings of node2 learn node1
+        let ring = node2.virtual_ring(v1).unwrap();
+        assert!(ring.lock_finger()?.contains(&Some(did1)));
+
+        listener1.abort();
+        listener2.abort();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_already_connect_fixture() -> Result<()> {
         // NodeA-NodeB-NodeC
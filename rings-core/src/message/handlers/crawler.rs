@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+use crate::dht::PeerRingTopology;
+use crate::err::Result;
+use crate::message::types::Message;
+use crate::message::types::QueryTopologyReport;
+use crate::message::types::QueryTopologySend;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::swarm::TransportManager;
+
+/// Number of nodes queried at the same time by crawler.
+pub const CRAWL_PARALLELISM: usize = 8;
+/// Timeout of a batch of topology queries, in milliseconds.
+pub const CRAWL_QUERY_TIMEOUT_MS: u64 = 3000;
+
+/// Network map built by crawler.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RingMap {
+    /// Routing state of each reached node, ordered by did
+    pub nodes: Vec<PeerRingTopology>,
+    /// Nodes reported as neighbours by others, but not answering
+    pub unreachable: Vec<Did>,
+}
+
+impl RingMap {
+    /// Export as Graphviz DOT.
+    /// Successor edges are solid, finger edges are dashed, unreachable nodes are red.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rings {\n");
+        for node in self.nodes.iter() {
+            writeln!(dot, "  \"{}\";", node.did.to_string()).ok();
+        }
+        for did in self.unreachable.iter() {
+            writeln!(dot, "  \"{}\" [color=red];", did.to_string()).ok();
+        }
+        for node in self.nodes.iter() {
+            let from = node.did.to_string();
+            for did in node.successors.iter() {
+                writeln!(dot, "  \"{}\" -> \"{}\";", from, did.to_string()).ok();
+            }
+            let fingers: BTreeSet<Did> = node
+                .fingers
+                .iter()
+                .map(|(_, did)| *did)
+                .filter(|did| !node.successors.contains(did))
+                .collect();
+            for did in fingers {
+                writeln!(
+                    dot,
+                    "  \"{}\" -> \"{}\" [style=dashed];",
+                    from,
+                    did.to_string()
+                )
+                .ok();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl MessageHandler {
+    /// Walk the ring from local node by following predecessors, successor lists and finger
+    /// tables of reached nodes, until no new node is found or `max_nodes` nodes are reached.
+    /// A node without a connection is asked via the node which reported it.
+    /// It waits for answers, see module `handlers::pending`.
+    pub async fn crawl(&self, max_nodes: usize) -> Result<RingMap> {
+        let local = self.dht.topology().await?;
+        let mut seen = HashSet::from([local.did]);
+        let mut queue: VecDeque<(Did, Option<Did>)> = VecDeque::new();
+        for n in local.neighbours() {
+            if seen.insert(n) {
+                queue.push_back((n, None));
+            }
+        }
+        let mut nodes = BTreeMap::from([(local.did, local)]);
+        let mut unreachable = BTreeSet::new();
+
+        while !queue.is_empty() && nodes.len() < max_nodes {
+            let n = CRAWL_PARALLELISM
+                .min(queue.len())
+                .min(max_nodes - nodes.len());
+            let batch = queue.drain(..n).collect::<Vec<_>>();
+            let mut answers = self.query_topologies(&batch).await;
+            for (did, _) in batch {
+                match answers.remove(&did) {
+                    Some(topology) => {
+                        for neighbour in topology.neighbours() {
+                            if seen.insert(neighbour) {
+                                queue.push_back((neighbour, Some(did)));
+                            }
+                        }
+                        nodes.insert(did, topology);
+                    }
+                    None => {
+                        unreachable.insert(did);
+                    }
+                }
+            }
+        }
+        Ok(RingMap {
+            nodes: nodes.into_values().collect(),
+            unreachable: unreachable.into_iter().collect(),
+        })
+    }
+
+    /// Ask a batch of nodes for their topology, return answers received before timeout.
+    async fn query_topologies(
+        &self,
+        batch: &[(Did, Option<Did>)],
+    ) -> HashMap<Did, PeerRingTopology> {
+        let mut pending = self.topology_queries.batch();
+        for (node, via) in batch.iter() {
+            let next_hop = match via {
+                Some(via) if self.swarm.get_transport(node).is_none() => *via,
+                _ => *node,
+            };
+            let request_id = pending.add(*node);
+            let payload = match MessagePayload::new_send(
+                Message::QueryTopologySend(QueryTopologySend { request_id }),
+                self.swarm.session_manager(),
+                next_hop,
+                *node,
+            ) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::warn!("failed to build topology query for {:?}: {:?}", node, e);
+                    pending.cancel(&request_id);
+                    continue;
+                }
+            };
+            if let Err(e) = self.send_payload(payload).await {
+                log::warn!("failed to send topology query to {:?}: {:?}", node, e);
+                pending.cancel(&request_id);
+            }
+        }
+        pending
+            .all(CRAWL_QUERY_TIMEOUT_MS)
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<QueryTopologySend> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &QueryTopologySend) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        // self is only a bridge to the node which is asked
+        if relay.destination != self.dht.id {
+            relay.relay(self.dht.id, Some(relay.destination))?;
+            return self.transpond_payload(ctx, relay).await;
+        }
+
+        let topology = self.dht.topology().await?;
+        relay.relay(self.dht.id, None)?;
+        self.send_report_message(
+            Message::QueryTopologyReport(QueryTopologyReport {
+                topology,
+                request_id: msg.request_id,
+            }),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<QueryTopologyReport> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &QueryTopologyReport) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.id, None)?;
+        if relay.next_hop.is_some() {
+            return self.transpond_payload(ctx, relay).await;
+        }
+
+        // only accept the topology of the node which is asked, reported by itself
+        let signer = ctx.origin_signer();
+        if self.topology_queries.requestee(&msg.request_id, signer) == Some(msg.topology.did) {
+            self.topology_queries
+                .answer(&msg.request_id, signer, msg.topology.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+
+    #[tokio::test]
+    async fn test_crawl_lonely_node() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, _swarm, node, path) = prepare_node(&key).await;
+
+        let map = node.crawl(16).await?;
+        assert_eq!(map.nodes.len(), 1);
+        assert_eq!(map.nodes[0].did, did);
+        assert!(map.unreachable.is_empty());
+        assert!(node.topology_queries.is_empty());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[test]
+    fn test_ring_map_to_dot() {
+        let dids = gen_ordered_dids(3);
+        let map = RingMap {
+            nodes: vec![PeerRingTopology {
+                did: dids[0],
+                predecessor: Some(dids[2]),
+                successors: vec![dids[1]],
+                fingers: vec![(0, dids[1]), (1, dids[2])],
+                cache_size: 0,
+                cache_stats: Default::default(),
+                storage_count: 0,
+            }],
+            unreachable: vec![dids[2]],
+        };
+        let (a, b, c) = (
+            dids[0].to_string(),
+            dids[1].to_string(),
+            dids[2].to_string(),
+        );
+        let dot = map.to_dot();
+        assert!(dot.starts_with("digraph rings {\n"));
+        assert!(dot.contains(&format!("\"{}\" [color=red];", c)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", a, b)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [style=dashed];", a, c)));
+        // finger which is also a successor is drawn once
+        assert!(!dot.contains(&format!("\"{}\" -> \"{}\" [style=dashed];", a, b)));
+        assert_eq!(map.nodes[0].neighbours(), vec![dids[2], dids[1]]);
+    }
+}
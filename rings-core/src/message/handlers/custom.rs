@@ -13,6 +13,28 @@ use crate::message::MessagePayload;
 use crate::message::PayloadSender;
 use crate::swarm::TransportManager;
 
+impl MessageHandler {
+    /// Forward a payload to its destination, directly if connected, or to the successor of it.
+    pub(crate) async fn transpond_to_destination(
+        &self,
+        ctx: &MessagePayload<Message>,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        if self.swarm.get_transport(&relay.destination).is_some() {
+            relay.relay(self.dht.id, Some(relay.destination))?;
+        } else {
+            let next_node = match self.dht.find_successor(relay.destination)? {
+                PeerRingAction::Some(node) => Some(node),
+                PeerRingAction::RemoteAction(node, _) => Some(node),
+                _ => None,
+            }
+            .ok_or(Error::MessageHandlerMissNextNode)?;
+            relay.relay(self.dht.id, Some(next_node))?;
+        }
+        self.transpond_payload(ctx, relay).await
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<MaybeEncrypted<CustomMessage>> for MessageHandler {
@@ -21,22 +43,8 @@ impl HandleMsg<MaybeEncrypted<CustomMessage>> for MessageHandler {
         ctx: &MessagePayload<Message>,
         _: &MaybeEncrypted<CustomMessage>,
     ) -> Result<()> {
-        let mut relay = ctx.relay.clone();
-
-        if self.dht.id != relay.destination {
-            if self.swarm.get_transport(&relay.destination).is_some() {
-                relay.relay(self.dht.id, Some(relay.destination))?;
-                return self.transpond_payload(ctx, relay).await;
-            } else {
-                let next_node = match self.dht.find_successor(relay.destination)? {
-                    PeerRingAction::Some(node) => Some(node),
-                    PeerRingAction::RemoteAction(node, _) => Some(node),
-                    _ => None,
-                }
-                .ok_or(Error::MessageHandlerMissNextNode)?;
-                relay.relay(self.dht.id, Some(next_node))?;
-                return self.transpond_payload(ctx, relay).await;
-            }
+        if self.dht.id != ctx.relay.destination {
+            return self.transpond_to_destination(ctx).await;
         }
 
         Ok(())
@@ -0,0 +1,116 @@
+use futures::stream;
+use futures::StreamExt;
+use futures::TryStreamExt;
+
+use crate::dht::file::FileManifest;
+use crate::dht::file::FILE_CHUNK_SIZE;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::ChordStorage;
+use crate::dht::Did;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::types::LookupStep;
+use crate::message::types::LookupTarget;
+use crate::message::MessageHandler;
+use crate::message::TChordStorage;
+
+/// Max number of chunks stored or fetched at the same time.
+pub const FILE_TRANSFER_PARALLELISM: usize = 8;
+
+impl MessageHandler {
+    /// Split `data` into chunks, store them with a manifest signed by the session of
+    /// local node on DHT, return address of the manifest, which identifies the file.
+    pub async fn store_file(&self, name: &str, data: &[u8]) -> Result<Did> {
+        let chunks = FileManifest::split(data, FILE_CHUNK_SIZE)?;
+        let manifest = FileManifest::new(
+            self.swarm.session_manager(),
+            name,
+            data.len() as u64,
+            FILE_CHUNK_SIZE as u64,
+            chunks.iter().map(|c| c.did()).collect(),
+        )?;
+        stream::iter(chunks)
+            .map(|c| self.store_with_backpressure(c))
+            .buffer_unordered(FILE_TRANSFER_PARALLELISM)
+            .try_collect::<Vec<_>>()
+            .await?;
+        let vnode: VirtualNode = manifest.try_into()?;
+        let did = vnode.did();
+        self.store_with_backpressure(vnode).await?;
+        Ok(did)
+    }
+
+    /// Store a vnode, if the send buffer to next hop is full, wait for it to drain and retry.
+    async fn store_with_backpressure(&self, vnode: VirtualNode) -> Result<()> {
+        loop {
+            match self.store(vnode.clone()).await {
+                Err(Error::SendBufferFullTo(address, label)) => {
+                    self.swarm.wait_for_send_buffer(&address, &label).await?
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Fetch manifest of file at `id`, then download its chunks in parallel.
+    /// Each chunk is verified against the manifest.
+    /// It waits for answers, see module `handlers::pending`.
+    pub async fn fetch_file(&self, id: Did) -> Result<(FileManifest, Vec<u8>)> {
+        let manifest = FileManifest::from_vnode(&self.fetch_vnode(id).await?)?;
+        let m = &manifest;
+        let chunks = stream::iter(manifest.chunks.iter().enumerate())
+            .map(|(i, did)| async move { m.verify_chunk(i, &self.fetch_vnode(*did).await?) })
+            .buffered(FILE_TRANSFER_PARALLELISM)
+            .try_collect::<Vec<_>>()
+            .await?;
+        let data = chunks.concat();
+        if data.len() as u64 != manifest.size {
+            return Err(Error::InvalidFileManifest);
+        }
+        Ok((manifest, data))
+    }
+
+    /// Get a vnode from cache or DHT, and wait for it.
+    async fn fetch_vnode(&self, id: Did) -> Result<VirtualNode> {
+        if let Some(v) = self.dht.fetch_cache(&id) {
+            return Ok(v);
+        }
+        match self.lookup_iteratively(id, LookupTarget::VNode).await? {
+            LookupStep::VNode(v) => Ok(v),
+            _ => Err(Error::VNodeNotFound(id)),
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+
+    #[tokio::test]
+    async fn test_store_and_fetch_file_on_lonely_node() -> Result<()> {
+        let key = SecretKey::random();
+        let (_did, _dht, _swarm, node, path) = prepare_node(&key).await;
+
+        let data = (0..(FILE_CHUNK_SIZE * 2 + 100))
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let id = node.store_file("data.bin", &data).await?;
+        let (manifest, fetched) = node.fetch_file(id).await?;
+        assert_eq!(manifest.name, "data.bin");
+        assert_eq!(manifest.owner, key.address());
+        assert_eq!(manifest.chunks.len(), 3);
+        assert_eq!(fetched, data);
+
+        // a chunk is not a manifest
+        assert!(node.fetch_file(manifest.chunks[0]).await.is_err());
+        assert!(matches!(
+            node.fetch_file(SecretKey::random().address().into()).await,
+            Err(Error::VNodeNotFound(_))
+        ));
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+
+use crate::dht::Chord;
+use crate::dht::ChordStorage;
+use crate::dht::Did;
+use crate::dht::PeerRingAction;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::types::FindSuccessorSend;
+use crate::message::types::LookupStep;
+use crate::message::types::LookupStepReport;
+use crate::message::types::LookupStepSend;
+use crate::message::types::LookupTarget;
+use crate::message::types::Message;
+use crate::message::FindSuccessorThen;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::swarm::TransportManager;
+
+/// Number of alternatives asked at the same time in one step of iterative lookup.
+pub const ITERATIVE_LOOKUP_PARALLELISM: usize = 3;
+/// Timeout of one step of iterative lookup, in milliseconds.
+pub const ITERATIVE_LOOKUP_STEP_TIMEOUT_MS: u64 = 3000;
+/// Max steps of iterative lookup.
+pub const ITERATIVE_LOOKUP_MAX_STEPS: usize = 32;
+
+/// How a query travels on the ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupMode {
+    /// Each hop forwards the query to the next hop via `MessageRelay`.
+    Recursive,
+    /// The originator asks each hop for the closest preceding nodes,
+    /// then contacts the next hop itself.
+    Iterative,
+}
+
+impl MessageHandler {
+    /// Find successor of `id` with lookup `mode`, then deal with the found successor by `then`.
+    pub async fn find_successor(
+        &self,
+        id: Did,
+        then: FindSuccessorThen,
+        mode: LookupMode,
+    ) -> Result<()> {
+        match mode {
+            LookupMode::Recursive => match self.dht.find_successor(id)? {
+                PeerRingAction::Some(succ) => self.find_successor_then(succ, then).await,
+                PeerRingAction::RemoteAction(next, _) => {
+                    self.send_direct_message(
+                        Message::FindSuccessorSend(FindSuccessorSend { id, then }),
+                        next,
+                    )
+                    .await
+                }
+                act => Err(Error::PeerRingUnexpectedAction(act)),
+            },
+            LookupMode::Iterative => {
+                match self.lookup_iteratively(id, LookupTarget::Successor).await? {
+                    LookupStep::Successor(succ) => self.find_successor_then(succ, then).await,
+                    _ => Err(Error::IterativeLookupFailed),
+                }
+            }
+        }
+    }
+
+    /// Answer one step of iterative lookup with local finger table and storage.
+    async fn lookup_step(&self, id: Did, target: LookupTarget) -> Result<LookupStep> {
+        let action = match target {
+            LookupTarget::Successor => self.dht.find_successor(id)?,
+            LookupTarget::VNode => self.dht.lookup(&id).await?,
+        };
+        match action {
+            PeerRingAction::Some(succ) => Ok(LookupStep::Successor(succ)),
+            PeerRingAction::SomeVNode(v) => Ok(LookupStep::VNode(v)),
+            PeerRingAction::None => Ok(LookupStep::NotFound),
+            PeerRingAction::RemoteAction(..) => Ok(LookupStep::Next(
+                self.dht
+                    .closest_preceding_nodes(id, ITERATIVE_LOOKUP_PARALLELISM)?,
+            )),
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+
+    /// Lookup `id` iteratively.
+    /// In each step, up to `ITERATIVE_LOOKUP_PARALLELISM` candidates are asked at the same time,
+    /// and the first answer is taken. If none of them answers before timeout, the next batch
+    /// of candidates is asked. A candidate without a connection is reached via the node
+    /// which reported it.
+    /// It waits for answers, see module `handlers::pending`.
+    pub async fn lookup_iteratively(&self, id: Did, target: LookupTarget) -> Result<LookupStep> {
+        // candidates with the node which reported it
+        let mut candidates: Vec<(Did, Option<Did>)> = match self.lookup_step(id, target).await? {
+            LookupStep::Next(nodes) => nodes.into_iter().map(|n| (n, None)).collect(),
+            step => return Ok(step),
+        };
+        let mut queried = HashSet::from([self.dht.id]);
+
+        for _ in 0..ITERATIVE_LOOKUP_MAX_STEPS {
+            candidates.retain(|(n, _)| !queried.contains(n));
+            let mut answer = None;
+            while answer.is_none() && !candidates.is_empty() {
+                let batch = candidates
+                    .drain(..ITERATIVE_LOOKUP_PARALLELISM.min(candidates.len()))
+                    .collect::<Vec<_>>();
+                queried.extend(batch.iter().map(|(n, _)| *n));
+                answer = self.query_lookup_step(id, target, &batch).await;
+            }
+            match answer {
+                Some((from, LookupStep::Next(nodes))) => {
+                    // keep the remaining alternatives as fallback
+                    candidates = nodes
+                        .into_iter()
+                        .map(|n| (n, Some(from)))
+                        .chain(candidates)
+                        .collect();
+                }
+                Some((_, step)) => return Ok(step),
+                None => return Err(Error::IterativeLookupFailed),
+            }
+        }
+        Err(Error::IterativeLookupFailed)
+    }
+
+    /// Ask a batch of candidates for one step of lookup, return the first answer.
+    async fn query_lookup_step(
+        &self,
+        id: Did,
+        target: LookupTarget,
+        batch: &[(Did, Option<Did>)],
+    ) -> Option<(Did, LookupStep)> {
+        let mut pending = self.lookups.batch();
+        for (node, via) in batch.iter() {
+            let next_hop = match via {
+                Some(via) if self.swarm.get_transport(node).is_none() => *via,
+                _ => *node,
+            };
+            let request_id = pending.add(*node);
+            let payload = match MessagePayload::new_send(
+                Message::LookupStepSend(LookupStepSend {
+                    id,
+                    target,
+                    request_id,
+                }),
+                self.swarm.session_manager(),
+                next_hop,
+                *node,
+            ) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::warn!("failed to build lookup step for {:?}: {:?}", node, e);
+                    pending.cancel(&request_id);
+                    continue;
+                }
+            };
+            if let Err(e) = self.send_payload(payload).await {
+                log::warn!("failed to send lookup step to {:?}: {:?}", node, e);
+                pending.cancel(&request_id);
+            }
+        }
+        pending.first(ITERATIVE_LOOKUP_STEP_TIMEOUT_MS).await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<LookupStepSend> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &LookupStepSend) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        // self is only a bridge to the node which is asked
+        if relay.destination != self.dht.id {
+            relay.relay(self.dht.id, Some(relay.destination))?;
+            return self.transpond_payload(ctx, relay).await;
+        }
+
+        let step = self.lookup_step(msg.id, msg.target).await?;
+        relay.relay(self.dht.id, None)?;
+        self.send_report_message(
+            Message::LookupStepReport(LookupStepReport {
+                id: msg.id,
+                step,
+                request_id: msg.request_id,
+            }),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<LookupStepReport> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &LookupStepReport) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.id, None)?;
+        if relay.next_hop.is_some() {
+            return self.transpond_payload(ctx, relay).await;
+        }
+
+        self.lookups
+            .answer(&msg.request_id, ctx.origin_signer(), msg.step.clone());
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+
+    #[tokio::test]
+    async fn test_lookup_iteratively_on_lonely_node() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, _swarm, node, path) = prepare_node(&key).await;
+
+        // a lonely node answers all steps itself, without asking others
+        assert_eq!(
+            node.lookup_iteratively(did, LookupTarget::Successor)
+                .await?,
+            LookupStep::Successor(did)
+        );
+        assert_eq!(
+            node.lookup_iteratively(did, LookupTarget::VNode).await?,
+            LookupStep::NotFound
+        );
+        assert!(node.lookups.is_empty());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+}
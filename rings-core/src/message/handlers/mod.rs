@@ -2,18 +2,31 @@ use std::sync::Arc;
 
 use async_recursion::async_recursion;
 use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::future::Either;
 use futures::lock::Mutex;
 use web3::types::Address;
 
+use self::pending::PendingRequests;
+use self::rpc::RpcHandlerFn;
 use super::CustomMessage;
+use super::LookupStep;
 use super::MaybeEncrypted;
 use super::Message;
 use super::MessagePayload;
+use super::NotifyLeave;
 use super::OriginVerificationGen;
 use super::PayloadSender;
+use super::RpcResult;
+use super::VirtualIdentities;
+use crate::dht::vnode::VirtualNode;
 use crate::dht::Chord;
+use crate::dht::Did;
 use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
+use crate::dht::PeerRingRemoteAction;
+use crate::dht::PeerRingTopology;
+use crate::dht::MAX_VIRTUAL_IDS;
 use crate::err::Error;
 use crate::err::Result;
 use crate::prelude::RTCSdpType;
@@ -21,13 +34,28 @@ use crate::prelude::Transport;
 use crate::session::SessionManager;
 use crate::swarm::Swarm;
 use crate::swarm::TransportManager;
+use crate::transports::helper::CONTROL_CHANNEL;
+use crate::transports::helper::DEFAULT_CHANNEL;
 use crate::types::ice_transport::IceTransport;
 use crate::types::ice_transport::IceTrickleScheme;
+use crate::utils::sleep;
 
 /// Operator and Handler for Connection
 pub mod connection;
+/// Operator and Handler for ring crawler
+pub mod crawler;
 /// Operator and Handler for CustomMessage
 pub mod custom;
+/// Operator for chunked file storage
+pub mod file;
+/// Operator and Handler for iterative lookup
+pub mod lookup;
+/// Requests waiting for answers from remote nodes
+pub mod pending;
+/// Operator and Handler for topic publish/subscribe
+pub mod pubsub;
+/// Operator and Handler for request/response RPC
+pub mod rpc;
 /// Operator and handler for DHT stablization
 pub mod stabilization;
 /// Operator and Handler for Storage
@@ -35,6 +63,9 @@ pub mod storage;
 /// Operator and Handler for SubRing
 pub mod subring;
 
+/// How long leaving waits for data channels to drain before closing them, in milliseconds.
+pub const LEAVE_DRAIN_TIMEOUT_MS: u64 = 3 * 1000;
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 pub trait MessageCallback {
@@ -45,6 +76,15 @@ pub trait MessageCallback {
         msg: &MaybeEncrypted<CustomMessage>,
     );
     async fn builtin_message(&self, handler: &MessageHandler, ctx: &MessagePayload<Message>);
+    /// Called once for each publication received on a subscribed topic.
+    async fn topic_message(
+        &self,
+        _handler: &MessageHandler,
+        _ctx: &MessagePayload<Message>,
+        _topic: &str,
+        _data: &[u8],
+    ) {
+    }
 }
 
 #[cfg(not(feature = "wasm"))]
@@ -58,6 +98,19 @@ pub struct MessageHandler {
     dht: Arc<PeerRing>,
     swarm: Arc<Swarm>,
     callback: Arc<Mutex<Option<CallbackFn>>>,
+    /// pending steps of iterative lookups
+    lookups: Arc<PendingRequests<LookupStep>>,
+    /// pending topology queries of crawler
+    topology_queries: Arc<PendingRequests<PeerRingTopology>>,
+    /// subscribed topics with members fetched when subscribing or publishing,
+    /// indexed by did of the topic's subring
+    subscriptions: Arc<DashMap<Did, (String, Vec<Did>)>>,
+    /// rings of identities hosted by this node, including its own one
+    identities: Arc<DashMap<Did, Arc<PeerRing>>>,
+    /// pending RPC calls, indexed by correlation id
+    rpc_calls: Arc<PendingRequests<MaybeEncrypted<RpcResult>>>,
+    /// handlers of RPC methods, indexed by method
+    rpc_handlers: Arc<DashMap<String, RpcHandlerFn>>,
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -69,17 +122,29 @@ pub trait HandleMsg<T> {
 impl MessageHandler {
     pub fn new_with_callback(dht: Arc<PeerRing>, swarm: Arc<Swarm>, callback: CallbackFn) -> Self {
         Self {
-            dht,
+            dht: dht.clone(),
             swarm,
             callback: Arc::new(Mutex::new(Some(callback))),
+            lookups: Arc::new(PendingRequests::new()),
+            topology_queries: Arc::new(PendingRequests::new()),
+            subscriptions: Arc::new(DashMap::new()),
+            identities: Arc::new(DashMap::from_iter([(dht.id, dht.clone())])),
+            rpc_calls: Arc::new(PendingRequests::new()),
+            rpc_handlers: Arc::new(DashMap::new()),
         }
     }
 
     pub fn new(dht: Arc<PeerRing>, swarm: Arc<Swarm>) -> Self {
         Self {
-            dht,
+            dht: dht.clone(),
             swarm,
             callback: Arc::new(Mutex::new(None)),
+            lookups: Arc::new(PendingRequests::new()),
+            topology_queries: Arc::new(PendingRequests::new()),
+            subscriptions: Arc::new(DashMap::new()),
+            identities: Arc::new(DashMap::from_iter([(dht.id, dht.clone())])),
+            rpc_calls: Arc::new(PendingRequests::new()),
+            rpc_handlers: Arc::new(DashMap::new()),
         }
     }
 
@@ -88,16 +153,219 @@ impl MessageHandler {
         *cb = Some(f)
     }
 
+    /// Routing state and storage usage of local node.
+    pub async fn topology(&self) -> Result<PeerRingTopology> {
+        self.dht.topology().await
+    }
+
+    /// Estimated number of nodes in the ring, see `PeerRing::estimate_network_size`.
+    pub fn estimate_network_size(&self) -> Result<u64> {
+        self.dht.estimate_network_size()
+    }
+
+    /// Host the `index`th virtual identity of this node, and return its Did.
+    /// The identity gets its own position on the ring, sharing swarm and storage with this node.
+    /// It joins all other local identities and connected peers, then gets announced to peers.
+    pub async fn add_virtual_identity(&self, index: u8) -> Result<Did> {
+        let ring = Arc::new(self.dht.new_virtual(index)?);
+        let id = ring.id;
+        if self.identities.contains_key(&id) {
+            return Ok(id);
+        }
+        self.swarm
+            .register_alias(&id.into(), &self.swarm.address())?;
+        for local in self.identities.iter() {
+            local.join(id)?;
+            ring.join(*local.key())?;
+        }
+        for address in self.swarm.get_addresses() {
+            ring.join(address.into())?;
+            for alias in self.swarm.aliases_of(&address) {
+                ring.join(alias.into())?;
+            }
+        }
+        self.identities.insert(id, ring);
+        let msg = Message::VirtualIdentities(VirtualIdentities { ids: vec![id] });
+        for address in self.swarm.get_addresses() {
+            if let Err(e) = self.send_direct_message(msg.clone(), address.into()).await {
+                log::warn!("failed to announce {:?} to {:?}: {:?}", id, address, e);
+            }
+        }
+        Ok(id)
+    }
+
+    /// Virtual identities hosted by this node, without its own Did.
+    pub fn virtual_identities(&self) -> Vec<Did> {
+        let physical: Did = self.swarm.address().into();
+        (1..=MAX_VIRTUAL_IDS)
+            .map(|i| physical.virtual_id(i))
+            .filter(|id| self.identities.contains_key(id))
+            .collect()
+    }
+
+    /// Ring of a virtual identity hosted by this node.
+    pub fn virtual_ring(&self, id: Did) -> Option<Arc<PeerRing>> {
+        if id == self.swarm.address().into() {
+            return None;
+        }
+        self.identities.get(&id).map(|r| r.clone())
+    }
+
+    /// Handler acting as identity of `ring`.
+    fn identity_handler(&self, ring: Arc<PeerRing>) -> Self {
+        Self {
+            dht: ring,
+            ..self.clone()
+        }
+    }
+
+    /// Let every virtual identity hosted here learn about new peers.
+    async fn broadcast_to_identities(&self, payload: &MessagePayload<Message>) {
+        let rings = self
+            .identities
+            .iter()
+            .filter(|r| *r.key() != self.dht.id)
+            .map(|r| r.clone())
+            .collect::<Vec<_>>();
+        for ring in rings {
+            let handler = self.identity_handler(ring);
+            let result = match &payload.data {
+                Message::JoinDHT(ref msg) => handler.handle(payload, msg).await,
+                Message::VirtualIdentities(ref msg) => handler.handle(payload, msg).await,
+                _ => return,
+            };
+            if let Err(e) = result {
+                log::warn!("virtual identity failed to handle {}: {}", payload.data, e);
+            }
+        }
+    }
+
     // disconnect a node if a node is in DHT
     pub async fn disconnect(&self, address: Address) -> Result<()> {
         log::info!("disconnect {:?}", address);
-        self.dht.remove(address.into())?;
+        let mut dids: Vec<Did> = vec![address.into()];
+        dids.extend(self.swarm.aliases_of(&address).into_iter().map(Did::from));
+        for ring in self.identities.iter() {
+            for did in dids.iter() {
+                ring.remove(*did)?;
+            }
+        }
         if let Some((_address, trans)) = self.swarm.remove_transport(&address) {
             trans.close().await?
         }
         Ok(())
     }
 
+    /// Leave the DHT gracefully, along with virtual identities hosted here.
+    /// For each identity, predecessor and successor are told to splice the ring, and owned VNodes
+    /// are handed off to the predecessor along with the notification, so they are
+    /// stored after the ring is spliced. Transports get closed once their data channels
+    /// are drained, or `LEAVE_DRAIN_TIMEOUT_MS` passed.
+    pub async fn leave(&self) -> Result<()> {
+        let rings = self
+            .identities
+            .iter()
+            .map(|r| r.clone())
+            .collect::<Vec<_>>();
+        for (i, ring) in rings.iter().enumerate() {
+            let id = ring.id;
+            if let Err(e) = self.identity_handler(ring.clone()).notify_leave().await {
+                log::warn!(
+                    "{:?} failed to notify neighbours while leaving: {:?}",
+                    id,
+                    e
+                );
+            }
+            // storage is shared, the identities left here take over the range of leaving one
+            for other in rings[i + 1..].iter() {
+                other.remove(id)?;
+            }
+        }
+        for (address, trans) in self.swarm.get_transports() {
+            self.swarm.remove_transport(&address);
+            // the notifications are queued on these channels
+            let drained = futures::future::try_join(
+                trans.wait_for_buffered_amount_low(CONTROL_CHANNEL, 0),
+                trans.wait_for_buffered_amount_low(DEFAULT_CHANNEL, 0),
+            );
+            match futures::future::select(
+                Box::pin(drained),
+                Box::pin(sleep(LEAVE_DRAIN_TIMEOUT_MS)),
+            )
+            .await
+            {
+                Either::Left((Ok(_), _)) => {}
+                Either::Left((Err(e), _)) => {
+                    log::warn!("data channels to {:?} are not drained: {:?}", address, e)
+                }
+                Either::Right(_) => {
+                    log::warn!("data channels to {:?} are not drained in time", address)
+                }
+            }
+            if let Err(e) = trans.close().await {
+                log::warn!(
+                    "failed to close transport to {:?} while leaving: {:?}",
+                    address,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Tell neighbours of `self.dht` that it's leaving, handing off its VNodes.
+    /// Identities hosted here are leaving as well, so they are not notified.
+    async fn notify_leave(&self) -> Result<()> {
+        let acts = match self.dht.leave().await? {
+            PeerRingAction::None => vec![],
+            PeerRingAction::MultiActions(acts) => acts,
+            act => vec![act],
+        };
+        let mut notifications = vec![];
+        let mut handoff = vec![];
+        for act in acts {
+            match act {
+                PeerRingAction::RemoteAction(
+                    next,
+                    PeerRingRemoteAction::NotifyLeave(predecessor, successor),
+                ) => notifications.push((next, predecessor, successor)),
+                PeerRingAction::RemoteAction(next, PeerRingRemoteAction::HandoffVNode(data)) => {
+                    handoff.push((next, data))
+                }
+                act => return Err(Error::PeerRingUnexpectedAction(act)),
+            }
+        }
+        for (next, predecessor, successor) in notifications {
+            if self.identities.contains_key(&next) {
+                continue;
+            }
+            let data = handoff
+                .iter()
+                .filter(|(did, _)| *did == next)
+                .flat_map(|(_, data)| data.clone())
+                .collect();
+            // handed off VNodes are split to fit in messages, the last one is not partial
+            let mut batches = VirtualNode::batch(data);
+            if batches.is_empty() {
+                batches.push(vec![]);
+            }
+            let count = batches.len();
+            for (i, data) in batches.into_iter().enumerate() {
+                let msg = Message::NotifyLeave(NotifyLeave {
+                    id: self.dht.id,
+                    predecessor,
+                    successor,
+                    data,
+                    partial: i + 1 < count,
+                });
+                if let Err(e) = self.send_direct_message(msg, next).await {
+                    log::warn!("failed to notify {:?} while leaving: {:?}", next, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn connect(&self, address: &Address) -> Result<Arc<Transport>> {
         if let Some(t) = self.swarm.get_and_check_transport(address).await {
             return Ok(t);
@@ -127,6 +395,25 @@ impl MessageHandler {
         Ok(transport)
     }
 
+    /// Send `msg` to `destination` through `next_hop`, over data channel `label`
+    /// which is opened to `next_hop` by [Swarm::open_channel].
+    pub async fn send_message_on(
+        &self,
+        label: &str,
+        msg: Message,
+        next_hop: Did,
+        destination: Did,
+    ) -> Result<()> {
+        let payload = MessagePayload::new_send_from(
+            msg,
+            self.swarm.session_manager(),
+            self.dht.id,
+            next_hop,
+            destination,
+        )?;
+        self.swarm.send_payload_on(label, payload).await
+    }
+
     async fn invoke_callback(&self, payload: &MessagePayload<Message>) -> Result<()> {
         let mut callback = self.callback.lock().await;
         if let Some(ref mut cb) = *callback {
@@ -156,20 +443,51 @@ impl MessageHandler {
             println!("{} got msg {}", self.swarm.address(), &payload.data);
         }
         log::trace!("NEW MESSAGE: {}", &payload.data);
+        // message to another identity hosted here is handled on its ring
+        if let Some(next_hop) = payload.relay.next_hop {
+            if next_hop != self.dht.id {
+                if let Some(ring) = self.identities.get(&next_hop).map(|r| r.clone()) {
+                    return self.identity_handler(ring).handle_payload(payload).await;
+                }
+            }
+        }
+        if self.dht.id == self.swarm.address().into() {
+            self.broadcast_to_identities(payload).await;
+        }
         match &payload.data {
             Message::JoinDHT(ref msg) => self.handle(payload, msg).await,
             Message::LeaveDHT(ref msg) => self.handle(payload, msg).await,
+            Message::VirtualIdentities(ref msg) => self.handle(payload, msg).await,
+            Message::NotifyLeave(ref msg) => self.handle(payload, msg).await,
             Message::ConnectNodeSend(ref msg) => self.handle(payload, msg).await,
             Message::ConnectNodeReport(ref msg) => self.handle(payload, msg).await,
             Message::AlreadyConnected(ref msg) => self.handle(payload, msg).await,
             Message::FindSuccessorSend(ref msg) => self.handle(payload, msg).await,
             Message::FindSuccessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::LookupStepSend(ref msg) => self.handle(payload, msg).await,
+            Message::LookupStepReport(ref msg) => self.handle(payload, msg).await,
+            Message::QueryTopologySend(ref msg) => self.handle(payload, msg).await,
+            Message::QueryTopologyReport(ref msg) => self.handle(payload, msg).await,
             Message::NotifyPredecessorSend(ref msg) => self.handle(payload, msg).await,
             Message::NotifyPredecessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::Ping(ref msg) => self.handle(payload, msg).await,
+            Message::Pong(ref msg) => self.handle(payload, msg).await,
             Message::SearchVNode(ref msg) => self.handle(payload, msg).await,
             Message::FoundVNode(ref msg) => self.handle(payload, msg).await,
             Message::StoreVNode(ref msg) => self.handle(payload, msg).await,
+            Message::StoreReplica(ref msg) => self.handle(payload, msg).await,
+            Message::RemoveVNode(ref msg) => self.handle(payload, msg).await,
+            Message::RemoveReplica(ref msg) => self.handle(payload, msg).await,
+            Message::SyncVNodeWithSuccessor(ref msg) => self.handle(payload, msg).await,
+            Message::JoinSubRing(ref msg) => self.handle(payload, msg).await,
+            Message::LeaveSubRing(ref msg) => self.handle(payload, msg).await,
+            Message::AdminSubRing(ref msg) => self.handle(payload, msg).await,
+            Message::PublishTopic(ref msg) => self.handle(payload, msg).await,
+            Message::RelayMessageDelivery(ref msg) => self.handle(payload, msg).await,
+            Message::RelayMessageAck(ref msg) => self.handle(payload, msg).await,
             Message::CustomMessage(ref msg) => self.handle(payload, msg).await,
+            Message::RpcRequest(ref msg) => self.handle(payload, msg).await,
+            Message::RpcResponse(ref msg) => self.handle(payload, msg).await,
             Message::MultiCall(ref msg) => {
                 for message in msg.messages.iter().cloned() {
                     let payload = MessagePayload::new(
@@ -182,10 +500,6 @@ impl MessageHandler {
                 }
                 Ok(())
             }
-            x => Err(Error::MessageHandlerUnsupportMessageType(format!(
-                "{:?}",
-                x
-            ))),
         }?;
         if let Err(e) = self.invoke_callback(payload).await {
             log::warn!("invoke callback error: {}", e);
@@ -213,6 +527,22 @@ impl MessageHandler {
             None
         }
     }
+
+    /// Handle the messages queued for now, without waiting for more.
+    /// Return the number of messages handled.
+    pub async fn handle_queued_messages(&self) -> usize {
+        let payloads = self.swarm.poll_queued_messages().await;
+        for payload in payloads.iter() {
+            if !payload.verify() {
+                log::error!("Cannot verify msg or it's expired: {:?}", payload);
+                continue;
+            }
+            if let Err(e) = self.handle_payload(payload).await {
+                log::error!("Error in handle_message: {}", e);
+            }
+        }
+        payloads.len()
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -222,6 +552,10 @@ impl PayloadSender<Message> for MessageHandler {
         self.swarm.session_manager()
     }
 
+    fn origin(&self) -> Result<Did> {
+        Ok(self.dht.id)
+    }
+
     async fn do_send_payload(
         &self,
         address: &Address,
@@ -237,21 +571,42 @@ mod listener {
 
     use async_trait::async_trait;
     use futures::pin_mut;
+    use futures::select;
+    use futures::stream::FuturesUnordered;
     use futures::stream::StreamExt;
 
     use super::MessageHandler;
+    use crate::message::Message;
     use crate::types::message::MessageListener;
 
     #[async_trait]
     impl MessageListener for MessageHandler {
         async fn listen(self: Arc<Self>) {
-            let payloads = self.swarm.iter_messages().await;
+            let payloads = self.swarm.iter_messages().await.fuse();
             pin_mut!(payloads);
-            while let Some(payload) = payloads.next().await {
+            // RPC requests are answered aside, so an RpcHandler can wait for other messages
+            let mut answering = FuturesUnordered::new();
+            loop {
+                let payload = select! {
+                    payload = payloads.next() => match payload {
+                        Some(payload) => payload,
+                        None => break,
+                    },
+                    _ = answering.select_next_some() => continue,
+                };
                 if !payload.verify() {
                     log::error!("Cannot verify msg or it's expired: {:?}", payload);
                     continue;
                 }
+                if matches!(payload.data, Message::RpcRequest(_)) {
+                    let handler = self.clone();
+                    answering.push(async move {
+                        if let Err(e) = handler.handle_payload(&payload).await {
+                            log::error!("Error in handle_message: {}", e);
+                        }
+                    });
+                    continue;
+                }
                 if let Err(e) = self.handle_payload(&payload).await {
                     log::error!("Error in handle_message: {}", e);
                     continue;
@@ -298,12 +653,12 @@ pub mod tests {
     use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
 
     use super::*;
+    use crate::dht::tests::new_test_ring;
     use crate::dht::Did;
     use crate::dht::PeerRing;
     use crate::ecc::SecretKey;
     use crate::message::MessageHandler;
     use crate::session::SessionManager;
-    use crate::storage::PersistenceStorage;
     use crate::swarm::Swarm;
     use crate::swarm::TransportManager;
     use crate::types::ice_transport::IceTrickleScheme;
@@ -315,15 +670,8 @@ pub mod tests {
         let stun = "stun://stun.l.google.com:19302";
 
         let did = key.address().into();
-        let path = PersistenceStorage::random_path("./tmp");
-        let dht = Arc::new(PeerRing::new_with_storage(
-            did,
-            Arc::new(
-                PersistenceStorage::new_with_path(path.as_str())
-                    .await
-                    .unwrap(),
-            ),
-        ));
+        let (dht, path) = new_test_ring(did).await;
+        let dht = Arc::new(dht);
         let sm = SessionManager::new_with_seckey(key).unwrap();
         let swarm = Arc::new(Swarm::new(stun, key.address(), sm));
         let node = MessageHandler::new(dht.clone(), Arc::clone(&swarm));
@@ -0,0 +1,202 @@
+//! Requests sent to remote nodes and waiting for answers, such as steps of iterative lookup,
+//! topology queries of crawler and RPC calls.
+//! Answers are handled by the message handler in `listen`, so a method waiting for them,
+//! directly or by an iterative lookup, blocks the handling loop if it's called inside
+//! a message handler, and times out. Call such methods from another task instead.
+//! RPC requests are answered aside from the loop, so an `RpcHandler` can call them.
+use std::collections::HashSet;
+
+use dashmap::DashMap;
+use futures::channel::mpsc;
+use futures::future::Either;
+use futures::StreamExt;
+
+use crate::dht::Did;
+use crate::utils::sleep;
+
+type AnswerSender<T> = mpsc::UnboundedSender<(Did, T)>;
+
+/// A request to `node` can be answered by itself or by the node hosting it.
+fn can_answer(node: &Did, signer: &Did) -> bool {
+    node == signer || node.is_virtual_of(signer)
+}
+
+/// Requests sent to remote nodes and waiting for answers, indexed by request id.
+/// An answer may be relayed, so it's correlated by the request id carried in the message,
+/// and accepted only if it's signed by the node which is asked, or by the node hosting it.
+pub struct PendingRequests<T> {
+    requests: DashMap<uuid::Uuid, (Did, AnswerSender<T>)>,
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        Self {
+            requests: DashMap::new(),
+        }
+    }
+}
+
+impl<T> PendingRequests<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Start a batch of requests, which are removed from the table when the batch is dropped.
+    pub fn batch(&self) -> PendingBatch<'_, T> {
+        let (tx, rx) = mpsc::unbounded();
+        PendingBatch {
+            table: self,
+            tx,
+            rx,
+            request_ids: HashSet::new(),
+        }
+    }
+
+    /// The node asked by request `request_id`, if `signer` is allowed to answer it.
+    pub fn requestee(&self, request_id: &uuid::Uuid, signer: Did) -> Option<Did> {
+        self.requests
+            .get(request_id)
+            .map(|pending| pending.0)
+            .filter(|node| can_answer(node, &signer))
+    }
+
+    /// Deliver `answer` of request `request_id` signed by `signer`, return false if it's dropped.
+    /// The request is removed by its first accepted answer, so duplicated answers are dropped.
+    pub fn answer(&self, request_id: &uuid::Uuid, signer: Did, answer: T) -> bool {
+        match self
+            .requests
+            .remove_if(request_id, |_, (node, _)| can_answer(node, &signer))
+        {
+            Some((_, (node, tx))) => tx.unbounded_send((node, answer)).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Requests sent together, with answers received by one channel.
+pub struct PendingBatch<'a, T> {
+    table: &'a PendingRequests<T>,
+    tx: AnswerSender<T>,
+    rx: mpsc::UnboundedReceiver<(Did, T)>,
+    request_ids: HashSet<uuid::Uuid>,
+}
+
+impl<'a, T> PendingBatch<'a, T> {
+    /// Register a request to `node`, return its request id.
+    pub fn add(&mut self, node: Did) -> uuid::Uuid {
+        let request_id = uuid::Uuid::new_v4();
+        self.table
+            .requests
+            .insert(request_id, (node, self.tx.clone()));
+        self.request_ids.insert(request_id);
+        request_id
+    }
+
+    /// Stop waiting for request `request_id`, for example when it failed to be sent.
+    pub fn cancel(&mut self, request_id: &uuid::Uuid) {
+        self.table.requests.remove(request_id);
+        self.request_ids.remove(request_id);
+    }
+
+    /// Wait for the first answer for up to `timeout_ms`.
+    pub async fn first(mut self, timeout_ms: u64) -> Option<(Did, T)> {
+        if self.request_ids.is_empty() {
+            return None;
+        }
+        match futures::future::select(self.rx.next(), Box::pin(sleep(timeout_ms))).await {
+            Either::Left((answer, _)) => answer,
+            Either::Right(_) => None,
+        }
+    }
+
+    /// Wait for answers of all requests for up to `timeout_ms`, return those received.
+    /// Each request is answered once.
+    pub async fn all(mut self, timeout_ms: u64) -> Vec<(Did, T)> {
+        let mut answers = vec![];
+        let mut timeout = Box::pin(sleep(timeout_ms));
+        while answers.len() < self.request_ids.len() {
+            match futures::future::select(self.rx.next(), timeout).await {
+                Either::Left((Some(answer), t)) => {
+                    answers.push(answer);
+                    timeout = t;
+                }
+                _ => break,
+            }
+        }
+        answers
+    }
+}
+
+impl<'a, T> Drop for PendingBatch<'a, T> {
+    fn drop(&mut self) {
+        for request_id in self.request_ids.iter() {
+            self.table.requests.remove(request_id);
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    #[tokio::test]
+    async fn test_pending_requests() {
+        let dids = gen_ordered_dids(3);
+        let table = PendingRequests::<u8>::new();
+
+        let mut batch = table.batch();
+        let a = batch.add(dids[0]);
+        let b = batch.add(dids[1]);
+        let c = batch.add(dids[2]);
+        batch.cancel(&c);
+        assert!(!table.answer(&c, dids[2], 0));
+
+        // answers are accepted only from the node which is asked
+        assert_eq!(table.requestee(&a, dids[0]), Some(dids[0]));
+        assert_eq!(table.requestee(&a, dids[1]), None);
+        assert!(!table.answer(&a, dids[1], 1));
+        assert!(table.answer(&a, dids[0], 2));
+        assert_eq!(table.requestee(&a, dids[0]), None);
+        assert!(table.answer(&b, dids[1], 3));
+        assert!(!table.answer(&uuid::Uuid::new_v4(), dids[1], 4));
+
+        assert_eq!(batch.all(100).await, vec![(dids[0], 2), (dids[1], 3)]);
+        assert!(table.is_empty());
+
+        // a virtual identity is answered by its hosting node
+        let mut batch = table.batch();
+        let v = batch.add(dids[0].virtual_id(1));
+        assert!(table.answer(&v, dids[0], 5));
+        assert_eq!(batch.first(100).await, Some((dids[0].virtual_id(1), 5)));
+        assert!(table.is_empty());
+
+        // unanswered requests time out
+        let mut batch = table.batch();
+        batch.add(dids[1]);
+        assert_eq!(batch.first(10).await, None);
+        assert!(table.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pending_requests_answered_once() {
+        let dids = gen_ordered_dids(2);
+        let table = PendingRequests::<u8>::new();
+
+        // a duplicated answer doesn't take the place of an answer of another request
+        let mut batch = table.batch();
+        let a = batch.add(dids[0]);
+        let b = batch.add(dids[1]);
+        assert!(table.answer(&a, dids[0], 1));
+        assert!(!table.answer(&a, dids[0], 2));
+        assert!(table.answer(&b, dids[1], 3));
+        assert!(!table.answer(&b, dids[1], 4));
+        assert_eq!(batch.all(100).await, vec![(dids[0], 1), (dids[1], 3)]);
+        assert!(table.is_empty());
+    }
+}
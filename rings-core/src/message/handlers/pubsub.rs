@@ -0,0 +1,318 @@
+#![warn(missing_docs)]
+use std::str::FromStr;
+
+use async_trait::async_trait;
+
+use crate::dht::Chord;
+use crate::dht::Did;
+use crate::dht::PeerRingAction;
+use crate::dht::SubRingManager;
+use crate::ecc::HashStr;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::types::Message;
+use crate::message::types::PublishTopic;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::message::SubRingOperator;
+use crate::swarm::TransportManager;
+
+/// Number of children of each node in the spanning tree of a publication.
+pub const PUBSUB_FANOUT: usize = 4;
+/// How long the tx_id of a received publication is remembered, in milliseconds.
+/// A payload older than this is expired and dropped anyway.
+/// Remembered tx_ids are swept on each stabilization.
+pub const PUBSUB_SEEN_TTL_MS: u128 = 60 * 1000;
+
+/// PubSub provides topics on top of SubRing, a topic is a subring with the same name.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait PubSub {
+    /// Subscribe a topic, by joining its subring.
+    /// Members of the topic are fetched to verify forwarded publications,
+    /// which waits for answers, see module `handlers::pending`.
+    async fn subscribe(&self, topic: &str) -> Result<()>;
+    /// Unsubscribe a topic, by leaving its subring.
+    async fn unsubscribe(&self, topic: &str) -> Result<()>;
+    /// Publish data to all subscribers of a topic.
+    /// It lists members of the topic's subring, which may take an iterative lookup,
+    /// see module `handlers::pending`.
+    async fn publish(&self, topic: &str, data: &[u8]) -> Result<()>;
+    /// Names of subscribed topics.
+    fn subscriptions(&self) -> Vec<String>;
+}
+
+fn topic_did(topic: &str) -> Result<Did> {
+    let address: HashStr = topic.to_owned().into();
+    Did::from_str(&address.inner())
+}
+
+/// Split `members` into up to `PUBSUB_FANOUT` subtrees rooted at `root`.
+/// Members are ordered clockwise from `root`, each subtree is a continuous arc of the ring,
+/// so the first member of each subtree is responsible to forward to the rest of it.
+fn spanning_subtrees(root: Did, members: &[Did]) -> Vec<Vec<Did>> {
+    let mut members = members
+        .iter()
+        .filter(|m| **m != root)
+        .copied()
+        .collect::<Vec<_>>();
+    members.sort_by_key(|m| m.bias(&root));
+    members.dedup();
+    if members.is_empty() {
+        return vec![];
+    }
+    let size = (members.len() + PUBSUB_FANOUT - 1) / PUBSUB_FANOUT;
+    members.chunks(size).map(|c| c.to_vec()).collect()
+}
+
+impl MessageHandler {
+    /// Remember tx_id of a publication, return false if it's seen before.
+    fn mark_published(&self, tx_id: uuid::Uuid) -> bool {
+        self.swarm.mark_seen(&tx_id, PUBSUB_SEEN_TTL_MS)
+    }
+
+    /// Keep `members` which are known members of `topic`, by the subring stored locally,
+    /// or else by the members fetched when subscribing or publishing.
+    /// A member joined after that is not forwarded to until the next refresh.
+    async fn verified_members(&self, topic: Did, members: &[Did]) -> Vec<Did> {
+        let known = match self.dht.get_subring(&topic).await {
+            Ok(subring) => subring.members,
+            Err(_) => self
+                .subscriptions
+                .get(&topic)
+                .map(|t| t.value().1.clone())
+                .unwrap_or_default(),
+        };
+        let (verified, unknown): (Vec<Did>, Vec<Did>) =
+            members.iter().copied().partition(|m| known.contains(m));
+        if !unknown.is_empty() {
+            log::warn!("drop unknown members of topic {:?}: {:?}", topic, unknown);
+        }
+        verified
+    }
+
+    /// Send a publication to each subtree of `members`, all with the same `tx_id`.
+    /// If the first member of a subtree can't be reached, the next one takes its place.
+    async fn forward_publication(
+        &self,
+        topic: Did,
+        data: &[u8],
+        members: &[Did],
+        tx_id: uuid::Uuid,
+    ) -> Result<()> {
+        for subtree in spanning_subtrees(self.dht.id, members) {
+            for (i, head) in subtree.iter().enumerate() {
+                let msg = Message::PublishTopic(PublishTopic {
+                    topic,
+                    data: data.to_vec(),
+                    members: subtree[i + 1..].to_vec(),
+                });
+                match self.send_publication(msg, *head, tx_id).await {
+                    Ok(()) => break,
+                    Err(e) => log::warn!("failed to publish to {:?}: {:?}", head, e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_publication(&self, msg: Message, target: Did, tx_id: uuid::Uuid) -> Result<()> {
+        let next_hop = if self.swarm.get_transport(&target).is_some() {
+            target
+        } else {
+            match self.dht.find_successor(target)? {
+                PeerRingAction::Some(node) => node,
+                PeerRingAction::RemoteAction(node, _) => node,
+                _ => return Err(Error::MessageHandlerMissNextNode),
+            }
+        };
+        let mut payload =
+            MessagePayload::new_send(msg, self.swarm.session_manager(), next_hop, target)?;
+        payload.tx_id = tx_id;
+        self.send_payload(payload).await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl PubSub for MessageHandler {
+    async fn subscribe(&self, topic: &str) -> Result<()> {
+        SubRingOperator::join(self, topic).await?;
+        let members = self.members(topic).await?;
+        self.subscriptions
+            .insert(topic_did(topic)?, (topic.to_owned(), members));
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, topic: &str) -> Result<()> {
+        self.subscriptions.remove(&topic_did(topic)?);
+        SubRingOperator::leave(self, topic).await
+    }
+
+    async fn publish(&self, topic: &str, data: &[u8]) -> Result<()> {
+        let did = topic_did(topic)?;
+        let members = self.members(topic).await?;
+        if let Some(mut subscription) = self.subscriptions.get_mut(&did) {
+            subscription.1 = members.clone();
+        }
+        let tx_id = uuid::Uuid::new_v4();
+        self.mark_published(tx_id);
+        self.forward_publication(did, data, &members, tx_id).await
+    }
+
+    fn subscriptions(&self) -> Vec<String> {
+        self.subscriptions
+            .iter()
+            .map(|e| e.value().0.clone())
+            .collect()
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<PublishTopic> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &PublishTopic) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        // self is only on the way to a member
+        if relay.destination != self.dht.id {
+            let next_node = if self.swarm.get_transport(&relay.destination).is_some() {
+                relay.destination
+            } else {
+                match self.dht.find_successor(relay.destination)? {
+                    PeerRingAction::Some(node) => node,
+                    PeerRingAction::RemoteAction(node, _) => node,
+                    _ => return Err(Error::MessageHandlerMissNextNode),
+                }
+            };
+            relay.relay(self.dht.id, Some(next_node))?;
+            return self.transpond_payload(ctx, relay).await;
+        }
+
+        if !self.mark_published(ctx.tx_id) {
+            return Ok(());
+        }
+        if let Some(topic) = self
+            .subscriptions
+            .get(&msg.topic)
+            .map(|t| t.value().0.clone())
+        {
+            let callback = self.callback.lock().await;
+            if let Some(ref cb) = *callback {
+                cb.topic_message(self, ctx, &topic, &msg.data).await;
+            }
+        }
+        // the member list is not signed, only forward to verified members
+        let members = self.verified_members(msg.topic, &msg.members).await;
+        self.forward_publication(msg.topic, &msg.data, &members, ctx.tx_id)
+            .await
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+    use crate::message::CustomMessage;
+    use crate::message::MaybeEncrypted;
+    use crate::message::MessageCallback;
+
+    struct TopicCounter(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl MessageCallback for TopicCounter {
+        async fn custom_message(
+            &self,
+            _handler: &MessageHandler,
+            _ctx: &MessagePayload<Message>,
+            _msg: &MaybeEncrypted<CustomMessage>,
+        ) {
+        }
+        async fn builtin_message(&self, _handler: &MessageHandler, _ctx: &MessagePayload<Message>) {
+        }
+        async fn topic_message(
+            &self,
+            _handler: &MessageHandler,
+            _ctx: &MessagePayload<Message>,
+            topic: &str,
+            data: &[u8],
+        ) {
+            assert_eq!(topic, "news");
+            assert_eq!(data, b"hello");
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_spanning_subtrees() {
+        let dids = gen_ordered_dids(10);
+        let root = dids[5];
+        let mut members = dids.clone();
+        members.reverse();
+        members.push(dids[0]);
+
+        let subtrees = spanning_subtrees(root, &members);
+        assert_eq!(subtrees.len(), 3);
+        // clockwise from root, root itself excluded, no duplication
+        assert_eq!(subtrees[0], vec![dids[6], dids[7], dids[8]]);
+        assert_eq!(subtrees[1], vec![dids[9], dids[0], dids[1]]);
+        assert_eq!(subtrees[2], vec![dids[2], dids[3], dids[4]]);
+        assert!(spanning_subtrees(root, &[root]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_publication_is_delivered_once() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, _swarm, node, path) = prepare_node(&key).await;
+        let counter = Arc::new(AtomicUsize::new(0));
+        node.set_callback(Box::new(TopicCounter(counter.clone())))
+            .await;
+
+        node.create("news").await?;
+        node.subscribe("news").await?;
+        assert_eq!(node.subscriptions(), vec!["news".to_string()]);
+        // lonely publisher has nobody to send to
+        node.publish("news", b"hello").await?;
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+
+        let payload = MessagePayload::new_send(
+            Message::PublishTopic(PublishTopic {
+                topic: topic_did("news")?,
+                data: b"hello".to_vec(),
+                members: vec![],
+            }),
+            node.swarm.session_manager(),
+            did,
+            did,
+        )?;
+        node.handle_payload(&payload).await?;
+        node.handle_payload(&payload).await?;
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+        // listed members which are not in the subring are not forwarded to
+        let stranger: Did = SecretKey::random().address().into();
+        assert_eq!(
+            node.verified_members(topic_did("news")?, &[did, stranger])
+                .await,
+            vec![did]
+        );
+
+        node.unsubscribe("news").await?;
+        assert!(node.subscriptions().is_empty());
+        let mut payload = payload;
+        payload.tx_id = uuid::Uuid::new_v4();
+        node.handle_payload(&payload).await?;
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+}
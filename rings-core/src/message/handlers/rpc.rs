@@ -0,0 +1,274 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::dht::Chord;
+use crate::dht::Did;
+use crate::dht::PeerRingAction;
+use crate::ecc::PublicKey;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::types::CustomMessage;
+use crate::message::types::MaybeEncrypted;
+use crate::message::types::Message;
+use crate::message::types::RpcRequest;
+use crate::message::types::RpcResponse;
+use crate::message::types::RpcResult;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::swarm::TransportManager;
+
+/// Timeout of an RPC call if not specified, in milliseconds.
+pub const RPC_TIMEOUT_MS: u64 = 10 * 1000;
+
+/// Handler of requests of an RPC method.
+/// Requests are answered aside from other messages by `listen`, so a handler can call
+/// other nodes, by `MessageHandler::call` for example, and get their answers.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait RpcHandler {
+    /// Answer `request` from the origin of `ctx`.
+    /// An error is sent back to the caller as its message.
+    async fn handle(
+        &self,
+        handler: &MessageHandler,
+        ctx: &MessagePayload<Message>,
+        request: &[u8],
+    ) -> std::result::Result<Vec<u8>, String>;
+}
+
+#[cfg(not(feature = "wasm"))]
+pub type RpcHandlerFn = Arc<dyn RpcHandler + Send + Sync>;
+
+#[cfg(feature = "wasm")]
+pub type RpcHandlerFn = Arc<dyn RpcHandler>;
+
+impl MessageHandler {
+    /// Answer requests of `method` by `handler`, replacing the former handler of it.
+    pub fn register_rpc_handler(&self, method: &str, handler: RpcHandlerFn) {
+        self.rpc_handlers.insert(method.to_string(), handler);
+    }
+
+    /// Stop answering requests of `method`, return false if it has no handler.
+    pub fn unregister_rpc_handler(&self, method: &str) -> bool {
+        self.rpc_handlers.remove(method).is_some()
+    }
+
+    /// Call `method` of `destination` with `request`, wait for the response for `RPC_TIMEOUT_MS`.
+    /// The request is encrypted if `pubkey`, the session key of destination, is given.
+    pub async fn call(
+        &self,
+        destination: Did,
+        method: &str,
+        request: &[u8],
+        pubkey: &Option<PublicKey>,
+    ) -> Result<Vec<u8>> {
+        self.call_with_timeout(destination, method, request, pubkey, RPC_TIMEOUT_MS)
+            .await
+    }
+
+    /// Call `method` of `destination` with `request`, wait for the response for `timeout_ms`.
+    /// The response is matched with the request by a correlation id.
+    /// It waits for the response, see module `handlers::pending`.
+    pub async fn call_with_timeout(
+        &self,
+        destination: Did,
+        method: &str,
+        request: &[u8],
+        pubkey: &Option<PublicKey>,
+        timeout_ms: u64,
+    ) -> Result<Vec<u8>> {
+        // a call to self is answered without going through network
+        if destination == self.dht.id {
+            let req = RpcRequest {
+                id: uuid::Uuid::new_v4(),
+                method: method.to_string(),
+                data: MaybeEncrypted::Plain(CustomMessage(request.to_vec())),
+            };
+            let payload = MessagePayload::new_send_from(
+                Message::RpcRequest(req),
+                self.swarm.session_manager(),
+                self.dht.id,
+                destination,
+                destination,
+            )?;
+            let result = self.answer_rpc(&payload, method, request).await;
+            return result.map(|d| d.0).map_err(Error::RpcCallFailed);
+        }
+        let next_hop = if self.swarm.get_transport(&destination.into()).is_some() {
+            destination
+        } else {
+            match self.dht.find_successor(destination)? {
+                PeerRingAction::Some(node) | PeerRingAction::RemoteAction(node, _)
+                    if node != self.dht.id =>
+                {
+                    node
+                }
+                _ => return Err(Error::NoNextHop),
+            }
+        };
+
+        let mut batch = self.rpc_calls.batch();
+        let req = RpcRequest {
+            id: batch.add(destination),
+            method: method.to_string(),
+            data: MaybeEncrypted::new(CustomMessage(request.to_vec()), pubkey)?,
+        };
+        self.send_message(Message::RpcRequest(req), next_hop, destination)
+            .await?;
+        let (_, result) = batch.first(timeout_ms).await.ok_or(Error::RpcTimeout)?;
+        let key = self.swarm.session_manager().session_key()?;
+        let (result, _) = result.decrypt(&key)?;
+        result.map(|d| d.0).map_err(Error::RpcCallFailed)
+    }
+
+    async fn answer_rpc(
+        &self,
+        ctx: &MessagePayload<Message>,
+        method: &str,
+        request: &[u8],
+    ) -> RpcResult {
+        let handler = self.rpc_handlers.get(method).map(|h| h.clone());
+        match handler {
+            Some(h) => h.handle(self, ctx, request).await.map(CustomMessage),
+            None => Err(format!("no handler of method {}", method)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RpcRequest> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &RpcRequest) -> Result<()> {
+        if self.dht.id != ctx.relay.destination {
+            return self.transpond_to_destination(ctx).await;
+        }
+        let result = match self.decrypt_msg(&msg.data) {
+            Ok(request) => self.answer_rpc(ctx, &msg.method, &request.0).await,
+            Err(e) => Err(format!("failed to decrypt request, {}", e)),
+        };
+        // only the caller can read the result, nodes relaying it can't
+        let resp = RpcResponse {
+            id: msg.id,
+            result: MaybeEncrypted::new(result, &Some(ctx.origin_session_pubkey()?))?,
+        };
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.id, None)?;
+        self.send_report_message(Message::RpcResponse(resp), ctx.tx_id, relay)
+            .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RpcResponse> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &RpcResponse) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.id, None)?;
+        if relay.next_hop.is_some() {
+            return self.transpond_payload(ctx, relay).await;
+        }
+
+        // only accept the response from the node which is called, or the node hosting it
+        self.rpc_calls
+            .answer(&msg.id, ctx.origin_signer(), msg.result.clone());
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+
+    struct Echo;
+
+    #[async_trait]
+    impl RpcHandler for Echo {
+        async fn handle(
+            &self,
+            _handler: &MessageHandler,
+            _ctx: &MessagePayload<Message>,
+            request: &[u8],
+        ) -> std::result::Result<Vec<u8>, String> {
+            match request {
+                b"fail" => Err("failed".to_string()),
+                _ => Ok(request.to_vec()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_call_on_lonely_node() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, _swarm, node, path) = prepare_node(&key).await;
+
+        node.register_rpc_handler("echo", Arc::new(Echo));
+        assert_eq!(
+            node.call(did, "echo", b"hello", &None).await?,
+            b"hello".to_vec()
+        );
+        assert!(matches!(
+            node.call(did, "echo", b"fail", &None).await,
+            Err(Error::RpcCallFailed(e)) if e == "failed"
+        ));
+        assert!(node.unregister_rpc_handler("echo"));
+        assert!(matches!(
+            node.call(did, "echo", b"hello", &None).await,
+            Err(Error::RpcCallFailed(_))
+        ));
+        // an unknown node can't be reached
+        assert!(node
+            .call(
+                SecretKey::random().address().into(),
+                "echo",
+                b"hello",
+                &None
+            )
+            .await
+            .is_err());
+        assert!(node.rpc_calls.is_empty());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rpc_response_is_correlated() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, swarm, node, path) = prepare_node(&key).await;
+
+        let other: Did = SecretKey::random().address().into();
+        let response = |id| -> Result<_> {
+            let resp = RpcResponse {
+                id,
+                result: MaybeEncrypted::Plain(Ok(CustomMessage(b"hello".to_vec()))),
+            };
+            let payload = MessagePayload::new_send(
+                Message::RpcResponse(resp.clone()),
+                swarm.session_manager(),
+                did,
+                did,
+            )?;
+            Ok((payload, resp))
+        };
+
+        // response signed by a node other than the callee is ignored
+        let mut batch = node.rpc_calls.batch();
+        let (payload, resp) = response(batch.add(other))?;
+        node.handle(&payload, &resp).await?;
+        assert_eq!(batch.first(10).await, None);
+
+        let mut batch = node.rpc_calls.batch();
+        let (payload, resp) = response(batch.add(did))?;
+        node.handle(&payload, &resp).await?;
+        assert_eq!(batch.first(10).await, Some((did, resp.result)));
+        assert!(node.rpc_calls.is_empty());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+}
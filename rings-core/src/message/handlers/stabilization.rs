@@ -1,14 +1,12 @@
 use async_trait::async_trait;
 
 use crate::dht::ChordStabilize;
-use crate::dht::ChordStorage;
-use crate::dht::PeerRingAction;
-use crate::dht::PeerRingRemoteAction;
 use crate::err::Result;
 use crate::message::types::Message;
 use crate::message::types::NotifyPredecessorReport;
 use crate::message::types::NotifyPredecessorSend;
-use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::types::Ping;
+use crate::message::types::Pong;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
@@ -54,24 +52,41 @@ impl HandleMsg<NotifyPredecessorReport> for MessageHandler {
         // if successor: predecessor is between (id, successor]
         // then update local successor
         if self.swarm.get_and_check_transport(&msg.id).await.is_none()
-            && msg.id != self.swarm.address().into()
+            && !self.swarm.is_local(&msg.id.into())
         {
             self.connect(&msg.id.into()).await?;
         } else {
             {
                 self.dht.lock_successor()?.update(msg.id)
             }
-            if let Ok(PeerRingAction::RemoteAction(
-                next,
-                PeerRingRemoteAction::SyncVNodeWithSuccessor(data),
-            )) = self.dht.sync_with_successor(msg.id).await
-            {
-                self.send_direct_message(
-                    Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data }),
-                    next,
-                )
-                .await?;
-            }
+            self.sync_vnodes_with_successor(msg.id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<Ping> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &Ping) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.id, None)?;
+        self.send_report_message(Message::Pong(Pong { id: msg.id }), ctx.tx_id, relay)
+            .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<Pong> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &Pong) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.id, None)?;
+        if relay.next_hop.is_some() {
+            return self.transpond_payload(ctx, relay).await;
+        }
+        if self.swarm.record_pong(&ctx.addr, &msg.id).is_none() {
+            log::debug!("unexpected pong {} from {:?}", msg.id, ctx.addr);
         }
         Ok(())
     }
@@ -532,6 +547,39 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_ping_pong_records_rtt() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (_did1, dht1, swarm1, node1, _path1) = prepare_node(&key1).await;
+        let (did2, dht2, swarm2, node2, _path2) = prepare_node(&key2).await;
+
+        test_only_two_nodes_establish_connection(
+            (&key1, dht1.clone(), &swarm1, &node1),
+            (&key2, dht2.clone(), &swarm2, &node2),
+        )
+        .await?;
+        assert!(swarm1.liveness(&key2.address()).is_none());
+
+        let id = swarm1.record_ping(&key2.address());
+        node1
+            .send_direct_message(Message::Ping(Ping { id }), did2)
+            .await?;
+        let ev2 = node2.listen_once().await.unwrap();
+        assert_eq!(ev2.addr, key1.address());
+        assert!(matches!(ev2.data, Message::Ping(Ping { id: x }) if x == id));
+
+        let ev1 = node1.listen_once().await.unwrap();
+        assert_eq!(ev1.addr, key2.address());
+        assert!(matches!(ev1.data, Message::Pong(Pong { id: x }) if x == id));
+
+        let liveness = swarm1.liveness(&key2.address()).unwrap();
+        assert_eq!(liveness.missed_pings, 0);
+        assert!(liveness.rtt.is_some());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     async fn run_stabilize_once(dht: Arc<PeerRing>, swarm: Arc<Swarm>) -> Result<()> {
         Stabilization::new(dht, swarm, 5).stabilize().await
     }
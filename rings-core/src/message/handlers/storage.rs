@@ -1,5 +1,8 @@
 use async_trait::async_trait;
 
+use crate::dht::file::FileManifest;
+use crate::dht::vnode::VNodeRemoval;
+use crate::dht::vnode::VNodeType;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::ChordStorage;
 use crate::dht::Did;
@@ -8,14 +11,29 @@ use crate::dht::PeerRingRemoteAction;
 use crate::err::Error;
 use crate::err::Result;
 use crate::message::types::FoundVNode;
+use crate::message::types::LookupStep;
+use crate::message::types::LookupTarget;
 use crate::message::types::Message;
+use crate::message::types::RelayMessageAck;
+use crate::message::types::RelayMessageDelivery;
+use crate::message::types::RemoveReplica;
+use crate::message::types::RemoveVNode;
 use crate::message::types::SearchVNode;
+use crate::message::types::StoreReplica;
 use crate::message::types::StoreVNode;
 use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::Decoder;
 use crate::message::HandleMsg;
+use crate::message::LookupMode;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
 use crate::message::PayloadSender;
+use crate::swarm::TransportManager;
+use crate::utils::get_epoch_ms;
+
+/// How long a message is kept for an offline destination, in milliseconds.
+/// A relayed message older than this is dropped on delivery.
+pub const RELAY_MESSAGE_TTL_MS: u128 = 7 * 24 * 60 * 60 * 1000;
 
 /// TChordStorage should imply necessary method for DHT storage
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -25,8 +43,12 @@ pub trait TChordStorage {
     async fn check_cache(&self, id: &Did) -> Option<VirtualNode>;
     /// fetch virtual node from DHT
     async fn fetch(&self, id: &Did) -> Result<()>;
+    /// fetch virtual node from DHT with lookup mode
+    async fn fetch_with_mode(&self, id: &Did, mode: LookupMode) -> Result<()>;
     /// store virtual node on DHT
     async fn store(&self, vnode: VirtualNode) -> Result<()>;
+    /// remove virtual node from DHT, the removal should be signed by owner of virtual node
+    async fn remove(&self, removal: VNodeRemoval) -> Result<()>;
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -37,19 +59,47 @@ impl TChordStorage for MessageHandler {
         self.dht.fetch_cache(id)
     }
 
-    /// Fetch virtual node, if exist in localstoreage, copy it to the cache,
-    /// else Query Remote Node
+    /// Fetch virtual node recursively
     async fn fetch(&self, id: &Did) -> Result<()> {
+        self.fetch_with_mode(id, LookupMode::Recursive).await
+    }
+
+    /// Fetch virtual node, if exist in localstoreage, copy it to the cache,
+    /// else Query Remote Node.
+    /// With iterative mode, the found virtual node is cached once the lookup is done.
+    async fn fetch_with_mode(&self, id: &Did, mode: LookupMode) -> Result<()> {
+        if mode == LookupMode::Iterative {
+            if let LookupStep::VNode(v) = self.lookup_iteratively(*id, LookupTarget::VNode).await? {
+                self.dht.cache(v);
+            }
+            return Ok(());
+        }
+
         // If peer found that data is on it's localstore, copy it to the cache
         match self.dht.lookup(id).await? {
             PeerRingAction::SomeVNode(v) => {
                 self.dht.cache(v);
                 Ok(())
             }
-            PeerRingAction::None => Ok(()),
+            // data is missing on the responsible node, try its replicas
+            PeerRingAction::None => {
+                if let Some(next) = self.dht.replica_holders()?.first() {
+                    self.send_direct_message(Message::SearchVNode(SearchVNode { id: *id }), *next)
+                        .await?;
+                }
+                Ok(())
+            }
+            // the responsible node is unreachable, fallback to local replica if any
             PeerRingAction::RemoteAction(next, _) => {
-                self.send_direct_message(Message::SearchVNode(SearchVNode { id: *id }), next)
-                    .await?;
+                if let Err(e) = self
+                    .send_direct_message(Message::SearchVNode(SearchVNode { id: *id }), next)
+                    .await
+                {
+                    match self.dht.local_replica(id).await {
+                        Some(v) => self.dht.cache(v),
+                        None => return Err(e),
+                    }
+                }
                 Ok(())
             }
             act => Err(Error::PeerRingUnexpectedAction(act)),
@@ -58,8 +108,8 @@ impl TChordStorage for MessageHandler {
 
     /// Store VirtualNode, TryInto<VirtualNode> is implementated for alot of types
     async fn store(&self, vnode: VirtualNode) -> Result<()> {
+        let relayed = relay_target(&vnode);
         match self.dht.store(vnode).await? {
-            PeerRingAction::None => Ok(()),
             PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindAndStore(vnode)) => {
                 self.send_direct_message(
                     Message::StoreVNode(StoreVNode { data: vec![vnode] }),
@@ -68,11 +118,146 @@ impl TChordStorage for MessageHandler {
                 .await?;
                 Ok(())
             }
-            act => Err(Error::PeerRingUnexpectedAction(act)),
+            act => {
+                self.replicate(act).await?;
+                self.deliver_relay_messages_if_connected(relayed).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove VirtualNode, the responsible node and replica holders will verify the owner
+    async fn remove(&self, removal: VNodeRemoval) -> Result<()> {
+        match self.dht.remove_vnode(removal).await? {
+            PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindAndRemove(removal)) => {
+                self.send_direct_message(
+                    Message::RemoveVNode(RemoveVNode { data: removal }),
+                    target,
+                )
+                .await?;
+                Ok(())
+            }
+            act => self.replicate(act).await,
         }
     }
 }
 
+/// Destination of messages kept by a RelayMessage vnode.
+fn relay_target(vnode: &VirtualNode) -> Option<Did> {
+    (vnode.kind == VNodeType::RelayMessage).then(|| vnode.did())
+}
+
+impl MessageHandler {
+    /// Move VNodes which `successor` is responsible for to it, see [ChordStorage::sync_with_successor].
+    /// They are sent in batches, each batch is removed locally once it's sent.
+    pub async fn sync_vnodes_with_successor(&self, successor: Did) -> Result<()> {
+        let data = match self.dht.sync_with_successor(successor).await? {
+            PeerRingAction::RemoteAction(_, PeerRingRemoteAction::SyncVNodeWithSuccessor(data)) => {
+                data
+            }
+            _ => return Ok(()),
+        };
+        for batch in VirtualNode::batch(data) {
+            self.send_direct_message(
+                Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor {
+                    data: batch.clone(),
+                }),
+                successor,
+            )
+            .await?;
+            self.dht.remove_synced(&batch).await?;
+        }
+        Ok(())
+    }
+
+    /// Send a message to `destination` directly. If it can't be reached, the message is stored
+    /// in the RelayMessage vnode of destination, and the node preceding destination delivers it
+    /// at once if it's connected to destination, or else once destination comes back online.
+    pub async fn send_or_store_message(&self, msg: Message, destination: Did) -> Result<()> {
+        let payload = MessagePayload::new_direct(msg, self.swarm.session_manager(), destination)?;
+        if self
+            .swarm
+            .get_and_check_transport(&destination.into())
+            .await
+            .is_some()
+        {
+            match self.send_payload(payload.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) => log::warn!("failed to send to {:?}, store it: {:?}", destination, e),
+            }
+        }
+        let vnode = VirtualNode::try_from(payload)?.with_ttl(RELAY_MESSAGE_TTL_MS);
+        self.store(vnode).await
+    }
+
+    /// Push messages relayed for `did` just stored locally, if `did` is connected.
+    /// It happens when `did` is online, but can't be reached by the sender.
+    async fn deliver_relay_messages_if_connected(&self, did: Option<Did>) {
+        let did = match did {
+            Some(did) => did,
+            None => return,
+        };
+        if self
+            .swarm
+            .get_and_check_transport(&did.into())
+            .await
+            .is_none()
+        {
+            return;
+        }
+        if let Err(e) = self.deliver_relay_messages(did).await {
+            log::warn!("failed to deliver relayed messages to {:?}: {:?}", did, e);
+        }
+    }
+
+    /// Push messages relayed for `did` to it, if they are stored locally.
+    /// They are kept until `did` acknowledges them.
+    pub async fn deliver_relay_messages(&self, did: Did) -> Result<()> {
+        match self.dht.relay_messages(did).await {
+            Some(vnode) => {
+                self.send_direct_message(
+                    Message::RelayMessageDelivery(RelayMessageDelivery { data: vnode.data }),
+                    did,
+                )
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Push replicas to successors, according to the action returned by `PeerRing::store`
+    /// or `PeerRing::remove_vnode`. A failed replica won't fail the store itself.
+    async fn replicate(&self, act: PeerRingAction) -> Result<()> {
+        let acts = match act {
+            PeerRingAction::None => vec![],
+            PeerRingAction::MultiActions(acts) => acts,
+            act => vec![act],
+        };
+        for act in acts {
+            match act {
+                PeerRingAction::RemoteAction(next, PeerRingRemoteAction::StoreReplica(data)) => {
+                    if let Err(e) = self
+                        .send_direct_message(Message::StoreReplica(StoreReplica { data }), next)
+                        .await
+                    {
+                        log::warn!("failed to push replica to {:?}: {:?}", next, e);
+                    }
+                }
+                PeerRingAction::RemoteAction(next, PeerRingRemoteAction::RemoveReplica(data)) => {
+                    if let Err(e) = self
+                        .send_direct_message(Message::RemoveReplica(RemoveReplica { data }), next)
+                        .await
+                    {
+                        log::warn!("failed to remove replica from {:?}: {:?}", next, e);
+                    }
+                }
+                act => return Err(Error::PeerRingUnexpectedAction(act)),
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<SearchVNode> for MessageHandler {
@@ -83,7 +268,22 @@ impl HandleMsg<SearchVNode> for MessageHandler {
 
         match self.dht.lookup(&msg.id).await {
             Ok(action) => match action {
-                PeerRingAction::None => Ok(()),
+                // data is missing on the responsible node, relay to a replica not visited yet
+                PeerRingAction::None => {
+                    let next = self
+                        .dht
+                        .replica_holders()?
+                        .into_iter()
+                        .find(|did| !relay.path.contains(did));
+                    match next {
+                        Some(next) => {
+                            relay.relay(self.dht.id, Some(next))?;
+                            relay.reset_destination(next)?;
+                            self.transpond_payload(ctx, relay).await
+                        }
+                        None => Ok(()),
+                    }
+                }
                 PeerRingAction::SomeVNode(v) => {
                     relay.relay(self.dht.id, None)?;
                     self.send_report_message(
@@ -93,9 +293,30 @@ impl HandleMsg<SearchVNode> for MessageHandler {
                     )
                     .await
                 }
+                // the responsible node already missed the data, or is unreachable,
+                // fallback to local replica
                 PeerRingAction::RemoteAction(next, _) => {
-                    relay.relay(self.dht.id, Some(next))?;
-                    self.transpond_payload(ctx, relay).await
+                    let result = match relay.path.contains(&next) {
+                        true => Err(Error::VNodeNotFound(msg.id)),
+                        false => {
+                            let mut relay = relay.clone();
+                            relay.relay(self.dht.id, Some(next))?;
+                            self.transpond_payload(ctx, relay).await
+                        }
+                    };
+                    match (result, self.dht.local_replica(&msg.id).await) {
+                        (Ok(()), _) => Ok(()),
+                        (Err(_), Some(v)) => {
+                            relay.relay(self.dht.id, None)?;
+                            self.send_report_message(
+                                Message::FoundVNode(FoundVNode { data: vec![v] }),
+                                ctx.tx_id,
+                                relay,
+                            )
+                            .await
+                        }
+                        (Err(e), None) => Err(e),
+                    }
                 }
                 act => Err(Error::PeerRingUnexpectedAction(act)),
             },
@@ -129,16 +350,27 @@ impl HandleMsg<StoreVNode> for MessageHandler {
     async fn handle(&self, ctx: &MessagePayload<Message>, msg: &StoreVNode) -> Result<()> {
         let virtual_peer = msg.data.clone();
         for p in virtual_peer {
+            // a manifest stored by its uploader is signed just now, others are moving it around
+            if p.kind == VNodeType::FileManifest {
+                let manifest = FileManifest::from_vnode(&p)?;
+                if ctx.origin_signer() == manifest.owner.into() && !manifest.is_fresh() {
+                    return Err(Error::InvalidFileManifest);
+                }
+            }
+            let relayed = relay_target(&p);
             match self.dht.store(p).await {
                 Ok(action) => match action {
-                    PeerRingAction::None => Ok(()),
-                    PeerRingAction::RemoteAction(next, _) => {
+                    PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindAndStore(_)) => {
                         let mut relay = ctx.relay.clone();
                         relay.reset_destination(next)?;
                         relay.relay(self.dht.id, Some(next))?;
                         self.transpond_payload(ctx, relay).await
                     }
-                    act => Err(Error::PeerRingUnexpectedAction(act)),
+                    act => {
+                        self.replicate(act).await?;
+                        self.deliver_relay_messages_if_connected(relayed).await;
+                        Ok(())
+                    }
                 },
                 Err(e) => Err(e),
             }?;
@@ -159,7 +391,6 @@ impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
         for data in msg.data.iter().cloned() {
             // only simply store here
             match self.dht.store(data).await {
-                Ok(PeerRingAction::None) => Ok(()),
                 Ok(PeerRingAction::RemoteAction(
                     next,
                     PeerRingRemoteAction::FindAndStore(peer),
@@ -170,7 +401,7 @@ impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
                     )
                     .await
                 }
-                Ok(_) => unreachable!(),
+                Ok(act) => self.replicate(act).await,
                 Err(e) => Err(e),
             }?;
         }
@@ -178,6 +409,129 @@ impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<StoreReplica> for MessageHandler {
+    // received replicas pushed by the owner of vnodes, the owner is the origin signer
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &StoreReplica) -> Result<()> {
+        let owner = ctx.origin_signer();
+        for data in msg.data.iter().cloned() {
+            self.dht.store_replica(owner, data).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RemoveVNode> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &RemoveVNode) -> Result<()> {
+        match self.dht.remove_vnode(msg.data.clone()).await? {
+            PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindAndRemove(_)) => {
+                let mut relay = ctx.relay.clone();
+                relay.reset_destination(next)?;
+                relay.relay(self.dht.id, Some(next))?;
+                self.transpond_payload(ctx, relay).await
+            }
+            act => self.replicate(act).await,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RemoveReplica> for MessageHandler {
+    // received replica removal pushed by the owner of vnode
+    async fn handle(&self, _ctx: &MessagePayload<Message>, msg: &RemoveReplica) -> Result<()> {
+        self.dht.remove_replica(msg.data.clone()).await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RelayMessageDelivery> for MessageHandler {
+    // handle each relayed custom message as if it's just received, then acknowledge them,
+    // including the dropped ones, so they are not delivered again
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &RelayMessageDelivery,
+    ) -> Result<()> {
+        let mut tx_ids = vec![];
+        let now = get_epoch_ms();
+        for encoded in msg.data.iter() {
+            let payload = match MessagePayload::<Message>::from_encoded(encoded) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::warn!("failed to decode relayed message: {:?}", e);
+                    continue;
+                }
+            };
+            tx_ids.push(payload.tx_id);
+            // a relayed message may outlive the ttl of payload,
+            // so its signature is checked, and its age against `RELAY_MESSAGE_TTL_MS`
+            if payload.relay.destination != self.dht.id
+                || !matches!(payload.data, Message::CustomMessage(_))
+                || now > payload.origin_verification.ts_ms + RELAY_MESSAGE_TTL_MS
+                || !payload.origin_verification.verify(&payload.data)
+            {
+                log::warn!("drop invalid relayed message: {:?}", payload.tx_id);
+                continue;
+            }
+            // a message may be delivered again by a replica holder, before it gets the ack
+            if !self.swarm.mark_seen(&payload.tx_id, RELAY_MESSAGE_TTL_MS) {
+                log::debug!("drop delivered relayed message: {:?}", payload.tx_id);
+                continue;
+            }
+            if let Err(e) = self.handle_payload(&payload).await {
+                log::warn!("failed to handle relayed message: {:?}", e);
+            }
+        }
+        self.send_direct_message(
+            Message::RelayMessageAck(RelayMessageAck {
+                did: self.dht.id,
+                tx_ids,
+            }),
+            ctx.addr.into(),
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RelayMessageAck> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &RelayMessageAck) -> Result<()> {
+        // only the destination can acknowledge, the ack forwarded to replica holders
+        // keeps the origin verification, so the signer of origin is checked
+        if ctx.origin_signer() != msg.did {
+            return Err(Error::InvalidRelayMessageAck);
+        }
+        let acts = match self.dht.ack_relay_messages(msg.did, &msg.tx_ids).await? {
+            PeerRingAction::None => vec![],
+            PeerRingAction::MultiActions(acts) => acts,
+            act => vec![act],
+        };
+        for act in acts {
+            match act {
+                PeerRingAction::RemoteAction(
+                    next,
+                    PeerRingRemoteAction::AckRelayMessageReplica(..),
+                ) => {
+                    let mut relay = ctx.relay.clone();
+                    relay.reset_destination(next)?;
+                    relay.relay(self.dht.id, Some(next))?;
+                    if let Err(e) = self.transpond_payload(ctx, relay).await {
+                        log::warn!("failed to ack replica on {:?}: {:?}", next, e);
+                    }
+                }
+                act => return Err(Error::PeerRingUnexpectedAction(act)),
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 mod test {
@@ -186,8 +540,11 @@ mod test {
     use super::*;
     use crate::dht::PeerRing;
     use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+    use crate::message::Encoder;
     use crate::message::FindSuccessorThen;
     use crate::message::MessageHandler;
+    use crate::message::Ping;
     use crate::prelude::RTCSdpType;
     use crate::session::SessionManager;
     use crate::storage::PersistenceStorage;
@@ -419,4 +776,67 @@ mod test {
         tokio::fs::remove_dir_all("./tmp").await.ok();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_store_message_for_offline_node() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, dht, _swarm, node, path) = prepare_node(&key).await;
+        let offline: Did = SecretKey::random().address().into();
+
+        node.send_or_store_message(Message::custom(b"hello", &None)?, offline)
+            .await?;
+        let vnode = dht.relay_messages(offline).await.unwrap();
+        assert_eq!(vnode.did(), offline);
+        assert!(vnode.expired_at.is_some());
+        let payload = MessagePayload::<Message>::from_encoded(&vnode.data[0])?;
+        assert_eq!(payload.relay.destination, offline);
+
+        // nobody but the destination can drop its messages
+        let ack = MessagePayload::new_direct(
+            Message::RelayMessageAck(RelayMessageAck {
+                did: offline,
+                tx_ids: vec![payload.tx_id],
+            }),
+            node.swarm.session_manager(),
+            did,
+        )?;
+        assert!(matches!(
+            node.handle_payload(&ack).await,
+            Err(Error::InvalidRelayMessageAck)
+        ));
+        assert!(dht.relay_messages(offline).await.is_some());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relay_message_delivery_is_filtered() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, swarm, node, path) = prepare_node(&key).await;
+        let sm = SessionManager::new_with_seckey(&SecretKey::random())?;
+
+        let custom = MessagePayload::new_direct(Message::custom(b"hello", &None)?, &sm, did)?;
+        let builtin = MessagePayload::new_direct(
+            Message::Ping(Ping {
+                id: uuid::Uuid::new_v4(),
+            }),
+            &sm,
+            did,
+        )?;
+        let delivery = MessagePayload::new_direct(
+            Message::RelayMessageDelivery(RelayMessageDelivery {
+                data: vec![custom.encode()?, custom.encode()?, builtin.encode()?],
+            }),
+            &sm,
+            did,
+        )?;
+        // the ack can't be sent without a transport to the deliverer
+        node.handle_payload(&delivery).await.ok();
+
+        // only the custom message is handled, and only once
+        assert!(!swarm.mark_seen(&custom.tx_id, RELAY_MESSAGE_TTL_MS));
+        assert!(swarm.mark_seen(&builtin.tx_id, RELAY_MESSAGE_TTL_MS));
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
 }
@@ -5,6 +5,7 @@ use async_trait::async_trait;
 
 use super::storage::TChordStorage;
 use crate::dht::subring::SubRing;
+use crate::dht::subring::SubRingAdminAction;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::Did;
 use crate::dht::PeerRingAction;
@@ -13,7 +14,11 @@ use crate::dht::SubRingManager;
 use crate::ecc::HashStr;
 use crate::err::Error;
 use crate::err::Result;
+use crate::message::types::AdminSubRing;
 use crate::message::types::JoinSubRing;
+use crate::message::types::LeaveSubRing;
+use crate::message::types::LookupStep;
+use crate::message::types::LookupTarget;
 use crate::message::types::Message;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
@@ -30,6 +35,19 @@ pub trait SubRingOperator {
     async fn create(&self, name: &str) -> Result<()>;
     /// join a subring
     async fn join(&self, name: &str) -> Result<()>;
+    /// leave a subring
+    async fn leave(&self, name: &str) -> Result<()>;
+    /// list joined nodes of a subring
+    /// If the subring is not stored locally, it's fetched with iterative lookup,
+    /// see module `handlers::pending`.
+    async fn members(&self, name: &str) -> Result<Vec<Did>>;
+    /// send an admin action, which is verified by the node storing the subring
+    async fn admin(&self, action: SubRingAdminAction) -> Result<()>;
+}
+
+fn subring_did(name: &str) -> Result<Did> {
+    let address: HashStr = name.to_owned().into();
+    Did::from_str(&address.inner())
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -43,8 +61,7 @@ impl SubRingOperator for MessageHandler {
     }
 
     async fn join(&self, name: &str) -> Result<()> {
-        let address: HashStr = name.to_owned().into();
-        let did = Did::from_str(&address.inner())?;
+        let did = subring_did(name)?;
         match self.dht.join_subring(&self.dht.id, &did).await {
             Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindAndJoinSubRing(rid))) => {
                 self.send_direct_message(Message::JoinSubRing(JoinSubRing { did: rid }), next)
@@ -55,6 +72,45 @@ impl SubRingOperator for MessageHandler {
             Err(e) => Err(e),
         }
     }
+
+    async fn leave(&self, name: &str) -> Result<()> {
+        let did = subring_did(name)?;
+        match self.dht.leave_subring(&self.dht.id, &did).await {
+            Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindAndLeaveSubRing(rid))) => {
+                self.send_direct_message(Message::LeaveSubRing(LeaveSubRing { did: rid }), next)
+                    .await
+            }
+            Ok(PeerRingAction::None) => Ok(()),
+            Ok(act) => Err(Error::PeerRingUnexpectedAction(act)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn members(&self, name: &str) -> Result<Vec<Did>> {
+        let did = subring_did(name)?;
+        if let Ok(subring) = self.dht.get_subring(&did).await {
+            return Ok(subring.members);
+        }
+        match self.lookup_iteratively(did, LookupTarget::VNode).await? {
+            LookupStep::VNode(vnode) => {
+                let subring: SubRing = vnode.try_into()?;
+                Ok(subring.members)
+            }
+            _ => Err(Error::SubRingNotFound),
+        }
+    }
+
+    async fn admin(&self, action: SubRingAdminAction) -> Result<()> {
+        match self.dht.admin_subring(&action).await {
+            Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindAndAdminSubRing(action))) => {
+                self.send_direct_message(Message::AdminSubRing(AdminSubRing { action }), next)
+                    .await
+            }
+            Ok(PeerRingAction::None) => Ok(()),
+            Ok(act) => Err(Error::PeerRingUnexpectedAction(act)),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -62,8 +118,8 @@ impl SubRingOperator for MessageHandler {
 impl HandleMsg<JoinSubRing> for MessageHandler {
     async fn handle(&self, ctx: &MessagePayload<Message>, msg: &JoinSubRing) -> Result<()> {
         let mut relay = ctx.relay.clone();
-        let origin = relay.origin();
-        match self.dht.join_subring(&origin, &msg.did).await {
+        // the joiner is the signer of origin, the relay path is not signed
+        match self.dht.join_subring(&ctx.origin_signer(), &msg.did).await {
             Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindAndJoinSubRing(_))) => {
                 relay.relay(self.dht.id, Some(next))?;
                 relay.reset_destination(next)?;
@@ -75,3 +131,82 @@ impl HandleMsg<JoinSubRing> for MessageHandler {
         }
     }
 }
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<LeaveSubRing> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &LeaveSubRing) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        // only the signer of origin can leave, the relay path is not signed
+        match self.dht.leave_subring(&ctx.origin_signer(), &msg.did).await {
+            Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindAndLeaveSubRing(_))) => {
+                relay.relay(self.dht.id, Some(next))?;
+                relay.reset_destination(next)?;
+                self.transpond_payload(ctx, relay).await
+            }
+            Ok(PeerRingAction::None) => Ok(()),
+            Ok(act) => Err(Error::PeerRingUnexpectedAction(act)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<AdminSubRing> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &AdminSubRing) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        // the signature of admin is verified by the node storing the subring
+        match self.dht.admin_subring(&msg.action).await {
+            Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindAndAdminSubRing(_))) => {
+                relay.relay(self.dht.id, Some(next))?;
+                relay.reset_destination(next)?;
+                self.transpond_payload(ctx, relay).await
+            }
+            Ok(PeerRingAction::None) => Ok(()),
+            Ok(act) => Err(Error::PeerRingUnexpectedAction(act)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dht::subring::SubRingAdminOp;
+    use crate::ecc::SecretKey;
+    use crate::message::handlers::tests::prepare_node;
+
+    #[tokio::test]
+    async fn test_subring_lifecycle_on_lonely_node() -> Result<()> {
+        let key = SecretKey::random();
+        let (did, _dht, _swarm, node, path) = prepare_node(&key).await;
+
+        node.create("lonely").await?;
+        assert_eq!(node.members("lonely").await?, vec![did]);
+        assert!(matches!(
+            node.members("missing").await,
+            Err(Error::SubRingNotFound)
+        ));
+
+        let rid = subring_did("lonely")?;
+        let forged = SubRingAdminAction::new(
+            &SecretKey::random(),
+            rid,
+            SubRingAdminOp::Rename("forged".into()),
+            1,
+        )?;
+        assert!(node.admin(forged).await.is_err());
+        let rename =
+            SubRingAdminAction::new(&key, rid, SubRingAdminOp::Rename("renamed".into()), 1)?;
+        node.admin(rename).await?;
+        assert_eq!(node.dht.get_subring(&rid).await?.name, "renamed");
+
+        // `MessageHandler::leave` leaves the ring, not the subring
+        SubRingOperator::leave(&node, "lonely").await?;
+        assert!(node.members("lonely").await?.is_empty());
+        tokio::fs::remove_dir_all(path).await.ok();
+        Ok(())
+    }
+}
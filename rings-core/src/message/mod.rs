@@ -6,6 +6,7 @@ pub use encoder::Encoded;
 pub use encoder::Encoder;
 
 mod payload;
+pub use payload::ChannelLabel;
 pub use payload::MessagePayload;
 pub use payload::OriginVerificationGen;
 pub use payload::PayloadSender;
@@ -14,6 +15,13 @@ mod types;
 pub use types::*;
 
 pub(self) mod handlers;
+pub use handlers::crawler::RingMap;
+pub use handlers::lookup::LookupMode;
+pub use handlers::pubsub::PubSub;
+pub use handlers::rpc::RpcHandler;
+pub use handlers::rpc::RpcHandlerFn;
+pub use handlers::storage::TChordStorage;
+pub use handlers::subring::SubRingOperator;
 pub use handlers::HandleMsg;
 pub use handlers::MessageCallback;
 pub use handlers::MessageHandler;
@@ -80,10 +80,27 @@ where T: Serialize + DeserializeOwned
         session_manager: &SessionManager,
         next_hop: Did,
         destination: Did,
+    ) -> Result<Self> {
+        Self::new_send_from(
+            data,
+            session_manager,
+            session_manager.authorizer()?.into(),
+            next_hop,
+            destination,
+        )
+    }
+
+    /// Create a SEND payload on behalf of `origin`, which is the authorizer or one of its virtual identities.
+    pub fn new_send_from(
+        data: T,
+        session_manager: &SessionManager,
+        origin: Did,
+        next_hop: Did,
+        destination: Did,
     ) -> Result<Self> {
         let relay = MessageRelay::new(
             RelayMethod::SEND,
-            vec![session_manager.authorizer()?.into()],
+            vec![origin],
             None,
             Some(next_hop),
             destination,
@@ -121,6 +138,12 @@ where T: Serialize + DeserializeOwned
         self.verification.verify(&self.data) && self.origin_verification.verify(&self.data)
     }
 
+    /// The authorizer of origin session. Unlike `addr` and `relay.origin()`,
+    /// it's signed by the origin and kept when the payload is forwarded.
+    pub fn origin_signer(&self) -> Did {
+        self.origin_verification.session.auth.authorizer.into()
+    }
+
     pub fn origin_session_pubkey(&self) -> Result<PublicKey> {
         self.origin_verification.session_pubkey(&self.data)
     }
@@ -177,6 +200,11 @@ where T: Serialize + DeserializeOwned
     }
 }
 
+/// Select the data channel a message is sent over.
+pub trait ChannelLabel {
+    fn channel_label(&self) -> &'static str;
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 pub trait PayloadSender<T>
@@ -193,10 +221,16 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static
         }
     }
 
+    /// Did that messages are sent from, the authorizer by default.
+    fn origin(&self) -> Result<Did> {
+        Ok(self.session_manager().authorizer()?.into())
+    }
+
     async fn send_message(&self, msg: T, next_hop: Did, destination: Did) -> Result<()> {
-        self.send_payload(MessagePayload::new_send(
+        self.send_payload(MessagePayload::new_send_from(
             msg,
             self.session_manager(),
+            self.origin()?,
             next_hop,
             destination,
         )?)
@@ -227,18 +261,20 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static
         .await
     }
 
+    /// Forward `payload` with `relay`, keeping its origin verification and tx_id.
     async fn transpond_payload(
         &self,
         payload: &MessagePayload<T>,
         relay: MessageRelay,
     ) -> Result<()> {
-        self.send_payload(MessagePayload::new(
+        let mut pl = MessagePayload::new(
             payload.data.clone(),
             self.session_manager(),
             OriginVerificationGen::Stick(payload.origin_verification.clone()),
             relay,
-        )?)
-        .await
+        )?;
+        pl.tx_id = payload.tx_id;
+        self.send_payload(pl).await
     }
 }
 
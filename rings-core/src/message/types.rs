@@ -2,13 +2,20 @@ use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::dht::subring::SubRingAdminAction;
+use crate::dht::vnode::VNodeRemoval;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::Did;
+use crate::dht::PeerRingTopology;
 use crate::ecc::elgamal;
 use crate::ecc::PublicKey;
 use crate::ecc::SecretKey;
 use crate::err::Error;
 use crate::err::Result;
+use crate::message::ChannelLabel;
+use crate::message::Encoded;
+use crate::transports::helper::CONTROL_CHANNEL;
+use crate::transports::helper::DEFAULT_CHANNEL;
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
 pub struct ConnectNodeSend {
@@ -37,6 +44,55 @@ pub struct FindSuccessorReport {
     pub then: FindSuccessorThen,
 }
 
+/// What an iterative lookup is looking for.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum LookupTarget {
+    /// The successor of an id
+    Successor,
+    /// The virtual node of an id
+    VNode,
+}
+
+/// Answer of a node to one step of an iterative lookup.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub enum LookupStep {
+    /// The successor of id is found
+    Successor(Did),
+    /// The virtual node is found
+    VNode(VirtualNode),
+    /// The node is responsible for the virtual node, but it's missing
+    NotFound,
+    /// Ask these nodes for the next step, ordered from the closest to id
+    Next(Vec<Did>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LookupStepSend {
+    pub id: Did,
+    pub target: LookupTarget,
+    /// Correlates the report with the query.
+    pub request_id: uuid::Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LookupStepReport {
+    pub id: Did,
+    pub step: LookupStep,
+    pub request_id: uuid::Uuid,
+}
+
+/// Ask a node for its routing state, used by crawler.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QueryTopologySend {
+    pub request_id: uuid::Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QueryTopologyReport {
+    pub topology: PeerRingTopology,
+    pub request_id: uuid::Uuid,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct NotifyPredecessorSend {
     pub id: Did,
@@ -47,6 +103,17 @@ pub struct NotifyPredecessorReport {
     pub id: Did,
 }
 
+/// Liveness probe, `id` is echoed back by `Pong`, round trip time is measured by the sender.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Ping {
+    pub id: uuid::Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Pong {
+    pub id: uuid::Uuid,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct JoinDHT {
     pub id: Did,
@@ -57,6 +124,25 @@ pub struct LeaveDHT {
     pub id: Did,
 }
 
+/// Announce virtual identities hosted by the sender.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct VirtualIdentities {
+    pub ids: Vec<Did>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NotifyLeave {
+    pub id: Did,
+    pub predecessor: Option<Did>,
+    pub successor: Option<Did>,
+    /// VNodes handed off to the predecessor, who takes over the range of leaving node.
+    pub data: Vec<VirtualNode>,
+    /// More VNodes are handed off by following notifications, which don't fit in one message.
+    /// The leaving node is disconnected by the last notification.
+    #[serde(default)]
+    pub partial: bool,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct SearchVNode {
     pub id: Did,
@@ -72,6 +158,21 @@ pub struct StoreVNode {
     pub data: Vec<VirtualNode>,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StoreReplica {
+    pub data: Vec<VirtualNode>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RemoveVNode {
+    pub data: VNodeRemoval,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RemoveReplica {
+    pub data: VNodeRemoval,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct MultiCall {
     pub messages: Vec<Message>,
@@ -87,9 +188,60 @@ pub struct JoinSubRing {
     pub did: Did,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LeaveSubRing {
+    pub did: Did,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AdminSubRing {
+    pub action: SubRingAdminAction,
+}
+
+/// Messages relayed for the receiver while it was offline, each one is an encoded payload.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RelayMessageDelivery {
+    pub data: Vec<Encoded>,
+}
+
+/// Acknowledge delivered relayed messages of `did`, by tx_id of payloads.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RelayMessageAck {
+    pub did: Did,
+    pub tx_ids: Vec<uuid::Uuid>,
+}
+
+/// A publication on topic, `members` are the subscribers which the receiver should forward to.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PublishTopic {
+    pub topic: Did,
+    pub data: Vec<u8>,
+    pub members: Vec<Did>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct CustomMessage(pub Vec<u8>);
 
+/// Request of an RPC call, answered by the handler of `method` on destination.
+/// `data` is encrypted if the caller knows the session key of destination.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RpcRequest {
+    pub id: uuid::Uuid,
+    pub method: String,
+    pub data: MaybeEncrypted<CustomMessage>,
+}
+
+/// Result of an RPC call, an error is told to the caller as its message.
+pub type RpcResult = std::result::Result<CustomMessage, String>;
+
+/// Response of an RPC call, matched with its request by `id`.
+/// `result` is encrypted by the session key of caller, which signed the request.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RpcResponse {
+    pub id: uuid::Uuid,
+    pub result: MaybeEncrypted<RpcResult>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum MaybeEncrypted<T> {
     Encrypted(Vec<(PublicKey, PublicKey)>),
@@ -111,19 +263,37 @@ pub enum Message {
     MultiCall(MultiCall),
     JoinDHT(JoinDHT),
     LeaveDHT(LeaveDHT),
+    VirtualIdentities(VirtualIdentities),
+    NotifyLeave(NotifyLeave),
     ConnectNodeSend(ConnectNodeSend),
     AlreadyConnected(AlreadyConnected),
     ConnectNodeReport(ConnectNodeReport),
     FindSuccessorSend(FindSuccessorSend),
     FindSuccessorReport(FindSuccessorReport),
+    LookupStepSend(LookupStepSend),
+    LookupStepReport(LookupStepReport),
+    QueryTopologySend(QueryTopologySend),
+    QueryTopologyReport(QueryTopologyReport),
     NotifyPredecessorSend(NotifyPredecessorSend),
     NotifyPredecessorReport(NotifyPredecessorReport),
+    Ping(Ping),
+    Pong(Pong),
     SearchVNode(SearchVNode),
     FoundVNode(FoundVNode),
     StoreVNode(StoreVNode),
+    StoreReplica(StoreReplica),
+    RemoveVNode(RemoveVNode),
+    RemoveReplica(RemoveReplica),
     SyncVNodeWithSuccessor(SyncVNodeWithSuccessor),
     JoinSubRing(JoinSubRing),
+    LeaveSubRing(LeaveSubRing),
+    AdminSubRing(AdminSubRing),
+    PublishTopic(PublishTopic),
+    RelayMessageDelivery(RelayMessageDelivery),
+    RelayMessageAck(RelayMessageAck),
     CustomMessage(MaybeEncrypted<CustomMessage>),
+    RpcRequest(RpcRequest),
+    RpcResponse(RpcResponse),
 }
 
 impl std::fmt::Display for Message {
@@ -140,6 +310,42 @@ impl Message {
     }
 }
 
+impl ChannelLabel for Message {
+    /// Stabilization and DHT control messages go over the control channel,
+    /// storage and application payloads over the default one.
+    fn channel_label(&self) -> &'static str {
+        let is_control = match self {
+            Message::MultiCall(m) => m
+                .messages
+                .iter()
+                .all(|m| m.channel_label() == CONTROL_CHANNEL),
+            Message::JoinDHT(_)
+            | Message::LeaveDHT(_)
+            | Message::VirtualIdentities(_)
+            | Message::NotifyLeave(_)
+            | Message::ConnectNodeSend(_)
+            | Message::AlreadyConnected(_)
+            | Message::ConnectNodeReport(_)
+            | Message::FindSuccessorSend(_)
+            | Message::FindSuccessorReport(_)
+            | Message::LookupStepSend(_)
+            | Message::LookupStepReport(_)
+            | Message::QueryTopologySend(_)
+            | Message::QueryTopologyReport(_)
+            | Message::NotifyPredecessorSend(_)
+            | Message::NotifyPredecessorReport(_)
+            | Message::Ping(_)
+            | Message::Pong(_) => true,
+            _ => false,
+        };
+        if is_control {
+            CONTROL_CHANNEL
+        } else {
+            DEFAULT_CHANNEL
+        }
+    }
+}
+
 impl<T> MaybeEncrypted<T>
 where T: Serialize + DeserializeOwned
 {
@@ -191,4 +397,27 @@ mod test {
         assert_eq!(plain, CustomMessage("hello".as_bytes().to_vec()));
         assert!(is_decrypted);
     }
+
+    #[test]
+    fn test_channel_label() {
+        let id: Did = SecretKey::random().address().into();
+        let join = Message::JoinDHT(JoinDHT { id });
+        let custom = Message::custom("hello".as_bytes(), &None).unwrap();
+        assert_eq!(join.channel_label(), CONTROL_CHANNEL);
+        assert_eq!(custom.channel_label(), DEFAULT_CHANNEL);
+        assert_eq!(
+            Message::MultiCall(MultiCall {
+                messages: vec![join.clone(), join.clone()]
+            })
+            .channel_label(),
+            CONTROL_CHANNEL
+        );
+        assert_eq!(
+            Message::MultiCall(MultiCall {
+                messages: vec![join, custom]
+            })
+            .channel_label(),
+            DEFAULT_CHANNEL
+        );
+    }
 }
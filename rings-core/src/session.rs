@@ -83,16 +83,26 @@ impl Session {
     }
 
     pub fn is_expired(&self) -> bool {
+        self.is_expired_at(utils::get_epoch_ms())
+    }
+
+    /// Check if the session is expired at `ts_ms`.
+    pub fn is_expired_at(&self, ts_ms: u128) -> bool {
         if let Ttl::Some(ttl_ms) = self.auth.ttl_ms {
-            let now = utils::get_epoch_ms();
-            now > self.auth.ts_ms + ttl_ms as u128
+            ts_ms > self.auth.ts_ms + ttl_ms as u128
         } else {
             false
         }
     }
 
     pub fn verify(&self) -> bool {
-        if self.is_expired() {
+        self.verify_at(utils::get_epoch_ms())
+    }
+
+    /// Verify the session as it was at `ts_ms`,
+    /// for data signed in the session and kept after it expires.
+    pub fn verify_at(&self, ts_ms: u128) -> bool {
+        if self.is_expired_at(ts_ms) {
             return false;
         }
         if let Ok(auth_str) = self.auth.to_string() {
@@ -0,0 +1,288 @@
+//! Bounded in-memory cache with LRU and TTL eviction.
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::utils::get_epoch_ms;
+
+/// Counters of a cache since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Number of lookups answered by the cache.
+    pub hits: u64,
+    /// Number of lookups missed, including those found expired.
+    pub misses: u64,
+    /// Number of entries dropped to make room for new ones.
+    pub evictions: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    expired_at: u128,
+    /// tick of last access, key of the entry in `Inner::order`
+    tick: u64,
+    /// tick of insertion, with `expired_at` key of the entry in `Inner::expiry`
+    inserted: u64,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// keys ordered from the least recently used
+    order: BTreeMap<u64, K>,
+    /// keys ordered from the earliest to expire
+    expiry: BTreeMap<(u128, u64), K>,
+    tick: u64,
+}
+
+impl<K, V> Inner<K, V>
+where K: Copy + Eq + Hash
+{
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        self.expiry.remove(&(entry.expired_at, entry.inserted));
+        Some(entry.value)
+    }
+
+    /// Drop the entry expiring first if it's expired.
+    fn pop_expired(&mut self, now: u128) -> bool {
+        match self.expiry.iter().next() {
+            Some((&(expired_at, _), &key)) if expired_at <= now => {
+                self.remove(&key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn prune(&mut self, now: u128) -> usize {
+        let mut n = 0;
+        while self.pop_expired(now) {
+            n += 1;
+        }
+        n
+    }
+}
+
+/// A cache keeping at most `capacity` entries, each for at most `ttl_ms` milliseconds.
+/// When full, an expired entry is dropped first, otherwise the least recently used one.
+/// Entries are only invalidated by `remove` or their expiry, so a cached copy of a value
+/// updated elsewhere is served until it expires.
+pub struct LruStorage<K, V> {
+    inner: Mutex<Inner<K, V>>,
+    capacity: usize,
+    ttl_ms: u128,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<K, V> LruStorage<K, V>
+where
+    K: Copy + Eq + Hash,
+    V: Clone,
+{
+    pub fn new(capacity: usize, ttl_ms: u64) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                expiry: BTreeMap::new(),
+                tick: 0,
+            }),
+            capacity,
+            ttl_ms: ttl_ms as u128,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    // a panic while holding the lock cannot leave the cache inconsistent,
+    // so a poisoned lock is still usable
+    fn lock(&self) -> MutexGuard<Inner<K, V>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Get a live entry and mark it as recently used.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let now = get_epoch_ms();
+        let mut inner = self.lock();
+        let tick = inner.next_tick();
+        let found = match inner.entries.get_mut(key) {
+            Some(e) if e.expired_at > now => {
+                let prev = e.tick;
+                e.tick = tick;
+                Some((prev, e.value.clone()))
+            }
+            Some(_) => {
+                inner.remove(key);
+                None
+            }
+            None => None,
+        };
+        match found {
+            Some((prev, value)) => {
+                inner.order.remove(&prev);
+                inner.order.insert(tick, *key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Put an entry which expires after the TTL of cache.
+    pub fn set(&self, key: &K, value: V) {
+        self.set_with_expiry(key, value, None)
+    }
+
+    /// Put an entry which expires after the TTL of cache, or at `expired_at` if it's earlier.
+    pub fn set_with_expiry(&self, key: &K, value: V, expired_at: Option<u128>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = get_epoch_ms();
+        let expired_at = match expired_at {
+            Some(ts) => ts.min(now + self.ttl_ms),
+            None => now + self.ttl_ms,
+        };
+        let mut inner = self.lock();
+        inner.remove(key);
+        if inner.entries.len() >= self.capacity && !inner.pop_expired(now) {
+            if let Some(lru) = inner.order.values().next().copied() {
+                inner.remove(&lru);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let tick = inner.next_tick();
+        inner.order.insert(tick, *key);
+        inner.expiry.insert((expired_at, tick), *key);
+        inner.entries.insert(*key, Entry {
+            value,
+            expired_at,
+            tick,
+            inserted: tick,
+        });
+    }
+
+    /// Invalidate an entry.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.lock().remove(key)
+    }
+
+    /// Drop all expired entries, return the number of dropped ones.
+    pub fn prune(&self) -> usize {
+        self.lock().prune(get_epoch_ms())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::Address;
+
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = LruStorage::<Address, u8>::new(2, 60000);
+        let (a, b, c) = (
+            SecretKey::random().address(),
+            SecretKey::random().address(),
+            SecretKey::random().address(),
+        );
+        cache.set(&a, 1);
+        cache.set(&b, 2);
+        // a is used after b, so b is the least recently used
+        assert_eq!(cache.get(&a), Some(1));
+        cache.set(&c, 3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(cache.get(&a), Some(1));
+        assert_eq!(cache.get(&c), Some(3));
+
+        // update of an existing entry evicts nothing
+        cache.set(&a, 4);
+        assert_eq!(cache.get(&a), Some(4));
+        assert_eq!(cache.get(&c), Some(3));
+
+        assert_eq!(cache.remove(&a), Some(4));
+        assert_eq!(cache.get(&a), None);
+        assert_eq!(cache.stats(), CacheStats {
+            hits: 5,
+            misses: 2,
+            evictions: 1,
+        });
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let cache = LruStorage::<Address, u8>::new(2, 60000);
+        let (a, b, c) = (
+            SecretKey::random().address(),
+            SecretKey::random().address(),
+            SecretKey::random().address(),
+        );
+        cache.set_with_expiry(&a, 1, Some(get_epoch_ms() - 1));
+        cache.set(&b, 2);
+        assert_eq!(cache.get(&a), None);
+        assert_eq!(cache.len(), 1);
+
+        // expired entries are dropped before live ones
+        cache.set_with_expiry(&a, 1, Some(get_epoch_ms() - 1));
+        cache.set(&c, 3);
+        assert_eq!(cache.get(&b), Some(2));
+        assert_eq!(cache.get(&c), Some(3));
+        assert_eq!(cache.stats().evictions, 0);
+        // both indexes follow the entries
+        let inner = cache.lock();
+        assert_eq!(inner.order.len(), 2);
+        assert_eq!(inner.expiry.len(), 2);
+        drop(inner);
+
+        let cache = LruStorage::<Address, u8>::new(2, 0);
+        cache.set(&a, 1);
+        assert_eq!(cache.prune(), 1);
+        assert!(cache.is_empty());
+
+        let cache = LruStorage::<Address, u8>::new(0, 60000);
+        cache.set(&a, 1);
+        assert!(cache.is_empty());
+    }
+}
@@ -1,6 +1,9 @@
+mod lru;
 mod memory;
 pub mod persistence;
 
+pub use lru::CacheStats;
+pub use lru::LruStorage;
 pub use memory::MemStorage;
 
 #[cfg(feature = "wasm")]
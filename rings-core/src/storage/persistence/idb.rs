@@ -1,9 +1,6 @@
 #![warn(missing_docs)]
 
 //! Storage for wasm
-use std::mem::size_of_val;
-use std::ops::Add;
-use std::ops::Sub;
 use std::str::FromStr;
 
 use async_trait::async_trait;
@@ -21,6 +18,7 @@ use super::PersistenceStorageReadAndWrite;
 use super::PersistenceStorageRemove;
 use crate::err::Error;
 use crate::err::Result;
+use crate::utils::get_epoch_ms;
 
 /// Default IndexedDB database and storage name
 pub const DEFAULT_REXIE_STORE_NAME: &str = "rings-storage";
@@ -32,6 +30,8 @@ struct DataStruct<T> {
     last_visit_time: i64,
     visit_count: u64,
     created_time: i64,
+    #[serde(default)]
+    expired_at: Option<u128>,
     data: T,
 }
 
@@ -44,12 +44,28 @@ impl<T> DataStruct<T> {
             last_visit_time: time_now,
             created_time: time_now,
             visit_count: 0,
+            expired_at: None,
             data,
         }
     }
+
+    /// Check if the entry is expired
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expired_at, Some(ts) if ts <= get_epoch_ms())
+    }
+}
+
+impl<T: Serialize> DataStruct<T> {
+    /// Size of key and JSON encoded data in bytes, visit times are not counted
+    pub fn size(&self) -> usize {
+        self.key.len() + serde_json::to_string(&self.data).map_or(0, |s| s.len())
+    }
 }
 
 /// StorageInstance struct
+///
+/// When the total size of keys and values exceeds `cap`, expired entries are dropped,
+/// then least recently visited entries are evicted until the storage fits in `cap`.
 pub struct IDBStorage {
     db: Rexie,
     cap: usize,
@@ -64,14 +80,14 @@ pub trait IDBStorageBasic {
 
 impl IDBStorage {
     /// New IDBStorage
-    /// * cap: rows of data limit
+    /// * cap: max_size in bytes
     pub async fn new_with_cap(cap: usize) -> Result<Self> {
         Self::new_with_cap_and_name(cap, DEFAULT_REXIE_STORE_NAME).await
     }
 
-    /// New IDBStorage with default capacity 50000 rows data limit
+    /// New IDBStorage with default capacity 50 megabytes
     pub async fn new() -> Result<Self> {
-        Self::new_with_cap(50000).await
+        Self::new_with_cap(50000000).await
     }
 
     /// New IDBStorage
@@ -83,6 +99,7 @@ impl IDBStorage {
     }
 
     /// New IDBStorage with capacity and name
+    /// * cap: max_size in bytes
     pub async fn new_with_cap_and_name(cap: usize, name: &str) -> Result<Self> {
         if cap == 0 {
             return Err(Error::InvalidCapacity);
@@ -139,6 +156,11 @@ where
         let k: JsValue = JsValue::from(key.to_string());
         let v = store.get(&k).await.map_err(Error::IDBError)?;
         let mut v: DataStruct<V> = v.into_serde().map_err(Error::Deserialize)?;
+        if v.is_expired() {
+            store.delete(&k).await.map_err(Error::IDBError)?;
+            tx.done().await.map_err(Error::IDBError)?;
+            return Err(Error::EntryNotFound);
+        }
         v.last_visit_time = chrono::Utc::now().timestamp_millis();
         v.visit_count += 1;
         store
@@ -162,27 +184,36 @@ where
         Ok(entries
             .iter()
             .filter_map(|(k, v)| {
-                Some((
-                    K::from_str(k.as_string().unwrap().as_str()).ok()?,
-                    v.into_serde::<DataStruct<V>>().unwrap().data,
-                ))
+                let v = v.into_serde::<DataStruct<V>>().ok()?;
+                if v.is_expired() {
+                    return None;
+                }
+                Some((K::from_str(k.as_string()?.as_str()).ok()?, v.data))
             })
             .collect::<Vec<(K, V)>>())
     }
 
     async fn put(&self, key: &K, entry: &V) -> Result<()> {
-        self.prune().await?;
+        self.put_with_expiry(key, entry, None).await
+    }
+
+    /// Storage will be pruned if its size exceeds cap.
+    async fn put_with_expiry(&self, key: &K, entry: &V, expired_at: Option<u128>) -> Result<()> {
         let (tx, store) = self.get_tx_store(TransactionMode::ReadWrite)?;
+        let mut data = DataStruct::new(key.to_string().as_str(), entry);
+        data.expired_at = expired_at;
         store
             .put(
-                &JsValue::from_serde(&DataStruct::new(key.to_string().as_str(), entry))
-                    .map_err(Error::Serialize)?,
+                &JsValue::from_serde(&data).map_err(Error::Serialize)?,
                 //Some(&key.into()),
                 None,
             )
             .await
             .map_err(Error::IDBError)?;
         tx.done().await.map_err(Error::IDBError)?;
+        if self.total_size().await? > self.max_size().await? {
+            self.prune().await?;
+        }
         Ok(())
     }
 }
@@ -229,43 +260,69 @@ impl PersistenceStorageOperation for IDBStorage {
             .get_all(None, None, None, None)
             .await
             .map_err(Error::IDBError)?;
-        let size = entries
-            .iter()
-            .map(|(k, v)| size_of_val(k) + size_of_val(v))
-            .sum::<usize>();
+        let mut size = 0;
+        for (_k, value) in entries.iter() {
+            let data_entry: DataStruct<serde_json::Value> =
+                value.into_serde().map_err(Error::Serialize)?;
+            size += data_entry.size();
+        }
         Ok(size)
     }
 
+    /// Drop expired entries, then evict least recently visited entries until size fits in cap.
     async fn prune(&self) -> Result<()> {
+        self.remove_expired().await?;
         let (tx, store) = self.get_tx_store(TransactionMode::ReadWrite)?;
-        let count = store.count(None).await.map_err(Error::IDBError)? as usize;
-        if count < self.cap {
-            return Ok(());
-        }
-        let delete_count = count.sub(self.cap).add(1);
-        let delete_count = u32::try_from(delete_count).unwrap_or(0);
-        if delete_count == 0 {
-            return Ok(());
-        }
-
         let item_index = store.index("last_visit_time").map_err(Error::IDBError)?;
         let entries = item_index
-            .get_all(None, Some(delete_count), None, None)
+            .get_all(None, None, None, None)
             .await
             .map_err(Error::IDBError)?;
-        log::debug!("entries: {:?}", entries);
-        if let Some((_k, value)) = entries.first() {
+        let mut data_entries = vec![];
+        for (_k, value) in entries.iter() {
             let data_entry: DataStruct<serde_json::Value> =
                 value.into_serde().map_err(Error::Serialize)?;
+            data_entries.push(data_entry);
+        }
+        let mut size = data_entries.iter().map(|e| e.size()).sum::<usize>();
+        // entries are ordered by last visit time, the least recently visited go first
+        for data_entry in data_entries.iter() {
+            if size <= self.cap {
+                break;
+            }
+            log::debug!("evict entry: {:?}", data_entry.key);
             store
                 .delete(&JsValue::from(&data_entry.key))
                 .await
                 .map_err(Error::IDBError)?;
+            size = size.saturating_sub(data_entry.size());
         }
         tx.done().await.map_err(Error::IDBError)?;
         Ok(())
     }
 
+    async fn remove_expired(&self) -> Result<usize> {
+        let (tx, store) = self.get_tx_store(TransactionMode::ReadWrite)?;
+        let entries = store
+            .get_all(None, None, None, None)
+            .await
+            .map_err(Error::IDBError)?;
+        let mut count = 0;
+        for (_k, value) in entries.iter() {
+            let data_entry: DataStruct<serde_json::Value> =
+                value.into_serde().map_err(Error::Serialize)?;
+            if data_entry.is_expired() {
+                store
+                    .delete(&JsValue::from(&data_entry.key))
+                    .await
+                    .map_err(Error::IDBError)?;
+                count += 1;
+            }
+        }
+        tx.done().await.map_err(Error::IDBError)?;
+        Ok(count)
+    }
+
     async fn close(self) -> Result<()> {
         self.db.close();
         Ok(())
@@ -2,8 +2,11 @@
 #![allow(clippy::ptr_offset_with_cast)]
 //! Persistence Storage for default, use `sled` as backend db.
 use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
 use async_trait::async_trait;
+use dashmap::DashMap;
 use itertools::Itertools;
 use serde::de::DeserializeOwned;
 use sled;
@@ -13,15 +16,71 @@ use super::PersistenceStorageReadAndWrite;
 use super::PersistenceStorageRemove;
 use crate::err::Error;
 use crate::err::Result;
+use crate::utils::get_epoch_ms;
 
 trait KvStorageBasic {
     fn get_db(&self) -> &sled::Db;
+    fn get_expiry_tree(&self) -> &sled::Tree;
+    fn get_visits(&self) -> &DashMap<Vec<u8>, u128>;
+    fn get_size(&self) -> &AtomicUsize;
+    fn get_cap(&self) -> usize;
+
+    /// Record the visit time of an entry, in memory, so reading doesn't write to db.
+    fn touch(&self, key: &[u8]) {
+        self.get_visits().insert(key.to_vec(), get_epoch_ms());
+    }
+
+    /// Check if an entry is expired
+    fn is_expired(&self, key: &[u8]) -> Result<bool> {
+        Ok(
+            match self.get_expiry_tree().get(key).map_err(Error::SledError)? {
+                Some(v) => decode_ms(&v) <= get_epoch_ms(),
+                None => false,
+            },
+        )
+    }
+
+    /// Remove an entry with its metadata
+    fn remove_entry(&self, key: &[u8]) -> Result<()> {
+        if let Some(v) = self.get_db().remove(key).map_err(Error::SledError)? {
+            sub_size(self.get_size(), key.len() + v.len());
+        }
+        self.get_expiry_tree()
+            .remove(key)
+            .map_err(Error::SledError)?;
+        self.get_visits().remove(key);
+        Ok(())
+    }
+}
+
+fn decode_ms(v: &[u8]) -> u128 {
+    v.try_into().map(u128::from_be_bytes).unwrap_or(0)
+}
+
+fn sub_size(size: &AtomicUsize, n: usize) {
+    size.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| {
+        Some(s.saturating_sub(n))
+    })
+    .ok();
 }
 
 /// StorageInstance struct
+///
+/// When the total size of keys and values exceeds `cap`, entries are evicted by:
+/// 1. dropping expired entries;
+/// 2. evicting least recently visited entries, until the storage fits in `cap`.
+///
+/// Visit times are only kept in memory, entries not visited since the storage
+/// is opened are treated as least recently visited.
 #[allow(dead_code)]
 pub struct KvStorage {
     db: sled::Db,
+    /// expiry time of entries in milliseconds
+    expiry: sled::Tree,
+    /// last visit time of entries in milliseconds
+    visits: DashMap<Vec<u8>, u128>,
+    /// total size of keys and values in bytes
+    size: AtomicUsize,
     cap: usize,
     path: String,
 }
@@ -38,8 +97,13 @@ impl KvStorage {
             .cache_capacity(cap as u64)
             .open()
             .map_err(Error::SledError)?;
+        let expiry = db.open_tree("expiry").map_err(Error::SledError)?;
+        let size = db.iter().flatten().map(|(k, v)| k.len() + v.len()).sum();
         Ok(Self {
             db,
+            expiry,
+            visits: DashMap::new(),
+            size: AtomicUsize::new(size),
             cap,
             path: path.as_ref().to_string_lossy().to_string(),
         })
@@ -87,12 +151,31 @@ impl KvStorageBasic for KvStorage {
     fn get_db(&self) -> &sled::Db {
         &self.db
     }
+
+    fn get_expiry_tree(&self) -> &sled::Tree {
+        &self.expiry
+    }
+
+    fn get_visits(&self) -> &DashMap<Vec<u8>, u128> {
+        &self.visits
+    }
+
+    fn get_size(&self) -> &AtomicUsize {
+        &self.size
+    }
+
+    fn get_cap(&self) -> usize {
+        self.cap
+    }
 }
 
 #[async_trait]
 impl PersistenceStorageOperation for KvStorage {
     async fn clear(&self) -> Result<()> {
         self.db.clear().map_err(Error::SledError)?;
+        self.expiry.clear().map_err(Error::SledError)?;
+        self.visits.clear();
+        self.size.store(0, Ordering::SeqCst);
         // self.db.flush_async().await.map_err(Error::SledError)?;
         Ok(())
     }
@@ -105,16 +188,49 @@ impl PersistenceStorageOperation for KvStorage {
         Ok(self.cap)
     }
 
-    /// Get the storage size, if applicable.
     async fn total_size(&self) -> Result<usize> {
-        Ok(self.db.len())
+        Ok(self.size.load(Ordering::SeqCst))
     }
 
     /// Prune database storage
+    /// Drop expired entries, then evict least recently visited entries until size fits in cap.
     async fn prune(&self) -> Result<()> {
+        self.remove_expired().await?;
+        if self.size.load(Ordering::SeqCst) <= self.cap {
+            return Ok(());
+        }
+        let visits = self
+            .db
+            .iter()
+            .keys()
+            .flatten()
+            .map(|k| (self.visits.get(k.as_ref()).map(|v| *v).unwrap_or(0), k))
+            .sorted_by_key(|(ts, _)| *ts)
+            .collect_vec();
+        for (_, k) in visits {
+            if self.size.load(Ordering::SeqCst) <= self.cap {
+                break;
+            }
+            self.remove_entry(&k)?;
+        }
         Ok(())
     }
 
+    async fn remove_expired(&self) -> Result<usize> {
+        let now = get_epoch_ms();
+        let expired = self
+            .expiry
+            .iter()
+            .flatten()
+            .filter(|(_, v)| decode_ms(v) <= now)
+            .map(|(k, _)| k)
+            .collect_vec();
+        for k in expired.iter() {
+            self.remove_entry(k)?;
+        }
+        Ok(expired.len())
+    }
+
     async fn close(self) -> Result<()> {
         Ok(())
     }
@@ -131,21 +247,47 @@ where
     async fn get(&self, key: &K) -> Result<V> {
         let k = key.to_string();
         let k = k.as_bytes();
+        if self.is_expired(k)? {
+            self.remove_entry(k)?;
+            return Err(Error::EntryNotFound);
+        }
         let v = self
             .get_db()
             .get(k)
             .map_err(Error::SledError)?
             .ok_or(Error::EntryNotFound)?;
+        self.touch(k);
         bincode::deserialize(v.as_ref()).map_err(Error::BincodeDeserialize)
     }
 
     /// Put `entry` in the cache under `key`.
     async fn put(&self, key: &K, value: &V) -> Result<()> {
-        self.prune().await?;
+        self.put_with_expiry(key, value, None).await
+    }
+
+    /// Put `entry` in the cache under `key`, with an optional expiry time.
+    /// Storage will be pruned if its size exceeds cap.
+    async fn put_with_expiry(&self, key: &K, value: &V, expired_at: Option<u128>) -> Result<()> {
+        let k = key.to_string();
+        let k = k.as_bytes();
         let data = bincode::serialize(value).map_err(Error::BincodeSerialize)?;
-        self.get_db()
-            .insert(key.to_string().as_bytes(), data)
-            .map_err(Error::SledError)?;
+        let size = k.len() + data.len();
+        if let Some(old) = self.get_db().insert(k, data).map_err(Error::SledError)? {
+            sub_size(self.get_size(), k.len() + old.len());
+        }
+        self.get_size().fetch_add(size, Ordering::SeqCst);
+        match expired_at {
+            Some(ts) => self
+                .get_expiry_tree()
+                .insert(k, ts.to_be_bytes().to_vec())
+                .map(|_| ()),
+            None => self.get_expiry_tree().remove(k).map(|_| ()),
+        }
+        .map_err(Error::SledError)?;
+        self.touch(k);
+        if self.get_size().load(Ordering::SeqCst) > self.get_cap() {
+            self.prune().await?;
+        }
         Ok(())
     }
 
@@ -153,6 +295,7 @@ where
         let iter = self.get_db().iter();
         Ok(iter
             .flatten()
+            .filter(|(k, _)| !self.is_expired(k).unwrap_or(false))
             .flat_map(|(k, v)| {
                 Some((
                     K::from_str(std::str::from_utf8(k.as_ref()).ok()?).ok()?,
@@ -170,10 +313,7 @@ where
     I: PersistenceStorageOperation + std::marker::Sync + KvStorageBasic,
 {
     async fn remove(&self, key: &K) -> Result<()> {
-        self.get_db()
-            .remove(key.to_string().as_bytes())
-            .map_err(Error::SledError)?;
-        Ok(())
+        self.remove_entry(key.to_string().as_bytes())
     }
 }
 
@@ -242,4 +382,70 @@ mod test {
         storage.get_db().flush_async().await.unwrap();
         drop(storage)
     }
+
+    #[tokio::test]
+    async fn test_kv_storage_expiry() {
+        let path = KvStorage::random_path("./tmp");
+        let storage = KvStorage::new_with_cap_and_path(4096, path.as_str())
+            .await
+            .unwrap();
+        let data = TestStorageStruct {
+            content: "test".to_string(),
+        };
+        let now = get_epoch_ms();
+        storage
+            .put_with_expiry(&"expired".to_owned(), &data, Some(now - 1))
+            .await
+            .unwrap();
+        storage
+            .put_with_expiry(&"alive".to_owned(), &data, Some(now + 60000))
+            .await
+            .unwrap();
+        storage.put(&"forever".to_owned(), &data).await.unwrap();
+        assert_eq!(storage.count().await.unwrap(), 3);
+
+        let got: Result<TestStorageStruct> = storage.get(&"expired".to_owned()).await;
+        assert!(got.is_err());
+        let all_entries: Vec<(String, TestStorageStruct)> = storage.get_all().await.unwrap();
+        assert_eq!(all_entries.len(), 2);
+
+        storage
+            .put_with_expiry(&"expired".to_owned(), &data, Some(now - 1))
+            .await
+            .unwrap();
+        assert_eq!(storage.remove_expired().await.unwrap(), 1);
+        assert_eq!(storage.count().await.unwrap(), 2);
+        storage.delete().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kv_storage_evict_least_recently_visited() {
+        let path = KvStorage::random_path("./tmp");
+        let data = TestStorageStruct {
+            content: "a string with thirty two bytes..".to_string(),
+        };
+        // each entry takes 5 bytes of key and 40 bytes of value
+        let storage = KvStorage::new_with_cap_and_path(100, path.as_str())
+            .await
+            .unwrap();
+        let (key1, key2, key3) = ("test1".to_owned(), "test2".to_owned(), "test3".to_owned());
+
+        storage.put(&key1, &data).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        storage.put(&key2, &data).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let _: TestStorageStruct = storage.get(&key1).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(storage.total_size().await.unwrap(), 90);
+
+        // key2 is least recently visited, and should be evicted
+        storage.put(&key3, &data).await.unwrap();
+        assert_eq!(storage.count().await.unwrap(), 2);
+        assert_eq!(storage.total_size().await.unwrap(), 90);
+        let got: Result<TestStorageStruct> = storage.get(&key2).await;
+        assert!(got.is_err());
+        let got: Result<TestStorageStruct> = storage.get(&key1).await;
+        assert!(got.is_ok());
+        storage.delete().await.unwrap();
+    }
 }
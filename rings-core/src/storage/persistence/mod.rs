@@ -20,6 +20,10 @@ pub trait PersistenceStorageReadAndWrite<K, V>: PersistenceStorageOperation {
     /// Put `entry` in the cache under `key`.
     async fn put(&self, key: &K, entry: &V) -> Result<()>;
 
+    /// Put `entry` in the cache under `key`, which will be dropped after `expired_at`.
+    /// * expired_at: timestamp in milliseconds, `None` means never expire.
+    async fn put_with_expiry(&self, key: &K, entry: &V, expired_at: Option<u128>) -> Result<()>;
+
     async fn get_all(&self) -> Result<Vec<(K, V)>>;
 }
 
@@ -39,18 +43,23 @@ pub trait PersistenceStorageOperation {
     /// All `Entry` will be deleted.
     async fn clear(&self) -> Result<()>;
 
-    /// Get the current storage usage, if applicable.
+    /// Get the number of entries.
     async fn count(&self) -> Result<u64>;
 
-    /// Get the maximum storage size, if applicable.
+    /// Get the maximum size of keys and values in bytes.
     async fn max_size(&self) -> Result<usize>;
 
-    /// Get the storage size, if applicable.
+    /// Get the total size of keys and values in bytes.
     async fn total_size(&self) -> Result<usize>;
 
-    /// Prune database storage
+    /// Prune database storage.
+    /// Expired entries are dropped first, then least recently visited entries
+    /// are evicted until the storage fits in `max_size`.
     async fn prune(&self) -> Result<()>;
 
+    /// Drop expired entries, return the number of dropped entries.
+    async fn remove_expired(&self) -> Result<usize>;
+
     /// Close db instance
     async fn close(self) -> Result<()>;
 }
@@ -12,31 +12,58 @@ use serde::Serialize;
 use web3::types::Address;
 
 use crate::channels::Channel;
+use crate::dht::Did;
 use crate::err::Error;
 use crate::err::Result;
 use crate::message;
+use crate::message::ChannelLabel;
 use crate::message::Decoder;
+use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::Message;
 use crate::message::MessagePayload;
 use crate::message::PayloadSender;
 use crate::session::SessionManager;
 use crate::storage::MemStorage;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::TricklePayload;
+use crate::transports::helper::SEND_BUFFER_HIGH_WATERMARK;
+use crate::transports::helper::SEND_BUFFER_LOW_WATERMARK;
+use crate::transports::Network;
 use crate::transports::Transport;
+use crate::transports::TransportKind;
 use crate::types::channel::Channel as ChannelTrait;
 use crate::types::channel::Event;
 use crate::types::ice_transport::IceServer;
 use crate::types::ice_transport::IceTransport;
-use crate::types::ice_transport::IceTransportCallback;
+use crate::utils::get_epoch_ms;
+
+/// Liveness of a connected peer, measured by Ping/Pong.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerLiveness {
+    /// Round trip time of the last answered ping, in milliseconds.
+    pub rtt: Option<u64>,
+    /// Number of pings sent since the last pong.
+    pub missed_pings: u8,
+}
 
 pub struct Swarm {
     table: MemStorage<Address, Arc<Transport>>,
+    liveness: MemStorage<Address, PeerLiveness>,
+    /// unanswered pings, mapped to the pinged address and local send time
+    pings: MemStorage<uuid::Uuid, (Address, u128)>,
+    /// tx_id of handled messages with their expiry, for duplicate suppression
+    seen: MemStorage<uuid::Uuid, u128>,
+    /// virtual identities of nodes, mapped to address of the hosting node
+    aliases: MemStorage<Address, Address>,
     pending: Arc<Mutex<Vec<Arc<Transport>>>>,
     ice_servers: Vec<IceServer>,
     transport_event_channel: Channel<Event>,
     session_manager: SessionManager,
     address: Address,
     external_address: Option<String>,
+    network: Network,
+    transport_kind: TransportKind,
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -60,6 +87,23 @@ impl Swarm {
         address: Address,
         session_manager: SessionManager,
         external_address: Option<String>,
+    ) -> Self {
+        Self::new_with_network(
+            ice_servers,
+            address,
+            session_manager,
+            external_address,
+            Network::default(),
+        )
+    }
+
+    /// Create a swarm which only connects to peers of `network`.
+    pub fn new_with_network(
+        ice_servers: &str,
+        address: Address,
+        session_manager: SessionManager,
+        external_address: Option<String>,
+        network: Network,
     ) -> Self {
         let ice_servers = ice_servers
             .split(';')
@@ -69,12 +113,18 @@ impl Swarm {
             .collect::<Vec<IceServer>>();
         Self {
             table: MemStorage::<Address, Arc<Transport>>::new(),
+            liveness: MemStorage::<Address, PeerLiveness>::new(),
+            pings: MemStorage::<uuid::Uuid, (Address, u128)>::new(),
+            seen: MemStorage::<uuid::Uuid, u128>::new(),
+            aliases: MemStorage::<Address, Address>::new(),
             transport_event_channel: Channel::new(),
             ice_servers,
             address,
             session_manager,
             pending: Arc::new(Mutex::new(vec![])),
             external_address,
+            network,
+            transport_kind: TransportKind::default(),
         }
     }
 
@@ -90,6 +140,34 @@ impl Swarm {
         &self.session_manager
     }
 
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// Set the kind of transports created by `new_transport`.
+    pub fn set_transport_kind(&mut self, kind: TransportKind) {
+        self.transport_kind = kind;
+    }
+
+    /// Create a transport to answer the handshake `offer`, of the kind chosen by the offering peer.
+    pub async fn new_transport_for(&self, offer: &Encoded) -> Result<Arc<Transport>> {
+        let payload: MessagePayload<TricklePayload> = offer.decode()?;
+        self.new_transport_of(payload.data.kind).await
+    }
+
+    /// Create a transport of `kind`, for a peer which is reached by it.
+    pub async fn new_transport_of(&self, kind: TransportKind) -> Result<Arc<Transport>> {
+        let event_sender = self.transport_event_channel.sender();
+        let mut transport = Transport::new_of(kind, event_sender)?;
+        transport.set_network(self.network.clone());
+        transport
+            .start(self.ice_servers.clone(), self.external_address.clone())
+            .await?
+            .apply_callback()
+            .await?;
+        Ok(Arc::new(transport))
+    }
+
     async fn load_message(
         &self,
         ev: Result<Option<Event>>,
@@ -158,6 +236,18 @@ impl Swarm {
         }
     }
 
+    /// Load the messages queued for now, without waiting for more.
+    pub async fn poll_queued_messages(&self) -> Vec<MessagePayload<Message>> {
+        let receiver = &self.transport_event_channel.receiver();
+        let mut messages = vec![];
+        while let Ok(Some(ev)) = Channel::try_recv(receiver).await {
+            if let Ok(Some(msg)) = self.load_message(Ok(Some(ev))).await {
+                messages.push(msg);
+            }
+        }
+        messages
+    }
+
     pub async fn iter_messages<'a, 'b>(
         &'a self,
     ) -> impl Stream<Item = MessagePayload<Message>> + 'b
@@ -203,6 +293,118 @@ impl Swarm {
         Ok(pending.iter().cloned().collect::<Vec<_>>())
     }
 
+    /// Get liveness of a peer, `None` if it was never pinged.
+    pub fn liveness(&self, address: &Address) -> Option<PeerLiveness> {
+        self.liveness.get(address)
+    }
+
+    /// Record a ping going to be sent to a peer, return id of the ping.
+    /// The send time is kept locally, so a peer cannot forge its round trip time.
+    pub fn record_ping(&self, address: &Address) -> uuid::Uuid {
+        let mut liveness = self.liveness.get(address).unwrap_or_default();
+        liveness.missed_pings = liveness.missed_pings.saturating_add(1);
+        self.liveness.set(address, liveness);
+        let id = uuid::Uuid::new_v4();
+        self.pings.set(&id, (*address, get_epoch_ms()));
+        id
+    }
+
+    /// Record a pong from a peer, with id of the ping it answers.
+    /// Return `None` if the ping is unknown or was not sent to that peer.
+    pub fn record_pong(&self, address: &Address, ping_id: &uuid::Uuid) -> Option<PeerLiveness> {
+        match self.pings.get(ping_id) {
+            Some((pinged, sent_at)) if pinged == *address => {
+                // earlier pings to the peer are answered by this pong as well
+                for (id, (addr, _)) in self.pings.items() {
+                    if addr == *address {
+                        self.pings.remove(&id);
+                    }
+                }
+                let liveness = PeerLiveness {
+                    rtt: Some(get_epoch_ms().saturating_sub(sent_at) as u64),
+                    missed_pings: 0,
+                };
+                self.liveness.set(address, liveness);
+                Some(liveness)
+            }
+            _ => None,
+        }
+    }
+
+    /// Remember `tx_id` of a handled message for `ttl_ms`, return false if it's seen before.
+    pub fn mark_seen(&self, tx_id: &uuid::Uuid, ttl_ms: u128) -> bool {
+        let now = get_epoch_ms();
+        match self.seen.get(tx_id) {
+            Some(expired_at) if now < expired_at => false,
+            _ => {
+                self.seen.set(tx_id, now + ttl_ms);
+                true
+            }
+        }
+    }
+
+    /// Forget expired tx_ids of handled messages, called by stabilization.
+    pub fn prune_seen(&self) {
+        let now = get_epoch_ms();
+        for (tx_id, expired_at) in self.seen.items() {
+            if expired_at <= now {
+                self.seen.remove(&tx_id);
+            }
+        }
+    }
+
+    /// Register `virtual_address` as a virtual identity hosted by node of `physical`.
+    /// Messages to a virtual identity are sent through the transport of its hosting node.
+    pub fn register_alias(&self, virtual_address: &Address, physical: &Address) -> Result<()> {
+        let did: Did = (*virtual_address).into();
+        if !did.is_virtual_of(&(*physical).into()) {
+            return Err(Error::InvalidVirtualIdentity);
+        }
+        self.aliases.set(virtual_address, *physical);
+        Ok(())
+    }
+
+    /// Address of node hosting `address`, which is `address` itself if it's not a virtual identity.
+    pub fn physical_address(&self, address: &Address) -> Address {
+        self.aliases.get(address).unwrap_or(*address)
+    }
+
+    /// Virtual identities hosted by node of `physical`.
+    pub fn aliases_of(&self, physical: &Address) -> Vec<Address> {
+        self.aliases
+            .items()
+            .into_iter()
+            .filter(|(_, p)| p == physical)
+            .map(|(v, _)| v)
+            .collect()
+    }
+
+    /// Check if `address` is this node or one of its virtual identities.
+    pub fn is_local(&self, address: &Address) -> bool {
+        self.physical_address(address) == self.address
+    }
+
+    /// Check if sending to `address` over data channel `label` would fail with
+    /// `Error::SendBufferFullTo`, because too many bytes are queued on the channel.
+    pub async fn is_send_buffer_full(&self, address: &Address, label: &str) -> bool {
+        match self.get_transport(address) {
+            Some(t) => t.buffered_amount(label).await > SEND_BUFFER_HIGH_WATERMARK,
+            None => false,
+        }
+    }
+
+    /// Wait until data channel `label` to `address` drains to `SEND_BUFFER_LOW_WATERMARK`,
+    /// woken by the buffered-amount-low event of the channel.
+    /// A sender which got `Error::SendBufferFullTo` waits for it before sending again.
+    pub async fn wait_for_send_buffer(&self, address: &Address, label: &str) -> Result<()> {
+        let transport = self
+            .get_transport(address)
+            .ok_or(Error::SwarmMissAddressInTable(*address))?;
+        transport
+            .wait_for_buffered_amount_low(label, SEND_BUFFER_LOW_WATERMARK)
+            .await
+    }
+
     pub fn find_pending_transport(&self, id: uuid::Uuid) -> Result<Option<Arc<Transport>>> {
         let pending = self
             .pending
@@ -218,15 +420,7 @@ impl TransportManager for Swarm {
     type Transport = Arc<Transport>;
 
     async fn new_transport(&self) -> Result<Self::Transport> {
-        let event_sender = self.transport_event_channel.sender();
-        let mut ice_transport = Transport::new(event_sender);
-        ice_transport
-            .start(self.ice_servers.clone(), self.external_address.clone())
-            .await?
-            .apply_callback()
-            .await?;
-
-        Ok(Arc::new(ice_transport))
+        self.new_transport_of(self.transport_kind).await
     }
 
     // register to swarm table
@@ -282,10 +476,19 @@ impl TransportManager for Swarm {
     }
 
     fn get_transport(&self, address: &Address) -> Option<Self::Transport> {
-        self.table.get(address)
+        self.table.get(&self.physical_address(address))
     }
 
     fn remove_transport(&self, address: &Address) -> Option<(Address, Self::Transport)> {
+        self.liveness.remove(address);
+        for (id, (addr, _)) in self.pings.items() {
+            if addr == *address {
+                self.pings.remove(&id);
+            }
+        }
+        for alias in self.aliases_of(address) {
+            self.aliases.remove(&alias);
+        }
         self.table.remove(address)
     }
 
@@ -305,13 +508,53 @@ impl TransportManager for Swarm {
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl<T> PayloadSender<T> for Swarm
-where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Debug
+where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Debug + ChannelLabel
 {
     fn session_manager(&self) -> &SessionManager {
         Swarm::session_manager(self)
     }
 
     async fn do_send_payload(&self, address: &Address, payload: MessagePayload<T>) -> Result<()> {
+        self.send_payload_via(address, payload.data.channel_label(), payload)
+            .await
+    }
+}
+
+impl Swarm {
+    /// Open data channel `label` to a connected peer, so applications can send their
+    /// messages apart from ring maintenance traffic. The peer can answer on it.
+    /// Unreliable channels only carry messages which fit in one frame of `MAX_FRAME_LEN` bytes.
+    pub async fn open_channel(
+        &self,
+        address: &Address,
+        label: &str,
+        options: DataChannelOptions,
+    ) -> Result<()> {
+        let transport = self
+            .get_and_check_transport(address)
+            .await
+            .ok_or(Error::SwarmMissAddressInTable(*address))?;
+        transport.wait_for_data_channel_open().await?;
+        transport.open_channel(label, options).await
+    }
+
+    /// Send a payload to its next hop over data channel `label`.
+    pub async fn send_payload_on<T>(&self, label: &str, payload: MessagePayload<T>) -> Result<()>
+    where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Debug {
+        let next_hop = payload.relay.next_hop.ok_or(Error::NoNextHop)?;
+        self.send_payload_via(&next_hop.into(), label, payload)
+            .await
+    }
+
+    async fn send_payload_via<T>(
+        &self,
+        address: &Address,
+        label: &str,
+        payload: MessagePayload<T>,
+    ) -> Result<()>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Debug,
+    {
         #[cfg(test)]
         {
             println!("+++++++++++++++++++++++++++++++++");
@@ -320,19 +563,32 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Deb
             println!("node {:?}", payload.relay.next_hop);
             println!("+++++++++++++++++++++++++++++++++");
         }
+        // identities hosted by this node talk to each other through local event channel
+        if self.is_local(address) && !self.aliases_of(&self.address).is_empty() {
+            let data: Vec<u8> = payload.encode()?.into();
+            return Channel::send(
+                &self.transport_event_channel.sender(),
+                Event::DataChannelMessage(data),
+            )
+            .await;
+        }
         let transport = self
             .get_and_check_transport(address)
             .await
             .ok_or(Error::SwarmMissAddressInTable(*address))?;
         log::trace!(
-            "SENT {:?}, to node {:?} via transport {:?}",
+            "SENT {:?}, to node {:?} via transport {:?} on channel {}",
             payload.clone(),
             payload.relay.next_hop,
-            transport.id
+            transport.id,
+            label
         );
         let data: Vec<u8> = payload.encode()?.into();
         transport.wait_for_data_channel_open().await?;
-        transport.send_message(data.as_slice()).await
+        match transport.send_message_on(label, data.as_slice()).await {
+            Err(Error::SendBufferFull(_)) => Err(Error::SendBufferFullTo(*address, label.into())),
+            ret => ret,
+        }
     }
 }
 
@@ -365,6 +621,52 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_swarm_liveness() -> Result<()> {
+        let swarm = new_swarm();
+        let peer = SecretKey::random().address();
+        assert!(swarm.liveness(&peer).is_none());
+
+        let first = swarm.record_ping(&peer);
+        let second = swarm.record_ping(&peer);
+        assert_eq!(swarm.liveness(&peer).unwrap().missed_pings, 2);
+        assert_eq!(swarm.liveness(&peer).unwrap().rtt, None);
+
+        // unknown ping, or a pong from another peer is ignored
+        let other = SecretKey::random().address();
+        assert!(swarm.record_pong(&peer, &uuid::Uuid::new_v4()).is_none());
+        assert!(swarm.record_pong(&other, &second).is_none());
+        assert_eq!(swarm.liveness(&peer).unwrap().missed_pings, 2);
+
+        time::sleep(time::Duration::from_millis(10)).await;
+        let liveness = swarm.record_pong(&peer, &second).unwrap();
+        assert_eq!(liveness.missed_pings, 0);
+        assert!(liveness.rtt.unwrap() >= 10);
+        assert_eq!(swarm.liveness(&peer), Some(liveness));
+        // a late pong of an earlier ping is not counted again
+        assert!(swarm.record_pong(&peer, &first).is_none());
+
+        swarm.remove_transport(&peer);
+        assert!(swarm.liveness(&peer).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_swarm_seen() -> Result<()> {
+        let swarm = new_swarm();
+        let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        assert!(swarm.mark_seen(&a, 60 * 1000));
+        assert!(!swarm.mark_seen(&a, 60 * 1000));
+        assert!(swarm.mark_seen(&b, 0));
+
+        // expired ones are forgotten
+        swarm.prune_seen();
+        assert!(!swarm.mark_seen(&a, 60 * 1000));
+        assert!(swarm.seen.get(&b).is_none());
+        assert!(swarm.mark_seen(&b, 0));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_swarm_register_and_get() -> Result<()> {
         let swarm1 = new_swarm();
@@ -386,7 +688,11 @@ mod tests {
         //     .await
         //     .is_err());
 
-        establish_connection(&transport1, &transport2).await?;
+        establish_connection(
+            transport1.as_webrtc().unwrap(),
+            transport2.as_webrtc().unwrap(),
+        )
+        .await?;
 
         // Can register if connected
         swarm1
@@ -419,8 +725,16 @@ mod tests {
         let transport_2_to_0 = swarm2.new_transport().await.unwrap();
         let transport_2_to_1 = swarm2.new_transport().await.unwrap();
 
-        establish_connection(&transport0, &transport_2_to_0).await?;
-        establish_connection(&transport1, &transport_2_to_1).await?;
+        establish_connection(
+            transport0.as_webrtc().unwrap(),
+            transport_2_to_0.as_webrtc().unwrap(),
+        )
+        .await?;
+        establish_connection(
+            transport1.as_webrtc().unwrap(),
+            transport_2_to_1.as_webrtc().unwrap(),
+        )
+        .await?;
 
         swarm1
             .register(&swarm2.address(), transport0.clone())
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use async_lock::RwLock as AsyncRwLock;
 use async_trait::async_trait;
@@ -10,6 +12,7 @@ use serde_json;
 use web3::types::Address;
 use webrtc::api::setting_engine::SettingEngine;
 use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::data_channel_state::RTCDataChannelState;
 use webrtc::data_channel::RTCDataChannel;
@@ -31,8 +34,19 @@ use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
 use crate::session::SessionManager;
+use crate::transports::fragment;
+use crate::transports::fragment::ChannelReassemblers;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::EventWaiters;
+use crate::transports::helper::HandshakeNonces;
+use crate::transports::helper::Network;
 use crate::transports::helper::Promise;
+use crate::transports::helper::TransportKind;
 use crate::transports::helper::TricklePayload;
+use crate::transports::helper::CONTROL_CHANNEL;
+use crate::transports::helper::DEFAULT_CHANNEL;
+use crate::transports::helper::SEND_BUFFER_HIGH_WATERMARK;
+use crate::transports::helper::SEND_BUFFER_LOW_WATERMARK;
 use crate::types::channel::Channel;
 use crate::types::channel::Event;
 use crate::types::ice_transport::IceCandidate;
@@ -48,9 +62,23 @@ pub struct DefaultTransport {
     pub id: uuid::Uuid,
     connection: Arc<FuturesMutex<Option<Arc<RTCPeerConnection>>>>,
     pending_candidates: Arc<FuturesMutex<Vec<RTCIceCandidate>>>,
-    data_channel: Arc<FuturesMutex<Option<Arc<RTCDataChannel>>>>,
+    data_channels: Arc<FuturesMutex<HashMap<String, Arc<RTCDataChannel>>>>,
     event_sender: EventSender,
     public_key: Arc<AsyncRwLock<Option<PublicKey>>>,
+    network: Network,
+    nonces: Arc<HandshakeNonces>,
+    reassemblers: Arc<Mutex<ChannelReassemblers>>,
+    channel_open: EventWaiters,
+    send_buffer: EventWaiters,
+}
+
+/// Handlers of events of data channels, created by either side.
+#[derive(Clone)]
+struct ChannelHandlers {
+    event_sender: EventSender,
+    reassemblers: Arc<Mutex<ChannelReassemblers>>,
+    channel_open: EventWaiters,
+    send_buffer: EventWaiters,
 }
 
 impl PartialEq for DefaultTransport {
@@ -79,9 +107,14 @@ impl IceTransport<Event, AcChannel<Event>> for DefaultTransport {
             id: uuid::Uuid::new_v4(),
             connection: Arc::new(FuturesMutex::new(None)),
             pending_candidates: Arc::new(FuturesMutex::new(vec![])),
-            data_channel: Arc::new(FuturesMutex::new(None)),
+            data_channels: Arc::new(FuturesMutex::new(HashMap::new())),
             public_key: Arc::new(AsyncRwLock::new(None)),
             event_sender,
+            network: Network::default(),
+            nonces: Arc::new(HandshakeNonces::new()),
+            reassemblers: Arc::new(Mutex::new(ChannelReassemblers::new())),
+            channel_open: EventWaiters::default(),
+            send_buffer: EventWaiters::default(),
         }
     }
 
@@ -114,7 +147,10 @@ impl IceTransport<Event, AcChannel<Event>> for DefaultTransport {
             Err(e) => Err(Error::RTCPeerConnectionCreateFailed(e)),
         }?;
 
-        self.setup_channel("rings").await?;
+        self.setup_channel(DEFAULT_CHANNEL, DataChannelOptions::reliable())
+            .await?;
+        self.setup_channel(CONTROL_CHANNEL, DataChannelOptions::reliable())
+            .await?;
         Ok(self)
     }
 
@@ -212,30 +248,81 @@ impl IceTransport<Event, AcChannel<Event>> for DefaultTransport {
     }
 
     async fn get_data_channel(&self) -> Option<Arc<RTCDataChannel>> {
-        self.data_channel.lock().await.clone()
+        self.get_channel(DEFAULT_CHANNEL).await
     }
 
     async fn send_message(&self, msg: &[u8]) -> Result<()> {
-        let size = msg.len();
-        match self.get_data_channel().await {
-            Some(cnn) => match cnn.send(&Bytes::from(msg.to_vec())).await {
-                Ok(s) => {
-                    if !s == size {
-                        Err(Error::RTCDataChannelMessageIncomplete(s, size))
-                    } else {
-                        Ok(())
-                    }
-                }
+        self.send_message_on(DEFAULT_CHANNEL, msg).await
+    }
+
+    async fn send_message_on(&self, label: &str, msg: &[u8]) -> Result<()> {
+        let cnn = self
+            .get_channel(label)
+            .await
+            .ok_or_else(|| Error::DataChannelNotFound(label.to_string()))?;
+        self.wait_for_channel_open(&cnn).await?;
+        let amount = cnn.buffered_amount().await;
+        if amount > SEND_BUFFER_HIGH_WATERMARK {
+            return Err(Error::SendBufferFull(amount));
+        }
+        let frames = fragment::fragment(msg)?;
+        if frames.len() > 1 && !Self::channel_options(&cnn).is_reliable() {
+            return Err(Error::MessageTooLargeForChannel(
+                label.to_string(),
+                msg.len(),
+            ));
+        }
+        for frame in frames {
+            let size = frame.len();
+            match cnn.send(&Bytes::from(frame)).await {
+                Ok(s) if s != size => return Err(Error::RTCDataChannelMessageIncomplete(s, size)),
+                Ok(_) => {}
                 Err(e) => {
-                    if cnn.ready_state() != RTCDataChannelState::Open {
+                    return if cnn.ready_state() != RTCDataChannelState::Open {
                         Err(Error::RTCDataChannelStateNotOpen)
                     } else {
                         Err(Error::RTCDataChannelSendTextFailed(e))
-                    }
+                    };
                 }
-            },
-            None => Err(Error::RTCDataChannelNotReady),
+            }
+        }
+        Ok(())
+    }
+
+    async fn open_channel(&self, label: &str, options: DataChannelOptions) -> Result<()> {
+        if self.get_channel(label).await.is_some() {
+            return Ok(());
+        }
+        self.setup_channel(label, options).await
+    }
+
+    async fn buffered_amount(&self, label: &str) -> usize {
+        match self.get_channel(label).await {
+            Some(dc) => dc.buffered_amount().await,
+            None => 0,
+        }
+    }
+
+    async fn wait_for_buffered_amount_low(&self, label: &str, threshold: usize) -> Result<()> {
+        let cnn = self
+            .get_channel(label)
+            .await
+            .ok_or_else(|| Error::DataChannelNotFound(label.to_string()))?;
+        if threshold < SEND_BUFFER_LOW_WATERMARK {
+            cnn.set_buffered_amount_low_threshold(threshold).await;
         }
+        while cnn.buffered_amount().await > threshold {
+            // wait for the event only if it didn't fire before the waiter is registered
+            let promise = self.send_buffer.promise();
+            if cnn.buffered_amount().await <= threshold {
+                break;
+            }
+            if cnn.ready_state() != RTCDataChannelState::Open {
+                return Err(Error::RTCDataChannelStateNotOpen);
+            }
+            promise.await?;
+        }
+        Ok(())
     }
 
     async fn add_ice_candidate(&self, candidate: IceCandidate) -> Result<()> {
@@ -272,14 +359,22 @@ impl IceTransport<Event, AcChannel<Event>> for DefaultTransport {
 }
 
 impl DefaultTransport {
-    pub async fn setup_channel(&mut self, name: &str) -> Result<()> {
+    /// Create data channel `name`. Messages are received on channels created by either side,
+    /// and sent on the channel created by this side, see `on_data_channel` for the other one.
+    pub async fn setup_channel(&self, name: &str, options: DataChannelOptions) -> Result<()> {
         match self.get_peer_connection().await {
             Some(peer_connection) => {
-                let channel = peer_connection.create_data_channel(name, None).await;
+                let init = RTCDataChannelInit {
+                    ordered: Some(options.ordered),
+                    max_retransmits: options.max_retransmits,
+                    ..Default::default()
+                };
+                let channel = peer_connection.create_data_channel(name, Some(init)).await;
                 match channel {
                     Ok(ch) => {
-                        let mut channel = self.data_channel.lock().await;
-                        *channel = Some(ch);
+                        self.channel_handlers().watch(&ch).await;
+                        let mut channels = self.data_channels.lock().await;
+                        channels.insert(name.to_string(), ch);
                         Ok(())
                     }
                     Err(_) => Err(Error::RTCDataChannelNotReady),
@@ -288,6 +383,80 @@ impl DefaultTransport {
             None => Err(Error::RTCPeerConnectionNotEstablish),
         }
     }
+
+    fn channel_handlers(&self) -> ChannelHandlers {
+        ChannelHandlers {
+            event_sender: self.event_sender.clone(),
+            reassemblers: Arc::clone(&self.reassemblers),
+            channel_open: self.channel_open.clone(),
+            send_buffer: self.send_buffer.clone(),
+        }
+    }
+
+    fn channel_options(ch: &RTCDataChannel) -> DataChannelOptions {
+        DataChannelOptions {
+            ordered: ch.ordered(),
+            max_retransmits: ch.max_retransmits(),
+        }
+    }
+}
+
+impl ChannelHandlers {
+    /// Deliver messages of data channel `ch` to swarm, and wake tasks waiting for
+    /// the channel to open or drain.
+    async fn watch(&self, ch: &Arc<RTCDataChannel>) {
+        ch.set_buffered_amount_low_threshold(SEND_BUFFER_LOW_WATERMARK)
+            .await;
+        let send_buffer = self.send_buffer.clone();
+        ch.on_buffered_amount_low(Box::new(move || {
+            send_buffer.wake();
+            Box::pin(async {})
+        }))
+        .await;
+        let channel_open = self.channel_open.clone();
+        ch.on_open(box move || {
+            channel_open.wake();
+            Box::pin(async {})
+        })
+        .await;
+        let channel_open = self.channel_open.clone();
+        ch.on_close(box move || {
+            // tasks waiting for the channel to open find it closed
+            channel_open.wake();
+            Box::pin(async {})
+        })
+        .await;
+
+        let label = ch.label().to_string();
+        let event_sender = self.event_sender.clone();
+        let reassemblers = Arc::clone(&self.reassemblers);
+        ch.on_message(Box::new(move |msg: DataChannelMessage| {
+            log::debug!("Message from DataChannel: '{:?}'", msg);
+            let event_sender = event_sender.clone();
+            let pushed = reassemblers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(&label, &msg.data);
+            Box::pin(async move {
+                let data = match pushed {
+                    Ok(Some(data)) => data,
+                    Ok(None) => return,
+                    Err(e) => {
+                        log::error!("Failed on reassemble msg, {:?}", e);
+                        return;
+                    }
+                };
+                if event_sender
+                    .send(Event::DataChannelMessage(data))
+                    .await
+                    .is_err()
+                {
+                    log::error!("Failed on handle msg")
+                };
+            })
+        }))
+        .await;
+    }
 }
 
 #[async_trait]
@@ -323,10 +492,14 @@ impl IceTransportCallback<Event, AcChannel<Event>> for DefaultTransport {
     async fn on_ice_connection_state_change(&self) -> Self::OnIceConnectionStateChangeHdlrFn {
         let event_sender = self.event_sender.clone();
         let public_key = Arc::clone(&self.public_key);
+        let channel_open = self.channel_open.clone();
+        let send_buffer = self.send_buffer.clone();
         let id = self.id;
         box move |cs: Self::IceConnectionState| {
             let event_sender = event_sender.clone();
             let public_key = Arc::clone(&public_key);
+            let channel_open = channel_open.clone();
+            let send_buffer = send_buffer.clone();
             let id = id;
             Box::pin(async move {
                 match cs {
@@ -343,6 +516,9 @@ impl IceTransportCallback<Event, AcChannel<Event>> for DefaultTransport {
                     Self::IceConnectionState::Failed
                     | Self::IceConnectionState::Disconnected
                     | Self::IceConnectionState::Closed => {
+                        // senders waiting for channels to open or drain find them closed
+                        channel_open.wake();
+                        send_buffer.wake();
                         let local_address: Address = public_key.read().await.unwrap().address();
                         if event_sender
                             .send(Event::ConnectClosed((local_address, id)))
@@ -379,25 +555,21 @@ impl IceTransportCallback<Event, AcChannel<Event>> for DefaultTransport {
     }
 
     async fn on_data_channel(&self) -> Self::OnDataChannelHdlrFn {
-        let event_sender = self.event_sender.clone();
+        let handlers = self.channel_handlers();
+        let data_channels = Arc::clone(&self.data_channels);
 
         box move |d: Arc<RTCDataChannel>| {
-            let event_sender = event_sender.clone();
+            let handlers = handlers.clone();
+            let data_channels = Arc::clone(&data_channels);
             Box::pin(async move {
-                d.on_message(Box::new(move |msg: DataChannelMessage| {
-                    log::debug!("Message from DataChannel: '{:?}'", msg);
-                    let event_sender = event_sender.clone();
-                    Box::pin(async move {
-                        if event_sender
-                            .send(Event::DataChannelMessage(msg.data.to_vec()))
-                            .await
-                            .is_err()
-                        {
-                            log::error!("Failed on handle msg")
-                        };
-                    })
-                }))
-                .await;
+                handlers.watch(&d).await;
+                // a channel opened by remote peer only, such as by `open_channel`,
+                // is used by this side to answer on it
+                data_channels
+                    .lock()
+                    .await
+                    .entry(d.label().to_string())
+                    .or_insert(d);
             })
         }
     }
@@ -436,10 +608,12 @@ impl IceTrickleScheme<Event, AcChannel<Event>> for DefaultTransport {
         if local_candidates_json.is_empty() {
             return Err(Error::FailedOnGatherLocalCandidate);
         }
-        let data = TricklePayload {
-            sdp: serde_json::to_string(&sdp).unwrap(),
-            candidates: local_candidates_json,
-        };
+        let data = self.network.trickle_payload(
+            TransportKind::WebRtc,
+            serde_json::to_string(&sdp).unwrap(),
+            local_candidates_json,
+            &self.nonces,
+        );
         log::trace!("prepared hanshake info :{:?}", data);
         let resp = MessagePayload::new_direct(
             data,
@@ -454,6 +628,8 @@ impl IceTrickleScheme<Event, AcChannel<Event>> for DefaultTransport {
         log::trace!("register remote info: {:?}", data);
         match data.verify() {
             true => {
+                self.network
+                    .check(TransportKind::WebRtc, &data.data, &self.nonces)?;
                 let sdp = serde_json::from_str::<RTCSessionDescription>(&data.data.sdp)
                     .map_err(Error::Deserialize)?;
                 log::trace!("setting remote sdp: {:?}", sdp);
@@ -485,29 +661,19 @@ impl IceTrickleScheme<Event, AcChannel<Event>> for DefaultTransport {
 }
 
 impl DefaultTransport {
+    /// Set the network of transport, checked in handshake.
+    pub fn set_network(&mut self, network: Network) {
+        self.network = network;
+    }
+
+    /// Get data channel `label` to send on, created by this side or else by remote peer.
+    pub async fn get_channel(&self, label: &str) -> Option<Arc<RTCDataChannel>> {
+        self.data_channels.lock().await.get(label).cloned()
+    }
+
     pub async fn wait_for_data_channel_open(&self) -> Result<()> {
         match self.get_data_channel().await {
-            Some(dc) => {
-                if dc.ready_state() == RTCDataChannelState::Open {
-                    Ok(())
-                } else {
-                    let promise = Promise::default();
-                    let state = Arc::clone(&promise.state());
-                    dc.on_open(box move || {
-                        let state = Arc::clone(&state);
-                        Box::pin(async move {
-                            let mut s = state.lock().unwrap();
-                            if let Some(w) = s.waker.take() {
-                                s.completed = true;
-                                s.successed = Some(true);
-                                w.wake();
-                            }
-                        })
-                    })
-                    .await;
-                    promise.await
-                }
-            }
+            Some(dc) => self.wait_for_channel_open(&dc).await,
             None => {
                 log::error!("{:?}", Error::RTCDataChannelNotReady);
                 Err(Error::RTCDataChannelNotReady)
@@ -515,6 +681,27 @@ impl DefaultTransport {
         }
     }
 
+    /// Wait for data channel `dc` to open, woken by open and close events of channels.
+    async fn wait_for_channel_open(&self, dc: &Arc<RTCDataChannel>) -> Result<()> {
+        let settled = |dc: &RTCDataChannel| match dc.ready_state() {
+            RTCDataChannelState::Open => Some(Ok(())),
+            RTCDataChannelState::Closing | RTCDataChannelState::Closed => {
+                Some(Err(Error::RTCDataChannelStateNotOpen))
+            }
+            _ => None,
+        };
+        loop {
+            if let Some(res) = settled(dc) {
+                return res;
+            }
+            // wait for the event only if it didn't fire before the waiter is registered
+            let promise = self.channel_open.promise();
+            if settled(dc).is_none() {
+                promise.await?;
+            }
+        }
+    }
+
     pub async fn connect_success_promise(&self) -> Result<Promise> {
         match self.get_peer_connection().await {
             Some(peer_connection) => {
@@ -577,6 +764,10 @@ pub mod tests {
     use crate::types::ice_transport::IceServer;
 
     async fn prepare_transport() -> Result<Transport> {
+        Ok(prepare_transport_with_channel().await?.0)
+    }
+
+    async fn prepare_transport_with_channel() -> Result<(Transport, Arc<AcChannel<Event>>)> {
         let ch = Arc::new(AcChannel::new());
         let mut trans = Transport::new(ch.sender());
 
@@ -586,7 +777,24 @@ pub mod tests {
             .await?
             .apply_callback()
             .await?;
-        Ok(trans)
+        Ok((trans, ch))
+    }
+
+    async fn recv_messages(ch: &AcChannel<Event>, n: usize) -> Result<Vec<Vec<u8>>> {
+        let mut received = vec![];
+        while received.len() < n {
+            let ev = tokio::time::timeout(
+                std::time::Duration::from_secs(5),
+                AcChannel::recv(&ch.receiver()),
+            )
+            .await
+            .expect("message not received")?;
+            if let Some(Event::DataChannelMessage(msg)) = ev {
+                received.push(msg);
+            }
+        }
+        received.sort();
+        Ok(received)
     }
 
     pub async fn establish_connection(
@@ -745,4 +953,68 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_labeled_data_channels() -> Result<()> {
+        let (transport1, ch1) = prepare_transport_with_channel().await?;
+        let (transport2, ch2) = prepare_transport_with_channel().await?;
+        establish_connection(&transport1, &transport2).await?;
+
+        assert!(transport1.get_channel(CONTROL_CHANNEL).await.is_some());
+        assert!(matches!(
+            transport1.send_message_on("app", b"app").await,
+            Err(Error::DataChannelNotFound(_))
+        ));
+        transport1
+            .open_channel("app", DataChannelOptions::unreliable())
+            .await?;
+        transport1
+            .send_message_on(CONTROL_CHANNEL, b"control")
+            .await?;
+        transport1.send_message_on("app", b"app").await?;
+
+        // messages of all channels are delivered to the same event channel
+        assert_eq!(recv_messages(&ch2, 2).await?, vec![
+            b"app".to_vec(),
+            b"control".to_vec()
+        ]);
+
+        // remote peer answers on the channel it didn't open
+        transport2.send_message_on("app", b"answer").await?;
+        assert_eq!(recv_messages(&ch1, 1).await?, vec![b"answer".to_vec()]);
+
+        // a message which needs fragments is refused by unreliable channel only
+        let large = vec![1u8; fragment::MAX_FRAME_LEN + 1];
+        assert!(matches!(
+            transport1.send_message_on("app", &large).await,
+            Err(Error::MessageTooLargeForChannel(label, _)) if label == "app"
+        ));
+        transport1.send_message_on(CONTROL_CHANNEL, &large).await?;
+        assert_eq!(recv_messages(&ch2, 1).await?, vec![large]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handshake_between_networks() -> Result<()> {
+        let mut transport1 = prepare_transport().await?;
+        let mut transport2 = prepare_transport().await?;
+        let mut transport3 = prepare_transport().await?;
+        transport1.set_network(Network::new("staging", Some("secret")));
+        transport2.set_network(Network::new("production", Some("secret")));
+        transport3.set_network(Network::new("staging", Some("other secret")));
+
+        let sm1 = SessionManager::new_with_seckey(&SecretKey::random())?;
+        let handshake_info1 = transport1
+            .get_handshake_info(&sm1, RTCSdpType::Offer)
+            .await?;
+        assert!(matches!(
+            transport2.register_remote_info(handshake_info1.clone()).await,
+            Err(Error::NetworkMismatch(remote, local)) if remote == "staging" && local == "production"
+        ));
+        assert!(matches!(
+            transport3.register_remote_info(handshake_info1).await,
+            Err(Error::InvalidNetworkPsk)
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,310 @@
+//! Framing of messages over data channel.
+//! A data channel message is limited by SCTP, and browsers may refuse messages bigger than 16KiB,
+//! so a large message is split into fragments, each carries a header of
+//! `FRAGMENT_TAG | message id (16 bytes) | index (u32 BE) | total (u32 BE)`.
+//! A message which fits in one fragment is sent as is, so peers without framing can read it.
+//! Encoded payloads are base58 strings, which never start with `FRAGMENT_TAG`.
+use std::collections::HashMap;
+
+use crate::err::Error;
+use crate::err::Result;
+use crate::utils::get_epoch_ms;
+
+/// First byte of a fragment.
+pub const FRAGMENT_TAG: u8 = 0xff;
+/// Length of fragment header.
+pub const FRAGMENT_HEADER_LEN: usize = 1 + 16 + 4 + 4;
+/// Max length of a data channel message, which is supported by all browsers.
+pub const MAX_FRAME_LEN: usize = 16 * 1024;
+/// Max length of a message, the biggest payloads are vnodes synced to a successor or handed off
+/// while leaving, which are sent in batches no longer than `MAX_VNODE_BATCH_LEN`.
+pub const MAX_MESSAGE_LEN: usize = 4 * 1024 * 1024;
+/// Max number of messages being reassembled at the same time on one data channel.
+pub const MAX_PENDING_MESSAGES: usize = 64;
+/// Max number of bytes held by messages being reassembled on one transport,
+/// shared by all of its data channels.
+pub const MAX_PENDING_BYTES: usize = 8 * 1024 * 1024;
+/// Fragments of a message not completed in time are dropped, in milliseconds.
+pub const REASSEMBLY_TIMEOUT_MS: u128 = 30 * 1000;
+
+/// Split `msg` into frames no longer than `MAX_FRAME_LEN`.
+/// A message longer than `MAX_MESSAGE_LEN` is refused, since peers can't reassemble it.
+pub fn fragment(msg: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if msg.len() > MAX_MESSAGE_LEN {
+        return Err(Error::MessageTooLarge(msg.len()));
+    }
+    if msg.len() <= MAX_FRAME_LEN && msg.first() != Some(&FRAGMENT_TAG) {
+        return Ok(vec![msg.to_vec()]);
+    }
+    let id = uuid::Uuid::new_v4();
+    let chunks = msg.chunks(MAX_FRAME_LEN - FRAGMENT_HEADER_LEN);
+    let total = chunks.len() as u32;
+    Ok(chunks
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut frame = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+            frame.push(FRAGMENT_TAG);
+            frame.extend_from_slice(id.as_bytes());
+            frame.extend_from_slice(&(i as u32).to_be_bytes());
+            frame.extend_from_slice(&total.to_be_bytes());
+            frame.extend_from_slice(chunk);
+            frame
+        })
+        .collect())
+}
+
+struct Pending {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    /// bytes of received fragments
+    bytes: usize,
+    started_at: u128,
+}
+
+/// Reassembly buffers of one data channel.
+#[derive(Default)]
+pub struct Reassembler {
+    pending: HashMap<uuid::Uuid, Pending>,
+    /// bytes held by all pending messages
+    bytes: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of messages being reassembled.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of bytes held by messages being reassembled.
+    pub fn pending_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Take a received frame, return the message once all of its fragments arrived.
+    pub fn push(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>> {
+        if frame.first() != Some(&FRAGMENT_TAG) {
+            return Ok(Some(frame.to_vec()));
+        }
+        if frame.len() < FRAGMENT_HEADER_LEN {
+            return Err(Error::InvalidFragment);
+        }
+        let id = uuid::Uuid::from_slice(&frame[1..17]).map_err(|_| Error::InvalidFragment)?;
+        let index = u32::from_be_bytes(frame[17..21].try_into().unwrap()) as usize;
+        let total = u32::from_be_bytes(frame[21..25].try_into().unwrap()) as usize;
+        if index >= total || total > MAX_MESSAGE_LEN / (MAX_FRAME_LEN - FRAGMENT_HEADER_LEN) + 1 {
+            return Err(Error::InvalidFragment);
+        }
+
+        let now = get_epoch_ms();
+        self.prune(now);
+        if !self.pending.contains_key(&id) && self.pending.len() >= MAX_PENDING_MESSAGES {
+            return Err(Error::TooManyPendingFragments);
+        }
+        let chunk = &frame[FRAGMENT_HEADER_LEN..];
+        if self.bytes + chunk.len() > MAX_PENDING_BYTES {
+            return Err(Error::TooManyPendingFragments);
+        }
+        let pending = self.pending.entry(id).or_insert_with(|| Pending {
+            fragments: vec![None; total],
+            received: 0,
+            bytes: 0,
+            started_at: now,
+        });
+        if pending.fragments.len() != total {
+            return Err(Error::InvalidFragment);
+        }
+        if pending.fragments[index].is_none() {
+            pending.fragments[index] = Some(chunk.to_vec());
+            pending.received += 1;
+            pending.bytes += chunk.len();
+            self.bytes += chunk.len();
+        }
+        if pending.received < total {
+            return Ok(None);
+        }
+        let msg = match self.pending.remove(&id) {
+            Some(p) => {
+                self.bytes -= p.bytes;
+                p.fragments.into_iter().flatten().flatten().collect()
+            }
+            None => vec![],
+        };
+        Ok(Some(msg))
+    }
+
+    /// Drop messages not completed in time.
+    pub fn prune(&mut self, now: u128) {
+        let bytes = &mut self.bytes;
+        self.pending.retain(|_, p| {
+            let live = now < p.started_at + REASSEMBLY_TIMEOUT_MS;
+            if !live {
+                *bytes -= p.bytes;
+            }
+            live
+        });
+    }
+}
+
+/// Reassembly buffers of all data channels of one transport, keyed by channel label.
+/// Fragments of a message are sent on one channel, so each channel is reassembled apart,
+/// while bytes held by all of them share the budget of `MAX_PENDING_BYTES`.
+#[derive(Default)]
+pub struct ChannelReassemblers {
+    channels: HashMap<String, Reassembler>,
+}
+
+impl ChannelReassemblers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of channels with messages being reassembled.
+    pub fn pending_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Number of bytes held by messages being reassembled on all channels.
+    pub fn pending_bytes(&self) -> usize {
+        self.channels.values().map(|r| r.pending_bytes()).sum()
+    }
+
+    /// Take a frame received on channel `label`, return the message once all of its
+    /// fragments arrived on that channel.
+    pub fn push(&mut self, label: &str, frame: &[u8]) -> Result<Option<Vec<u8>>> {
+        if frame.first() != Some(&FRAGMENT_TAG) {
+            return Ok(Some(frame.to_vec()));
+        }
+        let now = get_epoch_ms();
+        self.channels.values_mut().for_each(|r| r.prune(now));
+        let chunk_len = frame.len().saturating_sub(FRAGMENT_HEADER_LEN);
+        if self.pending_bytes() + chunk_len > MAX_PENDING_BYTES {
+            return Err(Error::TooManyPendingFragments);
+        }
+        let pushed = self
+            .channels
+            .entry(label.to_string())
+            .or_default()
+            .push(frame);
+        // channels opened by remote peer are not tracked once nothing is pending on them
+        self.channels.retain(|_, r| r.pending_len() > 0);
+        pushed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_message_is_not_framed() -> Result<()> {
+        let msg = b"base58 payload".to_vec();
+        assert_eq!(fragment(&msg)?, vec![msg.clone()]);
+        assert_eq!(Reassembler::new().push(&msg)?, Some(msg));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fragment_and_reassemble() -> Result<()> {
+        let msg = (0..(MAX_FRAME_LEN * 3))
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let frames = fragment(&msg)?;
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|f| f.len() <= MAX_FRAME_LEN));
+
+        // fragments of two messages arrive interleaved and out of order
+        let other = fragment(&msg[..MAX_FRAME_LEN + 1])?;
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(&frames[3])?, None);
+        assert_eq!(reassembler.push(&other[1])?, None);
+        assert_eq!(reassembler.push(&frames[0])?, None);
+        assert_eq!(reassembler.push(&frames[0])?, None);
+        assert_eq!(reassembler.push(&frames[2])?, None);
+        assert_eq!(reassembler.pending_len(), 2);
+        assert_eq!(reassembler.push(&frames[1])?, Some(msg.clone()));
+        assert_eq!(
+            reassembler.push(&other[0])?,
+            Some(msg[..MAX_FRAME_LEN + 1].to_vec())
+        );
+        assert_eq!(reassembler.pending_len(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reassembly_is_bounded() {
+        let mut reassembler = Reassembler::new();
+        for _ in 0..MAX_PENDING_MESSAGES {
+            let frames = fragment(&[1u8; MAX_FRAME_LEN + 1]).unwrap();
+            assert!(reassembler.push(&frames[0]).unwrap().is_none());
+        }
+        let frames = fragment(&[1u8; MAX_FRAME_LEN + 1]).unwrap();
+        assert!(reassembler.push(&frames[0]).is_err());
+
+        // a header claiming too many fragments, or out of range index is rejected
+        let mut frame = frames[0].clone();
+        frame[21..25].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(reassembler.push(&frame).is_err());
+        let mut frame = frames[0].clone();
+        frame[17..21].copy_from_slice(&2u32.to_be_bytes());
+        assert!(reassembler.push(&frame).is_err());
+        assert!(reassembler.push(&frames[0][..10]).is_err());
+
+        // a message too large to reassemble is refused by sender
+        assert!(matches!(
+            fragment(&vec![1u8; MAX_MESSAGE_LEN + 1]),
+            Err(Error::MessageTooLarge(_))
+        ));
+
+        // bytes held by partial messages are bounded, though each one is valid
+        let mut reassembler = Reassembler::new();
+        let msg = vec![1u8; MAX_MESSAGE_LEN];
+        for _ in 0..(MAX_PENDING_BYTES / MAX_MESSAGE_LEN) {
+            let frames = fragment(&msg).unwrap();
+            for frame in frames[1..].iter() {
+                assert!(reassembler.push(frame).unwrap().is_none());
+            }
+        }
+        assert!(reassembler.pending_bytes() <= MAX_PENDING_BYTES);
+        let frames = fragment(&msg).unwrap();
+        assert!(matches!(
+            frames[1..]
+                .iter()
+                .try_for_each(|f| reassembler.push(f).map(|_| ())),
+            Err(Error::TooManyPendingFragments)
+        ));
+    }
+
+    #[test]
+    fn test_reassemble_per_channel() -> Result<()> {
+        let msg = vec![1u8; MAX_FRAME_LEN * 2];
+        let frames = fragment(&msg)?;
+        let mut reassemblers = ChannelReassemblers::new();
+
+        // fragments of a message are only joined on the channel they are sent on
+        assert_eq!(reassemblers.push("rings", &frames[0])?, None);
+        assert_eq!(reassemblers.push("app", &frames[1])?, None);
+        assert_eq!(reassemblers.pending_channels(), 2);
+        assert_eq!(reassemblers.push("rings", &frames[1])?, None);
+        assert_eq!(reassemblers.push("rings", &frames[2])?, Some(msg.clone()));
+        assert_eq!(reassemblers.pending_channels(), 1);
+        assert_eq!(reassemblers.push("app", b"small")?, Some(b"small".to_vec()));
+
+        // all channels share the byte budget of transport
+        let mut reassemblers = ChannelReassemblers::new();
+        let msg = vec![1u8; MAX_MESSAGE_LEN];
+        let labels = ["rings", "rings-control", "app"];
+        let result = labels.iter().try_for_each(|label| {
+            fragment(&msg)?[1..]
+                .iter()
+                .try_for_each(|f| reassemblers.push(label, f).map(|_| ()))
+        });
+        assert!(matches!(result, Err(Error::TooManyPendingFragments)));
+        assert!(reassemblers.pending_bytes() <= MAX_PENDING_BYTES);
+        Ok(())
+    }
+}
@@ -5,13 +5,241 @@ use std::sync::Mutex;
 use std::task::Context;
 use std::task::Poll;
 
+use hmac::Hmac;
+use hmac::Mac;
+use hmac::NewMac;
 use serde::Deserialize;
 use serde::Serialize;
+use sha2::Sha256;
 
 use crate::err::Error;
 use crate::err::Result;
 use crate::types::ice_transport::IceCandidate;
 
+/// Sending fails with `Error::SendBufferFull` when more bytes than it are queued on a data channel.
+pub const SEND_BUFFER_HIGH_WATERMARK: usize = 1024 * 1024;
+/// Buffered-amount-low event of a data channel fires when queued bytes drop to it,
+/// so senders stopped by a full buffer can resume.
+pub const SEND_BUFFER_LOW_WATERMARK: usize = 256 * 1024;
+
+/// Label of the data channel carrying application payloads.
+pub const DEFAULT_CHANNEL: &str = "rings";
+/// Label of the data channel dedicated to stabilization and DHT control messages,
+/// so they are not delayed by large application payloads.
+pub const CONTROL_CHANNEL: &str = "rings-control";
+
+/// Delivery settings of a data channel.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataChannelOptions {
+    /// deliver messages in the order they are sent
+    pub ordered: bool,
+    /// retransmit a lost message at most this many times, `None` for reliable delivery
+    pub max_retransmits: Option<u16>,
+}
+
+impl Default for DataChannelOptions {
+    fn default() -> Self {
+        Self::reliable()
+    }
+}
+
+impl DataChannelOptions {
+    /// Ordered and reliable delivery.
+    pub fn reliable() -> Self {
+        Self {
+            ordered: true,
+            max_retransmits: None,
+        }
+    }
+
+    /// Unordered delivery without retransmission, lost messages are dropped.
+    pub fn unreliable() -> Self {
+        Self {
+            ordered: false,
+            max_retransmits: Some(0),
+        }
+    }
+
+    /// Whether messages are delivered in order without loss.
+    /// Only such channels carry fragmented messages, since a lost fragment drops the message.
+    pub fn is_reliable(&self) -> bool {
+        self.ordered && self.max_retransmits.is_none()
+    }
+}
+
+/// Network id of peers which don't tell their network in handshake.
+pub const DEFAULT_NETWORK_ID: &str = "rings";
+
+fn default_network_id() -> String {
+    DEFAULT_NETWORK_ID.to_string()
+}
+
+/// The network a node belongs to. Peers of different networks, or with different
+/// pre-shared keys, refuse the handshake of each other, so separate rings never merge.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    /// id of network
+    pub id: String,
+    /// optional pre-shared key, it's never sent, only a proof of it is in handshake.
+    /// The proof can be used to guess the key offline, so it should be a random secret.
+    pub psk: Option<String>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            id: default_network_id(),
+            psk: None,
+        }
+    }
+}
+
+impl Network {
+    /// Create a network with id and optional pre-shared key.
+    pub fn new(id: &str, psk: Option<&str>) -> Self {
+        Self {
+            id: id.to_string(),
+            psk: psk.map(|s| s.to_string()),
+        }
+    }
+
+    /// HMAC of the handshake keyed by pre-shared key.
+    fn psk_mac(&self, payload: &TricklePayload) -> Option<Hmac<Sha256>> {
+        let psk = self.psk.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(psk.as_bytes()).ok()?;
+        for part in [
+            self.id.as_str(),
+            payload.nonce.as_str(),
+            payload.challenge.as_deref().unwrap_or_default(),
+            payload.sdp.as_str(),
+        ] {
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(part.as_bytes());
+        }
+        Some(mac)
+    }
+
+    /// Build the handshake payload of local network.
+    /// An answer proves the pre-shared key over the nonce of remote offer, an offer
+    /// carries a fresh nonce which is remembered to check the answer.
+    pub fn trickle_payload(
+        &self,
+        kind: TransportKind,
+        sdp: String,
+        candidates: Vec<IceCandidate>,
+        nonces: &HandshakeNonces,
+    ) -> TricklePayload {
+        let nonce = uuid::Uuid::new_v4().to_string();
+        let challenge = nonces.remote.lock().unwrap().clone();
+        if challenge.is_none() {
+            *nonces.local.lock().unwrap() = Some(nonce.clone());
+        }
+        let mut payload = TricklePayload {
+            kind,
+            sdp,
+            candidates,
+            network_id: self.id.clone(),
+            psk_proof: None,
+            nonce,
+            challenge,
+        };
+        payload.psk_proof = self
+            .psk_mac(&payload)
+            .map(|mac| hex::encode(mac.finalize().into_bytes()));
+        payload
+    }
+
+    /// Check if the handshake payload of remote peer is from local network, for a transport of `kind`.
+    /// An answer must answer the nonce of local offer, so it can't be replayed.
+    pub fn check(
+        &self,
+        kind: TransportKind,
+        payload: &TricklePayload,
+        nonces: &HandshakeNonces,
+    ) -> Result<()> {
+        if payload.kind != kind {
+            return Err(Error::TransportKindMismatch(payload.kind, kind));
+        }
+        if payload.network_id != self.id {
+            return Err(Error::NetworkMismatch(
+                payload.network_id.clone(),
+                self.id.clone(),
+            ));
+        }
+        if let Some(challenge) = &payload.challenge {
+            if nonces.local.lock().unwrap().as_ref() != Some(challenge) {
+                return Err(Error::HandshakeChallengeMismatch);
+            }
+        }
+        match (self.psk_mac(payload), &payload.psk_proof) {
+            (None, None) => {}
+            (Some(mac), Some(proof)) => {
+                let proof = hex::decode(proof).map_err(|_| Error::InvalidNetworkPsk)?;
+                mac.verify(&proof).map_err(|_| Error::InvalidNetworkPsk)?;
+            }
+            _ => return Err(Error::InvalidNetworkPsk),
+        }
+        if payload.challenge.is_none() {
+            *nonces.remote.lock().unwrap() = Some(payload.nonce.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Nonces of the handshake of one transport.
+#[derive(Default, Debug)]
+pub struct HandshakeNonces {
+    /// nonce of the offer sent to remote peer
+    local: Mutex<Option<String>>,
+    /// nonce of the offer received from remote peer
+    remote: Mutex<Option<String>>,
+}
+
+impl HandshakeNonces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Backend of a transport, selected per peer by [Swarm](crate::swarm::Swarm).
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// data channels of WebRTC, connected through ICE
+    #[default]
+    WebRtc,
+    /// in-process channels, for simulation and tests
+    Memory,
+    /// websocket links, to nodes which listen on a public address
+    WebSocket,
+}
+
+/// Kind of a handshake message of transports without ICE.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HandshakeKind {
+    Offer,
+    Answer,
+}
+
+/// Connection state of transports without ICE, named after the ICE connection states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    New,
+    Checking,
+    Connected,
+    Disconnected,
+    Failed,
+    Closed,
+}
+
+impl LinkState {
+    /// A terminated link never comes back.
+    pub fn is_terminated(&self) -> bool {
+        matches!(self, Self::Disconnected | Self::Failed | Self::Closed)
+    }
+}
+
 #[derive(Default)]
 pub struct State {
     pub completed: bool,
@@ -21,8 +249,21 @@ pub struct State {
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct TricklePayload {
+    /// kind of the transport which made this handshake, the answer must use the same kind
+    #[serde(default)]
+    pub kind: TransportKind,
     pub sdp: String,
     pub candidates: Vec<IceCandidate>,
+    #[serde(default = "default_network_id")]
+    pub network_id: String,
+    #[serde(default)]
+    pub psk_proof: Option<String>,
+    /// fresh nonce of this handshake
+    #[serde(default)]
+    pub nonce: String,
+    /// nonce of the remote offer which this handshake answers, `None` for an offer
+    #[serde(default)]
+    pub challenge: Option<String>,
 }
 
 #[derive(Default)]
@@ -49,3 +290,115 @@ impl Future for Promise {
         }
     }
 }
+
+/// Tasks waiting for an event of any data channel of a transport, such as open or
+/// buffered-amount-low. They are woken by the event, and check their channel again.
+#[derive(Default, Clone)]
+pub struct EventWaiters(Arc<Mutex<Vec<Arc<Mutex<State>>>>>);
+
+impl EventWaiters {
+    /// A promise resolved by the next event, or the end of transport.
+    pub fn promise(&self) -> Promise {
+        let promise = Promise::default();
+        self.0.lock().unwrap().push(promise.state());
+        promise
+    }
+
+    /// Resolve all waiting promises.
+    pub fn wake(&self) {
+        let waiters = std::mem::take(&mut *self.0.lock().unwrap());
+        for state in waiters {
+            let mut s = state.lock().unwrap();
+            s.completed = true;
+            s.successed = Some(true);
+            if let Some(w) = s.waker.take() {
+                w.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "wasm"))]
+    #[tokio::test]
+    async fn test_event_waiters() {
+        let waiters = EventWaiters::default();
+        let mut first = Box::pin(waiters.promise());
+        let second = waiters.promise();
+
+        // nothing resolves the promises until the event fires, then all of them are woken
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        waiters.wake();
+        assert!(first.await.is_ok());
+        assert!(second.await.is_ok());
+
+        // a promise made after the event waits for the next one
+        let mut third = Box::pin(waiters.promise());
+        assert!(futures::poll!(third.as_mut()).is_pending());
+    }
+
+    #[test]
+    fn test_network_check() {
+        use super::TransportKind::WebRtc;
+
+        let staging = Network::new("staging", Some("secret"));
+        let (offerer, answerer) = (HandshakeNonces::new(), HandshakeNonces::new());
+        let offer = staging.trickle_payload(WebRtc, "offer".to_string(), vec![], &offerer);
+        assert!(offer.psk_proof.is_some());
+        assert!(offer.challenge.is_none());
+        assert!(staging.check(WebRtc, &offer, &answerer).is_ok());
+
+        // answer proves the key over the nonce of offer
+        let answer = staging.trickle_payload(WebRtc, "answer".to_string(), vec![], &answerer);
+        assert_eq!(answer.challenge, Some(offer.nonce.clone()));
+        assert!(staging.check(WebRtc, &answer, &offerer).is_ok());
+
+        // an answer to an earlier offer is refused
+        let _ = staging.trickle_payload(WebRtc, "offer".to_string(), vec![], &offerer);
+        assert!(matches!(
+            staging.check(WebRtc, &answer, &offerer),
+            Err(Error::HandshakeChallengeMismatch)
+        ));
+
+        // proof is bound to sdp
+        let mut forged = staging.trickle_payload(WebRtc, "offer".to_string(), vec![], &offerer);
+        forged.sdp = "other sdp".to_string();
+        assert!(matches!(
+            staging.check(WebRtc, &forged, &HandshakeNonces::new()),
+            Err(Error::InvalidNetworkPsk)
+        ));
+        assert!(matches!(
+            Network::new("staging", Some("other secret")).check(
+                WebRtc,
+                &offer,
+                &HandshakeNonces::new()
+            ),
+            Err(Error::InvalidNetworkPsk)
+        ));
+        assert!(matches!(
+            Network::new("staging", None).check(WebRtc, &offer, &HandshakeNonces::new()),
+            Err(Error::InvalidNetworkPsk)
+        ));
+        assert!(matches!(
+            Network::default().check(WebRtc, &offer, &HandshakeNonces::new()),
+            Err(Error::NetworkMismatch(..))
+        ));
+
+        // peers without network id are in the default network
+        let legacy: TricklePayload =
+            serde_json::from_str(r#"{"sdp":"sdp","candidates":[]}"#).unwrap();
+        assert_eq!(legacy.network_id, DEFAULT_NETWORK_ID);
+        assert!(Network::default()
+            .check(WebRtc, &legacy, &HandshakeNonces::new())
+            .is_ok());
+
+        // a handshake of another kind of transport is refused
+        assert!(matches!(
+            staging.check(TransportKind::Memory, &offer, &HandshakeNonces::new()),
+            Err(Error::TransportKindMismatch(WebRtc, TransportKind::Memory))
+        ));
+    }
+}
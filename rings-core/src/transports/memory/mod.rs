@@ -0,0 +1,3 @@
+pub mod transport;
+
+pub use transport::MemoryTransport;
@@ -0,0 +1,512 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+use async_lock::RwLock as AsyncRwLock;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde::Serialize;
+use web3::types::Address;
+
+use crate::channels::Channel as AcChannel;
+use crate::ecc::PublicKey;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::Encoded;
+use crate::message::Encoder;
+use crate::message::MessagePayload;
+use crate::session::SessionManager;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::HandshakeKind;
+use crate::transports::helper::HandshakeNonces;
+use crate::transports::helper::LinkState;
+use crate::transports::helper::Network;
+use crate::transports::helper::Promise;
+use crate::transports::helper::State;
+use crate::transports::helper::TransportKind;
+use crate::transports::helper::TricklePayload;
+use crate::types::channel::Channel;
+use crate::types::channel::Event;
+use crate::types::ice_transport::IceCandidate;
+use crate::types::ice_transport::IceServer;
+use crate::types::ice_transport::IceTransport;
+use crate::types::ice_transport::IceTransportCallback;
+use crate::types::ice_transport::IceTrickleScheme;
+
+type EventSender = <AcChannel<Event> as Channel<Event>>::Sender;
+
+lazy_static! {
+    /// Endpoints of started memory transports of this process, indexed by transport id.
+    /// A handshake links two of them, just like ICE links two peer connections.
+    /// Entries are weak, so a transport dropped without being closed is not kept alive.
+    static ref HUB: DashMap<uuid::Uuid, Weak<MemoryEndpoint>> = DashMap::new();
+}
+
+/// Session description of memory transport, it only tells the peer who we are.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MemorySdp {
+    pub sdp_type: HandshakeKind,
+    pub id: uuid::Uuid,
+}
+
+#[derive(Default)]
+struct Link {
+    remote: Option<uuid::Uuid>,
+    state: Option<LinkState>,
+    waiters: Vec<Arc<Mutex<State>>>,
+}
+
+/// The end of a memory transport which is reached by its peer.
+pub struct MemoryEndpoint {
+    id: uuid::Uuid,
+    link: Mutex<Link>,
+    event_sender: EventSender,
+    public_key: AsyncRwLock<Option<PublicKey>>,
+}
+
+impl MemoryEndpoint {
+    /// The endpoint of a started transport, if it's still alive.
+    fn lookup(id: &uuid::Uuid) -> Option<Arc<Self>> {
+        HUB.get(id).and_then(|e| e.value().upgrade())
+    }
+
+    fn state(&self) -> Option<LinkState> {
+        self.link.lock().unwrap().state
+    }
+
+    /// Update the state, resolve pending promises, and notify the swarm like an ICE state change.
+    /// A terminated link never comes back.
+    async fn set_state(&self, state: LinkState) {
+        let waiters = {
+            let mut link = self.link.lock().unwrap();
+            if link.state.map(|s| s.is_terminated()).unwrap_or(false) || link.state == Some(state) {
+                return;
+            }
+            link.state = Some(state);
+            if state == LinkState::Connected || state.is_terminated() {
+                std::mem::take(&mut link.waiters)
+            } else {
+                vec![]
+            }
+        };
+        for w in waiters.iter() {
+            complete(w, state == LinkState::Connected);
+        }
+        self.notify(state).await;
+    }
+
+    /// Tell the swarm that the link is connected or closed.
+    async fn notify(&self, state: LinkState) {
+        let remote_address: Option<Address> = self.public_key.read().await.map(|pk| pk.address());
+        let ev = match (state, remote_address) {
+            (LinkState::Connected, Some(address)) => Event::RegisterTransport((address, self.id)),
+            (s, Some(address)) if s.is_terminated() => Event::ConnectClosed((address, self.id)),
+            _ => {
+                log::debug!("MemoryTransport state change {:?}", state);
+                return;
+            }
+        };
+        if self.event_sender.send(ev).await.is_err() {
+            log::error!("Failed when send transport event");
+        }
+    }
+
+    /// Hand a message to the swarm, like a data channel message.
+    async fn deliver(&self, msg: Vec<u8>) {
+        if self
+            .event_sender
+            .send(Event::DataChannelMessage(msg))
+            .await
+            .is_err()
+        {
+            log::error!("Failed on handle msg")
+        };
+    }
+}
+
+/// Transport over in-process channels. It keeps the handshake of IceTrickleScheme,
+/// so Swarm and MessageHandler work unchanged, which makes it possible to run a large ring
+/// in one process without network.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    pub id: uuid::Uuid,
+    endpoint: Arc<MemoryEndpoint>,
+    network: Network,
+    nonces: Arc<HandshakeNonces>,
+}
+
+impl PartialEq for MemoryTransport {
+    fn eq(&self, other: &Self) -> bool {
+        self.id.eq(&other.id)
+    }
+}
+
+fn complete(state: &Arc<Mutex<State>>, success: bool) {
+    let mut s = state.lock().unwrap();
+    s.completed = true;
+    s.successed = Some(success);
+    if let Some(w) = s.waker.take() {
+        w.wake();
+    }
+}
+
+#[async_trait]
+impl IceTransport<Event, AcChannel<Event>> for MemoryTransport {
+    type Connection = MemoryEndpoint;
+    type Candidate = IceCandidate;
+    type Sdp = MemorySdp;
+    type DataChannel = MemoryEndpoint;
+    type IceConnectionState = LinkState;
+    type Msg = Vec<u8>;
+
+    fn new(event_sender: EventSender) -> Self {
+        let id = uuid::Uuid::new_v4();
+        Self {
+            id,
+            endpoint: Arc::new(MemoryEndpoint {
+                id,
+                link: Mutex::new(Link::default()),
+                event_sender,
+                public_key: AsyncRwLock::new(None),
+            }),
+            network: Network::default(),
+            nonces: Arc::new(HandshakeNonces::new()),
+        }
+    }
+
+    async fn start(
+        &mut self,
+        _ice_server: Vec<IceServer>,
+        _external_ip: Option<String>,
+    ) -> Result<&Self> {
+        self.endpoint.link.lock().unwrap().state = Some(LinkState::New);
+        HUB.retain(|_, e| e.strong_count() > 0);
+        HUB.insert(self.id, Arc::downgrade(&self.endpoint));
+        Ok(self)
+    }
+
+    async fn close(&self) -> Result<()> {
+        HUB.remove(&self.id);
+        if let Some(remote) = self.get_peer_connection().await {
+            remote.set_state(LinkState::Disconnected).await;
+        }
+        self.endpoint.set_state(LinkState::Closed).await;
+        Ok(())
+    }
+
+    async fn ice_connection_state(&self) -> Option<Self::IceConnectionState> {
+        self.endpoint.state()
+    }
+
+    async fn is_disconnected(&self) -> bool {
+        self.endpoint
+            .state()
+            .map(|s| s.is_terminated())
+            .unwrap_or(false)
+    }
+
+    async fn is_connected(&self) -> bool {
+        self.endpoint.state() == Some(LinkState::Connected)
+    }
+
+    async fn pubkey(&self) -> PublicKey {
+        self.endpoint.public_key.read().await.unwrap()
+    }
+
+    /// The endpoint on the other end, if it's still alive.
+    async fn get_peer_connection(&self) -> Option<Arc<MemoryEndpoint>> {
+        let remote = self.endpoint.link.lock().unwrap().remote?;
+        MemoryEndpoint::lookup(&remote)
+    }
+
+    async fn get_pending_candidates(&self) -> Vec<IceCandidate> {
+        vec![]
+    }
+
+    async fn get_answer(&self) -> Result<MemorySdp> {
+        if self.endpoint.link.lock().unwrap().remote.is_none() {
+            return Err(Error::RTCPeerConnectionNotEstablish);
+        }
+        let answer = MemorySdp {
+            sdp_type: HandshakeKind::Answer,
+            id: self.id,
+        };
+        self.set_local_description(answer.clone()).await?;
+        Ok(answer)
+    }
+
+    async fn get_answer_str(&self) -> Result<String> {
+        serde_json::to_string(&self.get_answer().await?).map_err(Error::Serialize)
+    }
+
+    async fn get_offer(&self) -> Result<MemorySdp> {
+        let offer = MemorySdp {
+            sdp_type: HandshakeKind::Offer,
+            id: self.id,
+        };
+        self.set_local_description(offer.clone()).await?;
+        Ok(offer)
+    }
+
+    async fn get_offer_str(&self) -> Result<String> {
+        serde_json::to_string(&self.get_offer().await?).map_err(Error::Serialize)
+    }
+
+    async fn get_data_channel(&self) -> Option<Arc<MemoryEndpoint>> {
+        if self.is_connected().await {
+            self.get_peer_connection().await
+        } else {
+            None
+        }
+    }
+
+    async fn send_message(&self, msg: &[u8]) -> Result<()> {
+        match self.get_data_channel().await {
+            Some(remote) => {
+                remote.deliver(msg.to_vec()).await;
+                Ok(())
+            }
+            None => Err(Error::RTCDataChannelStateNotOpen),
+        }
+    }
+
+    /// All channels share the same link.
+    async fn send_message_on(&self, _label: &str, msg: &[u8]) -> Result<()> {
+        self.send_message(msg).await
+    }
+
+    async fn open_channel(&self, _label: &str, _options: DataChannelOptions) -> Result<()> {
+        Ok(())
+    }
+
+    /// Messages are handed to remote peer directly, nothing is queued.
+    async fn buffered_amount(&self, _label: &str) -> usize {
+        0
+    }
+
+    async fn wait_for_buffered_amount_low(&self, _label: &str, _threshold: usize) -> Result<()> {
+        Ok(())
+    }
+
+    async fn add_ice_candidate(&self, _candidate: IceCandidate) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_local_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<MemorySdp> + Send {
+        let desc: MemorySdp = desc.into();
+        // answering side starts checking once the answer is created
+        if desc.sdp_type == HandshakeKind::Answer {
+            self.endpoint.set_state(LinkState::Checking).await;
+        }
+        Ok(())
+    }
+
+    async fn set_remote_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<MemorySdp> + Send {
+        let desc: MemorySdp = desc.into();
+        self.endpoint.link.lock().unwrap().remote = Some(desc.id);
+        // offering side links both ends once the answer arrives
+        if desc.sdp_type == HandshakeKind::Answer {
+            let remote = MemoryEndpoint::lookup(&desc.id)
+                .ok_or_else(|| Error::MemoryTransportNotFound(desc.id.to_string()))?;
+            if remote.link.lock().unwrap().remote != Some(self.id) {
+                return Err(Error::RTCSdpTypeNotMatch);
+            }
+            remote.set_state(LinkState::Connected).await;
+            self.endpoint.set_state(LinkState::Connected).await;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IceTransportCallback<Event, AcChannel<Event>> for MemoryTransport {
+    type OnLocalCandidateHdlrFn =
+        Box<dyn FnMut(Option<IceCandidate>) -> BoxFuture<'static, ()> + Send + Sync>;
+    type OnDataChannelHdlrFn = Box<dyn FnMut(Vec<u8>) -> BoxFuture<'static, ()> + Send + Sync>;
+    type OnIceConnectionStateChangeHdlrFn =
+        Box<(dyn FnMut(LinkState) -> BoxFuture<'static, ()> + Sync + Send + 'static)>;
+
+    /// Handlers are invoked directly by the transports, nothing to register.
+    async fn apply_callback(&self) -> Result<&Self> {
+        Ok(self)
+    }
+
+    async fn on_ice_connection_state_change(&self) -> Self::OnIceConnectionStateChangeHdlrFn {
+        let endpoint = Arc::clone(&self.endpoint);
+        box move |state: LinkState| {
+            let endpoint = Arc::clone(&endpoint);
+            Box::pin(async move { endpoint.notify(state).await })
+        }
+    }
+
+    async fn on_ice_candidate(&self) -> Self::OnLocalCandidateHdlrFn {
+        box move |_: Option<IceCandidate>| Box::pin(async move {})
+    }
+
+    async fn on_data_channel(&self) -> Self::OnDataChannelHdlrFn {
+        let endpoint = Arc::clone(&self.endpoint);
+        box move |msg: Vec<u8>| {
+            let endpoint = Arc::clone(&endpoint);
+            Box::pin(async move { endpoint.deliver(msg).await })
+        }
+    }
+}
+
+#[async_trait]
+impl IceTrickleScheme<Event, AcChannel<Event>> for MemoryTransport {
+    type SdpType = HandshakeKind;
+
+    async fn get_handshake_info(
+        &self,
+        session_manager: &SessionManager,
+        kind: HandshakeKind,
+    ) -> Result<Encoded> {
+        let sdp = match kind {
+            HandshakeKind::Answer => self.get_answer().await?,
+            HandshakeKind::Offer => self.get_offer().await?,
+        };
+        let data = self.network.trickle_payload(
+            TransportKind::Memory,
+            serde_json::to_string(&sdp).map_err(Error::Serialize)?,
+            vec![],
+            &self.nonces,
+        );
+        let resp = MessagePayload::new_direct(
+            data,
+            session_manager,
+            session_manager.authorizer()?.to_owned().into(), // This is a fake destination
+        )?;
+        Ok(resp.gzip(9)?.encode()?)
+    }
+
+    async fn register_remote_info(&self, data: Encoded) -> Result<Address> {
+        let data: MessagePayload<TricklePayload> = data.decode()?;
+        if !data.verify() {
+            log::error!("cannot verify message sig");
+            return Err(Error::VerifySignatureFailed);
+        }
+        self.network
+            .check(TransportKind::Memory, &data.data, &self.nonces)?;
+        let sdp = serde_json::from_str::<MemorySdp>(&data.data.sdp).map_err(Error::Deserialize)?;
+        // public key is required by the events emitted once connected
+        if let Ok(public_key) = data.origin_verification.session.authorizer_pubkey() {
+            let mut pk = self.endpoint.public_key.write().await;
+            *pk = Some(public_key);
+        };
+        self.set_remote_description(sdp).await?;
+        Ok(data.addr)
+    }
+
+    async fn wait_for_connected(&self) -> Result<()> {
+        let promise = self.connect_success_promise().await?;
+        promise.await
+    }
+}
+
+impl MemoryTransport {
+    /// Set the network of transport, checked in handshake.
+    pub fn set_network(&mut self, network: Network) {
+        self.network = network;
+    }
+
+    /// Data is delivered as soon as both ends are connected.
+    pub async fn wait_for_data_channel_open(&self) -> Result<()> {
+        self.wait_for_connected().await
+    }
+
+    pub async fn connect_success_promise(&self) -> Result<Promise> {
+        let promise = Promise::default();
+        let state = promise.state();
+        let mut link = self.endpoint.link.lock().unwrap();
+        match link.state {
+            Some(LinkState::Connected) => complete(&state, true),
+            Some(s) if s.is_terminated() => complete(&state, false),
+            _ => link.waiters.push(state),
+        }
+        Ok(promise)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryTransport as Transport;
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    fn prepare_transport(ch: &AcChannel<Event>) -> Transport {
+        Transport::new(ch.sender())
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_handshake_and_close() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+        let sm1 = SessionManager::new_with_seckey(&key1)?;
+        let sm2 = SessionManager::new_with_seckey(&key2)?;
+        let ch1 = AcChannel::<Event>::new();
+        let ch2 = AcChannel::<Event>::new();
+        let mut t1 = prepare_transport(&ch1);
+        let mut t2 = prepare_transport(&ch2);
+        assert_eq!(t1.ice_connection_state().await, None);
+        t1.start(vec![], None).await?;
+        t2.start(vec![], None).await?;
+
+        let offer = t1.get_handshake_info(&sm1, HandshakeKind::Offer).await?;
+        assert_eq!(t2.register_remote_info(offer).await?, key1.address());
+        let answer = t2.get_handshake_info(&sm2, HandshakeKind::Answer).await?;
+        assert_eq!(t2.ice_connection_state().await, Some(LinkState::Checking));
+        assert!(t1.send_message(b"too early").await.is_err());
+
+        let promise = t2.connect_success_promise().await?;
+        assert_eq!(t1.register_remote_info(answer).await?, key2.address());
+        promise.await?;
+        assert!(t1.is_connected().await);
+        assert!(matches!(
+            AcChannel::recv(&ch1.receiver()).await?,
+            Some(Event::RegisterTransport((address, id))) if address == key2.address() && id == t1.id
+        ));
+        assert!(matches!(
+            AcChannel::recv(&ch2.receiver()).await?,
+            Some(Event::RegisterTransport((address, id))) if address == key1.address() && id == t2.id
+        ));
+
+        t1.send_message(b"hello").await?;
+        assert!(matches!(
+            AcChannel::recv(&ch2.receiver()).await?,
+            Some(Event::DataChannelMessage(msg)) if msg == b"hello".to_vec()
+        ));
+
+        t1.close().await?;
+        assert!(t1.is_disconnected().await);
+        assert!(t2.is_disconnected().await);
+        assert!(matches!(
+            AcChannel::recv(&ch2.receiver()).await?,
+            Some(Event::ConnectClosed((address, id))) if address == key1.address() && id == t2.id
+        ));
+        assert!(t2.send_message(b"bye").await.is_err());
+        assert!(t1.wait_for_connected().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_is_released_on_drop() -> Result<()> {
+        let ch = AcChannel::<Event>::new();
+        let mut t1 = prepare_transport(&ch);
+        t1.start(vec![], None).await?;
+        let id = t1.id;
+        assert!(MemoryEndpoint::lookup(&id).is_some());
+
+        // a transport dropped without being closed is not kept by the hub
+        drop(t1);
+        assert!(MemoryEndpoint::lookup(&id).is_none());
+        let mut t2 = prepare_transport(&ch);
+        t2.start(vec![], None).await?;
+        assert!(HUB.get(&id).is_none());
+        Ok(())
+    }
+}
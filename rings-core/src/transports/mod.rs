@@ -1,11 +1,20 @@
 #[cfg(not(feature = "wasm"))]
 pub mod default;
+#[cfg(all(not(feature = "wasm"), feature = "memory"))]
+pub mod memory;
 #[cfg(feature = "wasm")]
 pub mod wasm;
+#[cfg(any(feature = "wasm", feature = "websocket"))]
+pub mod websocket;
+
+pub mod fragment;
+pub mod helper;
+mod transport;
 
 #[cfg(not(feature = "wasm"))]
-pub use default::DefaultTransport as Transport;
+pub use default::DefaultTransport as WebRtcTransport;
+pub use helper::Network;
+pub use helper::TransportKind;
+pub use transport::Transport;
 #[cfg(feature = "wasm")]
-pub use wasm::WasmTransport as Transport;
-
-pub mod helper;
+pub use wasm::WasmTransport as WebRtcTransport;
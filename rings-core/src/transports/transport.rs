@@ -0,0 +1,400 @@
+//! Transport of [Swarm](crate::swarm::Swarm), backed by one of the transports built in,
+//! so the swarm can reach each peer with the transport chosen for it.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use web3::types::Address;
+
+#[cfg(not(feature = "wasm"))]
+use self::default::parse_webrtc_sdp;
+#[cfg(feature = "wasm")]
+use self::wasm::parse_webrtc_sdp;
+use crate::channels::Channel as TransportChannel;
+use crate::ecc::PublicKey;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::Encoded;
+use crate::session::SessionManager;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::Network;
+use crate::transports::helper::Promise;
+use crate::transports::helper::TransportKind;
+#[cfg(all(not(feature = "wasm"), feature = "memory"))]
+use crate::transports::memory::MemorySdp;
+#[cfg(all(not(feature = "wasm"), feature = "memory"))]
+use crate::transports::memory::MemoryTransport;
+#[cfg(any(feature = "wasm", feature = "websocket"))]
+use crate::transports::websocket::WebSocketSdp;
+#[cfg(any(feature = "wasm", feature = "websocket"))]
+use crate::transports::websocket::WebSocketTransport;
+use crate::transports::WebRtcTransport;
+use crate::types::channel::Channel;
+use crate::types::channel::Event;
+use crate::types::ice_transport::IceCandidate;
+use crate::types::ice_transport::IceServer;
+use crate::types::ice_transport::IceTransport;
+use crate::types::ice_transport::IceTransportCallback;
+use crate::types::ice_transport::IceTrickleScheme;
+
+type EventSender = <TransportChannel<Event> as Channel<Event>>::Sender;
+type WebRtcSdpType = <WebRtcTransport as IceTrickleScheme<Event, TransportChannel<Event>>>::SdpType;
+
+#[derive(Clone)]
+enum Backend {
+    WebRtc(WebRtcTransport),
+    #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+    Memory(MemoryTransport),
+    #[cfg(any(feature = "wasm", feature = "websocket"))]
+    WebSocket(WebSocketTransport),
+}
+
+/// Call the same method on whichever backend.
+macro_rules! on_backend {
+    ($backend:expr, $t:ident => $call:expr) => {
+        match $backend {
+            Backend::WebRtc($t) => $call,
+            #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+            Backend::Memory($t) => $call,
+            #[cfg(any(feature = "wasm", feature = "websocket"))]
+            Backend::WebSocket($t) => $call,
+        }
+    };
+}
+
+/// A transport to one peer. It's a WebRTC transport unless another kind is asked,
+/// ICE states and session descriptions of other kinds are mapped to the WebRTC ones.
+#[derive(Clone)]
+pub struct Transport {
+    pub id: uuid::Uuid,
+    backend: Backend,
+}
+
+impl PartialEq for Transport {
+    fn eq(&self, other: &Self) -> bool {
+        self.id.eq(&other.id)
+    }
+}
+
+impl Transport {
+    /// Create a transport of `kind`, fail if it's not built in.
+    #[allow(unreachable_patterns)]
+    pub fn new_of(kind: TransportKind, event_sender: EventSender) -> Result<Self> {
+        let backend = match kind {
+            TransportKind::WebRtc => Backend::WebRtc(WebRtcTransport::new(event_sender)),
+            #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+            TransportKind::Memory => Backend::Memory(MemoryTransport::new(event_sender)),
+            #[cfg(any(feature = "wasm", feature = "websocket"))]
+            TransportKind::WebSocket => Backend::WebSocket(WebSocketTransport::new(event_sender)),
+            kind => return Err(Error::TransportNotSupported(kind)),
+        };
+        Ok(Self::from_backend(backend))
+    }
+
+    fn from_backend(backend: Backend) -> Self {
+        let id = on_backend!(&backend, t => t.id);
+        Self { id, backend }
+    }
+
+    /// Kind of the backend.
+    pub fn kind(&self) -> TransportKind {
+        match &self.backend {
+            Backend::WebRtc(_) => TransportKind::WebRtc,
+            #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+            Backend::Memory(_) => TransportKind::Memory,
+            #[cfg(any(feature = "wasm", feature = "websocket"))]
+            Backend::WebSocket(_) => TransportKind::WebSocket,
+        }
+    }
+
+    /// The WebRTC backend, if it's a WebRTC transport.
+    #[allow(unreachable_patterns)]
+    pub fn as_webrtc(&self) -> Option<&WebRtcTransport> {
+        match &self.backend {
+            Backend::WebRtc(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Set the network of transport, checked in handshake.
+    pub fn set_network(&mut self, network: Network) {
+        on_backend!(&mut self.backend, t => t.set_network(network))
+    }
+
+    /// Register the callbacks of backend.
+    pub async fn apply_callback(&self) -> Result<&Self> {
+        on_backend!(&self.backend, t => { t.apply_callback().await?; });
+        Ok(self)
+    }
+
+    pub async fn wait_for_data_channel_open(&self) -> Result<()> {
+        on_backend!(&self.backend, t => t.wait_for_data_channel_open().await)
+    }
+
+    pub async fn connect_success_promise(&self) -> Result<Promise> {
+        on_backend!(&self.backend, t => t.connect_success_promise().await)
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl IceTransport<Event, TransportChannel<Event>> for Transport {
+    type Connection = <WebRtcTransport as IceTransport<Event, TransportChannel<Event>>>::Connection;
+    type Candidate = <WebRtcTransport as IceTransport<Event, TransportChannel<Event>>>::Candidate;
+    /// Session description of backend in JSON.
+    type Sdp = String;
+    type DataChannel =
+        <WebRtcTransport as IceTransport<Event, TransportChannel<Event>>>::DataChannel;
+    type IceConnectionState =
+        <WebRtcTransport as IceTransport<Event, TransportChannel<Event>>>::IceConnectionState;
+    type Msg = <WebRtcTransport as IceTransport<Event, TransportChannel<Event>>>::Msg;
+
+    /// Create a WebRTC transport.
+    fn new(event_sender: EventSender) -> Self {
+        Self::from_backend(Backend::WebRtc(WebRtcTransport::new(event_sender)))
+    }
+
+    async fn start(&mut self, addr: Vec<IceServer>, external_id: Option<String>) -> Result<&Self> {
+        on_backend!(&mut self.backend, t => { t.start(addr, external_id).await?; });
+        Ok(self)
+    }
+
+    async fn close(&self) -> Result<()> {
+        on_backend!(&self.backend, t => t.close().await)
+    }
+
+    async fn ice_connection_state(&self) -> Option<Self::IceConnectionState> {
+        on_backend!(&self.backend, t => t.ice_connection_state().await.map(Into::into))
+    }
+
+    async fn is_connected(&self) -> bool {
+        on_backend!(&self.backend, t => t.is_connected().await)
+    }
+
+    async fn is_disconnected(&self) -> bool {
+        on_backend!(&self.backend, t => t.is_disconnected().await)
+    }
+
+    async fn pubkey(&self) -> PublicKey {
+        on_backend!(&self.backend, t => t.pubkey().await)
+    }
+
+    async fn get_peer_connection(&self) -> Option<Arc<Self::Connection>> {
+        match self.as_webrtc() {
+            Some(t) => t.get_peer_connection().await,
+            None => None,
+        }
+    }
+
+    async fn get_pending_candidates(&self) -> Vec<Self::Candidate> {
+        match self.as_webrtc() {
+            Some(t) => t.get_pending_candidates().await,
+            None => vec![],
+        }
+    }
+
+    async fn get_answer(&self) -> Result<String> {
+        on_backend!(&self.backend, t => t.get_answer_str().await)
+    }
+
+    async fn get_offer(&self) -> Result<String> {
+        on_backend!(&self.backend, t => t.get_offer_str().await)
+    }
+
+    async fn get_answer_str(&self) -> Result<String> {
+        self.get_answer().await
+    }
+
+    async fn get_offer_str(&self) -> Result<String> {
+        self.get_offer().await
+    }
+
+    async fn get_data_channel(&self) -> Option<Arc<Self::DataChannel>> {
+        match self.as_webrtc() {
+            Some(t) => t.get_data_channel().await,
+            None => None,
+        }
+    }
+
+    async fn send_message(&self, msg: &[u8]) -> Result<()> {
+        on_backend!(&self.backend, t => t.send_message(msg).await)
+    }
+
+    async fn send_message_on(&self, label: &str, msg: &[u8]) -> Result<()> {
+        on_backend!(&self.backend, t => t.send_message_on(label, msg).await)
+    }
+
+    async fn open_channel(&self, label: &str, options: DataChannelOptions) -> Result<()> {
+        on_backend!(&self.backend, t => t.open_channel(label, options).await)
+    }
+
+    async fn buffered_amount(&self, label: &str) -> usize {
+        on_backend!(&self.backend, t => t.buffered_amount(label).await)
+    }
+
+    async fn wait_for_buffered_amount_low(&self, label: &str, threshold: usize) -> Result<()> {
+        on_backend!(&self.backend, t => t.wait_for_buffered_amount_low(label, threshold).await)
+    }
+
+    async fn set_local_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<String> + Send {
+        let desc: String = desc.into();
+        match &self.backend {
+            Backend::WebRtc(t) => t.set_local_description(parse_webrtc_sdp(desc)?).await,
+            #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+            Backend::Memory(t) => {
+                t.set_local_description(parse_sdp::<MemorySdp>(&desc)?)
+                    .await
+            }
+            #[cfg(any(feature = "wasm", feature = "websocket"))]
+            Backend::WebSocket(t) => {
+                t.set_local_description(parse_sdp::<WebSocketSdp>(&desc)?)
+                    .await
+            }
+        }
+    }
+
+    async fn add_ice_candidate(&self, candidate: IceCandidate) -> Result<()> {
+        on_backend!(&self.backend, t => t.add_ice_candidate(candidate).await)
+    }
+
+    async fn set_remote_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<String> + Send {
+        let desc: String = desc.into();
+        match &self.backend {
+            Backend::WebRtc(t) => t.set_remote_description(parse_webrtc_sdp(desc)?).await,
+            #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+            Backend::Memory(t) => {
+                t.set_remote_description(parse_sdp::<MemorySdp>(&desc)?)
+                    .await
+            }
+            #[cfg(any(feature = "wasm", feature = "websocket"))]
+            Backend::WebSocket(t) => {
+                t.set_remote_description(parse_sdp::<WebSocketSdp>(&desc)?)
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl IceTrickleScheme<Event, TransportChannel<Event>> for Transport {
+    type SdpType = WebRtcSdpType;
+
+    async fn get_handshake_info(
+        &self,
+        session_manager: &SessionManager,
+        kind: Self::SdpType,
+    ) -> Result<Encoded> {
+        match &self.backend {
+            Backend::WebRtc(t) => t.get_handshake_info(session_manager, kind).await,
+            #[cfg(all(not(feature = "wasm"), feature = "memory"))]
+            Backend::Memory(t) => {
+                t.get_handshake_info(session_manager, kind.try_into()?)
+                    .await
+            }
+            #[cfg(any(feature = "wasm", feature = "websocket"))]
+            Backend::WebSocket(t) => {
+                t.get_handshake_info(session_manager, kind.try_into()?)
+                    .await
+            }
+        }
+    }
+
+    async fn register_remote_info(&self, data: Encoded) -> Result<Address> {
+        on_backend!(&self.backend, t => t.register_remote_info(data).await)
+    }
+
+    async fn wait_for_connected(&self) -> Result<()> {
+        on_backend!(&self.backend, t => t.wait_for_connected().await)
+    }
+}
+
+#[cfg(any(
+    all(not(feature = "wasm"), feature = "memory"),
+    feature = "wasm",
+    feature = "websocket"
+))]
+fn parse_sdp<T: serde::de::DeserializeOwned>(desc: &str) -> Result<T> {
+    serde_json::from_str::<T>(desc).map_err(Error::Deserialize)
+}
+
+#[cfg(not(feature = "wasm"))]
+mod default {
+    use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+    use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+    use crate::err::Error;
+    use crate::err::Result;
+    use crate::transports::helper::HandshakeKind;
+    use crate::transports::helper::LinkState;
+
+    pub fn parse_webrtc_sdp(desc: String) -> Result<RTCSessionDescription> {
+        serde_json::from_str::<RTCSessionDescription>(&desc).map_err(Error::Deserialize)
+    }
+
+    impl From<LinkState> for RTCIceConnectionState {
+        fn from(s: LinkState) -> Self {
+            match s {
+                LinkState::New => Self::New,
+                LinkState::Checking => Self::Checking,
+                LinkState::Connected => Self::Connected,
+                LinkState::Disconnected => Self::Disconnected,
+                LinkState::Failed => Self::Failed,
+                LinkState::Closed => Self::Closed,
+            }
+        }
+    }
+
+    impl TryFrom<RTCSdpType> for HandshakeKind {
+        type Error = Error;
+        fn try_from(s: RTCSdpType) -> Result<Self> {
+            match s {
+                RTCSdpType::Offer => Ok(Self::Offer),
+                RTCSdpType::Answer => Ok(Self::Answer),
+                _ => Err(Error::RTCSdpTypeNotMatch),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod wasm {
+    use web_sys::RtcIceConnectionState;
+    use web_sys::RtcSdpType;
+
+    use crate::err::Error;
+    use crate::err::Result;
+    use crate::transports::helper::HandshakeKind;
+    use crate::transports::helper::LinkState;
+    use crate::transports::wasm::RtcSessionDescriptionWrapper;
+
+    pub fn parse_webrtc_sdp(desc: String) -> Result<RtcSessionDescriptionWrapper> {
+        RtcSessionDescriptionWrapper::try_from(desc)
+    }
+
+    impl From<LinkState> for RtcIceConnectionState {
+        fn from(s: LinkState) -> Self {
+            match s {
+                LinkState::New => Self::New,
+                LinkState::Checking => Self::Checking,
+                LinkState::Connected => Self::Connected,
+                LinkState::Disconnected => Self::Disconnected,
+                LinkState::Failed => Self::Failed,
+                LinkState::Closed => Self::Closed,
+            }
+        }
+    }
+
+    impl TryFrom<RtcSdpType> for HandshakeKind {
+        type Error = Error;
+        fn try_from(s: RtcSdpType) -> Result<Self> {
+            match s {
+                RtcSdpType::Offer => Ok(Self::Offer),
+                RtcSdpType::Answer => Ok(Self::Answer),
+                _ => Err(Error::RTCSdpTypeNotMatch),
+            }
+        }
+    }
+}
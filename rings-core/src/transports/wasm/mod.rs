@@ -1,5 +1,6 @@
 mod helper;
 mod transport;
+pub(crate) use helper::RtcSessionDescriptionWrapper;
 pub use transport::WasmTransport;
 use wasm_bindgen::JsValue;
 use web_sys::RtcIceCandidateInit;
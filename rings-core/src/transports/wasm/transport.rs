@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
@@ -16,6 +17,7 @@ use web_sys::MessageEvent;
 use web_sys::RtcConfiguration;
 use web_sys::RtcDataChannel;
 use web_sys::RtcDataChannelEvent;
+use web_sys::RtcDataChannelInit;
 use web_sys::RtcDataChannelState;
 use web_sys::RtcIceCandidate;
 use web_sys::RtcIceCandidateInit;
@@ -36,8 +38,19 @@ use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
 use crate::session::SessionManager;
+use crate::transports::fragment;
+use crate::transports::fragment::ChannelReassemblers;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::EventWaiters;
+use crate::transports::helper::HandshakeNonces;
+use crate::transports::helper::Network;
 use crate::transports::helper::Promise;
+use crate::transports::helper::TransportKind;
 use crate::transports::helper::TricklePayload;
+use crate::transports::helper::CONTROL_CHANNEL;
+use crate::transports::helper::DEFAULT_CHANNEL;
+use crate::transports::helper::SEND_BUFFER_HIGH_WATERMARK;
+use crate::transports::helper::SEND_BUFFER_LOW_WATERMARK;
 use crate::types::channel::Channel;
 use crate::types::channel::Event;
 use crate::types::ice_transport::IceCandidate;
@@ -53,9 +66,23 @@ pub struct WasmTransport {
     pub id: uuid::Uuid,
     connection: Option<Arc<RtcPeerConnection>>,
     pending_candidates: Arc<Mutex<Vec<RtcIceCandidate>>>,
-    channel: Option<Arc<RtcDataChannel>>,
+    channels: Arc<Mutex<HashMap<String, Arc<RtcDataChannel>>>>,
     event_sender: EventSender,
     public_key: Arc<RwLock<Option<PublicKey>>>,
+    network: Network,
+    nonces: Arc<HandshakeNonces>,
+    reassemblers: Arc<Mutex<ChannelReassemblers>>,
+    channel_open: EventWaiters,
+    send_buffer: EventWaiters,
+}
+
+/// Handlers of events of data channels, created by either side.
+#[derive(Clone)]
+struct ChannelHandlers {
+    event_sender: EventSender,
+    reassemblers: Arc<Mutex<ChannelReassemblers>>,
+    channel_open: EventWaiters,
+    send_buffer: EventWaiters,
 }
 
 impl PartialEq for WasmTransport {
@@ -87,9 +114,14 @@ impl IceTransport<Event, CbChannel<Event>> for WasmTransport {
             id: uuid::Uuid::new_v4(),
             connection: None,
             pending_candidates: Arc::new(Mutex::new(vec![])),
-            channel: None,
+            channels: Arc::new(Mutex::new(HashMap::new())),
             public_key: Arc::new(RwLock::new(None)),
             event_sender,
+            network: Network::default(),
+            nonces: Arc::new(HandshakeNonces::new()),
+            reassemblers: Arc::new(Mutex::new(ChannelReassemblers::new())),
+            channel_open: EventWaiters::default(),
+            send_buffer: EventWaiters::default(),
         }
     }
 
@@ -120,7 +152,10 @@ impl IceTransport<Event, CbChannel<Event>> for WasmTransport {
             .ok()
             .as_ref()
             .map(|c| Arc::new(c.to_owned()));
-        self.setup_channel("rings").await;
+        self.setup_channel(DEFAULT_CHANNEL, DataChannelOptions::reliable())
+            .await?;
+        self.setup_channel(CONTROL_CHANNEL, DataChannelOptions::reliable())
+            .await?;
         return Ok(self);
     }
 
@@ -222,18 +257,67 @@ impl IceTransport<Event, CbChannel<Event>> for WasmTransport {
     }
 
     async fn get_data_channel(&self) -> Option<Arc<Self::DataChannel>> {
-        self.channel.as_ref().map(Arc::clone)
+        self.get_channel(DEFAULT_CHANNEL)
     }
 
     async fn send_message(&self, msg: &[u8]) -> Result<()> {
-        match self.get_data_channel().await {
-            Some(cnn) => cnn
-                .send_with_u8_array(msg)
-                .map_err(|e| Error::RTCDataChannelSendTextFailed(format!("{:?}", e))),
-            None => Err(Error::RTCDataChannelNotReady),
+        self.send_message_on(DEFAULT_CHANNEL, msg).await
+    }
+
+    async fn send_message_on(&self, label: &str, msg: &[u8]) -> Result<()> {
+        let cnn = self
+            .get_channel(label)
+            .ok_or_else(|| Error::DataChannelNotFound(label.to_string()))?;
+        self.wait_for_channel_open(&cnn).await?;
+        let amount = cnn.buffered_amount() as usize;
+        if amount > SEND_BUFFER_HIGH_WATERMARK {
+            return Err(Error::SendBufferFull(amount));
+        }
+        let frames = fragment::fragment(msg)?;
+        if frames.len() > 1 && !Self::channel_options(&cnn).is_reliable() {
+            return Err(Error::MessageTooLargeForChannel(
+                label.to_string(),
+                msg.len(),
+            ));
+        }
+        for frame in frames {
+            cnn.send_with_u8_array(&frame)
+                .map_err(|e| Error::RTCDataChannelSendTextFailed(format!("{:?}", e)))?;
+        }
+        Ok(())
+    }
+
+    async fn open_channel(&self, label: &str, options: DataChannelOptions) -> Result<()> {
+        if self.get_channel(label).is_some() {
+            return Ok(());
+        }
+        self.setup_channel(label, options).await
+    }
+
+    async fn buffered_amount(&self, label: &str) -> usize {
+        match self.get_channel(label) {
+            Some(cnn) => cnn.buffered_amount() as usize,
+            None => 0,
         }
     }
 
+    async fn wait_for_buffered_amount_low(&self, label: &str, threshold: usize) -> Result<()> {
+        let cnn = self
+            .get_channel(label)
+            .ok_or_else(|| Error::DataChannelNotFound(label.to_string()))?;
+        if threshold < SEND_BUFFER_LOW_WATERMARK {
+            cnn.set_buffered_amount_low_threshold(threshold as u32);
+        }
+        while cnn.buffered_amount() as usize > threshold {
+            if cnn.ready_state() != RtcDataChannelState::Open {
+                return Err(Error::RTCDataChannelStateNotOpen);
+            }
+            // events are dispatched by the event loop, so none fires before it's awaited
+            self.send_buffer.promise().await?;
+        }
+        Ok(())
+    }
+
     async fn set_local_description<T>(&self, desc: T) -> Result<()>
     where T: Into<Self::Sdp> {
         match &self.get_peer_connection().await {
@@ -305,14 +389,123 @@ impl IceTransport<Event, CbChannel<Event>> for WasmTransport {
 }
 
 impl WasmTransport {
-    pub async fn setup_channel(&mut self, name: &str) {
-        if let Some(conn) = &self.connection {
-            let channel = conn.create_data_channel(name);
-            self.channel = Some(Arc::new(channel));
+    /// Create data channel `name`. Messages are received on channels created by either side,
+    /// and sent on the channel created by this side, see `on_data_channel` for the other one.
+    pub async fn setup_channel(&self, name: &str, options: DataChannelOptions) -> Result<()> {
+        let conn = self
+            .connection
+            .as_ref()
+            .ok_or(Error::RTCPeerConnectionNotEstablish)?;
+        let mut init = RtcDataChannelInit::new();
+        init.ordered(options.ordered);
+        if let Some(n) = options.max_retransmits {
+            init.max_retransmits(n);
+        }
+        let channel = conn.create_data_channel_with_data_channel_dict(name, &init);
+        self.channel_handlers().watch(&channel);
+        self.channels
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(channel));
+        Ok(())
+    }
+
+    /// Get data channel `label` to send on, created by this side or else by remote peer.
+    pub fn get_channel(&self, label: &str) -> Option<Arc<RtcDataChannel>> {
+        self.channels.lock().unwrap().get(label).cloned()
+    }
+
+    fn channel_handlers(&self) -> ChannelHandlers {
+        ChannelHandlers {
+            event_sender: Arc::clone(&self.event_sender),
+            reassemblers: Arc::clone(&self.reassemblers),
+            channel_open: self.channel_open.clone(),
+            send_buffer: self.send_buffer.clone(),
+        }
+    }
+
+    fn channel_options(ch: &RtcDataChannel) -> DataChannelOptions {
+        DataChannelOptions {
+            ordered: ch.ordered(),
+            max_retransmits: ch.max_retransmits(),
         }
     }
 }
 
+impl ChannelHandlers {
+    /// Deliver messages of data channel `ch` to swarm, and wake tasks waiting for
+    /// the channel to open or drain. Handlers live as long as the channel.
+    fn watch(&self, ch: &RtcDataChannel) {
+        ch.set_buffered_amount_low_threshold(SEND_BUFFER_LOW_WATERMARK as u32);
+        let send_buffer = self.send_buffer.clone();
+        let on_buffered_amount_low =
+            Closure::wrap(Box::new(move || send_buffer.wake()) as Box<dyn FnMut()>);
+        ch.set_onbufferedamountlow(Some(on_buffered_amount_low.as_ref().unchecked_ref()));
+        on_buffered_amount_low.forget();
+
+        let channel_open = self.channel_open.clone();
+        let on_open = Closure::wrap(Box::new(move || channel_open.wake()) as Box<dyn FnMut()>);
+        ch.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        on_open.forget();
+        // tasks waiting for the channel to open find it closed
+        let channel_open = self.channel_open.clone();
+        let on_close = Closure::wrap(Box::new(move || channel_open.wake()) as Box<dyn FnMut()>);
+        ch.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        on_close.forget();
+
+        let label = ch.label();
+        let event_sender = Arc::clone(&self.event_sender);
+        let reassemblers = Arc::clone(&self.reassemblers);
+        let on_message_cb = Closure::wrap(
+            (box move |ev: MessageEvent| {
+                let data = ev.data();
+                let label = label.clone();
+                let event_sender = Arc::clone(&event_sender);
+                let reassemblers = Arc::clone(&reassemblers);
+                spawn_local(async move {
+                    let msg = if data.has_type::<web_sys::Blob>() {
+                        let data: web_sys::Blob = data.clone().into();
+                        if data.size() == 0f64 {
+                            return;
+                        }
+                        let data_buffer =
+                            wasm_bindgen_futures::JsFuture::from(data.array_buffer()).await;
+                        if let Err(e) = data_buffer {
+                            log::error!("Failed to read array_buffer from Blob, {:?}", e);
+                            return;
+                        }
+                        Uint8Array::new(&data_buffer.unwrap()).to_vec()
+                    } else {
+                        Uint8Array::new(data.as_ref()).to_vec()
+                    };
+                    if msg.is_empty() {
+                        return;
+                    }
+                    let pushed = reassemblers
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push(&label, &msg);
+                    let msg = match pushed {
+                        Ok(Some(msg)) => msg,
+                        Ok(None) => return,
+                        Err(e) => {
+                            log::error!("Failed on reassemble msg, {:?}", e);
+                            return;
+                        }
+                    };
+                    if let Err(e) =
+                        CbChannel::send(&event_sender, Event::DataChannelMessage(msg)).await
+                    {
+                        log::error!("Failed on handle msg, {:?}", e);
+                    }
+                });
+            }) as Box<dyn FnMut(MessageEvent)>,
+        );
+        ch.set_onmessage(Some(on_message_cb.as_ref().unchecked_ref()));
+        on_message_cb.forget();
+    }
+}
+
 #[async_trait(?Send)]
 impl IceTransportCallback<Event, CbChannel<Event>> for WasmTransport {
     type OnLocalCandidateHdlrFn = Box<dyn FnMut(RtcPeerConnectionIceEvent)>;
@@ -351,10 +544,14 @@ impl IceTransportCallback<Event, CbChannel<Event>> for WasmTransport {
         let peer_connection = self.get_peer_connection().await;
         let id = self.id;
         let public_key = Arc::clone(&self.public_key);
+        let channel_open = self.channel_open.clone();
+        let send_buffer = self.send_buffer.clone();
         box move |ev: web_sys::Event| {
             let mut peer_connection = peer_connection.clone();
             let event_sender = Arc::clone(&event_sender);
             let public_key = Arc::clone(&public_key);
+            let channel_open = channel_open.clone();
+            let send_buffer = send_buffer.clone();
             let id = id;
 
             // log::debug!("got state event {:?}", ev.type_());
@@ -385,6 +582,9 @@ impl IceTransportCallback<Event, CbChannel<Event>> for WasmTransport {
                         Self::IceConnectionState::Failed
                         | Self::IceConnectionState::Disconnected
                         | Self::IceConnectionState::Closed => {
+                            // senders waiting for channels to open or drain find them closed
+                            channel_open.wake();
+                            send_buffer.wake();
                             let local_address: Address =
                                 (*public_key.read().unwrap()).unwrap().address();
                             if CbChannel::send(
@@ -424,46 +624,20 @@ impl IceTransportCallback<Event, CbChannel<Event>> for WasmTransport {
     }
 
     async fn on_data_channel(&self) -> Self::OnDataChannelHdlrFn {
-        let event_sender = self.event_sender.clone();
+        let handlers = self.channel_handlers();
+        let channels = Arc::clone(&self.channels);
 
         box move |ev: RtcDataChannelEvent| {
             log::debug!("channel open");
-            let event_sender = Arc::clone(&event_sender);
             let ch = ev.channel();
-            let on_message_cb = Closure::wrap(
-                (box move |ev: MessageEvent| {
-                    let data = ev.data();
-                    let event_sender = Arc::clone(&event_sender);
-                    spawn_local(async move {
-                        let msg = if data.has_type::<web_sys::Blob>() {
-                            let data: web_sys::Blob = data.clone().into();
-                            if data.size() == 0f64 {
-                                return;
-                            }
-                            let data_buffer =
-                                wasm_bindgen_futures::JsFuture::from(data.array_buffer()).await;
-                            if let Err(e) = data_buffer {
-                                log::error!("Failed to read array_buffer from Blob, {:?}", e);
-                                return;
-                            }
-                            Uint8Array::new(&data_buffer.unwrap()).to_vec()
-                        } else {
-                            Uint8Array::new(data.as_ref()).to_vec()
-                        };
-                        if msg.is_empty() {
-                            return;
-                        }
-                        let event_sender = Arc::clone(&event_sender);
-                        if let Err(e) =
-                            CbChannel::send(&event_sender, Event::DataChannelMessage(msg)).await
-                        {
-                            log::error!("Failed on handle msg, {:?}", e);
-                        }
-                    });
-                }) as Box<dyn FnMut(MessageEvent)>,
-            );
-            ch.set_onmessage(Some(on_message_cb.as_ref().unchecked_ref()));
-            on_message_cb.forget();
+            handlers.watch(&ch);
+            // a channel opened by remote peer only, such as by `open_channel`,
+            // is used by this side to answer on it
+            channels
+                .lock()
+                .unwrap()
+                .entry(ch.label())
+                .or_insert_with(|| Arc::new(ch));
         }
     }
 }
@@ -499,11 +673,13 @@ impl IceTrickleScheme<Event, CbChannel<Event>> for WasmTransport {
             return Err(Error::FailedOnGatherLocalCandidate);
         }
 
-        let data = TricklePayload {
-            sdp: serde_json::to_string(&RtcSessionDescriptionWrapper::from(sdp))
+        let data = self.network.trickle_payload(
+            TransportKind::WebRtc,
+            serde_json::to_string(&RtcSessionDescriptionWrapper::from(sdp))
                 .map_err(Error::Deserialize)?,
-            candidates: local_candidates_json,
-        };
+            local_candidates_json,
+            &self.nonces,
+        );
         log::debug!("prepared handshake info :{:?}", data);
         let resp = MessagePayload::new_direct(
             data,
@@ -519,6 +695,8 @@ impl IceTrickleScheme<Event, CbChannel<Event>> for WasmTransport {
 
         match data.verify() {
             true => {
+                self.network
+                    .check(TransportKind::WebRtc, &data.data, &self.nonces)?;
                 if let Ok(public_key) = data.origin_verification.session.authorizer_pubkey() {
                     let mut pk = self.public_key.write().unwrap();
                     *pk = Some(public_key);
@@ -548,46 +726,33 @@ impl IceTrickleScheme<Event, CbChannel<Event>> for WasmTransport {
 }
 
 impl WasmTransport {
+    /// Set the network of transport, checked in handshake.
+    pub fn set_network(&mut self, network: Network) {
+        self.network = network;
+    }
+
     pub async fn wait_for_data_channel_open(&self) -> Result<()> {
-        let dc = self.get_data_channel().await;
-        match dc {
-            Some(dc) => {
-                if dc.ready_state() == RtcDataChannelState::Open {
-                    return Ok(());
-                }
-                let promise = Promise::default();
-                let state = Arc::clone(&promise.state());
-                let dc_cloned = Arc::clone(&dc);
-                let callback = Closure::wrap(Box::new(move || {
-                    log::debug!(
-                        "wait_for_data_channel_open, state: {:?}",
-                        dc_cloned.ready_state()
-                    );
-                    match dc_cloned.ready_state() {
-                        RtcDataChannelState::Open => {
-                            let state = Arc::clone(&state);
-                            let mut s = state.lock().unwrap();
-                            if let Some(w) = s.waker.take() {
-                                w.wake();
-                                s.completed = true;
-                                s.successed = Some(true);
-                            }
-                        }
-                        x => {
-                            log::debug!("datachannel status: {:?}", x)
-                        }
-                    }
-                }) as Box<dyn FnMut()>);
-                dc.set_onopen(Some(callback.as_ref().unchecked_ref()));
-                callback.forget();
-                promise.await?;
-            }
+        match self.get_data_channel().await {
+            Some(dc) => self.wait_for_channel_open(&dc).await,
             None => {
                 log::error!("{:?}", Error::RTCDataChannelNotReady);
-                return Err(Error::RTCDataChannelNotReady);
+                Err(Error::RTCDataChannelNotReady)
             }
-        };
-        Ok(())
+        }
+    }
+
+    /// Wait for data channel `dc` to open, woken by open and close events of channels.
+    async fn wait_for_channel_open(&self, dc: &Arc<RtcDataChannel>) -> Result<()> {
+        loop {
+            match dc.ready_state() {
+                RtcDataChannelState::Open => return Ok(()),
+                RtcDataChannelState::Closing | RtcDataChannelState::Closed => {
+                    return Err(Error::RTCDataChannelStateNotOpen);
+                }
+                // events are dispatched by the event loop, so none fires before it's awaited
+                _ => self.channel_open.promise().await?,
+            }
+        }
     }
 }
 
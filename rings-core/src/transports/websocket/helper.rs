@@ -0,0 +1,174 @@
+//! Handshake of websocket transports, shared by the listening transport of native nodes
+//! and the dialing transport of browsers.
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use serde::Serialize;
+use web3::types::Address;
+
+use crate::ecc::PublicKey;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::Encoded;
+use crate::message::Encoder;
+use crate::message::MessagePayload;
+use crate::session::SessionManager;
+use crate::transports::helper::HandshakeKind;
+use crate::transports::helper::HandshakeNonces;
+use crate::transports::helper::LinkState;
+use crate::transports::helper::Network;
+use crate::transports::helper::Promise;
+use crate::transports::helper::State;
+use crate::transports::helper::TransportKind;
+use crate::transports::helper::TricklePayload;
+use crate::types::channel::Event;
+
+/// Session description of websocket transport.
+/// A side with `url` accepts the connection, the other side dials it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WebSocketSdp {
+    pub sdp_type: HandshakeKind,
+    pub id: uuid::Uuid,
+    pub url: Option<String>,
+}
+
+/// First frame sent by the dialing side, signed by the dialer.
+/// It answers the nonce of the handshake of accepting side and names both transports,
+/// so it can't be replayed to bind the accepting transport to another peer.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Hello {
+    /// id of the accepting transport
+    pub target: uuid::Uuid,
+    /// id of the dialing transport
+    pub id: uuid::Uuid,
+    /// nonce of the handshake of accepting transport
+    pub challenge: String,
+    /// signed handshake of dialing transport
+    pub handshake: String,
+}
+
+#[derive(Default)]
+pub struct Link {
+    pub local_kind: Option<HandshakeKind>,
+    /// nonce of local handshake
+    pub local_nonce: Option<String>,
+    pub remote: Option<uuid::Uuid>,
+    pub remote_url: Option<String>,
+    /// nonce of remote handshake
+    pub remote_nonce: Option<String>,
+    pub state: Option<LinkState>,
+    pub waiters: Vec<Arc<Mutex<State>>>,
+}
+
+impl Link {
+    /// Update the state, return the promises to resolve, `None` if nothing changed.
+    /// A terminated link never comes back.
+    pub fn set_state(&mut self, state: LinkState) -> Option<Vec<Arc<Mutex<State>>>> {
+        if self.state.map(|s| s.is_terminated()).unwrap_or(false) || self.state == Some(state) {
+            return None;
+        }
+        self.state = Some(state);
+        if state == LinkState::Connected || state.is_terminated() {
+            Some(std::mem::take(&mut self.waiters))
+        } else {
+            Some(vec![])
+        }
+    }
+
+    /// A promise resolved once the link is connected or terminated.
+    pub fn promise(&mut self) -> Promise {
+        let promise = Promise::default();
+        let state = promise.state();
+        match self.state {
+            Some(LinkState::Connected) => complete(&state, true),
+            Some(s) if s.is_terminated() => complete(&state, false),
+            _ => self.waiters.push(state),
+        }
+        promise
+    }
+}
+
+pub fn complete(state: &Arc<Mutex<State>>, success: bool) {
+    let mut s = state.lock().unwrap();
+    s.completed = true;
+    s.successed = Some(success);
+    if let Some(w) = s.waker.take() {
+        w.wake();
+    }
+}
+
+/// Event told to swarm when the link of transport `id` to peer `address` changes.
+pub fn link_event(state: LinkState, address: Option<Address>, id: uuid::Uuid) -> Option<Event> {
+    match (state, address) {
+        (LinkState::Connected, Some(address)) => Some(Event::RegisterTransport((address, id))),
+        (s, Some(address)) if s.is_terminated() => Some(Event::ConnectClosed((address, id))),
+        _ => None,
+    }
+}
+
+/// Sign the handshake payload of a websocket transport.
+pub fn sign_handshake(
+    network: &Network,
+    nonces: &HandshakeNonces,
+    session_manager: &SessionManager,
+    sdp: &WebSocketSdp,
+) -> Result<(Encoded, String)> {
+    let data = network.trickle_payload(
+        TransportKind::WebSocket,
+        serde_json::to_string(sdp).map_err(Error::Serialize)?,
+        vec![],
+        nonces,
+    );
+    let nonce = data.nonce.clone();
+    let resp = MessagePayload::new_direct(
+        data,
+        session_manager,
+        session_manager.authorizer()?.to_owned().into(), // This is a fake destination
+    )?;
+    Ok((resp.gzip(9)?.encode()?, nonce))
+}
+
+/// Decode a handshake of remote peer and verify its signature, the network is not checked yet.
+pub fn decode_handshake(data: &Encoded) -> Result<(MessagePayload<TricklePayload>, WebSocketSdp)> {
+    let data: MessagePayload<TricklePayload> = data.decode()?;
+    if !data.verify() {
+        log::error!("cannot verify message sig");
+        return Err(Error::VerifySignatureFailed);
+    }
+    let sdp = serde_json::from_str::<WebSocketSdp>(&data.data.sdp).map_err(Error::Deserialize)?;
+    Ok((data, sdp))
+}
+
+/// Check a decoded handshake against local network, return the public key of remote peer.
+pub fn check_handshake(
+    network: &Network,
+    nonces: &HandshakeNonces,
+    data: &MessagePayload<TricklePayload>,
+) -> Result<PublicKey> {
+    network.check(TransportKind::WebSocket, &data.data, nonces)?;
+    data.origin_verification.session.authorizer_pubkey()
+}
+
+/// Sign the hello of dialing transport `id`, to accepting transport `target`
+/// which made its handshake with nonce `challenge`.
+pub fn sign_hello(
+    session_manager: &SessionManager,
+    target: uuid::Uuid,
+    id: uuid::Uuid,
+    challenge: String,
+    handshake: &Encoded,
+) -> Result<Vec<u8>> {
+    let hello = Hello {
+        target,
+        id,
+        challenge,
+        handshake: handshake.to_string(),
+    };
+    let payload = MessagePayload::new_direct(
+        hello,
+        session_manager,
+        session_manager.authorizer()?.to_owned().into(), // This is a fake destination
+    )?;
+    Ok(payload.encode()?.into())
+}
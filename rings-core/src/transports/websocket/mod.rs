@@ -0,0 +1,12 @@
+//! Transport over WebSocket. Native nodes listen and dial, browsers can only dial.
+pub mod helper;
+#[cfg(all(not(feature = "wasm"), feature = "websocket"))]
+pub mod transport;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use helper::WebSocketSdp;
+#[cfg(all(not(feature = "wasm"), feature = "websocket"))]
+pub use transport::WebSocketTransport;
+#[cfg(feature = "wasm")]
+pub use wasm::WebSocketTransport;
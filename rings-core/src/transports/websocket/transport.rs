@@ -0,0 +1,657 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+use async_lock::RwLock as AsyncRwLock;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use futures::future::Either;
+use futures::lock::Mutex as FuturesMutex;
+use futures::Sink;
+use futures::SinkExt;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+use web3::types::Address;
+
+use super::helper::check_handshake;
+use super::helper::complete;
+use super::helper::decode_handshake;
+use super::helper::link_event;
+use super::helper::sign_handshake;
+use super::helper::sign_hello;
+use super::helper::Hello;
+use super::helper::Link;
+use super::helper::WebSocketSdp;
+use crate::channels::Channel as AcChannel;
+use crate::ecc::PublicKey;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::Encoded;
+use crate::message::MessagePayload;
+use crate::session::SessionManager;
+use crate::transports::fragment::MAX_MESSAGE_LEN;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::HandshakeKind;
+use crate::transports::helper::HandshakeNonces;
+use crate::transports::helper::LinkState;
+use crate::transports::helper::Network;
+use crate::transports::helper::Promise;
+use crate::transports::helper::TricklePayload;
+use crate::types::channel::Channel;
+use crate::types::channel::Event;
+use crate::types::ice_transport::IceCandidate;
+use crate::types::ice_transport::IceServer;
+use crate::types::ice_transport::IceTransport;
+use crate::types::ice_transport::IceTransportCallback;
+use crate::types::ice_transport::IceTrickleScheme;
+use crate::utils::get_epoch_ms;
+use crate::utils::sleep;
+
+type EventSender = <AcChannel<Event> as Channel<Event>>::Sender;
+type WsSink = Box<dyn Sink<WsMessage, Error = WsError> + Send + Unpin>;
+
+/// Time for the remote peer to dial in, the accepting transport fails after it.
+pub const ACCEPT_TIMEOUT_MS: u128 = 30000;
+/// Time for an incoming connection to finish websocket handshake and send its hello.
+pub const HELLO_TIMEOUT_MS: u64 = 10000;
+/// Max length of the hello, which is the first frame of an incoming connection.
+pub const MAX_HELLO_LEN: usize = 64 * 1024;
+
+/// Frames and messages are not fragmented over websocket, but no longer than a message.
+fn ws_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_LEN),
+        max_frame_size: Some(MAX_MESSAGE_LEN),
+        ..Default::default()
+    }
+}
+
+lazy_static! {
+    /// Transports waiting for the remote peer to dial in, indexed by transport id, with their deadline.
+    static ref ACCEPTING: DashMap<uuid::Uuid, (WebSocketTransport, u128)> = DashMap::new();
+    /// Public url of the local listener, advertised in handshake.
+    static ref LISTEN_URL: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Transport over WebSocket, for nodes reachable by a public address.
+/// It keeps the session signed handshake of IceTrickleScheme,
+/// but instead of ICE, one side dials the listener advertised by the other.
+#[derive(Clone)]
+pub struct WebSocketTransport {
+    pub id: uuid::Uuid,
+    link: Arc<Mutex<Link>>,
+    sink: Arc<FuturesMutex<Option<WsSink>>>,
+    handshake: Arc<Mutex<Option<Encoded>>>,
+    session_manager: Arc<Mutex<Option<SessionManager>>>,
+    event_sender: EventSender,
+    public_key: Arc<AsyncRwLock<Option<PublicKey>>>,
+    network: Network,
+    nonces: Arc<HandshakeNonces>,
+}
+
+impl PartialEq for WebSocketTransport {
+    fn eq(&self, other: &Self) -> bool {
+        self.id.eq(&other.id)
+    }
+}
+
+fn listen_url() -> Option<String> {
+    LISTEN_URL.read().unwrap().clone()
+}
+
+/// Fail the accepting transports which are not dialed in time, and forget them.
+async fn prune_accepting() {
+    let now = get_epoch_ms();
+    let expired = ACCEPTING
+        .iter()
+        .filter(|e| e.value().1 < now)
+        .map(|e| *e.key())
+        .collect::<Vec<_>>();
+    for id in expired {
+        if let Some((_, (transport, _))) = ACCEPTING.remove(&id) {
+            log::debug!("websocket transport {} is not dialed in time", id);
+            transport.set_state(LinkState::Failed).await;
+        }
+    }
+}
+
+#[async_trait]
+impl IceTransport<Event, AcChannel<Event>> for WebSocketTransport {
+    type Connection = ();
+    type Candidate = IceCandidate;
+    type Sdp = WebSocketSdp;
+    type DataChannel = ();
+    type IceConnectionState = LinkState;
+    type Msg = Vec<u8>;
+
+    fn new(event_sender: EventSender) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            link: Arc::new(Mutex::new(Link::default())),
+            sink: Arc::new(FuturesMutex::new(None)),
+            handshake: Arc::new(Mutex::new(None)),
+            session_manager: Arc::new(Mutex::new(None)),
+            public_key: Arc::new(AsyncRwLock::new(None)),
+            event_sender,
+            network: Network::default(),
+            nonces: Arc::new(HandshakeNonces::new()),
+        }
+    }
+
+    async fn start(
+        &mut self,
+        _ice_server: Vec<IceServer>,
+        _external_ip: Option<String>,
+    ) -> Result<&Self> {
+        self.link.lock().unwrap().state = Some(LinkState::New);
+        Ok(self)
+    }
+
+    async fn close(&self) -> Result<()> {
+        ACCEPTING.remove(&self.id);
+        if let Some(mut sink) = self.sink.lock().await.take() {
+            if let Err(e) = sink.send(WsMessage::Close(None)).await {
+                log::debug!("failed to send close frame: {:?}", e);
+            }
+        }
+        self.set_state(LinkState::Closed).await;
+        Ok(())
+    }
+
+    async fn ice_connection_state(&self) -> Option<Self::IceConnectionState> {
+        self.link.lock().unwrap().state
+    }
+
+    async fn is_disconnected(&self) -> bool {
+        self.ice_connection_state()
+            .await
+            .map(|s| s.is_terminated())
+            .unwrap_or(false)
+    }
+
+    async fn is_connected(&self) -> bool {
+        self.ice_connection_state().await == Some(LinkState::Connected)
+    }
+
+    async fn pubkey(&self) -> PublicKey {
+        self.public_key.read().await.unwrap()
+    }
+
+    async fn get_peer_connection(&self) -> Option<Arc<()>> {
+        None
+    }
+
+    async fn get_pending_candidates(&self) -> Vec<IceCandidate> {
+        vec![]
+    }
+
+    async fn get_answer(&self) -> Result<WebSocketSdp> {
+        if self.link.lock().unwrap().remote.is_none() {
+            return Err(Error::RTCPeerConnectionNotEstablish);
+        }
+        let answer = WebSocketSdp {
+            sdp_type: HandshakeKind::Answer,
+            id: self.id,
+            url: listen_url(),
+        };
+        self.set_local_description(answer.clone()).await?;
+        Ok(answer)
+    }
+
+    async fn get_answer_str(&self) -> Result<String> {
+        serde_json::to_string(&self.get_answer().await?).map_err(Error::Serialize)
+    }
+
+    async fn get_offer(&self) -> Result<WebSocketSdp> {
+        let offer = WebSocketSdp {
+            sdp_type: HandshakeKind::Offer,
+            id: self.id,
+            url: listen_url(),
+        };
+        self.set_local_description(offer.clone()).await?;
+        Ok(offer)
+    }
+
+    async fn get_offer_str(&self) -> Result<String> {
+        serde_json::to_string(&self.get_offer().await?).map_err(Error::Serialize)
+    }
+
+    async fn get_data_channel(&self) -> Option<Arc<()>> {
+        None
+    }
+
+    async fn send_message(&self, msg: &[u8]) -> Result<()> {
+        if !self.is_connected().await {
+            return Err(Error::RTCDataChannelStateNotOpen);
+        }
+        match self.sink.lock().await.as_mut() {
+            Some(sink) => sink
+                .send(WsMessage::Binary(msg.to_vec()))
+                .await
+                .map_err(Error::WebSocketError),
+            None => Err(Error::RTCDataChannelNotReady),
+        }
+    }
+
+    /// All channels share the same link.
+    async fn send_message_on(&self, _label: &str, msg: &[u8]) -> Result<()> {
+        self.send_message(msg).await
+    }
+
+    async fn open_channel(&self, _label: &str, _options: DataChannelOptions) -> Result<()> {
+        Ok(())
+    }
+
+    /// Sending waits for the socket to flush, so nothing is queued.
+    async fn buffered_amount(&self, _label: &str) -> usize {
+        0
+    }
+
+    async fn wait_for_buffered_amount_low(&self, _label: &str, _threshold: usize) -> Result<()> {
+        Ok(())
+    }
+
+    async fn add_ice_candidate(&self, _candidate: IceCandidate) -> Result<()> {
+        Ok(())
+    }
+
+    /// Wait for the remote peer to dial in if we have a listener.
+    async fn set_local_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<WebSocketSdp> + Send {
+        let desc: WebSocketSdp = desc.into();
+        self.link.lock().unwrap().local_kind = Some(desc.sdp_type);
+        if desc.url.is_some() {
+            prune_accepting().await;
+            ACCEPTING.insert(self.id, (self.clone(), get_epoch_ms() + ACCEPT_TIMEOUT_MS));
+        }
+        if desc.sdp_type == HandshakeKind::Answer {
+            self.set_state(LinkState::Checking).await;
+        }
+        Ok(())
+    }
+
+    /// Only the transport of the first remote handshake is accepted.
+    async fn set_remote_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<WebSocketSdp> + Send {
+        let desc: WebSocketSdp = desc.into();
+        let mut link = self.link.lock().unwrap();
+        if link.remote.is_some() && link.remote != Some(desc.id) {
+            return Err(Error::RTCSdpTypeNotMatch);
+        }
+        link.remote = Some(desc.id);
+        link.remote_url = desc.url;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IceTransportCallback<Event, AcChannel<Event>> for WebSocketTransport {
+    type OnLocalCandidateHdlrFn =
+        Box<dyn FnMut(Option<IceCandidate>) -> BoxFuture<'static, ()> + Send + Sync>;
+    type OnDataChannelHdlrFn = Box<dyn FnMut(Vec<u8>) -> BoxFuture<'static, ()> + Send + Sync>;
+    type OnIceConnectionStateChangeHdlrFn =
+        Box<(dyn FnMut(LinkState) -> BoxFuture<'static, ()> + Sync + Send + 'static)>;
+
+    /// Handlers are invoked by the socket reader, nothing to register.
+    async fn apply_callback(&self) -> Result<&Self> {
+        Ok(self)
+    }
+
+    async fn on_ice_connection_state_change(&self) -> Self::OnIceConnectionStateChangeHdlrFn {
+        let event_sender = self.event_sender.clone();
+        let public_key = Arc::clone(&self.public_key);
+        let id = self.id;
+        box move |cs: LinkState| {
+            let event_sender = event_sender.clone();
+            let public_key = Arc::clone(&public_key);
+            Box::pin(async move {
+                let remote_address: Option<Address> =
+                    public_key.read().await.map(|pk| pk.address());
+                let ev = match link_event(cs, remote_address, id) {
+                    Some(ev) => ev,
+                    None => {
+                        log::debug!("WebSocketTransport state change {:?}", cs);
+                        return;
+                    }
+                };
+                if event_sender.send(ev).await.is_err() {
+                    log::error!("Failed when send transport event");
+                }
+            })
+        }
+    }
+
+    async fn on_ice_candidate(&self) -> Self::OnLocalCandidateHdlrFn {
+        box move |_: Option<IceCandidate>| Box::pin(async move {})
+    }
+
+    async fn on_data_channel(&self) -> Self::OnDataChannelHdlrFn {
+        let event_sender = self.event_sender.clone();
+        box move |msg: Vec<u8>| {
+            let event_sender = event_sender.clone();
+            Box::pin(async move {
+                if event_sender
+                    .send(Event::DataChannelMessage(msg))
+                    .await
+                    .is_err()
+                {
+                    log::error!("Failed on handle msg")
+                };
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl IceTrickleScheme<Event, AcChannel<Event>> for WebSocketTransport {
+    type SdpType = HandshakeKind;
+
+    /// The answering side dials the offering side right away if it has a listener.
+    async fn get_handshake_info(
+        &self,
+        session_manager: &SessionManager,
+        kind: HandshakeKind,
+    ) -> Result<Encoded> {
+        let sdp = match kind {
+            HandshakeKind::Answer => self.get_answer().await?,
+            HandshakeKind::Offer => self.get_offer().await?,
+        };
+        let (handshake, nonce) =
+            sign_handshake(&self.network, &self.nonces, session_manager, &sdp)?;
+        self.link.lock().unwrap().local_nonce = Some(nonce);
+        *self.handshake.lock().unwrap() = Some(handshake.clone());
+        *self.session_manager.lock().unwrap() = Some(session_manager.clone());
+
+        if kind == HandshakeKind::Answer {
+            let remote_url = self.link.lock().unwrap().remote_url.clone();
+            match remote_url {
+                Some(url) => self.dial(&url).await?,
+                None if sdp.url.is_some() => {}
+                None => return Err(Error::WebSocketNoListener),
+            }
+        }
+        Ok(handshake)
+    }
+
+    /// The offering side dials the answering side if it got no connection yet.
+    async fn register_remote_info(&self, data: Encoded) -> Result<Address> {
+        let (data, sdp) = decode_handshake(&data)?;
+        self.adopt_remote(&data).await?;
+        self.set_remote_description(sdp.clone()).await?;
+        if sdp.sdp_type == HandshakeKind::Answer && !self.is_connected().await {
+            if let Some(url) = sdp.url {
+                self.dial(&url).await?;
+            }
+        }
+        Ok(data.addr)
+    }
+
+    async fn wait_for_connected(&self) -> Result<()> {
+        let promise = self.connect_success_promise().await?;
+        promise.await
+    }
+}
+
+impl WebSocketTransport {
+    /// Set the network of transport, checked in handshake.
+    pub fn set_network(&mut self, network: Network) {
+        self.network = network;
+    }
+
+    /// Accept websocket connections on `bind`, peers will dial `public_url`.
+    /// Once listening, all transports of this process advertise `public_url` in handshake.
+    pub async fn listen(bind: &str, public_url: &str) -> Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(bind).await.map_err(Error::IOError)?;
+        log::info!("websocket transport listening on {}", bind);
+        Ok(Self::listen_on(listener, public_url))
+    }
+
+    /// Accept websocket connections on a bound listener, peers will dial `public_url`.
+    pub fn listen_on(listener: TcpListener, public_url: &str) -> JoinHandle<()> {
+        *LISTEN_URL.write().unwrap() = Some(public_url.to_string());
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(async move {
+                            if let Err(e) = Self::accept(stream).await {
+                                log::warn!("refused websocket from {}: {:?}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => log::error!("failed to accept websocket: {:?}", e),
+                }
+            }
+        })
+    }
+
+    async fn accept<S>(stream: S) -> Result<()>
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        // a peer holding the socket without saying hello is dropped
+        let handshake = Box::pin(async move {
+            let mut ws = tokio_tungstenite::accept_async_with_config(stream, Some(ws_config()))
+                .await
+                .map_err(Error::WebSocketError)?;
+            let hello: MessagePayload<Hello> = match ws.next().await {
+                Some(Ok(WsMessage::Binary(data))) if data.len() <= MAX_HELLO_LEN => {
+                    Encoded::try_from(data)?.decode()?
+                }
+                _ => return Err(Error::InvalidTransport),
+            };
+            Ok((ws, hello))
+        });
+        let (ws, hello) =
+            match futures::future::select(handshake, Box::pin(sleep(HELLO_TIMEOUT_MS))).await {
+                Either::Left((ret, _)) => ret?,
+                Either::Right(_) => return Err(Error::WebSocketHelloTimeout),
+            };
+        let target = hello.data.target;
+        prune_accepting().await;
+        let transport = ACCEPTING
+            .get(&target)
+            .map(|e| e.value().0.clone())
+            .ok_or_else(|| Error::WebSocketTransportNotFound(target.to_string()))?;
+        transport.check_hello(&hello).await?;
+        // only the first valid hello takes the transport
+        ACCEPTING
+            .remove(&target)
+            .ok_or_else(|| Error::WebSocketTransportNotFound(target.to_string()))?;
+        transport.attach(ws).await;
+        Ok(())
+    }
+
+    /// Check the hello of dialing peer: it's signed by the peer of its handshake, answers the nonce
+    /// of local handshake, and comes from the remote transport of the handshake received if any.
+    async fn check_hello(&self, hello: &MessagePayload<Hello>) -> Result<()> {
+        if !hello.verify() {
+            return Err(Error::VerifySignatureFailed);
+        }
+        let local_kind = {
+            let link = self.link.lock().unwrap();
+            if link.local_nonce.as_ref() != Some(&hello.data.challenge) {
+                return Err(Error::HandshakeChallengeMismatch);
+            }
+            if link.remote.is_some() && link.remote != Some(hello.data.id) {
+                return Err(Error::WebSocketHelloMismatch);
+            }
+            link.local_kind
+        };
+        let (data, sdp) = decode_handshake(&hello.data.handshake.as_str().into())?;
+        if data.addr != hello.addr || sdp.id != hello.data.id || Some(sdp.sdp_type) == local_kind {
+            return Err(Error::WebSocketHelloMismatch);
+        }
+        self.adopt_remote(&data).await?;
+        self.set_remote_description(sdp).await
+    }
+
+    async fn dial(&self, url: &str) -> Result<()> {
+        let hello = {
+            let link = self.link.lock().unwrap();
+            let session_manager = self.session_manager.lock().unwrap().clone();
+            let handshake = self.handshake.lock().unwrap().clone();
+            match (
+                link.remote,
+                link.remote_nonce.clone(),
+                session_manager,
+                handshake,
+            ) {
+                (Some(target), Some(challenge), Some(session_manager), Some(handshake)) => {
+                    sign_hello(&session_manager, target, self.id, challenge, &handshake)?
+                }
+                _ => return Err(Error::RTCPeerConnectionNotEstablish),
+            }
+        };
+        let (mut ws, _) = tokio_tungstenite::connect_async_with_config(url, Some(ws_config()))
+            .await
+            .map_err(Error::WebSocketError)?;
+        ws.send(WsMessage::Binary(hello))
+            .await
+            .map_err(Error::WebSocketError)?;
+        ACCEPTING.remove(&self.id);
+        self.attach(ws).await;
+        Ok(())
+    }
+
+    /// Check the verified handshake of remote peer against local network,
+    /// and remember its public key and nonce.
+    async fn adopt_remote(&self, data: &MessagePayload<TricklePayload>) -> Result<()> {
+        let public_key = check_handshake(&self.network, &self.nonces, data)?;
+        let mut pk = self.public_key.write().await;
+        if pk.map(|k| k != public_key).unwrap_or(false) {
+            return Err(Error::VerifySignatureFailed);
+        }
+        *pk = Some(public_key);
+        self.link.lock().unwrap().remote_nonce = Some(data.data.nonce.clone());
+        Ok(())
+    }
+
+    /// Take over a connected socket, forward incoming frames to swarm until it's closed.
+    async fn attach<S>(&self, ws: WebSocketStream<S>)
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        let (sink, mut stream) = ws.split();
+        *self.sink.lock().await = Some(Box::new(sink));
+        self.set_state(LinkState::Connected).await;
+
+        let transport = self.clone();
+        tokio::spawn(async move {
+            let mut on_message = transport.on_data_channel().await;
+            while let Some(msg) = stream.next().await {
+                match msg {
+                    Ok(WsMessage::Binary(data)) => on_message(data).await,
+                    Ok(WsMessage::Close(_)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+            transport.set_state(LinkState::Disconnected).await;
+        });
+    }
+
+    /// Update the state, resolve pending promises, and notify the swarm like an ICE state change.
+    async fn set_state(&self, state: LinkState) {
+        let waiters = match self.link.lock().unwrap().set_state(state) {
+            Some(waiters) => waiters,
+            None => return,
+        };
+        for w in waiters.iter() {
+            complete(w, state == LinkState::Connected);
+        }
+        let mut on_state_change = self.on_ice_connection_state_change().await;
+        on_state_change(state).await;
+    }
+
+    /// Frames can be sent as soon as the socket is attached.
+    pub async fn wait_for_data_channel_open(&self) -> Result<()> {
+        self.wait_for_connected().await
+    }
+
+    pub async fn connect_success_promise(&self) -> Result<Promise> {
+        Ok(self.link.lock().unwrap().promise())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebSocketTransport as Transport;
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    #[tokio::test]
+    async fn test_websocket_handshake_and_close() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(Error::IOError)?;
+        let url = format!("ws://{}", listener.local_addr().map_err(Error::IOError)?);
+        let listener = Transport::listen_on(listener, &url);
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+        let sm1 = SessionManager::new_with_seckey(&key1)?;
+        let sm2 = SessionManager::new_with_seckey(&key2)?;
+        let ch1 = AcChannel::<Event>::new();
+        let ch2 = AcChannel::<Event>::new();
+        let mut t1 = Transport::new(ch1.sender());
+        let mut t2 = Transport::new(ch2.sender());
+        t1.start(vec![], None).await?;
+        t2.start(vec![], None).await?;
+
+        let offer = t1.get_handshake_info(&sm1, HandshakeKind::Offer).await?;
+        assert_eq!(t2.register_remote_info(offer).await?, key1.address());
+        // t2 dials the listener of t1 while answering
+        let answer = t2.get_handshake_info(&sm2, HandshakeKind::Answer).await?;
+        t1.wait_for_connected().await?;
+        t2.wait_for_connected().await?;
+        assert_eq!(t1.register_remote_info(answer).await?, key2.address());
+        assert!(matches!(
+            AcChannel::recv(&ch1.receiver()).await?,
+            Some(Event::RegisterTransport((address, id))) if address == key2.address() && id == t1.id
+        ));
+        assert!(matches!(
+            AcChannel::recv(&ch2.receiver()).await?,
+            Some(Event::RegisterTransport((address, id))) if address == key1.address() && id == t2.id
+        ));
+
+        // a hello must be signed by the peer of its handshake, and answer the local nonce
+        let challenge = t1.link.lock().unwrap().local_nonce.clone().unwrap();
+        let sm3 = SessionManager::new_with_seckey(&SecretKey::random())?;
+        let forged = sign_hello(&sm3, t1.id, t2.id, challenge.clone(), &answer)?;
+        assert!(matches!(
+            t1.check_hello(&Encoded::try_from(forged)?.decode()?).await,
+            Err(Error::WebSocketHelloMismatch)
+        ));
+        let replayed = sign_hello(&sm2, t1.id, t2.id, "other nonce".to_string(), &answer)?;
+        assert!(matches!(
+            t1.check_hello(&Encoded::try_from(replayed)?.decode()?)
+                .await,
+            Err(Error::HandshakeChallengeMismatch)
+        ));
+        let other = sign_hello(&sm2, t1.id, uuid::Uuid::new_v4(), challenge, &answer)?;
+        assert!(matches!(
+            t1.check_hello(&Encoded::try_from(other)?.decode()?).await,
+            Err(Error::WebSocketHelloMismatch)
+        ));
+
+        t2.send_message(b"hello").await?;
+        assert!(matches!(
+            AcChannel::recv(&ch1.receiver()).await?,
+            Some(Event::DataChannelMessage(msg)) if msg == b"hello".to_vec()
+        ));
+
+        t1.close().await?;
+        assert!(matches!(
+            AcChannel::recv(&ch2.receiver()).await?,
+            Some(Event::ConnectClosed((address, id))) if address == key1.address() && id == t2.id
+        ));
+        assert!(t2.is_disconnected().await);
+        assert!(t2.send_message(b"bye").await.is_err());
+        listener.abort();
+        Ok(())
+    }
+}
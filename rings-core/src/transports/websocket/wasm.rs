@@ -0,0 +1,443 @@
+//! Websocket transport of browsers. A browser can't listen, so it always dials
+//! a native node which listens on a public address.
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::spawn_local;
+use web3::types::Address;
+use web_sys::BinaryType;
+use web_sys::MessageEvent;
+use web_sys::WebSocket;
+
+use super::helper::check_handshake;
+use super::helper::complete;
+use super::helper::decode_handshake;
+use super::helper::link_event;
+use super::helper::sign_handshake;
+use super::helper::sign_hello;
+use super::helper::Link;
+use super::helper::WebSocketSdp;
+use crate::channels::Channel as CbChannel;
+use crate::ecc::PublicKey;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::Encoded;
+use crate::message::MessagePayload;
+use crate::session::SessionManager;
+use crate::transports::helper::DataChannelOptions;
+use crate::transports::helper::HandshakeKind;
+use crate::transports::helper::HandshakeNonces;
+use crate::transports::helper::LinkState;
+use crate::transports::helper::Network;
+use crate::transports::helper::Promise;
+use crate::transports::helper::TricklePayload;
+use crate::types::channel::Channel;
+use crate::types::channel::Event;
+use crate::types::ice_transport::IceCandidate;
+use crate::types::ice_transport::IceServer;
+use crate::types::ice_transport::IceTransport;
+use crate::types::ice_transport::IceTrickleScheme;
+
+type EventSender = <CbChannel<Event> as Channel<Event>>::Sender;
+
+/// The socket with its handlers, handlers are detached when it's dropped.
+struct Socket {
+    ws: WebSocket,
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        self.ws.set_onerror(None);
+        self.ws.close().ok();
+    }
+}
+
+/// State shared with the handlers of socket.
+#[derive(Clone)]
+struct Notifier {
+    id: uuid::Uuid,
+    link: Arc<Mutex<Link>>,
+    event_sender: EventSender,
+    public_key: Arc<RwLock<Option<PublicKey>>>,
+}
+
+impl Notifier {
+    /// Update the state, resolve pending promises, and notify the swarm like an ICE state change.
+    async fn set_state(&self, state: LinkState) {
+        let waiters = match self.link.lock().unwrap().set_state(state) {
+            Some(waiters) => waiters,
+            None => return,
+        };
+        for w in waiters.iter() {
+            complete(w, state == LinkState::Connected);
+        }
+        let remote_address: Option<Address> =
+            (*self.public_key.read().unwrap()).map(|pk| pk.address());
+        match link_event(state, remote_address, self.id) {
+            Some(ev) => {
+                if CbChannel::send(&self.event_sender, ev).await.is_err() {
+                    log::error!("Failed when send transport event");
+                }
+            }
+            None => log::debug!("WebSocketTransport state change {:?}", state),
+        }
+    }
+
+    async fn deliver(&self, msg: Vec<u8>) {
+        if CbChannel::send(&self.event_sender, Event::DataChannelMessage(msg))
+            .await
+            .is_err()
+        {
+            log::error!("Failed on handle msg")
+        };
+    }
+}
+
+/// Transport over WebSocket, dialing the listener of a native node.
+#[derive(Clone)]
+pub struct WebSocketTransport {
+    pub id: uuid::Uuid,
+    notifier: Notifier,
+    socket: Arc<Mutex<Option<Socket>>>,
+    handshake: Arc<Mutex<Option<Encoded>>>,
+    session_manager: Arc<Mutex<Option<SessionManager>>>,
+    network: Network,
+    nonces: Arc<HandshakeNonces>,
+}
+
+impl PartialEq for WebSocketTransport {
+    fn eq(&self, other: &Self) -> bool {
+        self.id.eq(&other.id)
+    }
+}
+
+#[async_trait(?Send)]
+impl IceTransport<Event, CbChannel<Event>> for WebSocketTransport {
+    type Connection = ();
+    type Candidate = IceCandidate;
+    type Sdp = WebSocketSdp;
+    type DataChannel = ();
+    type IceConnectionState = LinkState;
+    type Msg = Vec<u8>;
+
+    fn new(event_sender: EventSender) -> Self {
+        let id = uuid::Uuid::new_v4();
+        Self {
+            id,
+            notifier: Notifier {
+                id,
+                link: Arc::new(Mutex::new(Link::default())),
+                event_sender,
+                public_key: Arc::new(RwLock::new(None)),
+            },
+            socket: Arc::new(Mutex::new(None)),
+            handshake: Arc::new(Mutex::new(None)),
+            session_manager: Arc::new(Mutex::new(None)),
+            network: Network::default(),
+            nonces: Arc::new(HandshakeNonces::new()),
+        }
+    }
+
+    async fn start(
+        &mut self,
+        _ice_server: Vec<IceServer>,
+        _external_ip: Option<String>,
+    ) -> Result<&Self> {
+        self.notifier.link.lock().unwrap().state = Some(LinkState::New);
+        Ok(self)
+    }
+
+    async fn close(&self) -> Result<()> {
+        let socket = self.socket.lock().unwrap().take();
+        drop(socket);
+        self.notifier.set_state(LinkState::Closed).await;
+        Ok(())
+    }
+
+    async fn ice_connection_state(&self) -> Option<Self::IceConnectionState> {
+        self.notifier.link.lock().unwrap().state
+    }
+
+    async fn is_disconnected(&self) -> bool {
+        self.ice_connection_state()
+            .await
+            .map(|s| s.is_terminated())
+            .unwrap_or(false)
+    }
+
+    async fn is_connected(&self) -> bool {
+        self.ice_connection_state().await == Some(LinkState::Connected)
+    }
+
+    async fn pubkey(&self) -> PublicKey {
+        self.notifier.public_key.read().unwrap().unwrap()
+    }
+
+    async fn get_peer_connection(&self) -> Option<Arc<()>> {
+        None
+    }
+
+    async fn get_pending_candidates(&self) -> Vec<IceCandidate> {
+        vec![]
+    }
+
+    async fn get_answer(&self) -> Result<WebSocketSdp> {
+        if self.notifier.link.lock().unwrap().remote.is_none() {
+            return Err(Error::RTCPeerConnectionNotEstablish);
+        }
+        let answer = WebSocketSdp {
+            sdp_type: HandshakeKind::Answer,
+            id: self.id,
+            url: None,
+        };
+        self.set_local_description(answer.clone()).await?;
+        Ok(answer)
+    }
+
+    async fn get_answer_str(&self) -> Result<String> {
+        serde_json::to_string(&self.get_answer().await?).map_err(Error::Serialize)
+    }
+
+    async fn get_offer(&self) -> Result<WebSocketSdp> {
+        let offer = WebSocketSdp {
+            sdp_type: HandshakeKind::Offer,
+            id: self.id,
+            url: None,
+        };
+        self.set_local_description(offer.clone()).await?;
+        Ok(offer)
+    }
+
+    async fn get_offer_str(&self) -> Result<String> {
+        serde_json::to_string(&self.get_offer().await?).map_err(Error::Serialize)
+    }
+
+    async fn get_data_channel(&self) -> Option<Arc<()>> {
+        None
+    }
+
+    async fn send_message(&self, msg: &[u8]) -> Result<()> {
+        if !self.is_connected().await {
+            return Err(Error::RTCDataChannelStateNotOpen);
+        }
+        match self.socket.lock().unwrap().as_ref() {
+            Some(socket) => socket
+                .ws
+                .send_with_u8_array(msg)
+                .map_err(|e| Error::WebSocketError(format!("{:?}", e))),
+            None => Err(Error::RTCDataChannelNotReady),
+        }
+    }
+
+    /// All channels share the same link.
+    async fn send_message_on(&self, _label: &str, msg: &[u8]) -> Result<()> {
+        self.send_message(msg).await
+    }
+
+    async fn open_channel(&self, _label: &str, _options: DataChannelOptions) -> Result<()> {
+        Ok(())
+    }
+
+    /// All channels share the same socket.
+    async fn buffered_amount(&self, _label: &str) -> usize {
+        self.socket
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|s| s.ws.buffered_amount() as usize)
+            .unwrap_or(0)
+    }
+
+    /// Websocket has no buffered-amount-low event, and sending never fails on a full buffer,
+    /// so there is nothing to wait for.
+    async fn wait_for_buffered_amount_low(&self, _label: &str, _threshold: usize) -> Result<()> {
+        Ok(())
+    }
+
+    async fn add_ice_candidate(&self, _candidate: IceCandidate) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_local_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<WebSocketSdp> {
+        let desc: WebSocketSdp = desc.into();
+        self.notifier.link.lock().unwrap().local_kind = Some(desc.sdp_type);
+        Ok(())
+    }
+
+    /// Only the transport of the first remote handshake is accepted.
+    async fn set_remote_description<T>(&self, desc: T) -> Result<()>
+    where T: Into<WebSocketSdp> {
+        let desc: WebSocketSdp = desc.into();
+        let mut link = self.notifier.link.lock().unwrap();
+        if link.remote.is_some() && link.remote != Some(desc.id) {
+            return Err(Error::RTCSdpTypeNotMatch);
+        }
+        link.remote = Some(desc.id);
+        link.remote_url = desc.url;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl IceTrickleScheme<Event, CbChannel<Event>> for WebSocketTransport {
+    type SdpType = HandshakeKind;
+
+    /// The answering side dials the offering side, which must be listening.
+    async fn get_handshake_info(
+        &self,
+        session_manager: &SessionManager,
+        kind: HandshakeKind,
+    ) -> Result<Encoded> {
+        let sdp = match kind {
+            HandshakeKind::Answer => self.get_answer().await?,
+            HandshakeKind::Offer => self.get_offer().await?,
+        };
+        let (handshake, nonce) =
+            sign_handshake(&self.network, &self.nonces, session_manager, &sdp)?;
+        self.notifier.link.lock().unwrap().local_nonce = Some(nonce);
+        *self.handshake.lock().unwrap() = Some(handshake.clone());
+        *self.session_manager.lock().unwrap() = Some(session_manager.clone());
+
+        if kind == HandshakeKind::Answer {
+            let remote_url = self.notifier.link.lock().unwrap().remote_url.clone();
+            match remote_url {
+                Some(url) => self.dial(&url).await?,
+                None => return Err(Error::WebSocketNoListener),
+            }
+        }
+        Ok(handshake)
+    }
+
+    /// The offering side dials the answering side, which must be listening.
+    async fn register_remote_info(&self, data: Encoded) -> Result<Address> {
+        let (data, sdp) = decode_handshake(&data)?;
+        self.adopt_remote(&data)?;
+        self.set_remote_description(sdp.clone()).await?;
+        if sdp.sdp_type == HandshakeKind::Answer {
+            match sdp.url {
+                Some(url) => self.dial(&url).await?,
+                None => return Err(Error::WebSocketNoListener),
+            }
+        }
+        Ok(data.addr)
+    }
+
+    async fn wait_for_connected(&self) -> Result<()> {
+        let promise = self.connect_success_promise().await?;
+        promise.await
+    }
+}
+
+impl WebSocketTransport {
+    /// Set the network of transport, checked in handshake.
+    pub fn set_network(&mut self, network: Network) {
+        self.network = network;
+    }
+
+    /// Handlers are bound to the socket when dialing, nothing to register.
+    pub async fn apply_callback(&self) -> Result<&Self> {
+        Ok(self)
+    }
+
+    /// Open a socket to `url`, the hello is sent once it's open.
+    async fn dial(&self, url: &str) -> Result<()> {
+        let hello = {
+            let link = self.notifier.link.lock().unwrap();
+            let session_manager = self.session_manager.lock().unwrap().clone();
+            let handshake = self.handshake.lock().unwrap().clone();
+            match (
+                link.remote,
+                link.remote_nonce.clone(),
+                session_manager,
+                handshake,
+            ) {
+                (Some(target), Some(challenge), Some(session_manager), Some(handshake)) => {
+                    sign_hello(&session_manager, target, self.id, challenge, &handshake)?
+                }
+                _ => return Err(Error::RTCPeerConnectionNotEstablish),
+            }
+        };
+        let ws = WebSocket::new(url).map_err(|e| Error::WebSocketError(format!("{:?}", e)))?;
+        ws.set_binary_type(BinaryType::Arraybuffer);
+
+        let on_open = {
+            let ws = ws.clone();
+            let notifier = self.notifier.clone();
+            Closure::wrap(
+                (box move || {
+                    if let Err(e) = ws.send_with_u8_array(&hello) {
+                        log::error!("failed to send websocket hello: {:?}", e);
+                        return;
+                    }
+                    let notifier = notifier.clone();
+                    spawn_local(async move { notifier.set_state(LinkState::Connected).await });
+                }) as Box<dyn FnMut()>,
+            )
+        };
+        let on_message = {
+            let notifier = self.notifier.clone();
+            Closure::wrap(
+                (box move |ev: MessageEvent| {
+                    let msg = Uint8Array::new(&ev.data()).to_vec();
+                    let notifier = notifier.clone();
+                    spawn_local(async move { notifier.deliver(msg).await });
+                }) as Box<dyn FnMut(MessageEvent)>,
+            )
+        };
+        let on_close = {
+            let notifier = self.notifier.clone();
+            Closure::wrap(
+                (box move |_: JsValue| {
+                    let notifier = notifier.clone();
+                    spawn_local(async move { notifier.set_state(LinkState::Disconnected).await });
+                }) as Box<dyn FnMut(JsValue)>,
+            )
+        };
+        ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(on_close.as_ref().unchecked_ref()));
+        *self.socket.lock().unwrap() = Some(Socket {
+            ws,
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_close: on_close,
+        });
+        self.notifier.set_state(LinkState::Checking).await;
+        Ok(())
+    }
+
+    /// Check the verified handshake of remote peer against local network,
+    /// and remember its public key and nonce.
+    fn adopt_remote(&self, data: &MessagePayload<TricklePayload>) -> Result<()> {
+        let public_key = check_handshake(&self.network, &self.nonces, data)?;
+        let mut pk = self.notifier.public_key.write().unwrap();
+        if pk.map(|k| k != public_key).unwrap_or(false) {
+            return Err(Error::VerifySignatureFailed);
+        }
+        *pk = Some(public_key);
+        self.notifier.link.lock().unwrap().remote_nonce = Some(data.data.nonce.clone());
+        Ok(())
+    }
+
+    /// Frames can be sent as soon as the socket is open.
+    pub async fn wait_for_data_channel_open(&self) -> Result<()> {
+        self.wait_for_connected().await
+    }
+
+    pub async fn connect_success_promise(&self) -> Result<Promise> {
+        Ok(self.notifier.link.lock().unwrap().promise())
+    }
+}
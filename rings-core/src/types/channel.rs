@@ -22,4 +22,6 @@ pub trait Channel<T: Send> {
     fn receiver(&self) -> Self::Receiver;
     async fn send(sender: &Self::Sender, msg: T) -> Result<()>;
     async fn recv(receiver: &Self::Receiver) -> Result<Option<T>>;
+    /// Receive a queued message without waiting, `None` if the queue is empty.
+    async fn try_recv(receiver: &Self::Receiver) -> Result<Option<T>>;
 }
@@ -11,6 +11,7 @@ use crate::ecc::PublicKey;
 use crate::err::Result;
 use crate::message::Encoded;
 use crate::session::SessionManager;
+use crate::transports::helper::DataChannelOptions;
 use crate::types::channel::Channel;
 
 /// Struct From [webrtc-rs](https://docs.rs/webrtc/latest/webrtc/ice_transport/ice_candidate/struct.RTCIceCandidateInit.html)
@@ -54,7 +55,20 @@ pub trait IceTransport<E: Send, Ch: Channel<E>> {
     async fn get_answer_str(&self) -> Result<String>;
     async fn get_offer_str(&self) -> Result<String>;
     async fn get_data_channel(&self) -> Option<Arc<Self::DataChannel>>;
+    /// Send `msg` over the default data channel.
     async fn send_message(&self, msg: &[u8]) -> Result<()>;
+    /// Send `msg` over data channel `label`, opened by `open_channel` of either side.
+    /// It doesn't wait for a full send buffer, but fails with `Error::SendBufferFull`,
+    /// then `wait_for_buffered_amount_low` tells when to send again.
+    /// A message which needs fragments is refused by unreliable channels.
+    async fn send_message_on(&self, label: &str, msg: &[u8]) -> Result<()>;
+    /// Open data channel `label` if it's not opened yet, remote peer can send on it as well.
+    async fn open_channel(&self, label: &str, options: DataChannelOptions) -> Result<()>;
+    /// Number of bytes queued on data channel `label` but not sent yet.
+    async fn buffered_amount(&self, label: &str) -> usize;
+    /// Wait until no more than `threshold` bytes are queued on data channel `label`,
+    /// woken by the buffered-amount-low event of the channel.
+    async fn wait_for_buffered_amount_low(&self, label: &str, threshold: usize) -> Result<()>;
     async fn set_local_description<T>(&self, desc: T) -> Result<()>
     where T: Into<Self::Sdp> + Send;
     async fn add_ice_candidate(&self, candidate: IceCandidate) -> Result<()>;
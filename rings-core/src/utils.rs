@@ -3,3 +3,32 @@ use chrono::Utc;
 pub fn get_epoch_ms() -> u128 {
     Utc::now().timestamp_millis() as u128
 }
+
+/// Wait for `ms` milliseconds.
+#[cfg(not(feature = "wasm"))]
+pub async fn sleep(ms: u64) {
+    futures_timer::Delay::new(std::time::Duration::from_millis(ms)).await
+}
+
+/// Wait for `ms` milliseconds.
+/// `window` is missing in web workers, the global scope of worker is used there.
+#[cfg(feature = "wasm")]
+pub async fn sleep(ms: u64) {
+    use wasm_bindgen::JsCast;
+
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let scheduled = match web_sys::window() {
+            Some(window) => {
+                window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms as i32)
+            }
+            None => js_sys::global()
+                .unchecked_into::<web_sys::WorkerGlobalScope>()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms as i32),
+        };
+        // never hang if timer is not available
+        if scheduled.is_err() {
+            resolve.call0(&wasm_bindgen::JsValue::NULL).ok();
+        }
+    });
+    wasm_bindgen_futures::JsFuture::from(promise).await.ok();
+}
@@ -1,2 +1,4 @@
 pub mod test_message_handler;
+#[cfg(feature = "memory")]
+pub mod test_simulator;
 pub mod test_stabilize;
@@ -3,7 +3,13 @@ pub mod test {
     use std::str::FromStr;
     use std::sync::Arc;
 
+    use async_trait::async_trait;
+    use num_bigint::BigUint;
+    use rings_core::dht::file::FILE_CHUNK_SIZE;
+    use rings_core::dht::vnode::VNodeType;
     use rings_core::dht::vnode::VirtualNode;
+    use rings_core::dht::Chord;
+    use rings_core::dht::ChordStorage;
     use rings_core::dht::Did;
     use rings_core::dht::PeerRing;
     use rings_core::ecc::SecretKey;
@@ -14,7 +20,10 @@ pub mod test {
     use rings_core::message::FindSuccessorThen;
     use rings_core::message::Message;
     use rings_core::message::MessageHandler;
+    use rings_core::message::MessagePayload;
     use rings_core::message::PayloadSender;
+    use rings_core::message::RpcHandler;
+    use rings_core::message::SubRingOperator;
     use rings_core::session::SessionManager;
     use rings_core::storage::PersistenceStorage;
     use rings_core::storage::PersistenceStorageOperation;
@@ -130,6 +139,49 @@ pub mod test {
         Ok((transport1, transport2))
     }
 
+    /// Three nodes ordered by did, node1 and node3 are only connected via node2,
+    /// thus messages between them are relayed. Routing tables are set in place.
+    pub async fn prepare_relayed_nodes(
+    ) -> Result<Vec<(Did, Arc<PeerRing>, Arc<Swarm>, MessageHandler)>> {
+        let mut keys = vec![
+            SecretKey::random(),
+            SecretKey::random(),
+            SecretKey::random(),
+        ];
+        keys.sort_by_key(|key| key.address());
+        let mut nodes = vec![];
+        for key in keys.iter() {
+            let did: Did = key.address().into();
+            let path = PersistenceStorage::random_path("./tmp");
+            let dht = Arc::new(new_chord(did, path.as_str()).await);
+            let swarm = Arc::new(new_swarm(key));
+            let handler = MessageHandler::new(Arc::clone(&dht), Arc::clone(&swarm));
+            nodes.push((did, dht, swarm, handler));
+        }
+        for (a, b) in [(0, 1), (1, 2)] {
+            let (transport_a, transport_b) =
+                establish_connection(Arc::clone(&nodes[a].2), Arc::clone(&nodes[b].2)).await?;
+            transport_a.wait_for_data_channel_open().await?;
+            transport_b.wait_for_data_channel_open().await?;
+            nodes[a].1.join(nodes[b].0)?;
+            nodes[b].1.join(nodes[a].0)?;
+        }
+        Ok(nodes)
+    }
+
+    /// Run listening loops of `handlers` until `f` is done.
+    pub async fn with_listening<F, T>(handlers: Vec<MessageHandler>, f: F) -> T
+    where F: std::future::Future<Output = T> {
+        tokio::select! {
+            _ = futures::future::join_all(handlers.into_iter().map(|handler| async move {
+                loop {
+                    Arc::new(handler.clone()).listen().await;
+                }
+            })) => unreachable!(),
+            ret = f => ret,
+        }
+    }
+
     #[tokio::test]
     async fn test_handle_join() -> Result<()> {
         let key1 = SecretKey::random();
@@ -611,4 +663,242 @@ pub mod test {
         tokio::fs::remove_dir_all("./tmp").await.ok();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_handle_sync_vnode_with_successor() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+
+        // vnode is in range (node2, node3], it was kept by node1 before node2 joined
+        let vnode = VirtualNode {
+            address: nodes[1].0 + Did::from(BigUint::from(1u16)),
+            data: vec!["sync data".to_string().encode()?],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        nodes[0].1.storage.put(&vnode.did(), &vnode).await?;
+
+        // node1 sends the vnode to node2, which stores it when handled
+        let synced: VirtualNode = with_listening(handlers, async {
+            nodes[0].3.sync_vnodes_with_successor(nodes[1].0).await?;
+            for _ in 0..50 {
+                let synced: Result<VirtualNode> = nodes[1].1.storage.get(&vnode.did()).await;
+                if synced.is_ok() {
+                    return synced;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+            nodes[1].1.storage.get(&vnode.did()).await
+        })
+        .await?;
+        assert_eq!(synced, vnode);
+        let kept: Result<VirtualNode> = nodes[0].1.storage.get(&vnode.did()).await;
+        assert!(kept.is_err());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_join_subring() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+        let name = "test_subring";
+
+        // node1 creates the subring, node3 joins it via node2,
+        // and leaving is signed by the leaver, node2 can't make node3 leave
+        let (joined, forged, left) = with_listening(handlers, async {
+            SubRingOperator::create(&nodes[0].3, name).await?;
+            wait_for_members(&nodes[0].3, name, |m| !m.is_empty()).await?;
+            SubRingOperator::join(&nodes[2].3, name).await?;
+            let joined = wait_for_members(&nodes[0].3, name, |m| m.contains(&nodes[2].0)).await?;
+            SubRingOperator::leave(&nodes[1].3, name).await?;
+            sleep(Duration::from_millis(500)).await;
+            let forged = SubRingOperator::members(&nodes[0].3, name).await?;
+            SubRingOperator::leave(&nodes[2].3, name).await?;
+            let left = wait_for_members(&nodes[0].3, name, |m| !m.contains(&nodes[2].0)).await?;
+            Ok::<_, Error>((joined, forged, left))
+        })
+        .await?;
+        assert!(joined.contains(&nodes[2].0));
+        assert!(forged.contains(&nodes[2].0));
+        assert!(!left.contains(&nodes[2].0));
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    /// Subring messages are not acknowledged, poll members until `f` holds.
+    async fn wait_for_members<F>(handler: &MessageHandler, name: &str, f: F) -> Result<Vec<Did>>
+    where F: Fn(&Vec<Did>) -> bool {
+        for _ in 0..50 {
+            if let Ok(members) = SubRingOperator::members(handler, name).await {
+                if f(&members) {
+                    return Ok(members);
+                }
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        SubRingOperator::members(handler, name).await
+    }
+
+    #[tokio::test]
+    async fn test_lookup_iteratively_relayed() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+
+        // vnode is in range (node3, node2], node3 is responsible for it
+        let vnode = VirtualNode {
+            address: nodes[2].0 + Did::from(BigUint::from(1u16)),
+            data: vec!["relayed answer".to_string().encode()?],
+            kind: VNodeType::Data,
+            expired_at: None,
+        };
+        assert!(nodes[2].1.store(vnode.clone()).await?.is_none());
+
+        // node1 is told to ask node3 by node2, and asks node3 via node2,
+        // the answer of node3 is relayed back by node2
+        let step = with_listening(
+            handlers,
+            nodes[0]
+                .3
+                .lookup_iteratively(vnode.did(), message::LookupTarget::VNode),
+        )
+        .await?;
+        assert_eq!(step, message::LookupStep::VNode(vnode));
+        assert!(nodes[0].2.get_transport(&nodes[2].2.address()).is_none());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_crawl_relayed() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+
+        // node3 is reported by node2, and its topology is relayed back by node2
+        let map = with_listening(handlers, nodes[0].3.crawl(16)).await?;
+        assert_eq!(
+            map.nodes.iter().map(|n| n.did).collect::<Vec<_>>(),
+            nodes.iter().map(|n| n.0).collect::<Vec<_>>()
+        );
+        assert!(map.unreachable.is_empty());
+        assert!(nodes[0].2.get_transport(&nodes[2].2.address()).is_none());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_and_fetch_file_relayed() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+        let data = (0..(FILE_CHUNK_SIZE * 4 + 100))
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+
+        // chunks are spread over the ring by node1, and node3 fetches them via node2
+        let (manifest, fetched) = with_listening(handlers, async {
+            let id = nodes[0].3.store_file("data.bin", &data).await?;
+            // storing is not acknowledged, retry until all chunks are in place
+            for _ in 0..50 {
+                if let Ok(file) = nodes[2].3.fetch_file(id).await {
+                    return Ok(file);
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+            nodes[2].3.fetch_file(id).await
+        })
+        .await?;
+        assert_eq!(manifest.name, "data.bin");
+        assert_eq!(manifest.owner, nodes[0].2.address());
+        assert_eq!(manifest.chunks.len(), 5);
+        assert_eq!(fetched, data);
+        assert!(nodes[0].2.get_transport(&nodes[2].2.address()).is_none());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    /// Echo requests of one caller only.
+    struct EchoTo(Did);
+
+    #[async_trait]
+    impl RpcHandler for EchoTo {
+        async fn handle(
+            &self,
+            _handler: &MessageHandler,
+            ctx: &MessagePayload<Message>,
+            request: &[u8],
+        ) -> std::result::Result<Vec<u8>, String> {
+            if ctx.origin_signer() != self.0 {
+                return Err("unexpected caller".to_string());
+            }
+            Ok(request.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_call_relayed() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+        nodes[2]
+            .3
+            .register_rpc_handler("echo", Arc::new(EchoTo(nodes[0].0)));
+        let pubkey = Some(nodes[2].2.session_manager().session_key()?.pubkey());
+
+        // node1 calls node3 via node2, the response signed by node3 is relayed back
+        let (plain, encrypted, other) = with_listening(handlers, async {
+            let plain = nodes[0].3.call(nodes[2].0, "echo", b"hello", &None).await?;
+            let encrypted = nodes[0]
+                .3
+                .call(nodes[2].0, "echo", b"secret", &pubkey)
+                .await?;
+            let other = nodes[1].3.call(nodes[2].0, "echo", b"hello", &None).await;
+            Ok::<_, Error>((plain, encrypted, other))
+        })
+        .await?;
+        assert_eq!(plain, b"hello".to_vec());
+        assert_eq!(encrypted, b"secret".to_vec());
+        assert!(matches!(other, Err(Error::RpcCallFailed(e)) if e == "unexpected caller"));
+        assert!(nodes[0].2.get_transport(&nodes[2].2.address()).is_none());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    /// Forward requests to "echo" of another node.
+    struct Forward(Did);
+
+    #[async_trait]
+    impl RpcHandler for Forward {
+        async fn handle(
+            &self,
+            handler: &MessageHandler,
+            _ctx: &MessagePayload<Message>,
+            request: &[u8],
+        ) -> std::result::Result<Vec<u8>, String> {
+            handler
+                .call(self.0, "echo", request, &None)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_handler_calls_other_node() -> Result<()> {
+        let nodes = prepare_relayed_nodes().await?;
+        let handlers = nodes.iter().map(|n| n.3.clone()).collect();
+        nodes[1]
+            .3
+            .register_rpc_handler("echo", Arc::new(EchoTo(nodes[2].0)));
+        nodes[2]
+            .3
+            .register_rpc_handler("forward", Arc::new(Forward(nodes[1].0)));
+
+        // node3 answers node1 after its own call to node2 is answered
+        let forwarded = with_listening(
+            handlers,
+            nodes[0].3.call(nodes[2].0, "forward", b"hello", &None),
+        )
+        .await?;
+        assert_eq!(forwarded, b"hello".to_vec());
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
@@ -0,0 +1,214 @@
+//! Deterministic ring simulator over memory transports, no network required.
+//! Nothing runs in background: the simulator hands queued messages to nodes one by one
+//! until no message is left, so each run handles the same messages in the same order.
+//! Run with `cargo test -p rings-core --features memory`.
+#[cfg(test)]
+pub mod test {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use rings_core::dht::Did;
+    use rings_core::dht::PeerRing;
+    use rings_core::dht::Stabilization;
+    use rings_core::ecc::SecretKey;
+    use rings_core::err::Result;
+    use rings_core::message::MessageHandler;
+    use rings_core::session::SessionManager;
+    use rings_core::storage::PersistenceStorage;
+    use rings_core::swarm::Swarm;
+    use rings_core::swarm::TransportManager;
+    use rings_core::transports::TransportKind;
+    use rings_core::types::ice_transport::IceTransport;
+    use rings_core::types::ice_transport::IceTrickleScheme;
+    use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+
+    /// Passes over all nodes allowed before the queues must be drained.
+    const MAX_PASSES: usize = 10000;
+
+    struct SimNode {
+        did: Did,
+        dht: Arc<PeerRing>,
+        swarm: Arc<Swarm>,
+        handler: MessageHandler,
+        stabilization: Stabilization,
+        path: String,
+    }
+
+    /// A ring of Swarm + MessageHandler + Stabilization nodes in one process.
+    /// Node keys are derived from their index, so each run builds the same ring.
+    struct Simulator {
+        nodes: Vec<SimNode>,
+        next_index: usize,
+    }
+
+    impl Simulator {
+        fn new() -> Self {
+            Self {
+                nodes: vec![],
+                next_index: 0,
+            }
+        }
+
+        async fn spawn_node(&mut self) -> Result<usize> {
+            self.next_index += 1;
+            let key = SecretKey::from_str(&format!("{:064x}", self.next_index))?;
+            let did: Did = key.address().into();
+            let path = PersistenceStorage::random_path("./tmp");
+            let storage = PersistenceStorage::new_with_cap_and_path(1000000, path.as_str()).await?;
+            let dht = Arc::new(PeerRing::new_with_storage(did, Arc::new(storage)));
+            let session = SessionManager::new_with_seckey(&key)?;
+            let mut swarm = Swarm::new("stun://stun.l.google.com:19302", key.address(), session);
+            swarm.set_transport_kind(TransportKind::Memory);
+            let swarm = Arc::new(swarm);
+            let handler = MessageHandler::new(dht.clone(), swarm.clone());
+            let stabilization = Stabilization::new(dht.clone(), swarm.clone(), 1);
+            self.nodes.push(SimNode {
+                did,
+                dht,
+                swarm,
+                handler,
+                stabilization,
+                path,
+            });
+            Ok(self.nodes.len() - 1)
+        }
+
+        /// Handshake node `a` with node `b` directly, as a bootstrap connection.
+        /// Both transports are pending, so the swarms register them and join each other on connected.
+        async fn connect(&self, a: usize, b: usize) -> Result<()> {
+            let (swarm_a, swarm_b) = (&self.nodes[a].swarm, &self.nodes[b].swarm);
+            let transport_a = swarm_a.new_transport().await?;
+            swarm_a.push_pending_transport(&transport_a)?;
+            let offer = transport_a
+                .get_handshake_info(swarm_a.session_manager(), RTCSdpType::Offer)
+                .await?;
+
+            let transport_b = swarm_b.new_transport().await?;
+            transport_b.register_remote_info(offer).await?;
+            let answer = transport_b
+                .get_handshake_info(swarm_b.session_manager(), RTCSdpType::Answer)
+                .await?;
+            swarm_b.push_pending_transport(&transport_b)?;
+
+            transport_a.register_remote_info(answer).await?;
+            transport_a.wait_for_connected().await?;
+            transport_b.wait_for_connected().await?;
+            self.run_until_idle().await;
+            Ok(())
+        }
+
+        /// Stop a node and close all its transports, like a crashed peer.
+        async fn kill(&mut self, i: usize) -> Result<()> {
+            let node = self.nodes.remove(i);
+            for (_, transport) in node.swarm.get_transports() {
+                transport.close().await?;
+            }
+            for transport in node.swarm.pending_transports().await? {
+                transport.close().await?;
+            }
+            tokio::fs::remove_dir_all(node.path).await.ok();
+            Ok(())
+        }
+
+        /// Hand queued messages to nodes in order, until all queues are drained.
+        async fn run_until_idle(&self) {
+            for _ in 0..MAX_PASSES {
+                let mut handled = 0;
+                for node in self.nodes.iter() {
+                    handled += node.handler.handle_queued_messages().await;
+                }
+                if handled == 0 {
+                    return;
+                }
+            }
+            panic!("messages still queued after {} passes", MAX_PASSES);
+        }
+
+        async fn stabilize(&self) {
+            for node in self.nodes.iter() {
+                node.stabilization.stabilize().await.ok();
+                self.run_until_idle().await;
+            }
+        }
+
+        /// Whether every node points to its neighbours in the ring made of all nodes.
+        fn is_converged(&self) -> Result<bool> {
+            let mut dids = self.nodes.iter().map(|n| n.did).collect::<Vec<_>>();
+            dids.sort();
+            let n = dids.len();
+            for node in self.nodes.iter() {
+                let i = dids.iter().position(|d| *d == node.did).unwrap();
+                let successor = dids[(i + 1) % n];
+                let predecessor = dids[(i + n - 1) % n];
+                if node.dht.lock_successor()?.min() != successor
+                    || *node.dht.lock_predecessor()? != Some(predecessor)
+                {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+
+        /// Run stabilization rounds until the ring converges, return the rounds used.
+        async fn converge(&self, max_rounds: usize) -> Result<usize> {
+            for round in 0..max_rounds {
+                if self.is_converged()? {
+                    return Ok(round);
+                }
+                self.stabilize().await;
+            }
+            assert!(self.is_converged()?, "ring not converged");
+            Ok(max_rounds)
+        }
+
+        /// Spawn `n` nodes, each joins through an earlier node.
+        async fn grow(&mut self, n: usize) -> Result<()> {
+            for _ in 0..n {
+                let i = self.spawn_node().await?;
+                if i > 0 {
+                    self.connect(i, i / 2).await?;
+                }
+            }
+            Ok(())
+        }
+
+        async fn shutdown(mut self) {
+            while !self.nodes.is_empty() {
+                self.kill(0).await.ok();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulated_ring_converges() -> Result<()> {
+        let mut sim = Simulator::new();
+        sim.grow(100).await?;
+        sim.converge(200).await?;
+        sim.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_simulated_ring_churn() -> Result<()> {
+        let mut sim = Simulator::new();
+        sim.grow(30).await?;
+        sim.converge(100).await?;
+
+        // a third of the ring leaves, then new nodes join through survivors
+        for i in (0..10).rev() {
+            sim.kill(i * 3).await?;
+        }
+        sim.run_until_idle().await;
+        sim.converge(100).await?;
+        assert_eq!(sim.nodes.len(), 20);
+
+        for _ in 0..10 {
+            let i = sim.spawn_node().await?;
+            sim.connect(i, i % 20).await?;
+        }
+        sim.converge(100).await?;
+        assert_eq!(sim.nodes.len(), 30);
+        sim.shutdown().await;
+        Ok(())
+    }
+}
@@ -1,5 +1,3 @@
-use std::mem::size_of_val;
-
 use rexie::TransactionMode;
 use rings_core::storage::persistence::idb::IDBStorageBasic;
 use rings_core::storage::persistence::IDBStorage;
@@ -30,7 +28,7 @@ struct TestDataStruct {
 
 #[wasm_bindgen_test]
 async fn test_create_put_data() {
-    let instance = create_db_instance(4).await;
+    let instance = create_db_instance(1024).await;
     let key = "1".to_string();
     let value = TestDataStruct {
         content: "content1".to_string(),
@@ -103,7 +101,7 @@ async fn test_create_put_data() {
 
 #[wasm_bindgen_test]
 async fn test_indexed_db_count() {
-    let instance = create_db_instance(4).await;
+    let instance = create_db_instance(1024).await;
     instance
         .put(&"1".to_string(), &serde_json::json!("test1"))
         .await
@@ -132,7 +130,7 @@ async fn test_indexed_db_count() {
 
 #[wasm_bindgen_test]
 async fn test_indexed_db_remove() {
-    let instance = create_db_instance(4).await;
+    let instance = create_db_instance(1024).await;
     let key1 = "1".to_string();
     let key2 = "2".to_string();
     let key3 = "3".to_string();
@@ -170,7 +168,8 @@ async fn test_indexed_db_remove() {
 #[wasm_bindgen_test]
 async fn test_idb_prune() {
     super::setup_log();
-    let instance = create_db_instance(4).await;
+    // each entry takes 1 byte of key and 19 bytes of data, 4 entries fit in cap
+    let instance = create_db_instance(80).await;
     let key1 = "1".to_string();
     let key2 = "2".to_string();
     let key3 = "3".to_string();
@@ -232,14 +231,13 @@ async fn test_idb_prune() {
 
 #[wasm_bindgen_test]
 async fn test_idb_total_size() {
-    let instance = create_db_instance(4).await;
+    let instance = create_db_instance(1024).await;
     let key1 = "1".to_string();
     let value1 = TestDataStruct {
         content: "test1".to_owned(),
     };
     instance.put(&key1, &value1).await.unwrap();
-    let expect_size =
-        size_of_val(&JsValue::from(key1)) + size_of_val(&JsValue::from_serde(&value1).unwrap());
+    let expect_size = key1.len() + serde_json::to_string(&value1).unwrap().len();
     let total_size = instance.total_size().await.unwrap();
     assert!(total_size > 0, "total_size should > 0");
     assert!(
@@ -14,7 +14,6 @@ use rings_core::types::channel::Channel;
 use rings_core::types::channel::Event;
 use rings_core::types::ice_transport::IceServer;
 use rings_core::types::ice_transport::IceTransport;
-use rings_core::types::ice_transport::IceTransportCallback;
 use rings_core::types::ice_transport::IceTrickleScheme;
 use wasm_bindgen_test::wasm_bindgen_test_configure;
 use wasm_bindgen_test::*;
@@ -16,7 +16,6 @@ use rings_core::types::channel::Channel;
 use rings_core::types::channel::Event;
 use rings_core::types::ice_transport::IceServer;
 use rings_core::types::ice_transport::IceTransport;
-use rings_core::types::ice_transport::IceTransportCallback;
 use rings_core::types::ice_transport::IceTrickleScheme;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::JsFuture;
@@ -10,9 +10,11 @@ use serde::Deserialize;
 use serde::Serialize;
 
 use self::utils::from_rtc_ice_connection_state;
+use crate::jsonrpc::response;
 use crate::prelude::js_sys;
 use crate::prelude::rings_core::async_trait;
 use crate::prelude::rings_core::dht::PeerRing;
+use crate::prelude::rings_core::dht::PeerRingConfig;
 use crate::prelude::rings_core::dht::Stabilization;
 use crate::prelude::rings_core::dht::TStabilize;
 use crate::prelude::rings_core::ecc::SecretKey;
@@ -32,6 +34,7 @@ use crate::prelude::rings_core::session::Signer;
 use crate::prelude::rings_core::storage::PersistenceStorage;
 use crate::prelude::rings_core::swarm::Swarm;
 use crate::prelude::rings_core::swarm::TransportManager;
+use crate::prelude::rings_core::transports::helper::DEFAULT_CHANNEL;
 use crate::prelude::rings_core::transports::Transport;
 use crate::prelude::rings_core::types::ice_transport::IceTransport;
 use crate::prelude::rings_core::types::message::MessageListener;
@@ -160,18 +163,52 @@ impl Client {
         signed_data: js_sys::Uint8Array,
         stuns: String,
         storage_name: String,
+    ) -> Promise {
+        Self::new_client_with_storage_and_config(
+            unsigned_info,
+            signed_data,
+            stuns,
+            storage_name,
+            JsValue::undefined(),
+        )
+    }
+
+    /// Create a new client instance with ring config.
+    /// ``` typescript
+    /// const config = { successor_max: 3, finger_size: 160, cache_capacity: 1024, cache_ttl_ms: 300000, replica_factor: 1 };
+    /// ```
+    /// Missing fields of config are set to default.
+    pub fn new_client_with_storage_and_config(
+        unsigned_info: &UnsignedInfo,
+        signed_data: js_sys::Uint8Array,
+        stuns: String,
+        storage_name: String,
+        config: JsValue,
     ) -> Promise {
         let unsigned_info = unsigned_info.clone();
         let signed_data = signed_data.to_vec();
         future_to_promise(async move {
+            let config: PeerRingConfig = if config.is_undefined() || config.is_null() {
+                PeerRingConfig::default()
+            } else {
+                config
+                    .into_serde()
+                    .map_err(|e| JsError::new(e.to_string().as_str()))?
+            };
             let random_key = unsigned_info.random_key;
             let session = SessionManager::new(&signed_data, &unsigned_info.auth, &random_key);
             let swarm = Arc::new(Swarm::new(&stuns, unsigned_info.key_addr, session));
 
-            let storage = PersistenceStorage::new_with_cap_and_name(50000, storage_name.as_str())
-                .await
-                .map_err(JsError::from)?;
-            let pr = PeerRing::new_with_storage(swarm.address().into(), Arc::new(storage));
+            let storage =
+                PersistenceStorage::new_with_cap_and_name(50000000, storage_name.as_str())
+                    .await
+                    .map_err(JsError::from)?;
+            let pr = PeerRing::new_with_storage_and_config(
+                swarm.address().into(),
+                Arc::new(storage),
+                config,
+            )
+            .map_err(JsError::from)?;
 
             let dht = Arc::new(pr);
             let msg_handler = Arc::new(MessageHandler::new(dht.clone(), swarm.clone()));
@@ -362,7 +399,10 @@ impl Client {
             let states = futures::future::join_all(states_async).await;
             let mut js_array = js_sys::Array::new();
             js_array.extend(peers.iter().zip(states.iter()).flat_map(|(x, y)| {
-                JsValue::try_from(&Peer::from((*y, x.address.clone(), x.transport.id)))
+                JsValue::try_from(&Peer {
+                    rtt: x.rtt,
+                    ..Peer::from((*y, x.address.clone(), x.transport.id))
+                })
             }));
             Ok(js_array.into())
         })
@@ -380,6 +420,75 @@ impl Client {
         })
     }
 
+    /// leave the DHT gracefully, stored data will be handed off to successor
+    pub fn leave_dht(&self) -> Promise {
+        let p = self.processor.clone();
+        future_to_promise(async move {
+            p.leave_dht().await.map_err(JsError::from)?;
+            Ok(JsValue::from_bool(true))
+        })
+    }
+
+    /// remove a virtual node from DHT, `sig` should be signed by the owner of virtual node
+    pub fn remove_vnode(&self, id: String, seq: u64, sig: js_sys::Uint8Array) -> Promise {
+        let p = self.processor.clone();
+        future_to_promise(async move {
+            p.remove_vnode(id.as_str(), seq, &sig.to_vec())
+                .await
+                .map_err(JsError::from)?;
+            Ok(JsValue::from_str(&id))
+        })
+    }
+
+    /// store a file on DHT, signed by the session of the node, resolve to id of the file
+    pub fn store_file(&self, name: String, data: js_sys::Uint8Array) -> Promise {
+        let p = self.processor.clone();
+        future_to_promise(async move {
+            let id = p
+                .store_file(name.as_str(), &data.to_vec())
+                .await
+                .map_err(JsError::from)?;
+            Ok(JsValue::from_str(&id.to_string()))
+        })
+    }
+
+    /// fetch a file from DHT by its id, resolve to `{name, owner, size, data}`,
+    /// data is base64 encoded
+    pub fn fetch_file(&self, id: String) -> Promise {
+        let p = self.processor.clone();
+        future_to_promise(async move {
+            let file = p.fetch_file(id.as_str()).await.map_err(JsError::from)?;
+            Ok(JsValue::from_serde(&response::File::from(file)).map_err(JsError::from)?)
+        })
+    }
+
+    /// check if sending to a peer over data channel `label`, the default one if omitted,
+    /// would fail because too many bytes are queued
+    pub fn is_send_buffer_full(&self, address: String, label: Option<String>) -> Promise {
+        let p = self.processor.clone();
+        future_to_promise(async move {
+            let label = label.unwrap_or_else(|| DEFAULT_CHANNEL.to_string());
+            let full = p
+                .is_send_buffer_full(address.as_str(), label.as_str())
+                .await
+                .map_err(JsError::from)?;
+            Ok(JsValue::from_bool(full))
+        })
+    }
+
+    /// resolve once data channel `label` to a peer, the default one if omitted, drains,
+    /// so sending can resume
+    pub fn wait_for_send_buffer(&self, address: String, label: Option<String>) -> Promise {
+        let p = self.processor.clone();
+        future_to_promise(async move {
+            let label = label.unwrap_or_else(|| DEFAULT_CHANNEL.to_string());
+            p.wait_for_send_buffer(address.as_str(), label.as_str())
+                .await
+                .map_err(JsError::from)?;
+            Ok(JsValue::null())
+        })
+    }
+
     pub fn list_pendings(&self) -> Promise {
         let p = self.processor.clone();
         future_to_promise(async move {
@@ -541,6 +650,7 @@ pub struct Peer {
     address: String,
     transport_id: String,
     state: Option<String>,
+    rtt: Option<u64>,
 }
 
 #[wasm_bindgen]
@@ -559,6 +669,12 @@ impl Peer {
     pub fn state(&self) -> Option<String> {
         self.state.to_owned()
     }
+
+    /// round trip time in milliseconds
+    #[wasm_bindgen(getter)]
+    pub fn rtt(&self) -> Option<u64> {
+        self.rtt
+    }
 }
 
 impl From<(Option<RtcIceConnectionState>, Token, Uuid)> for Peer {
@@ -567,6 +683,7 @@ impl From<(Option<RtcIceConnectionState>, Token, Uuid)> for Peer {
             address: address.to_string(),
             transport_id: transport_id.to_string(),
             state: st.map(from_rtc_ice_connection_state),
+            rtt: None,
         }
     }
 }
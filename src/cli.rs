@@ -6,6 +6,9 @@ use serde_json::json;
 
 use crate::jsonrpc;
 use crate::jsonrpc::method::Method;
+use crate::jsonrpc::response::File;
+use crate::jsonrpc::response::NodeStatus;
+use crate::jsonrpc::response::NodeTopology;
 use crate::jsonrpc::response::Peer;
 use crate::jsonrpc::response::TransportAndIce;
 use crate::jsonrpc_client::SimpleClient;
@@ -143,11 +146,21 @@ impl Client {
 
         let mut display = String::new();
         display.push_str("Successful\n");
-        display.push_str("Address, TransportId, Status\n");
+        display.push_str("Address, TransportId, Status, RTT(ms)\n");
         display.push_str(
             peers
                 .iter()
-                .map(|peer| format!("{}, {}, {}", peer.address, peer.transport_id, peer.state))
+                .map(|peer| {
+                    format!(
+                        "{}, {}, {}, {}",
+                        peer.address,
+                        peer.transport_id,
+                        peer.state,
+                        peer.rtt
+                            .map(|x| x.to_string())
+                            .unwrap_or_else(|| "-".to_owned())
+                    )
+                })
                 .collect::<Vec<_>>()
                 .join("\n")
                 .as_str(),
@@ -206,6 +219,121 @@ impl Client {
             .map_err(|e| anyhow::anyhow!("{}", e))?;
         ClientOutput::ok("Done.".into(), ())
     }
+
+    pub async fn node_topology(&self) -> Output<NodeTopology> {
+        let resp = self
+            .client
+            .call_method(Method::NodeTopology.as_str(), Params::Array(vec![]))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let topology: NodeTopology =
+            serde_json::from_value(resp).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let mut display = String::new();
+        display.push_str("Successful\n");
+        display.push_str(format!("Did: {}\n", topology.did).as_str());
+        display.push_str(
+            format!(
+                "Predecessor: {}\n",
+                topology.predecessor.as_deref().unwrap_or("-")
+            )
+            .as_str(),
+        );
+        display.push_str(format!("Successors: {}\n", topology.successors.join(", ")).as_str());
+        display.push_str("Fingers:\n");
+        for finger in topology.fingers.iter() {
+            display.push_str(format!("  [{}] {}\n", finger.index, finger.did).as_str());
+        }
+        display.push_str(format!("Cache size: {}\n", topology.cache_size).as_str());
+        display.push_str(
+            format!(
+                "Cache hits/misses: {}/{}\n",
+                topology.cache_hits, topology.cache_misses
+            )
+            .as_str(),
+        );
+        display.push_str(format!("Storage count: {}", topology.storage_count).as_str());
+
+        ClientOutput::ok(display, topology)
+    }
+
+    pub async fn node_status(&self) -> Output<NodeStatus> {
+        let resp = self
+            .client
+            .call_method(Method::NodeStatus.as_str(), Params::Array(vec![]))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let status: NodeStatus =
+            serde_json::from_value(resp).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(
+            format!(
+                "Successful\nDid: {}\nPeers: {}\nEstimated network size: {}",
+                status.did, status.peers, status.estimated_network_size
+            ),
+            status,
+        )
+    }
+
+    /// Crawl the ring, `format` is `json` or `dot`.
+    pub async fn crawl_ring(&self, format: &str, max_nodes: u64) -> Output<String> {
+        let mut params = serde_json::Map::new();
+        params.insert("format".to_owned(), json!(format));
+        params.insert("max_nodes".to_owned(), json!(max_nodes));
+        let resp = self
+            .client
+            .call_method(Method::CrawlRing.as_str(), Params::Map(params))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let map = match resp {
+            Value::String(dot) => dot,
+            v => serde_json::to_string_pretty(&v).map_err(|e| anyhow::anyhow!("{}", e))?,
+        };
+        ClientOutput::ok(map.clone(), map)
+    }
+
+    /// Store `data` on DHT as a file named `name`, return id of the file.
+    pub async fn store_file(&self, name: &str, data: &[u8]) -> Output<String> {
+        let mut params = serde_json::Map::new();
+        params.insert("name".to_owned(), json!(name));
+        params.insert("data".to_owned(), json!(base64::encode(data)));
+        let resp = self
+            .client
+            .call_method(Method::StoreFile.as_str(), Params::Map(params))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let id = resp
+            .get("id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("id not found"))?
+            .to_owned();
+        ClientOutput::ok(format!("Successful\nFile id: {}", id), id)
+    }
+
+    /// Fetch a file from DHT by its id, the result holds the decoded content.
+    pub async fn fetch_file(&self, id: &str) -> Output<Vec<u8>> {
+        let mut params = serde_json::Map::new();
+        params.insert("id".to_owned(), json!(id));
+        let resp = self
+            .client
+            .call_method(Method::FetchFile.as_str(), Params::Map(params))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let file: File = serde_json::from_value(resp).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let data = base64::decode(&file.data).map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok(
+            format!(
+                "Successful\nName: {}\nOwner: {}\nSize: {}",
+                file.name, file.owner, file.size
+            ),
+            data,
+        )
+    }
 }
 
 impl<T> ClientOutput<T> {
@@ -46,6 +46,24 @@ pub enum Error {
     MessagePayload(rings_core::err::Error),
     #[error("No Permission")]
     NoPermission,
+    #[error("Leave DHT error: {0}")]
+    LeaveDHT(rings_core::err::Error),
+    #[error("Remove virtual node error: {0}")]
+    RemoveVNode(rings_core::err::Error),
+    #[error("Inspect topology error: {0}")]
+    Topology(rings_core::err::Error),
+    #[error("Crawl ring error: {0}")]
+    CrawlRing(rings_core::err::Error),
+    #[error("Node status error: {0}")]
+    NodeStatus(rings_core::err::Error),
+    #[error("RPC call error: {0}")]
+    RpcCall(rings_core::err::Error),
+    #[error("Store file error: {0}")]
+    StoreFile(rings_core::err::Error),
+    #[error("Fetch file error: {0}")]
+    FetchFile(rings_core::err::Error),
+    #[error("Send buffer error: {0}")]
+    SendBuffer(rings_core::err::Error),
 }
 
 impl Error {
@@ -72,6 +90,15 @@ impl Error {
             Error::SendMessage(_) => 18,
             Error::MessagePayload(_) => 19,
             Error::NoPermission => 20,
+            Error::LeaveDHT(_) => 21,
+            Error::RemoveVNode(_) => 22,
+            Error::Topology(_) => 23,
+            Error::CrawlRing(_) => 24,
+            Error::NodeStatus(_) => 25,
+            Error::RpcCall(_) => 26,
+            Error::StoreFile(_) => 27,
+            Error::FetchFile(_) => 28,
+            Error::SendBuffer(_) => 29,
         };
         -32000 - code
     }
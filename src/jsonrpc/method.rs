@@ -25,6 +25,20 @@ pub enum Method {
     ListPendings,
     /// Close pending connect
     ClosePendingTransport,
+    /// Remove a virtual node from DHT, signed by its owner
+    RemoveVNode,
+    /// Dump routing state of the node
+    NodeTopology,
+    /// Crawl the ring and build a network map
+    CrawlRing,
+    /// Status of the node
+    NodeStatus,
+    /// Store a file on DHT, signed by the session of the node
+    StoreFile,
+    /// Fetch a file from DHT by its id
+    FetchFile,
+    /// Check if the send buffer of a data channel to a peer is full
+    IsSendBufferFull,
 }
 
 impl Method {
@@ -41,6 +55,13 @@ impl Method {
             Method::AcceptAnswer => "acceptAnswer",
             Method::ListPendings => "listPendings",
             Method::ClosePendingTransport => "closePendingTransport",
+            Method::RemoveVNode => "removeVNode",
+            Method::NodeTopology => "nodeTopology",
+            Method::CrawlRing => "crawlRing",
+            Method::NodeStatus => "nodeStatus",
+            Method::StoreFile => "storeFile",
+            Method::FetchFile => "fetchFile",
+            Method::IsSendBufferFull => "isSendBufferFull",
         }
     }
 }
@@ -66,6 +87,13 @@ impl TryFrom<&str> for Method {
             "acceptAnswer" => Self::AcceptAnswer,
             "listPendings" => Self::ListPendings,
             "closePendingTransport" => Self::ClosePendingTransport,
+            "removeVNode" => Self::RemoveVNode,
+            "nodeTopology" => Self::NodeTopology,
+            "crawlRing" => Self::CrawlRing,
+            "nodeStatus" => Self::NodeStatus,
+            "storeFile" => Self::StoreFile,
+            "fetchFile" => Self::FetchFile,
+            "isSendBufferFull" => Self::IsSendBufferFull,
             _ => return Err(Error::InvalidMethod),
         })
     }
@@ -6,6 +6,8 @@ use serde_json::Value as JsonValue;
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::rings_core::dht::file::FileManifest;
+use crate::prelude::rings_core::dht::PeerRingTopology;
 use crate::prelude::rings_core::message::Encoded;
 use crate::prelude::rings_core::prelude::web3::contract::tokens::Tokenizable;
 use crate::prelude::rings_core::prelude::web3::types::Address;
@@ -17,6 +19,9 @@ pub struct Peer {
     pub address: String,
     pub transport_id: String,
     pub state: String,
+    /// round trip time in milliseconds
+    #[serde(default)]
+    pub rtt: Option<u64>,
 }
 
 impl Peer {
@@ -40,6 +45,7 @@ impl From<(Address, &Arc<Transport>, Option<String>)> for Peer {
             address: address.into_token().to_string(),
             transport_id: transport.id.to_string(),
             state: state.unwrap_or_else(|| "Unknown".to_owned()),
+            rtt: None,
         }
     }
 }
@@ -50,6 +56,7 @@ impl From<(&processor::Peer, Option<String>)> for Peer {
             address: p.address.clone().into_token().to_string(),
             transport_id: p.transport.id.to_string(),
             state: state.unwrap_or_else(|| "Unknown".to_owned()),
+            rtt: p.rtt,
         }
     }
 }
@@ -112,3 +119,104 @@ impl From<(Arc<Transport>, Encoded)> for TransportAndIce {
         }
     }
 }
+
+/// Routing state of a node
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct NodeTopology {
+    pub did: String,
+    pub predecessor: Option<String>,
+    pub successors: Vec<String>,
+    /// non-empty entries of finger table
+    pub fingers: Vec<FingerEntry>,
+    pub cache_size: usize,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub storage_count: u64,
+}
+
+/// An entry of finger table
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FingerEntry {
+    pub index: usize,
+    pub did: String,
+}
+
+impl NodeTopology {
+    pub fn to_json_obj(&self) -> Result<JsonValue> {
+        serde_json::to_value(self).map_err(|_| Error::JsonSerializeError)
+    }
+}
+
+impl From<PeerRingTopology> for NodeTopology {
+    fn from(t: PeerRingTopology) -> Self {
+        Self {
+            did: t.did.to_string(),
+            predecessor: t.predecessor.map(|x| x.to_string()),
+            successors: t.successors.iter().map(|x| x.to_string()).collect(),
+            fingers: t
+                .fingers
+                .iter()
+                .map(|(index, did)| FingerEntry {
+                    index: *index,
+                    did: did.to_string(),
+                })
+                .collect(),
+            cache_size: t.cache_size,
+            cache_hits: t.cache_stats.hits,
+            cache_misses: t.cache_stats.misses,
+            storage_count: t.storage_count,
+        }
+    }
+}
+
+/// Status of a node
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct NodeStatus {
+    pub did: String,
+    pub peers: usize,
+    pub estimated_network_size: u64,
+}
+
+impl NodeStatus {
+    pub fn to_json_obj(&self) -> Result<JsonValue> {
+        serde_json::to_value(self).map_err(|_| Error::JsonSerializeError)
+    }
+}
+
+impl From<processor::NodeStatus> for NodeStatus {
+    fn from(s: processor::NodeStatus) -> Self {
+        Self {
+            did: s.did.to_string(),
+            peers: s.peers,
+            estimated_network_size: s.estimated_network_size,
+        }
+    }
+}
+
+/// A file fetched from DHT
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct File {
+    pub name: String,
+    /// address of uploader
+    pub owner: String,
+    pub size: u64,
+    /// base64 encoded content
+    pub data: String,
+}
+
+impl File {
+    pub fn to_json_obj(&self) -> Result<JsonValue> {
+        serde_json::to_value(self).map_err(|_| Error::JsonSerializeError)
+    }
+}
+
+impl From<(FileManifest, Vec<u8>)> for File {
+    fn from((manifest, data): (FileManifest, Vec<u8>)) -> Self {
+        Self {
+            name: manifest.name,
+            owner: manifest.owner.into_token().to_string(),
+            size: manifest.size,
+            data: base64::encode(data),
+        }
+    }
+}
@@ -17,6 +17,7 @@ use super::response::Peer;
 use super::response::TransportAndIce;
 use crate::error::Error as ServerError;
 use crate::prelude::rings_core::prelude::Address;
+use crate::prelude::rings_core::transports::helper::DEFAULT_CHANNEL;
 use crate::processor;
 use crate::processor::Processor;
 
@@ -58,6 +59,13 @@ pub(crate) async fn build_handler(handler: &mut MetaIoHandler<RpcMeta>) {
         close_pending_transport,
     );
     handler.add_method_with_meta(Method::SendTo.as_str(), send_message);
+    handler.add_method_with_meta(Method::RemoveVNode.as_str(), remove_vnode);
+    handler.add_method_with_meta(Method::NodeTopology.as_str(), node_topology);
+    handler.add_method_with_meta(Method::CrawlRing.as_str(), crawl_ring);
+    handler.add_method_with_meta(Method::NodeStatus.as_str(), node_status);
+    handler.add_method_with_meta(Method::StoreFile.as_str(), store_file);
+    handler.add_method_with_meta(Method::FetchFile.as_str(), fetch_file);
+    handler.add_method_with_meta(Method::IsSendBufferFull.as_str(), is_send_buffer_full);
 }
 
 async fn connect_peer_via_http(params: Params, meta: RpcMeta) -> Result<Value> {
@@ -196,3 +204,119 @@ async fn send_message(params: Params, meta: RpcMeta) -> Result<Value> {
         .await?;
     Ok(serde_json::json!({}))
 }
+
+async fn remove_vnode(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: serde_json::Map<String, Value> = params.parse()?;
+    let id = params
+        .get("id")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let seq = params
+        .get("seq")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_u64()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let sig = params
+        .get("sig")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let sig = base64::decode(sig).map_err(|_| Error::from(ServerError::DecodedError))?;
+    meta.processor.remove_vnode(id, seq, &sig).await?;
+    Ok(serde_json::json!({}))
+}
+
+async fn node_topology(_params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let topology = meta.processor.topology().await?;
+    response::NodeTopology::from(topology)
+        .to_json_obj()
+        .map_err(Error::from)
+}
+
+/// Default max number of nodes reached by crawler.
+const DEFAULT_CRAWL_MAX_NODES: u64 = 1024;
+
+async fn crawl_ring(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: serde_json::Map<String, Value> = match params {
+        Params::None => serde_json::Map::new(),
+        params => params.parse()?,
+    };
+    let max_nodes = match params.get("max_nodes") {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_CRAWL_MAX_NODES,
+    };
+    let map = meta.processor.crawl_ring(max_nodes as usize).await?;
+    match params.get("format").and_then(|v| v.as_str()) {
+        None | Some("json") => {
+            serde_json::to_value(&map).map_err(|_| Error::from(ServerError::JsonSerializeError))
+        }
+        Some("dot") => Ok(Value::String(map.to_dot())),
+        Some(_) => Err(Error::new(ErrorCode::InvalidParams)),
+    }
+}
+
+async fn node_status(_params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let status = meta.processor.node_status().await?;
+    response::NodeStatus::from(status)
+        .to_json_obj()
+        .map_err(Error::from)
+}
+
+async fn store_file(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: serde_json::Map<String, Value> = params.parse()?;
+    let name = params
+        .get("name")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let data = params
+        .get("data")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let data = base64::decode(data).map_err(|_| Error::from(ServerError::DecodedError))?;
+    let id = meta.processor.store_file(name, &data).await?;
+    Ok(serde_json::json!({ "id": id.to_string() }))
+}
+
+async fn fetch_file(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: serde_json::Map<String, Value> = params.parse()?;
+    let id = params
+        .get("id")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let (manifest, data) = meta.processor.fetch_file(id).await?;
+    response::File::from((manifest, data))
+        .to_json_obj()
+        .map_err(Error::from)
+}
+
+/// Check the send buffer of data channel `label` to `address`, the default channel if omitted.
+/// Senders should hold on while it's full, since sending fails instead of waiting.
+async fn is_send_buffer_full(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: serde_json::Map<String, Value> = params.parse()?;
+    let address = params
+        .get("address")
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let label = match params.get("label") {
+        Some(v) => v
+            .as_str()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?,
+        None => DEFAULT_CHANNEL,
+    };
+    let full = meta.processor.is_send_buffer_full(address, label).await?;
+    Ok(Value::Bool(full))
+}
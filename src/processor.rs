@@ -11,13 +11,19 @@ use crate::error::Result;
 use crate::jsonrpc::method;
 use crate::jsonrpc::response::TransportAndIce;
 use crate::jsonrpc_client::SimpleClient;
+use crate::prelude::rings_core::dht::file::FileManifest;
+use crate::prelude::rings_core::dht::vnode::VNodeRemoval;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::dht::PeerRingTopology;
 use crate::prelude::rings_core::dht::Stabilization;
 use crate::prelude::rings_core::ecc::PublicKey;
 use crate::prelude::rings_core::ecc::SecretKey;
 use crate::prelude::rings_core::message::Encoded;
 use crate::prelude::rings_core::message::Message;
 use crate::prelude::rings_core::message::MessageHandler;
-use crate::prelude::rings_core::message::PayloadSender;
+use crate::prelude::rings_core::message::RingMap;
+use crate::prelude::rings_core::message::RpcHandlerFn;
+use crate::prelude::rings_core::message::TChordStorage;
 use crate::prelude::rings_core::prelude::libsecp256k1;
 use crate::prelude::rings_core::prelude::uuid;
 use crate::prelude::rings_core::prelude::web3::contract::tokens::Tokenizable;
@@ -180,10 +186,14 @@ impl Processor {
     /// 5. PeerA: accept_answer.
     pub async fn answer_offer(&self, ice_info: &str) -> Result<(Arc<Transport>, Encoded)> {
         log::info!("connect peer via ice: {}", ice_info);
-        let transport = self.swarm.new_transport().await.map_err(|e| {
-            log::error!("new_transport failed: {}", e);
-            Error::NewTransportError
-        })?;
+        let transport = self
+            .swarm
+            .new_transport_for(&Encoded::from_encoded_str(ice_info))
+            .await
+            .map_err(|e| {
+                log::error!("new_transport failed: {}", e);
+                Error::NewTransportError
+            })?;
         match self.handshake(&transport, ice_info).await {
             Ok(v) => Ok((transport, v)),
             Err(e) => {
@@ -281,7 +291,13 @@ impl Processor {
             "addresses: {:?}",
             transports.iter().map(|(a, _b)| a).collect::<Vec<_>>()
         );
-        let data = transports.iter().map(|x| x.into()).collect::<Vec<Peer>>();
+        let data = transports
+            .iter()
+            .map(|x| Peer {
+                rtt: self.swarm.liveness(&x.0).and_then(|l| l.rtt),
+                ..Peer::from(x)
+            })
+            .collect::<Vec<Peer>>();
         Ok(data)
     }
 
@@ -292,7 +308,10 @@ impl Processor {
             .swarm
             .get_transport(&address)
             .ok_or(Error::TransportNotFound)?;
-        Ok(Peer::from(&(address, transport)))
+        Ok(Peer {
+            rtt: self.swarm.liveness(&address).and_then(|l| l.rtt),
+            ..Peer::from(&(address, transport))
+        })
     }
 
     /// Disconnect a peer with web3 address.
@@ -310,6 +329,69 @@ impl Processor {
         Ok(())
     }
 
+    /// Leave the DHT gracefully, hand off stored data to successor and close all transports.
+    pub async fn leave_dht(&self) -> Result<()> {
+        self.msg_handler.leave().await.map_err(Error::LeaveDHT)
+    }
+
+    /// Remove a virtual node from DHT.
+    /// `sig` should be signed by the owner of virtual node, see `VNodeRemoval`.
+    pub async fn remove_vnode(&self, id: &str, seq: u64, sig: &[u8]) -> Result<()> {
+        let did = Did::from_str(id).map_err(|_| Error::InvalidAddress)?;
+        let removal = VNodeRemoval {
+            did,
+            seq,
+            sig: sig.to_vec(),
+        };
+        self.msg_handler
+            .remove(removal)
+            .await
+            .map_err(Error::RemoveVNode)
+    }
+
+    /// Store `data` on DHT as a file named `name`, signed by the session of local node.
+    /// Return id of the file, which is the address of its manifest.
+    pub async fn store_file(&self, name: &str, data: &[u8]) -> Result<Did> {
+        self.msg_handler
+            .store_file(name, data)
+            .await
+            .map_err(Error::StoreFile)
+    }
+
+    /// Fetch a file from DHT by its id, the chunks are verified against its manifest.
+    pub async fn fetch_file(&self, id: &str) -> Result<(FileManifest, Vec<u8>)> {
+        let did = Did::from_str(id).map_err(|_| Error::InvalidAddress)?;
+        self.msg_handler
+            .fetch_file(did)
+            .await
+            .map_err(Error::FetchFile)
+    }
+
+    /// Dump routing state of local node, including predecessor, successors and finger table.
+    pub async fn topology(&self) -> Result<PeerRingTopology> {
+        self.msg_handler.topology().await.map_err(Error::Topology)
+    }
+
+    /// Walk the ring and collect routing state of every reachable node, up to `max_nodes`.
+    pub async fn crawl_ring(&self, max_nodes: usize) -> Result<RingMap> {
+        self.msg_handler
+            .crawl(max_nodes)
+            .await
+            .map_err(Error::CrawlRing)
+    }
+
+    /// Summary of local node, with an estimation of network size.
+    pub async fn node_status(&self) -> Result<NodeStatus> {
+        Ok(NodeStatus {
+            did: self.swarm.address().into(),
+            peers: self.swarm.get_transport_numbers(),
+            estimated_network_size: self
+                .msg_handler
+                .estimate_network_size()
+                .map_err(Error::NodeStatus)?,
+        })
+    }
+
     /// List all pending transport.
     pub async fn list_pendings(&self) -> Result<Vec<Arc<Transport>>> {
         let pendings = self
@@ -350,13 +432,43 @@ impl Processor {
         );
         let destination = Address::from_str(destination).map_err(|_| Error::InvalidAddress)?;
         let msg = Message::custom(msg, &None).map_err(Error::SendMessage)?;
-        // self.swarm.do_send_payload(address, payload)
-        self.swarm
-            .send_direct_message(msg, destination.into())
+        // an unreachable destination gets the message from the node preceding it
+        self.msg_handler
+            .send_or_store_message(msg, destination.into())
             .await
             .map_err(Error::SendMessage)?;
         Ok(())
     }
+
+    /// Check if sending to a connected peer over data channel `label` would fail,
+    /// because too many bytes are queued on the channel.
+    pub async fn is_send_buffer_full(&self, address: &str, label: &str) -> Result<bool> {
+        let address = Address::from_str(address).map_err(|_| Error::InvalidAddress)?;
+        Ok(self.swarm.is_send_buffer_full(&address, label).await)
+    }
+
+    /// Wait until data channel `label` to a connected peer drains, so sending can resume.
+    pub async fn wait_for_send_buffer(&self, address: &str, label: &str) -> Result<()> {
+        let address = Address::from_str(address).map_err(|_| Error::InvalidAddress)?;
+        self.swarm
+            .wait_for_send_buffer(&address, label)
+            .await
+            .map_err(Error::SendBuffer)
+    }
+
+    /// Call `method` of an address with `request`, and wait for its response.
+    pub async fn call(&self, destination: &str, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        let destination = Address::from_str(destination).map_err(|_| Error::InvalidAddress)?;
+        self.msg_handler
+            .call(destination.into(), method, request, &None)
+            .await
+            .map_err(Error::RpcCall)
+    }
+
+    /// Answer RPC requests of `method` by `handler`.
+    pub fn register_rpc_handler(&self, method: &str, handler: RpcHandlerFn) {
+        self.msg_handler.register_rpc_handler(method, handler)
+    }
 }
 
 /// Peer struct
@@ -366,6 +478,19 @@ pub struct Peer {
     pub address: Token,
     /// transport of the connection.
     pub transport: Arc<Transport>,
+    /// round trip time measured by ping, in milliseconds.
+    pub rtt: Option<u64>,
+}
+
+/// Status of local node
+#[derive(Clone, Debug)]
+pub struct NodeStatus {
+    /// did of the node.
+    pub did: Did,
+    /// number of connected peers.
+    pub peers: usize,
+    /// number of nodes in the ring, estimated from density of known nodes.
+    pub estimated_network_size: u64,
 }
 
 impl From<(Address, Arc<Transport>)> for Peer {
@@ -373,6 +498,7 @@ impl From<(Address, Arc<Transport>)> for Peer {
         Self {
             address: address.into_token(),
             transport,
+            rtt: None,
         }
     }
 }
@@ -382,6 +508,7 @@ impl From<&(Address, Arc<Transport>)> for Peer {
         Self {
             address: address.into_token(),
             transport: transport.clone(),
+            rtt: None,
         }
     }
 }